@@ -1,18 +1,126 @@
-use std::{env, path::Path, process::Command};
+use std::{
+    env,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// `#include "..."` is resolved relative to this directory
+const SHADER_DIR: &str = "./src/graphics";
+
+/// files with these extensions are compiled as that stage
+const STAGES: &[&str] = &["vert", "frag", "comp"];
 
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let mut stage_files = Vec::new();
+    find_shaders(Path::new(SHADER_DIR), &mut stage_files);
+    println!("cargo:rerun-if-changed=./examples/posterize.glsl");
+
+    // combined files, selected with `-DCOMP=1`
+    compile("./src/graphics/shader.glsl", "comp", "shader.comp.spirv");
+    compile("./src/graphics/fxaa.glsl", "comp", "fxaa.comp.spirv");
+    compile("./src/graphics/hud.glsl", "comp", "hud.comp.spirv");
+    compile("./src/graphics/hdr.glsl", "comp", "hdr.comp.spirv");
+    compile("./src/graphics/tonemap.glsl", "comp", "tonemap.comp.spirv");
+    compile("./examples/posterize.glsl", "comp", "posterize.comp.spirv");
+
+    // one file per stage, each gets a `Shader::<NAME>_<STAGE>` const
+    let mut consts = String::from("impl Shader {\n");
+    stage_files.sort();
+    for path in &stage_files {
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        let stage = path.extension().unwrap().to_string_lossy();
+        let out = format!("{stem}.{stage}.spirv");
+        compile(&path.to_string_lossy(), &stage, &out);
+
+        let name = format!("{stem}_{stage}")
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        writeln!(
+            consts,
+            "pub const {name}: &[u32] = read_shader(include_bytes!(concat!(\
+             env!(\"OUT_DIR\"), \"/{out}\")));"
+        )
+        .unwrap();
+    }
+    consts.push_str("}\n");
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("shaders.rs"), consts).unwrap();
+}
+
+/// collects the single stage files, every shader source is watched,
+/// so edits to included files rebuild too
+fn find_shaders(dir: &Path, stage_files: &mut Vec<PathBuf>) {
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let entries = fs::read_dir(dir).unwrap_or_else(|err| {
+        panic!("failed to read {}: {err}", dir.display())
+    });
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_shaders(&path, stage_files);
+            continue;
+        }
+        let Some(ext) = path.extension().and_then(|ext| ext.to_str()) else {
+            continue;
+        };
+        if STAGES.contains(&ext) {
+            stage_files.push(path.clone());
+        } else if ext != "glsl" && ext != "h" {
+            continue;
+        }
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+}
+
+fn compile(src: &str, stage: &str, out: &str) {
     let out_dir = env::var("OUT_DIR").unwrap();
-    let dest = Path::new(&out_dir).join("shader.comp.spirv");
+    let dest = Path::new(&out_dir).join(out);
+
+    let mut cmd = Command::new("glslc");
+    cmd.arg(format!("-D{}=1", stage.to_ascii_uppercase()));
+    if env::var_os("CARGO_FEATURE_DEBUG_VISUALS").is_some() {
+        cmd.arg("-DDEBUG_VISUALS=1");
+    }
+    if env::var_os("CARGO_FEATURE_VOXEL32").is_some() {
+        cmd.arg("-DVOXEL32=1");
+    }
 
-    let status = Command::new("glslc")
-        .arg("-DCOMP=1")
-        .arg("-fshader-stage=comp")
-        .arg("./src/graphics/shader.glsl")
+    let output = cmd
+        .arg(format!("-fshader-stage={stage}"))
+        .arg("-I")
+        .arg(SHADER_DIR)
+        .arg(src)
         .arg("-o")
         .arg(dest)
-        .status()
-        .unwrap();
-    if !status.success() {
-        panic!();
+        .output()
+        .unwrap_or_else(|err| match err.kind() {
+            io::ErrorKind::NotFound => panic!(
+                "glslc was not found in PATH, install the Vulkan SDK \
+                 or shaderc to compile the shaders"
+            ),
+            _ => panic!("failed to run glslc: {err}"),
+        });
+
+    // warnings show up in the cargo output even if the build succeeds
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in stderr.lines() {
+        println!("cargo:warning={line}");
+    }
+    if !output.status.success() {
+        panic!(
+            "glslc failed to compile {src} ({}):\n{stderr}",
+            output.status
+        );
     }
 }
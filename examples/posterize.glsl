@@ -0,0 +1,26 @@
+#version 460
+
+// an example custom pass, registered by main.rs with LUMINARY_POSTERIZE=N
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+// the `hdr_color` resource
+layout(rgba16f, set = 0, binding = 0) uniform image2D hdr_color;
+
+layout(push_constant) uniform PushConstant {
+    // color steps per channel
+    float levels;
+} push;
+
+void main() {
+    ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(hdr_color);
+
+    if (coord.x >= size.x || coord.y >= size.y) {
+        return;
+    }
+
+    vec4 col = imageLoad(hdr_color, coord);
+    col.rgb = floor(col.rgb * push.levels + 0.5) / push.levels;
+    imageStore(hdr_color, coord, col);
+}
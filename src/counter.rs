@@ -1,40 +1,598 @@
-use std::time::{Duration, Instant};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use rustc_hash::FxHashMap;
 
 use crate::cold;
 
 //
 
+/// call rate and time between calls over one [`Counter`] interval,
+/// the times are in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameStats {
+    pub per_second: f32,
+    pub average: f32,
+    pub min: f32,
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+    pub max: f32,
+}
+
 pub struct Counter {
     count: usize,
     last_time: Instant,
     interval: Duration,
+
+    /// the previous call, the first call has no delta
+    last_call: Option<Instant>,
+    /// ring buffer of the latest deltas in milliseconds
+    deltas: Box<[f32]>,
+    /// deltas written this interval, can be more than the capacity
+    written: usize,
 }
 
 impl Counter {
+    /// deltas kept per interval, older ones are overwritten
+    pub const CAPACITY: usize = 4096;
+
     pub fn new(interval: Duration) -> Self {
+        Self::new_at(interval, Instant::now())
+    }
+
+    /// a counter that starts at `now`, see [`Self::next_at`]
+    pub fn new_at(interval: Duration, now: Instant) -> Self {
         Self {
             count: 0,
-            last_time: Instant::now(),
+            last_time: now,
             interval,
+
+            last_call: None,
+            deltas: vec![0.0; Self::CAPACITY].into_boxed_slice(),
+            written: 0,
         }
     }
 
-    /// returns the number of times this function has
-    /// been called per second on average
-    pub fn next(&mut self) -> Option<f32> {
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// returns the stats of the interval once it has elapsed
+    pub fn next(&mut self) -> Option<FrameStats> {
+        self.next_at(Instant::now())
+    }
+
+    /// [`Self::next`] with the current time given by the caller
+    pub fn next_at(&mut self, now: Instant) -> Option<FrameStats> {
         self.count += 1;
 
-        let elapsed = self.last_time.elapsed();
-        if elapsed >= self.interval {
+        if let Some(last_call) = self.last_call.replace(now) {
+            let delta = now.saturating_duration_since(last_call);
+            self.deltas[self.written % Self::CAPACITY] =
+                delta.as_secs_f32() * 1000.0;
+            self.written += 1;
+        }
+
+        if now.saturating_duration_since(self.last_time) >= self.interval {
             cold();
+            return self.report_at(now);
+        }
+
+        None
+    }
 
-            let count = self.count;
-            self.count = 0;
-            self.last_time = Instant::now();
+    /// the frame time in milliseconds that the fraction `p` of the
+    /// deltas so far are at or below, without starting a new interval
+    pub fn percentile(&self, p: f32) -> Option<f32> {
+        let mut deltas = self.deltas().to_vec();
+        deltas.sort_unstable_by(f32::total_cmp);
+        percentile(&deltas, p)
+    }
+
+    /// the shortest delta so far in milliseconds
+    pub fn min(&self) -> Option<f32> {
+        self.deltas().iter().copied().min_by(f32::total_cmp)
+    }
+
+    /// the longest delta so far in milliseconds
+    pub fn max(&self) -> Option<f32> {
+        self.deltas().iter().copied().max_by(f32::total_cmp)
+    }
+
+    /// the mean delta so far in milliseconds
+    pub fn mean(&self) -> Option<f32> {
+        let deltas = self.deltas();
+        (!deltas.is_empty())
+            .then(|| deltas.iter().sum::<f32>() / deltas.len() as f32)
+    }
+
+    /// the latest deltas of this interval, in ring buffer order
+    fn deltas(&self) -> &[f32] {
+        &self.deltas[..self.written.min(Self::CAPACITY)]
+    }
 
-            return Some((count as f64 / elapsed.as_secs_f64()) as f32);
+    /// the running average so far, without starting a new interval
+    pub fn current_estimate(&self) -> Option<f32> {
+        self.estimate_at(Instant::now())
+    }
+
+    fn estimate_at(&self, now: Instant) -> Option<f32> {
+        let elapsed = now.saturating_duration_since(self.last_time);
+        let elapsed = elapsed.as_secs_f64();
+        if self.count == 0 || elapsed == 0.0 {
+            return None;
         }
 
-        None
+        Some((self.count as f64 / elapsed) as f32)
+    }
+
+    /// end the current interval early and return its stats
+    pub fn report(&mut self) -> Option<FrameStats> {
+        self.report_at(Instant::now())
+    }
+
+    /// [`Self::report`] with the current time given by the caller
+    pub fn report_at(&mut self, now: Instant) -> Option<FrameStats> {
+        let stats = self.estimate_at(now).map(|per_second| FrameStats {
+            per_second,
+            average: self.mean().unwrap_or(0.0),
+            min: self.min().unwrap_or(0.0),
+            p50: self.percentile(0.50).unwrap_or(0.0),
+            p95: self.percentile(0.95).unwrap_or(0.0),
+            p99: self.percentile(0.99).unwrap_or(0.0),
+            max: self.max().unwrap_or(0.0),
+        });
+
+        self.count = 0;
+        self.last_time = now;
+        self.written = 0;
+
+        stats
+    }
+}
+
+/// nearest rank percentile of `sorted`, `p` is clamped to 0..=1
+fn percentile(sorted: &[f32], p: f32) -> Option<f32> {
+    let rank = (sorted.len() as f32 * p.clamp(0.0, 1.0)).ceil() as usize;
+    sorted.get(rank.max(1) - 1).copied()
+}
+
+//
+
+/// named counters and timers shared between threads,
+/// clones refer to the same registry
+#[derive(Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<MetricsInner>>,
+}
+
+#[derive(Default)]
+struct MetricsInner {
+    totals: FxHashMap<&'static str, Arc<Total>>,
+    timers: FxHashMap<&'static str, Arc<Timer>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the counter called `name`, created on first use,
+    /// keep the handle around to skip the lookup
+    pub fn counter(&self, name: &'static str) -> Arc<Total> {
+        self.inner
+            .lock()
+            .unwrap()
+            .totals
+            .entry(name)
+            .or_default()
+            .clone()
+    }
+
+    /// the timer called `name`, created on first use,
+    /// keep the handle around to skip the lookup
+    pub fn timer(&self, name: &'static str) -> Arc<Timer> {
+        self.inner
+            .lock()
+            .unwrap()
+            .timers
+            .entry(name)
+            .or_default()
+            .clone()
+    }
+
+    /// everything recorded since the last report, sorted by name,
+    /// every counter and timer starts over from zero
+    pub fn report(&self) -> MetricsReport {
+        let inner = self.inner.lock().unwrap();
+
+        let mut totals = inner
+            .totals
+            .iter()
+            .map(|(name, total)| (*name, total.take()))
+            .collect::<Vec<_>>();
+        totals.sort_unstable_by_key(|(name, _)| *name);
+
+        let mut timers = inner
+            .timers
+            .iter()
+            .map(|(name, timer)| (*name, timer.take()))
+            .collect::<Vec<_>>();
+        timers.sort_unstable_by_key(|(name, _)| *name);
+
+        MetricsReport { totals, timers }
+    }
+}
+
+/// a sum that can be added to from any thread without locking
+#[derive(Debug, Default)]
+pub struct Total {
+    value: AtomicU64,
+}
+
+impl Total {
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn take(&self) -> u64 {
+        self.value.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// durations that can be recorded from any thread without locking
+#[derive(Debug, Default)]
+pub struct Timer {
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl Timer {
+    pub fn record(&self, duration: Duration) {
+        let nanos = duration.as_nanos().try_into().unwrap_or(u64::MAX);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// record the time `f` takes
+    pub fn time<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let value = f();
+        self.record(start.elapsed());
+        value
+    }
+
+    fn take(&self) -> TimerStats {
+        TimerStats {
+            count: self.count.swap(0, Ordering::Relaxed),
+            total: Duration::from_nanos(
+                self.total_nanos.swap(0, Ordering::Relaxed),
+            ),
+            max: Duration::from_nanos(
+                self.max_nanos.swap(0, Ordering::Relaxed),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TimerStats {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl TimerStats {
+    pub fn average(&self) -> Duration {
+        self.total
+            .checked_div(self.count.try_into().unwrap_or(u32::MAX))
+            .unwrap_or_default()
+    }
+}
+
+/// one aggregated [`Metrics::report`]
+#[derive(Debug, Clone, Default)]
+pub struct MetricsReport {
+    pub totals: Vec<(&'static str, u64)>,
+    pub timers: Vec<(&'static str, TimerStats)>,
+}
+
+impl fmt::Display for MetricsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        let mut sep = |f: &mut fmt::Formatter<'_>| {
+            if !mem::take(&mut first) {
+                f.write_str(" ")?;
+            }
+            Ok(())
+        };
+
+        for (name, total) in &self.totals {
+            sep(f)?;
+            write!(f, "{name}={total}")?;
+        }
+        for (name, stats) in &self.timers {
+            sep(f)?;
+            write!(
+                f,
+                "{name}={:.3}ms(max {:.3}ms, n={})",
+                stats.average().as_secs_f64() * 1000.0,
+                stats.max.as_secs_f64() * 1000.0,
+                stats.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+//
+
+/// one [`MetricsSink`] row
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MetricsRow {
+    pub fps: f32,
+    pub frame_ms: f32,
+    pub p95_ms: f32,
+    /// sum of the averaged GPU regions
+    pub gpu_ms: f32,
+    pub memory_bytes: u64,
+}
+
+/// appends a CSV row per report interval and writes a JSON summary
+/// next to it on drop, timestamps are seconds since the sink was created,
+/// so runs can be compared row by row
+///
+/// the first IO error is logged and disables the sink
+pub struct MetricsSink {
+    csv: Option<BufWriter<File>>,
+    summary_path: PathBuf,
+    start: Instant,
+
+    rows: usize,
+    sum: MetricsRow,
+    worst_p95_ms: f32,
+    peak_memory_bytes: u64,
+}
+
+impl MetricsSink {
+    const HEADER: &str = "secs,fps,frame_ms,p95_ms,gpu_ms,memory_bytes";
+
+    /// the summary goes to `csv_path` with a `.json` extension
+    pub fn new(csv_path: impl AsRef<Path>) -> Self {
+        let csv_path = csv_path.as_ref();
+        let csv = File::create(csv_path)
+            .map(BufWriter::new)
+            .and_then(|mut csv| {
+                writeln!(csv, "{}", Self::HEADER)?;
+                Ok(csv)
+            })
+            .inspect_err(|err| {
+                tracing::error!(
+                    "metrics disabled, failed to create {}: {err}",
+                    csv_path.display()
+                );
+            })
+            .ok();
+
+        Self {
+            csv,
+            summary_path: csv_path.with_extension("json"),
+            start: Instant::now(),
+
+            rows: 0,
+            sum: MetricsRow::default(),
+            worst_p95_ms: 0.0,
+            peak_memory_bytes: 0,
+        }
+    }
+
+    pub fn record(&mut self, row: MetricsRow) {
+        let Some(csv) = self.csv.as_mut() else {
+            return;
+        };
+
+        let secs = self.start.elapsed().as_secs_f32();
+        let result = writeln!(
+            csv,
+            "{secs:.3},{:.2},{:.3},{:.3},{:.3},{}",
+            row.fps, row.frame_ms, row.p95_ms, row.gpu_ms, row.memory_bytes
+        )
+        .and_then(|_| csv.flush());
+        if let Err(err) = result {
+            tracing::error!("metrics disabled, failed to write a row: {err}");
+            self.csv = None;
+            return;
+        }
+
+        self.rows += 1;
+        self.sum.fps += row.fps;
+        self.sum.frame_ms += row.frame_ms;
+        self.sum.gpu_ms += row.gpu_ms;
+        self.worst_p95_ms = self.worst_p95_ms.max(row.p95_ms);
+        self.peak_memory_bytes = self.peak_memory_bytes.max(row.memory_bytes);
+    }
+
+    fn summary(&self) -> String {
+        let rows = self.rows.max(1) as f32;
+        format!(
+            "{{\n  \"rows\": {},\n  \"secs\": {:.3},\n  \
+             \"mean_fps\": {:.2},\n  \"mean_frame_ms\": {:.3},\n  \
+             \"worst_p95_ms\": {:.3},\n  \"mean_gpu_ms\": {:.3},\n  \
+             \"peak_memory_bytes\": {}\n}}\n",
+            self.rows,
+            self.start.elapsed().as_secs_f32(),
+            self.sum.fps / rows,
+            self.sum.frame_ms / rows,
+            self.worst_p95_ms,
+            self.sum.gpu_ms / rows,
+            self.peak_memory_bytes,
+        )
+    }
+}
+
+impl Drop for MetricsSink {
+    fn drop(&mut self) {
+        if self.csv.is_none() {
+            return;
+        }
+
+        match fs::write(&self.summary_path, self.summary()) {
+            Ok(()) => {
+                tracing::info!("saved {}", self.summary_path.display())
+            }
+            Err(err) => tracing::error!(
+                "failed to write {}: {err}",
+                self.summary_path.display()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn counter_stats() {
+        let start = Instant::now();
+        let mut counter = Counter::new_at(Duration::from_secs(5), start);
+
+        // frames of 1, 2, .. 100 ms, the interval ends after 5.05 s
+        let mut now = start;
+        assert_eq!(counter.next_at(now), None);
+        for ms in 1..100 {
+            now += Duration::from_millis(ms);
+            assert_eq!(counter.next_at(now), None, "{ms}");
+        }
+        now += Duration::from_millis(100);
+        let stats = counter.next_at(now).unwrap();
+
+        assert!(close(stats.per_second, 101.0 / 5.05), "{stats:?}");
+        assert!(close(stats.average, 50.5), "{stats:?}");
+        assert!(close(stats.min, 1.0), "{stats:?}");
+        assert!(close(stats.p50, 50.0), "{stats:?}");
+        assert!(close(stats.p95, 95.0), "{stats:?}");
+        assert!(close(stats.p99, 99.0), "{stats:?}");
+        assert!(close(stats.max, 100.0), "{stats:?}");
+
+        // the next interval starts empty
+        assert_eq!(counter.report_at(now), None);
+    }
+
+    #[test]
+    fn counter_keeps_the_latest_deltas() {
+        let start = Instant::now();
+        let mut counter = Counter::new_at(Duration::MAX, start);
+
+        let mut now = start;
+        counter.next_at(now);
+        for _ in 0..Counter::CAPACITY {
+            now += Duration::from_millis(9);
+            counter.next_at(now);
+        }
+        // overwrite the oldest deltas, the 9 ms ones are the maximum
+        for _ in 0..10 {
+            now += Duration::from_millis(2);
+            counter.next_at(now);
+        }
+
+        let stats = counter.report_at(now).unwrap();
+        assert!(close(stats.min, 2.0), "{stats:?}");
+        assert!(close(stats.max, 9.0), "{stats:?}");
+        assert!(close(stats.p50, 9.0), "{stats:?}");
+    }
+
+    #[test]
+    fn counter_stats_mid_interval() {
+        let start = Instant::now();
+        let mut counter = Counter::new_at(Duration::MAX, start);
+        assert_eq!(counter.percentile(0.5), None);
+        assert_eq!(counter.min(), None);
+        assert_eq!(counter.mean(), None);
+
+        // 8 frames of 10 ms, 1 of 40 ms and 1 of 2 ms
+        let mut now = start;
+        counter.next_at(now);
+        for ms in [10, 10, 10, 40, 10, 10, 2, 10, 10, 10] {
+            now += Duration::from_millis(ms);
+            assert_eq!(counter.next_at(now), None);
+        }
+
+        assert!(close(counter.min().unwrap(), 2.0));
+        assert!(close(counter.max().unwrap(), 40.0));
+        assert!(close(counter.mean().unwrap(), 12.2));
+        assert!(close(counter.percentile(0.5).unwrap(), 10.0));
+        assert!(close(counter.percentile(0.9).unwrap(), 10.0));
+        assert!(close(counter.percentile(0.99).unwrap(), 40.0));
+
+        // reading them doesn't end the interval, reporting does
+        let stats = counter.report_at(now).unwrap();
+        assert!(close(stats.p99, 40.0), "{stats:?}");
+        assert_eq!(counter.max(), None);
+    }
+
+    #[test]
+    fn nearest_rank_percentile() {
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(percentile(&sorted, 0.0), Some(1.0));
+        assert_eq!(percentile(&sorted, 0.5), Some(2.0));
+        assert_eq!(percentile(&sorted, 0.51), Some(3.0));
+        assert_eq!(percentile(&sorted, 2.0), Some(4.0));
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+
+    #[test]
+    fn metrics_from_many_threads() {
+        let metrics = Metrics::new();
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                let metrics = metrics.clone();
+                s.spawn(move || {
+                    // looked up by name, every thread gets the same handles
+                    let bytes = metrics.counter("bytes");
+                    let wait = metrics.timer("wait");
+                    for i in 1..=1000 {
+                        bytes.add(2);
+                        wait.record(Duration::from_micros(i));
+                    }
+                });
+            }
+        });
+
+        let report = metrics.report();
+        assert_eq!(report.totals, [("bytes", 16000)]);
+        let [("wait", wait)] = report.timers[..] else {
+            panic!("{report:?}");
+        };
+        assert_eq!(wait.count, 8000);
+        assert_eq!(wait.max, Duration::from_micros(1000));
+        assert_eq!(wait.total, Duration::from_micros(8 * 500500));
+        assert_eq!(wait.average(), Duration::from_nanos(500500));
+
+        // reporting starts every metric over
+        let report = metrics.report();
+        assert_eq!(report.totals, [("bytes", 0)]);
+        assert_eq!(report.timers, [("wait", TimerStats::default())]);
+        assert_eq!(
+            report.to_string(),
+            "bytes=0 wait=0.000ms(max 0.000ms, n=0)"
+        );
     }
 }
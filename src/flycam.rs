@@ -1,53 +1,395 @@
-use glam::{Mat3, Mat4, Vec2, Vec3};
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use eyre::{Result, eyre};
+use glam::{Mat4, Quat, Vec2, Vec3};
 
 //
 
 pub struct Flycam {
     position: Vec3,
-    yaw: f32,
-    pitch: f32,
+    /// world space, units per second
+    velocity: Vec3,
+    /// how quickly the velocity reaches the input velocity, per second
+    accel: f32,
+    /// how quickly the velocity decays without input, per second
+    damping: f32,
+    /// rotates +Z to the looking direction
+    orientation: Quat,
+    /// FPS style, yaw is around the world Y axis, movement ignores pitch
+    /// and there is no roll, otherwise the camera flies in full 6-DOF
+    world_locked: bool,
+    /// the position wraps around at this extent, for toroidal worlds
+    wrap: Option<Vec3>,
+    /// radians per mouse count
+    sensitivity: f32,
+    invert_y: bool,
+}
+
+/// where the camera is and where it looks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub position: Vec3,
+    pub orientation: Quat,
+}
+
+impl CameraPose {
+    /// `{"position":[x,y,z],"orientation":[x,y,z,w]}`,
+    /// floats are written so that they parse back exactly
+    pub fn to_json(self) -> String {
+        let p = self.position;
+        let o = self.orientation;
+        format!(
+            "{{\"position\":[{:?},{:?},{:?}],\"orientation\":[{:?},{:?},{:?},{:?}]}}\n",
+            p.x, p.y, p.z, o.x, o.y, o.z, o.w
+        )
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let array = |key: &str| -> Result<Vec<f32>> {
+            let key = format!("\"{key}\"");
+            let rest = &json[json
+                .find(&key)
+                .ok_or_else(|| eyre!("missing {key}"))?
+                + key.len()..];
+            let start = rest
+                .find('[')
+                .ok_or_else(|| eyre!("{key} is not an array"))?;
+            let end = rest
+                .find(']')
+                .ok_or_else(|| eyre!("{key} is not an array"))?;
+            rest[start + 1..end]
+                .split(',')
+                .map(|v| Ok(v.trim().parse::<f32>()?))
+                .collect()
+        };
+
+        let position: [f32; 3] = array("position")?
+            .try_into()
+            .map_err(|_| eyre!("position needs 3 values"))?;
+        let orientation: [f32; 4] = array("orientation")?
+            .try_into()
+            .map_err(|_| eyre!("orientation needs 4 values"))?;
+
+        // a saved pose is already normalized and kept bit exact
+        let orientation = Quat::from_array(orientation);
+        Ok(Self {
+            position: Vec3::from_array(position),
+            orientation: if orientation.is_normalized() {
+                orientation
+            } else {
+                orientation.normalize()
+            },
+        })
+    }
+}
+
+/// perspective projection parameters, the aspect comes from the window
+#[derive(Debug, Clone, Copy)]
+pub struct Projection {
+    /// vertical field of view in radians
+    fov: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Projection {
+    pub const MIN_FOV: f32 = 10.0f32.to_radians();
+    pub const MAX_FOV: f32 = 170.0f32.to_radians();
+
+    pub fn fov(&self) -> f32 {
+        self.fov
+    }
+
+    /// clamped to ([`Self::MIN_FOV`], [`Self::MAX_FOV`])
+    pub fn set_fov(&mut self, fov: f32) {
+        self.fov = fov.clamp(Self::MIN_FOV, Self::MAX_FOV);
+    }
+
+    pub fn matrix(&self, aspect: f32) -> Mat4 {
+        Mat4::perspective_rh(self.fov, aspect, self.near, self.far)
+    }
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self {
+            fov: 90.0f32.to_radians(),
+            near: 0.01,
+            far: 10.0,
+        }
+    }
 }
 
+//
+
 impl Flycam {
-    pub const fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             position: Vec3::splat(40.0),
-            yaw: std::f32::consts::PI + std::f32::consts::FRAC_PI_4,
-            pitch: -std::f32::consts::FRAC_PI_4,
+            velocity: Vec3::ZERO,
+            accel: 12.0,
+            damping: 8.0,
+            orientation: Self::level(PI + FRAC_PI_4, -FRAC_PI_4),
+            world_locked: true,
+            wrap: None,
+            sensitivity: 0.001,
+            invert_y: false,
+        }
+    }
+
+    /// orientation without roll
+    fn level(yaw: f32, pitch: f32) -> Quat {
+        Quat::from_rotation_y(yaw) * Quat::from_rotation_x(-pitch)
+    }
+
+    pub fn pose(&self) -> CameraPose {
+        CameraPose {
+            position: self.position,
+            orientation: self.orientation,
         }
     }
 
-    pub fn movement(&mut self, delta: Vec3) {
-        self.position += Mat3::from_rotation_y(self.yaw) * delta;
+    /// also stops any movement
+    pub fn set_pose(&mut self, pose: CameraPose) {
+        self.position = pose.position;
+        self.orientation = pose.orientation;
+        self.velocity = Vec3::ZERO;
+        self.wrap_position();
+    }
+
+    /// accelerate towards `input` units per second relative to the camera,
+    /// or slow down if there is no input, then move by `dt` seconds
+    pub fn movement(&mut self, input: Vec3, dt: f32) {
+        if input == Vec3::ZERO {
+            self.velocity *= (-self.damping * dt).exp();
+        } else {
+            let target = if self.world_locked {
+                let dir = self.looking_to();
+                Quat::from_rotation_y(dir.x.atan2(dir.z)) * input
+            } else {
+                self.orientation * input
+            };
+            self.velocity =
+                target + (self.velocity - target) * (-self.accel * dt).exp();
+        }
+
+        self.position += self.velocity * dt;
+        self.wrap_position();
 
         // tracing::info!("pos={}", self.position);
     }
+    fn wrap_position(&mut self) {
+        if let Some(extent) = self.wrap {
+            // keeps the position small so precision never degrades
+            self.position = self.position.rem_euclid(extent);
+        }
+    }
 
-    pub fn mouse_delta(&mut self, delta: Vec2) {
-        self.yaw -= delta.x * 0.001;
-        self.pitch += delta.y * 0.001;
+    pub fn set_wrap(&mut self, extent: Option<Vec3>) {
+        self.wrap = extent;
+        self.wrap_position();
+    }
 
-        self.pitch = self.pitch.clamp(
-            -std::f32::consts::FRAC_PI_2 + f32::EPSILON,
-            std::f32::consts::FRAC_PI_2 - f32::EPSILON,
-        );
+    pub fn set_accel(&mut self, accel: f32) {
+        self.accel = accel;
+    }
+
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping;
+    }
+
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        self.sensitivity = sensitivity;
+    }
+
+    pub fn set_invert_y(&mut self, invert_y: bool) {
+        self.invert_y = invert_y;
+    }
+
+    /// FPS style movement, see [`Self::world_locked`],
+    /// locking removes any roll
+    pub fn set_world_locked(&mut self, world_locked: bool) {
+        self.world_locked = world_locked;
+        if world_locked {
+            self.look_to(self.looking_to());
+        }
+    }
+
+    pub fn world_locked(&self) -> bool {
+        self.world_locked
+    }
+
+    /// raw mouse motion, positive x is right and positive y is down
+    pub fn mouse_delta(&mut self, delta: Vec2) {
+        let y = if self.invert_y { -delta.y } else { delta.y };
+        self.yaw(delta.x * self.sensitivity);
+        self.pitch(-y * self.sensitivity);
 
-        // tracing::info!("yaw={} pitch={}", self.yaw, self.pitch);
         // tracing::info!("looking_to={}", self.looking_to());
     }
 
+    /// turn right, around the world Y axis if world locked
+    pub fn yaw(&mut self, angle: f32) {
+        let rotation = Quat::from_rotation_y(angle);
+        self.orientation = if self.world_locked {
+            rotation * self.orientation
+        } else {
+            self.orientation * rotation
+        }
+        .normalize();
+    }
+
+    /// look up, world locked cameras stop just short of straight up or down
+    pub fn pitch(&mut self, angle: f32) {
+        let angle = if self.world_locked {
+            let current = self.looking_to().y.clamp(-1.0, 1.0).asin();
+            let limit = FRAC_PI_2 - f32::EPSILON;
+            (current + angle).clamp(-limit, limit) - current
+        } else {
+            angle
+        };
+        self.orientation =
+            (self.orientation * Quat::from_rotation_x(-angle)).normalize();
+    }
+
+    /// rotate clockwise around the looking direction,
+    /// does nothing if world locked
+    pub fn roll(&mut self, angle: f32) {
+        if self.world_locked {
+            return;
+        }
+        self.orientation =
+            (self.orientation * Quat::from_rotation_z(angle)).normalize();
+    }
+
+    /// rotate the camera around the vertical axis going through `target`
+    pub fn orbit(&mut self, target: Vec3, angle: f32) {
+        let rotation = Quat::from_rotation_y(angle);
+        self.position = target + rotation * (self.position - target);
+        self.orientation = (rotation * self.orientation).normalize();
+    }
+
+    pub fn look_at(&mut self, target: Vec3) {
+        self.look_to(target - self.position);
+    }
+
+    /// look along `dir` without roll
+    fn look_to(&mut self, dir: Vec3) {
+        let dir = dir.normalize_or_zero();
+        if dir == Vec3::ZERO {
+            return;
+        }
+
+        let pitch = dir
+            .y
+            .asin()
+            .clamp(-FRAC_PI_2 + f32::EPSILON, FRAC_PI_2 - f32::EPSILON);
+        self.orientation = Self::level(dir.x.atan2(dir.z), pitch);
+    }
+
     /// view matrix
     pub fn view_matrix(&self) -> Mat4 {
         let eye = self.position;
         let dir = self.looking_to();
-        Mat4::look_to_rh(eye, dir, Vec3::NEG_Y)
+        Mat4::look_to_rh(eye, dir, self.orientation * Vec3::NEG_Y)
     }
 
     pub fn looking_to(&self) -> Vec3 {
-        let yaw_sin = self.yaw.sin();
-        let yaw_cos = self.yaw.cos();
-        let pitch_sin = self.pitch.sin();
-        let pitch_cos = self.pitch.cos();
-        Vec3::new(yaw_sin * pitch_cos, pitch_sin, yaw_cos * pitch_cos)
+        self.orientation * Vec3::Z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn looking_after(eye: &mut Flycam, delta: Vec2) -> Vec3 {
+        eye.set_pose(CameraPose {
+            position: Vec3::ZERO,
+            orientation: Quat::IDENTITY,
+        });
+        eye.mouse_delta(delta);
+        eye.looking_to()
+    }
+
+    #[test]
+    fn mouse_sensitivity() {
+        let mut eye = Flycam::new();
+        let slow = looking_after(&mut eye, Vec2::new(100.0, 0.0));
+        eye.set_sensitivity(0.002);
+        let fast = looking_after(&mut eye, Vec2::new(100.0, 0.0));
+
+        assert!((slow.x.atan2(slow.z) - 0.1).abs() < 1e-5);
+        assert!((fast.x.atan2(fast.z) - 0.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn mouse_invert_y() {
+        let mut eye = Flycam::new();
+        // moving the mouse down looks down
+        let normal = looking_after(&mut eye, Vec2::new(0.0, 100.0));
+        eye.set_invert_y(true);
+        let inverted = looking_after(&mut eye, Vec2::new(0.0, 100.0));
+
+        assert!(normal.y < 0.0);
+        assert!((inverted.y + normal.y).abs() < 1e-6);
+    }
+
+    #[test]
+    fn movement_accelerates_and_damps() {
+        let mut eye = Flycam::new();
+        eye.set_world_locked(false);
+        eye.set_pose(CameraPose {
+            position: Vec3::ZERO,
+            orientation: Quat::IDENTITY,
+        });
+        eye.set_accel(10.0);
+        eye.set_damping(5.0);
+
+        // 1 - e^-10 of the way to the target velocity after a second
+        eye.movement(Vec3::Z * 2.0, 1.0);
+        let expected = 2.0 * (1.0 - (-10.0f32).exp());
+        assert!((eye.velocity.z - expected).abs() < 1e-5);
+        assert!((eye.pose().position.z - expected).abs() < 1e-5);
+
+        eye.movement(Vec3::ZERO, 0.5);
+        let damped = expected * (-2.5f32).exp();
+        assert!((eye.velocity.z - damped).abs() < 1e-5);
+    }
+
+    #[test]
+    fn pose_round_trip() {
+        let mut eye = Flycam::new();
+        eye.set_world_locked(false);
+        eye.mouse_delta(Vec2::new(123.0, -45.0));
+        eye.roll(0.3);
+        eye.movement(Vec3::new(1.0, 2.0, 3.0), 0.7);
+        let pose = eye.pose();
+
+        let loaded = CameraPose::from_json(&pose.to_json()).unwrap();
+        assert_eq!(loaded, pose);
+
+        // the loaded pose is used by the very next view matrix
+        let mut other = Flycam::new();
+        other.set_pose(loaded);
+        assert_eq!(other.view_matrix(), eye.view_matrix());
+    }
+
+    #[test]
+    fn pose_json_errors() {
+        assert!(CameraPose::from_json("{}").is_err());
+        assert!(
+            CameraPose::from_json(
+                r#"{"position":[1,2],"orientation":[0,0,0,1]}"#
+            )
+            .is_err()
+        );
+        // an unnormalized orientation is normalized
+        let pose = CameraPose::from_json(
+            r#"{"position":[1,2,3],"orientation":[0,0,0,2]}"#,
+        )
+        .unwrap();
+        assert_eq!(pose.orientation, Quat::IDENTITY);
+        assert_eq!(pose.position, Vec3::new(1.0, 2.0, 3.0));
     }
 }
@@ -1,13 +1,19 @@
-use std::{ffi::c_void, ptr::NonNull, slice};
+use std::{
+    ffi::c_void, marker::PhantomData, mem, ops::Deref, ptr::NonNull, slice,
+};
 
 use ash::{Device, vk};
-use eyre::Result;
+use bytemuck::Pod;
+use eyre::{Result, bail};
 use gpu_allocator::{
     MemoryLocation,
     vulkan::{AllocationCreateDesc, AllocationScheme, Allocator},
 };
 
-use super::delete_queue::DeleteQueue;
+use super::{
+    debug::set_object_name,
+    delete_queue::{DeleteQueue, Owned},
+};
 
 //
 
@@ -37,7 +43,62 @@ impl Buffer {
             capacity: 0,
             usage: vk::BufferUsageFlags::empty(),
             location: MemoryLocation::GpuOnly,
+            name: None,
+        }
+    }
+}
+
+//
+
+/// a [`Buffer`] of `T`s, lengths are counted in elements
+pub struct TypedBuffer<T: Pod> {
+    inner: Buffer,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> TypedBuffer<T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// `None` if the buffer is not CPU mappable
+    pub fn as_typed_slice(&self) -> Option<&[T]> {
+        let bytes = self.inner.as_slice()?;
+        Some(bytemuck::cast_slice(
+            &bytes[..self.len * mem::size_of::<T>()],
+        ))
+    }
+
+    /// `None` if the buffer is not CPU mappable
+    pub fn as_typed_slice_mut(&mut self) -> Option<&mut [T]> {
+        let len = self.len;
+        let bytes = self.inner.as_slice_mut()?;
+        Some(bytemuck::cast_slice_mut(
+            &mut bytes[..len * mem::size_of::<T>()],
+        ))
+    }
+
+    /// copy `data` to the start of a CPU mappable buffer
+    pub fn write_slice(&mut self, data: &[T]) -> Result<()> {
+        let len = self.len;
+        let Some(dst) = self.as_typed_slice_mut() else {
+            bail!("cannot write to a buffer that is not CPU mappable");
+        };
+        if data.len() > len {
+            bail!("{} elements do not fit a buffer of {len}", data.len());
         }
+
+        dst[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+impl<T: Pod> Deref for TypedBuffer<T> {
+    type Target = Buffer;
+
+    fn deref(&self) -> &Buffer {
+        &self.inner
     }
 }
 
@@ -47,6 +108,7 @@ pub struct BufferBuilder {
     capacity: usize,
     usage: vk::BufferUsageFlags,
     location: MemoryLocation,
+    name: Option<&'static str>,
 }
 
 impl BufferBuilder {
@@ -55,6 +117,12 @@ impl BufferBuilder {
         self
     }
 
+    /// the capacity of `len` elements of `T`
+    pub const fn len<T: Pod>(&mut self, len: usize) -> &mut Self {
+        self.capacity = len * mem::size_of::<T>();
+        self
+    }
+
     pub const fn usage(&mut self, usage: vk::BufferUsageFlags) -> &mut Self {
         self.usage = usage;
         self
@@ -65,6 +133,12 @@ impl BufferBuilder {
         self
     }
 
+    /// shown in validation messages and RenderDoc captures
+    pub const fn name(&mut self, name: &'static str) -> &mut Self {
+        self.name = Some(name);
+        self
+    }
+
     pub fn build(
         &self,
         device: &Device,
@@ -77,11 +151,14 @@ impl BufferBuilder {
 
         let buffer = unsafe { device.create_buffer(&create_info, None)? };
         delete_queue.push(buffer);
+        if let Some(name) = self.name {
+            set_object_name(device, buffer, name);
+        }
         let requirements =
             unsafe { device.get_buffer_memory_requirements(buffer) };
 
         let alloc_desc = AllocationCreateDesc {
-            name: "",
+            name: self.name.unwrap_or(""),
             requirements,
             location: self.location,
             linear: true,
@@ -99,6 +176,32 @@ impl BufferBuilder {
 
         Ok(Buffer { buffer, size, ptr })
     }
+
+    /// a buffer that is destroyed when dropped, see [`Owned`]
+    pub fn build_owned<'a>(
+        &self,
+        device: &'a Device,
+        allocator: &'a mut Allocator,
+    ) -> Result<Owned<'a, Buffer>> {
+        Owned::new(device, allocator, |allocator, delete_queue| {
+            self.build(device, allocator, delete_queue)
+        })
+    }
+
+    /// the trailing bytes of a capacity that is not a multiple
+    /// of the element size are unused
+    pub fn build_typed<T: Pod>(
+        &self,
+        device: &Device,
+        allocator: &mut Allocator,
+        delete_queue: &mut DeleteQueue,
+    ) -> Result<TypedBuffer<T>> {
+        Ok(TypedBuffer {
+            inner: self.build(device, allocator, delete_queue)?,
+            len: self.capacity / mem::size_of::<T>(),
+            _marker: PhantomData,
+        })
+    }
 }
 
 //
@@ -106,3 +209,68 @@ impl BufferBuilder {
 #[derive(Clone, Copy)]
 struct SyncPtr(NonNull<c_void>);
 unsafe impl Sync for SyncPtr {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a mapped buffer backed by host memory, no device needed
+    fn host_buffer<T: Pod>(backing: &mut [u64], len: usize) -> TypedBuffer<T> {
+        let size = mem::size_of_val(backing);
+        let ptr = NonNull::new(backing.as_mut_ptr().cast()).unwrap();
+        TypedBuffer {
+            inner: Buffer {
+                buffer: vk::Buffer::null(),
+                size: size as u64,
+                ptr: Some(SyncPtr(ptr)),
+            },
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    #[test]
+    fn builder_len() {
+        assert_eq!(Buffer::builder().len::<u32>(5).capacity, 20);
+        assert_eq!(Buffer::builder().len::<[u16; 4]>(3).capacity, 24);
+        assert_eq!(Buffer::builder().len::<u8>(0).capacity, 0);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut backing = [0u64; 4];
+        let mut buffer = host_buffer::<u32>(&mut backing, 8);
+        assert_eq!(buffer.len(), 8);
+
+        let data: Vec<u32> = (1..=6).collect();
+        buffer.write_slice(&data).unwrap();
+        assert_eq!(buffer.as_typed_slice().unwrap(), [1, 2, 3, 4, 5, 6, 0, 0]);
+        assert_eq!(backing[0], 1 | 2 << 32);
+    }
+
+    #[test]
+    fn write_too_long() {
+        let mut backing = [0u64; 2];
+        let mut buffer = host_buffer::<u32>(&mut backing, 3);
+        assert!(buffer.write_slice(&[7; 4]).is_err());
+        buffer.write_slice(&[7; 3]).unwrap();
+        // the bytes past the length are not part of the typed slice
+        assert_eq!(buffer.as_typed_slice().unwrap(), [7; 3]);
+        assert_eq!(backing[1] >> 32, 0);
+    }
+
+    #[test]
+    fn not_mappable() {
+        let mut buffer = TypedBuffer::<u32> {
+            inner: Buffer {
+                buffer: vk::Buffer::null(),
+                size: 16,
+                ptr: None,
+            },
+            len: 4,
+            _marker: PhantomData,
+        };
+        assert!(buffer.as_typed_slice().is_none());
+        assert!(buffer.write_slice(&[1]).is_err());
+    }
+}
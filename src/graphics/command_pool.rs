@@ -0,0 +1,95 @@
+use std::thread::{self, ThreadId};
+
+use ash::{Device, vk};
+use eyre::{Result, eyre};
+use rustc_hash::FxHashMap;
+
+use super::delete_queue::DeleteQueue;
+
+//
+
+/// how long the command buffers of a pool live
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lifetime {
+    /// re-recorded by one frame in flight,
+    /// the whole pool is reset instead of single buffers
+    Frame(usize),
+    /// kept around and reset one buffer at a time
+    LongLived,
+}
+
+/// command pools keyed by queue family, recording thread and lifetime,
+/// every pool is destroyed through the delete queue
+#[derive(Default)]
+pub struct CommandPools {
+    pools: FxHashMap<(u32, ThreadId, Lifetime), vk::CommandPool>,
+    buffers: usize,
+}
+
+impl CommandPools {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the pool of the calling thread, created on first use
+    pub fn pool(
+        &mut self,
+        device: &Device,
+        delete_queue: &mut DeleteQueue,
+        queue_family: u32,
+        lifetime: Lifetime,
+    ) -> Result<vk::CommandPool> {
+        let key = (queue_family, thread::current().id(), lifetime);
+        if let Some(pool) = self.pools.get(&key) {
+            return Ok(*pool);
+        }
+
+        let flags = match lifetime {
+            Lifetime::Frame(_) => vk::CommandPoolCreateFlags::TRANSIENT,
+            Lifetime::LongLived => {
+                vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER
+            }
+        };
+        let create_info = vk::CommandPoolCreateInfo::default()
+            .flags(flags)
+            .queue_family_index(queue_family);
+
+        let pool = unsafe { device.create_command_pool(&create_info, None)? };
+        delete_queue.push(pool);
+        self.pools.insert(key, pool);
+
+        Ok(pool)
+    }
+
+    /// allocate a primary command buffer from [`Self::pool`]
+    pub fn allocate(
+        &mut self,
+        device: &Device,
+        delete_queue: &mut DeleteQueue,
+        queue_family: u32,
+        lifetime: Lifetime,
+    ) -> Result<(vk::CommandPool, vk::CommandBuffer)> {
+        let pool = self.pool(device, delete_queue, queue_family, lifetime)?;
+
+        let allocate_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+
+        let cbuf = unsafe { device.allocate_command_buffers(&allocate_info)? }
+            .into_iter()
+            .next()
+            .ok_or_else(|| eyre!("did not get any command buffers"))?;
+        self.buffers += 1;
+
+        Ok((pool, cbuf))
+    }
+
+    pub fn pool_count(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        self.buffers
+    }
+}
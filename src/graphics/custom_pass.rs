@@ -0,0 +1,466 @@
+use ash::{Device, vk};
+use eyre::{Result, bail};
+use glam::UVec3;
+use gpu_allocator::vulkan::Allocator;
+
+use super::{
+    Graphics,
+    delete_queue::DeleteQueue,
+    descriptor::{
+        DescriptorPool, DescriptorSet, DescriptorSetLayout,
+        DescriptorSetUpdateEntry, DescriptorWriteBatcher,
+    },
+    image::Image,
+    pipeline::{ComputePipeline, PipelineCache, PipelineLayout},
+    shader::Shader,
+};
+
+//
+
+/// push constants of a custom pass, the shader can use any prefix of it
+pub type PushBlob = [u8; CustomPass::PUSH_SIZE];
+
+/// where a custom pass runs in the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertionPoint {
+    /// right after the scene, before FXAA
+    PreFxaa,
+    /// after FXAA, before the HUD is drawn on top
+    PreHud,
+}
+
+/// how a custom pass uses an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn writes(self) -> bool {
+        matches!(self, Self::Write | Self::ReadWrite)
+    }
+
+    /// how the shader uses the image at `binding` of set 0, from its
+    /// `readonly` and `writeonly` qualifiers, `None` if it has no such
+    /// image or can neither read nor write it
+    fn in_shader(spirv: &[u32], binding: u32) -> Option<Self> {
+        const OP_DECORATE: u32 = 71;
+        const NON_WRITABLE: u32 = 24;
+        const NON_READABLE: u32 = 25;
+        const BINDING: u32 = 33;
+        const DESCRIPTOR_SET: u32 = 34;
+
+        // (target, decoration, first literal) of every OpDecorate
+        let mut decorations = Vec::new();
+        let mut rest = spirv.get(Shader::SPIRV_HEADER_WORDS..)?;
+        while let Some(&first) = rest.first() {
+            let len = (first >> 16) as usize;
+            let Some(words) = rest.get(..len.max(1)) else {
+                break;
+            };
+            if first & 0xffff == OP_DECORATE && len >= 3 {
+                decorations.push((
+                    words[1],
+                    words[2],
+                    words.get(3).copied().unwrap_or(0),
+                ));
+            }
+            rest = &rest[words.len()..];
+        }
+        let has = |target, decoration, value| {
+            decorations.contains(&(target, decoration, value))
+        };
+
+        let target =
+            decorations
+                .iter()
+                .find_map(|&(target, decoration, value)| {
+                    (decoration == BINDING
+                        && value == binding
+                        && has(target, DESCRIPTOR_SET, 0))
+                    .then_some(target)
+                })?;
+        match (has(target, NON_WRITABLE, 0), has(target, NON_READABLE, 0)) {
+            (false, false) => Some(Self::ReadWrite),
+            (true, false) => Some(Self::Read),
+            (false, true) => Some(Self::Write),
+            (true, true) => None,
+        }
+    }
+}
+
+/// a compute shader run by luminary on its own images
+///
+/// the shader has `local_size_x = 16, local_size_y = 16`, is dispatched
+/// over the render target and finds the declared `resources` as
+/// `rgba16f` storage images at bindings 0.. of set 0 in the same order,
+/// followed by `private_images` images of its own
+pub struct CustomPassDesc<'a> {
+    pub name: &'static str,
+    pub spirv: &'a [u32],
+    /// built-in images by name, only `hdr_color` exists so far
+    pub resources: &'a [(&'a str, Access)],
+    /// scratch images at the render target size, their contents
+    /// are undefined at the start of every frame
+    pub private_images: u32,
+    pub insertion: InsertionPoint,
+}
+
+/// handle returned by [`Graphics::add_custom_pass`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomPassId(pub(super) usize);
+
+pub struct CustomPass {
+    pub name: &'static str,
+    pub insertion: InsertionPoint,
+
+    resources: usize,
+    /// the color image is the render target in `sets[0]`
+    /// and the FXAA target in `sets[1]`
+    sets: [DescriptorSet; 2],
+    pipeline: ComputePipeline<PushBlob>,
+    push: PushBlob,
+
+    private: Vec<Image>,
+    private_delete_queue: DeleteQueue,
+}
+
+impl CustomPass {
+    /// the guaranteed minimum of `maxPushConstantsSize`
+    pub const PUSH_SIZE: usize = 128;
+    /// built-in images a pass can declare
+    const RESOURCES: &[&str] = &["hdr_color"];
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &Device,
+        allocator: &mut Allocator,
+        delete_queue: &mut DeleteQueue,
+        pipeline_cache: &PipelineCache,
+        desc: &CustomPassDesc,
+        render_target: &Image,
+        fxaa_target: &Image,
+    ) -> Result<Self> {
+        Self::validate(desc)?;
+
+        let bindings = desc.resources.len() as u32 + desc.private_images;
+        let mut set_layout =
+            DescriptorSetLayout::builder().name("custom_pass_set_layout");
+        for binding in 0..bindings {
+            set_layout = set_layout.add_binding(
+                binding,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            );
+        }
+        let set_layout = set_layout.build(device, delete_queue)?;
+
+        // a pool of its own, so passes never run the shared one dry
+        let descriptor_pool = DescriptorPool::builder()
+            .add_type_allocation(
+                vk::DescriptorType::STORAGE_IMAGE,
+                bindings * 2,
+            )
+            .max_sets(2)
+            .build(device, delete_queue)?;
+        let sets = [
+            descriptor_pool.alloc(device, &set_layout)?,
+            descriptor_pool.alloc(device, &set_layout)?,
+        ];
+
+        let pipeline_layout =
+            PipelineLayout::new(device, delete_queue, &set_layout)?;
+        let mut init_delete_queue = DeleteQueue::new();
+        let shader = Shader::new(device, &mut init_delete_queue, desc.spirv)?;
+        let pipeline = ComputePipeline::new(
+            device,
+            delete_queue,
+            pipeline_cache,
+            pipeline_layout,
+            &shader,
+        )?;
+        init_delete_queue.flush(device, allocator);
+
+        let mut pass = Self {
+            name: desc.name,
+            insertion: desc.insertion,
+
+            resources: desc.resources.len(),
+            sets,
+            pipeline,
+            push: [0; Self::PUSH_SIZE],
+
+            private: Vec::new(),
+            private_delete_queue: DeleteQueue::new(),
+        };
+        for _ in 0..desc.private_images {
+            pass.private.push(Graphics::create_render_image(
+                device,
+                allocator,
+                &mut pass.private_delete_queue,
+                render_target.extent,
+            )?);
+        }
+        for (set, color) in
+            pass.sets.iter_mut().zip([render_target, fxaa_target])
+        {
+            let mut update = set.update(device);
+            for binding in 0..pass.resources as u32 {
+                update.write(
+                    binding,
+                    DescriptorSetUpdateEntry::storage_image(color),
+                );
+            }
+            for (i, image) in pass.private.iter().enumerate() {
+                update.write(
+                    (pass.resources + i) as u32,
+                    DescriptorSetUpdateEntry::storage_image(image),
+                );
+            }
+        }
+
+        Ok(pass)
+    }
+
+    /// readable errors for mistakes the validation layers
+    /// would only complain about much later, if at all
+    fn validate(desc: &CustomPassDesc) -> Result<()> {
+        let name = desc.name;
+        if desc.spirv.first() != Some(&Shader::SPIRV_MAGIC) {
+            bail!("custom pass '{name}': the shader is not SPIR-V");
+        }
+        if desc.resources.is_empty() && desc.private_images == 0 {
+            bail!("custom pass '{name}' does not use any images");
+        }
+        for (i, &(resource, access)) in desc.resources.iter().enumerate() {
+            if !Self::RESOURCES.contains(&resource) {
+                bail!(
+                    "custom pass '{name}' declares unknown resource \
+                     '{resource}', available: {:?}",
+                    Self::RESOURCES
+                );
+            }
+            if desc.resources[..i]
+                .iter()
+                .any(|(other, _)| *other == resource)
+            {
+                bail!("custom pass '{name}' declares '{resource}' twice");
+            }
+            // an image declared read-only is not synchronized for writes
+            if let Some(used) = Access::in_shader(desc.spirv, i as u32)
+                && access != Access::ReadWrite
+                && used != access
+            {
+                bail!(
+                    "custom pass '{name}' declares '{resource}' as \
+                     {access:?}, but the shader uses it as {used:?}"
+                );
+            }
+        }
+        if !desc.resources.iter().any(|(_, access)| access.writes()) {
+            bail!(
+                "custom pass '{name}' does not write any built-in resource, \
+                 so it would have no effect"
+            );
+        }
+        Ok(())
+    }
+
+    /// replace the push constants, shorter blobs are zero padded
+    pub fn set_push(&mut self, bytes: &[u8]) -> Result<()> {
+        if bytes.len() > Self::PUSH_SIZE {
+            bail!(
+                "custom pass '{}': {}B of push constants, at most {}B fit",
+                self.name,
+                bytes.len(),
+                Self::PUSH_SIZE
+            );
+        }
+        self.push = [0; Self::PUSH_SIZE];
+        self.push[..bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// match the size of a new render target,
+    /// the old private images are deleted with `delete_queue`
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        allocator: &mut Allocator,
+        delete_queue: &mut DeleteQueue,
+        descriptor_writes: &mut DescriptorWriteBatcher,
+        render_target: &Image,
+        fxaa_target: &Image,
+    ) -> Result<()> {
+        delete_queue.append(&mut self.private_delete_queue);
+        for image in &mut self.private {
+            *image = Graphics::create_render_image(
+                device,
+                allocator,
+                &mut self.private_delete_queue,
+                render_target.extent,
+            )?;
+        }
+
+        for (set, color) in self.sets.iter().zip([render_target, fxaa_target]) {
+            for binding in 0..self.resources as u32 {
+                descriptor_writes.write(
+                    set,
+                    binding,
+                    DescriptorSetUpdateEntry::storage_image(color),
+                );
+            }
+            for (i, image) in self.private.iter().enumerate() {
+                descriptor_writes.write(
+                    set,
+                    (self.resources + i) as u32,
+                    DescriptorSetUpdateEntry::storage_image(image),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn push(&self) -> &PushBlob {
+        &self.push
+    }
+
+    pub fn private_images(&self) -> impl Iterator<Item = &Image> {
+        self.private.iter()
+    }
+
+    /// run over `extent`, the color image is the FXAA target if `fxaa`
+    /// is set, every image has to be in the GENERAL layout
+    pub fn dispatch(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        fxaa: bool,
+    ) {
+        self.pipeline.bind(device, cbuf);
+        self.pipeline.bind_sets(
+            device,
+            cbuf,
+            0,
+            &[self.sets[fxaa as usize].set],
+            &[],
+        );
+        self.pipeline.write_push_constant(device, cbuf, &self.push);
+        self.pipeline.dispatch(
+            device,
+            cbuf,
+            UVec3::new(
+                extent.width.div_ceil(16),
+                extent.height.div_ceil(16),
+                1,
+            ),
+        );
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.private_delete_queue.flush(device, allocator);
+    }
+
+    /// [`Self::destroy`] for a lost device
+    pub fn leak(&mut self) {
+        self.private_delete_queue.leak();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a module header and `OpDecorate`s of images 1.., at bindings 0..
+    /// of set 0, with extra decorations
+    fn spirv(images: &[&[u32]]) -> Vec<u32> {
+        let mut words = vec![Shader::SPIRV_MAGIC, 0x0001_0600, 0, 16, 0];
+        for (i, extra) in images.iter().enumerate() {
+            let id = i as u32 + 1;
+            words.extend([4 << 16 | 71, id, 34, 0]);
+            words.extend([4 << 16 | 71, id, 33, i as u32]);
+            for &decoration in *extra {
+                words.extend([3 << 16 | 71, id, decoration]);
+            }
+        }
+        // OpCapability Shader, skipped over
+        words.extend([2 << 16 | 17, 1]);
+        words
+    }
+
+    fn desc<'a>(
+        spirv: &'a [u32],
+        resources: &'a [(&'a str, Access)],
+    ) -> CustomPassDesc<'a> {
+        CustomPassDesc {
+            name: "test",
+            spirv,
+            resources,
+            private_images: 0,
+            insertion: InsertionPoint::PreHud,
+        }
+    }
+
+    #[test]
+    fn shader_access() {
+        let code = spirv(&[&[], &[24], &[25], &[24, 25]]);
+        assert_eq!(Access::in_shader(&code, 0), Some(Access::ReadWrite));
+        assert_eq!(Access::in_shader(&code, 1), Some(Access::Read));
+        assert_eq!(Access::in_shader(&code, 2), Some(Access::Write));
+        assert_eq!(Access::in_shader(&code, 3), None);
+        assert_eq!(Access::in_shader(&code, 4), None);
+        assert_eq!(Access::in_shader(&code[..3], 0), None);
+    }
+
+    #[test]
+    fn declared_access_matches_the_shader() {
+        let writeonly = spirv(&[&[25]]);
+        let both = spirv(&[&[]]);
+        let color = |access| [("hdr_color", access)];
+
+        CustomPass::validate(&desc(&writeonly, &color(Access::Write))).unwrap();
+        CustomPass::validate(&desc(&writeonly, &color(Access::ReadWrite)))
+            .unwrap();
+        let err = CustomPass::validate(&desc(&both, &color(Access::Write)))
+            .unwrap_err();
+        assert!(err.to_string().contains("uses it as ReadWrite"), "{err}");
+
+        // a read-only pass has no effect
+        let readonly = spirv(&[&[24]]);
+        let err = CustomPass::validate(&desc(&readonly, &color(Access::Read)))
+            .unwrap_err();
+        assert!(err.to_string().contains("does not write"), "{err}");
+    }
+
+    #[test]
+    fn misdeclared_passes() {
+        let code = spirv(&[&[], &[]]);
+        let err = CustomPass::validate(&desc(&[0; 8], &[]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("not SPIR-V"), "{err}");
+
+        let err = CustomPass::validate(&desc(&code, &[]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("does not use any images"), "{err}");
+
+        let err =
+            CustomPass::validate(&desc(&code, &[("depth", Access::Read)]))
+                .unwrap_err()
+                .to_string();
+        assert!(err.contains("unknown resource 'depth'"), "{err}");
+
+        let twice = [
+            ("hdr_color", Access::ReadWrite),
+            ("hdr_color", Access::ReadWrite),
+        ];
+        let err = CustomPass::validate(&desc(&code, &twice))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("twice"), "{err}");
+    }
+}
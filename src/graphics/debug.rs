@@ -1,10 +1,15 @@
 use std::{
-    ffi::c_void,
+    ffi::{CString, c_void},
+    mem,
     ptr::{self, NonNull},
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
 use ash::{
-    Entry, Instance,
+    Device, Entry, Instance,
     ext::debug_utils,
     vk::{self, Handle},
 };
@@ -19,12 +24,17 @@ use crate::cold;
 pub struct DebugUtils {
     debug_messenger: vk::DebugUtilsMessengerEXT,
     destroy_fp: vk::PFN_vkDestroyDebugUtilsMessengerEXT,
+    set_name_fp: vk::PFN_vkSetDebugUtilsObjectNameEXT,
 }
 
 impl DebugUtils {
     pub fn new(entry: &Entry, instance: &Instance) -> Result<Self> {
         let debug_utils_loader = debug_utils::Instance::new(entry, instance);
         let destroy_fp = debug_utils_loader.fp().destroy_debug_utils_messenger_ext;
+        // loaded through the instance, so it works with any device made from it
+        let device_fp = debug_utils::DeviceFn::load(|name| unsafe {
+            mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+        });
 
         let create_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
             .message_severity(
@@ -40,13 +50,32 @@ impl DebugUtils {
 
         let debug_messenger =
             unsafe { debug_utils_loader.create_debug_utils_messenger(&create_info, None)? };
+        let set_name_fp = device_fp.set_debug_utils_object_name_ext;
+        _ = DEVICE_FP.set(device_fp);
 
         Ok(Self {
             debug_messenger,
             destroy_fp,
+            set_name_fp,
         })
     }
 
+    /// name a Vulkan object for validation messages and RenderDoc
+    pub fn set_name<H: Handle>(&self, device: &Device, handle: H, name: &str) -> Result<()> {
+        if self.debug_messenger.is_null() {
+            cold();
+            return Ok(());
+        }
+
+        let name = CString::new(name)?;
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(handle)
+            .object_name(&name);
+
+        unsafe { (self.set_name_fp)(device.handle(), &name_info).result()? };
+        Ok(())
+    }
+
     pub fn destroy(&mut self, instance: &Instance) {
         if self.debug_messenger.is_null() {
             cold();
@@ -64,6 +93,81 @@ impl DebugUtils {
 //     }
 // }
 
+/// set once a [`DebugUtils`] exists, so the builders can name
+/// what they create without a [`DebugUtils`] being passed around
+static DEVICE_FP: OnceLock<debug_utils::DeviceFn> = OnceLock::new();
+
+/// name a Vulkan object for validation messages and RenderDoc,
+/// does nothing without the debug utils extension
+pub fn set_object_name<H: Handle>(device: &Device, handle: H, name: &str) {
+    let Some(device_fp) = DEVICE_FP.get() else {
+        return;
+    };
+    let Ok(name) = CString::new(name) else {
+        return;
+    };
+
+    let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+        .object_handle(handle)
+        .object_name(&name);
+    if let Err(err) =
+        unsafe { (device_fp.set_debug_utils_object_name_ext)(device.handle(), &name_info) }
+            .result()
+    {
+        tracing::warn!("failed to name {name:?}: {err}");
+    }
+}
+
+/// a labeled region of a command buffer for RenderDoc captures,
+/// closed when dropped, so returning early with `?` can't leave it open,
+/// records nothing without the debug utils extension
+#[must_use]
+pub struct CmdLabel {
+    cbuf: vk::CommandBuffer,
+}
+
+impl CmdLabel {
+    pub fn begin(cbuf: vk::CommandBuffer, name: &str, color: [f32; 4]) -> Self {
+        if let Some(device_fp) = DEVICE_FP.get() {
+            // an unnamed region still pairs up with the end
+            let name = CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT::default().label_name(&name).color(color);
+            unsafe { (device_fp.cmd_begin_debug_utils_label_ext)(cbuf, &label) };
+        }
+
+        Self { cbuf }
+    }
+
+    /// a single marker between the commands of `cbuf`
+    pub fn insert(cbuf: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+        let Some(device_fp) = DEVICE_FP.get() else {
+            return;
+        };
+
+        let name = CString::new(name).unwrap_or_default();
+        let label = vk::DebugUtilsLabelEXT::default().label_name(&name).color(color);
+        unsafe { (device_fp.cmd_insert_debug_utils_label_ext)(cbuf, &label) };
+    }
+
+    /// close the region before the end of the scope
+    pub fn end(self) {}
+}
+
+impl Drop for CmdLabel {
+    fn drop(&mut self) {
+        if let Some(device_fp) = DEVICE_FP.get() {
+            unsafe { (device_fp.cmd_end_debug_utils_label_ext)(self.cbuf) };
+        }
+    }
+}
+
+/// validation errors reported since the process started
+static VALIDATION_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+pub fn validation_errors() -> u64 {
+    VALIDATION_ERRORS.load(Ordering::Relaxed)
+}
+
 unsafe extern "system" fn callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_types: vk::DebugUtilsMessageTypeFlagsEXT,
@@ -77,6 +181,7 @@ unsafe extern "system" fn callback(
         .unwrap_or("<invalid utf8>");
 
     if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        VALIDATION_ERRORS.fetch_add(1, Ordering::Relaxed);
         tracing::error!("Vulkan validation error ({message_types:?})\n{message}");
     } else if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
         tracing::warn!("Vulkan validation warning ({message_types:?})\n{message}");
@@ -88,3 +193,69 @@ unsafe extern "system" fn callback(
 
     vk::FALSE
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CStr, sync::Mutex};
+
+    use super::*;
+
+    /// what the stub [`vk::PFN_vkSetDebugUtilsObjectNameEXT`] was called with
+    static NAMED: Mutex<Vec<(vk::ObjectType, u64, String)>> = Mutex::new(Vec::new());
+
+    unsafe extern "system" fn record_name(
+        _device: vk::Device,
+        p_name_info: *const vk::DebugUtilsObjectNameInfoEXT<'_>,
+    ) -> vk::Result {
+        let info = unsafe { &*p_name_info };
+        let name = unsafe { CStr::from_ptr(info.p_object_name) };
+        NAMED.lock().unwrap().push((
+            info.object_type,
+            info.object_handle,
+            name.to_string_lossy().into_owned(),
+        ));
+        vk::Result::SUCCESS
+    }
+
+    unsafe extern "system" fn destroy_nothing(
+        _instance: vk::Instance,
+        _messenger: vk::DebugUtilsMessengerEXT,
+        _p_allocator: *const vk::AllocationCallbacks<'_>,
+    ) {
+    }
+
+    fn debug_utils(debug_messenger: vk::DebugUtilsMessengerEXT) -> DebugUtils {
+        DebugUtils {
+            debug_messenger,
+            destroy_fp: destroy_nothing,
+            set_name_fp: record_name,
+        }
+    }
+
+    fn device() -> Device {
+        unsafe { Device::load_with(|_| ptr::null(), vk::Device::null()) }
+    }
+
+    #[test]
+    fn set_name() {
+        let device = device();
+        let buffer = vk::Buffer::from_raw(0x1234);
+        let debug_utils = debug_utils(vk::DebugUtilsMessengerEXT::from_raw(1));
+
+        debug_utils.set_name(&device, buffer, "voxels").unwrap();
+        assert!(debug_utils.set_name(&device, buffer, "vox\0els").is_err());
+
+        let named = NAMED.lock().unwrap();
+        assert_eq!(named.as_slice(), [(vk::ObjectType::BUFFER, 0x1234, "voxels".to_owned())]);
+    }
+
+    #[test]
+    fn set_name_without_messenger() {
+        let device = device();
+        let debug_utils = debug_utils(vk::DebugUtilsMessengerEXT::null());
+
+        debug_utils.set_name(&device, vk::Image::from_raw(0x5678), "render_target").unwrap();
+        let named = NAMED.lock().unwrap();
+        assert!(!named.iter().any(|(_, handle, _)| *handle == 0x5678));
+    }
+}
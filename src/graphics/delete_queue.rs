@@ -1,6 +1,8 @@
 //
 
-use ash::{Device, vk};
+use std::ops::{Deref, DerefMut};
+
+use ash::{Device, khr, vk};
 use eyre::Result;
 use gpu_allocator::vulkan::{Allocation, Allocator};
 
@@ -30,6 +32,15 @@ impl DeleteQueue {
         self.inner.append(&mut from.inner);
     }
 
+    /// forget every entry without destroying it,
+    /// nothing can be destroyed on a lost device
+    pub fn leak(&mut self) {
+        if !self.inner.is_empty() {
+            tracing::debug!("leaking {} objects", self.inner.len());
+        }
+        self.inner.clear();
+    }
+
     pub fn flush(&mut self, device: &Device, alloc: &mut Allocator) {
         if self.inner.is_empty() {
             return;
@@ -56,6 +67,58 @@ impl Drop for DeleteQueue {
 
 //
 
+/// a resource that is destroyed when it goes out of scope,
+/// for one-shot objects like staging buffers that nothing else uses
+/// after the submit that needed them has finished,
+/// everything a frame in flight might use goes through a [`DeleteQueue`]
+pub struct Owned<'a, T> {
+    value: T,
+    delete_queue: DeleteQueue,
+    device: &'a Device,
+    allocator: &'a mut Allocator,
+}
+
+impl<'a, T> Owned<'a, T> {
+    /// `create` has to push everything it creates to the given queue,
+    /// which is flushed right away if it fails
+    pub fn new(
+        device: &'a Device,
+        allocator: &'a mut Allocator,
+        create: impl FnOnce(&mut Allocator, &mut DeleteQueue) -> Result<T>,
+    ) -> Result<Self> {
+        let mut delete_queue = DeleteQueue::new();
+        match create(allocator, &mut delete_queue) {
+            Ok(value) => Ok(Self { value, delete_queue, device, allocator }),
+            Err(err) => {
+                delete_queue.flush(device, allocator);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<T> Deref for Owned<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Owned<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> Drop for Owned<'_, T> {
+    fn drop(&mut self) {
+        self.delete_queue.flush(self.device, self.allocator);
+    }
+}
+
+//
+
 #[must_use]
 pub enum DeletionEntry {
     Semaphore(vk::Semaphore),
@@ -69,7 +132,12 @@ pub enum DeletionEntry {
     DescriptorPool(vk::DescriptorPool),
     DescriptorSetLayout(vk::DescriptorSetLayout),
     Pipeline(vk::Pipeline),
+    PipelineCache(vk::PipelineCache),
     PipelineLayout(vk::PipelineLayout),
+    QueryPool(vk::QueryPool),
+    Sampler(vk::Sampler),
+    /// a retired swapchain, with the loader that destroys it
+    Swapchain(vk::SwapchainKHR, khr::swapchain::Device),
 }
 
 impl DeletionEntry {
@@ -120,10 +188,26 @@ impl DeletionEntry {
                 tracing::debug!("deleting pipeline");
                 device.destroy_pipeline(pipeline, None);
             },
+            DeletionEntry::PipelineCache(pipeline_cache) => unsafe {
+                tracing::debug!("deleting pipeline cache");
+                device.destroy_pipeline_cache(pipeline_cache, None);
+            },
             DeletionEntry::PipelineLayout(pipeline_layout) => unsafe {
                 tracing::debug!("deleting pipeline layout");
                 device.destroy_pipeline_layout(pipeline_layout, None);
             },
+            DeletionEntry::QueryPool(query_pool) => unsafe {
+                tracing::debug!("deleting query pool");
+                device.destroy_query_pool(query_pool, None);
+            },
+            DeletionEntry::Sampler(sampler) => unsafe {
+                tracing::debug!("deleting sampler");
+                device.destroy_sampler(sampler, None);
+            },
+            DeletionEntry::Swapchain(swapchain, loader) => unsafe {
+                tracing::debug!("deleting swapchain");
+                loader.destroy_swapchain(swapchain, None);
+            },
         }
 
         Ok(())
@@ -148,5 +232,21 @@ macro_rules! impl_from {
 
 impl_from! {
     Semaphore, Fence, CommandPool, Image, ImageView, Buffer,
-    ShaderModule, DescriptorPool, DescriptorSetLayout, Pipeline, PipelineLayout,
+    ShaderModule, DescriptorPool, DescriptorSetLayout, Pipeline, PipelineCache,
+    PipelineLayout, QueryPool, Sampler,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leak() {
+        let mut queue = DeleteQueue::new();
+        queue.push(vk::Semaphore::null());
+        queue.push(vk::Fence::null());
+        queue.leak();
+        // dropped without flushing or destroying anything
+        assert!(queue.inner.is_empty());
+    }
 }
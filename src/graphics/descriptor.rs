@@ -1,9 +1,12 @@
 use std::{mem, slice};
 
-use ash::{Device, vk};
-use eyre::Result;
+use ash::{Device, Instance, vk};
+use eyre::{Result, bail};
 
-use super::{buffer::Buffer, delete_queue::DeleteQueue, image::Image};
+use super::{
+    buffer::Buffer, debug::set_object_name, delete_queue::DeleteQueue,
+    image::Image,
+};
 
 //
 
@@ -34,7 +37,8 @@ impl DescriptorSet {
 
 pub struct DescriptorSetUpdate<'a> {
     device: &'a Device,
-    entries: Vec<(u32, DescriptorSetUpdateEntry)>,
+    /// binding, array element and what is written to it
+    entries: Vec<(u32, u32, DescriptorSetUpdateEntry)>,
     set: &'a DescriptorSet,
 }
 
@@ -44,36 +48,125 @@ impl DescriptorSetUpdate<'_> {
         binding: u32,
         entry: DescriptorSetUpdateEntry,
     ) -> &mut Self {
-        self.entries.push((binding, entry));
+        self.entries.push((binding, 0, entry));
+        self
+    }
+
+    /// write `entries` to consecutive elements of an array binding,
+    /// starting at `first_element`
+    pub fn write_array(
+        &mut self,
+        binding: u32,
+        first_element: u32,
+        entries: &[DescriptorSetUpdateEntry],
+    ) -> &mut Self {
+        self.entries.extend(
+            (first_element..)
+                .zip(entries)
+                .map(|(element, entry)| (binding, element, *entry)),
+        );
         self
     }
 }
 
 impl Drop for DescriptorSetUpdate<'_> {
     fn drop(&mut self) {
-        let writes: Box<[vk::WriteDescriptorSet]> = self
-            .entries
-            .iter()
-            .map(|(binding, entry)| {
-                let base = vk::WriteDescriptorSet::default()
-                    .dst_binding(*binding)
-                    .dst_set(self.set.set)
-                    .dst_array_element(0)
-                    .descriptor_count(1);
-
-                entry.fill(base)
-            })
-            .collect();
+        let set = self.set.set;
+        update_descriptor_sets(
+            self.device,
+            self.entries.iter().map(|(binding, element, entry)| {
+                (set, *binding, *element, entry)
+            }),
+        );
+    }
+}
+
+//
+
+/// collects descriptor writes and issues them with
+/// a single `update_descriptor_sets` call on [`Self::flush`],
+/// has to be flushed before the sets are bound
+#[derive(Default)]
+pub struct DescriptorWriteBatcher {
+    /// the entries own their image and buffer infos,
+    /// so nothing dangles until the flush
+    writes: Vec<(vk::DescriptorSet, u32, DescriptorSetUpdateEntry)>,
+    /// `update_descriptor_sets` calls since the last [`Self::take_calls`]
+    calls: u32,
+}
+
+impl DescriptorWriteBatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        unsafe { self.device.update_descriptor_sets(&writes, &[]) };
+    pub fn write(
+        &mut self,
+        set: &DescriptorSet,
+        binding: u32,
+        entry: DescriptorSetUpdateEntry,
+    ) -> &mut Self {
+        self.writes.push((set.set, binding, entry));
+        self
+    }
+
+    /// writes are applied in the order they were recorded
+    pub fn flush(&mut self, device: &Device) {
+        if self.writes.is_empty() {
+            return;
+        }
+
+        update_descriptor_sets(
+            device,
+            self.writes
+                .iter()
+                .map(|(set, binding, entry)| (*set, *binding, 0, entry)),
+        );
+        self.writes.clear();
+        self.calls += 1;
+    }
+
+    pub fn take_calls(&mut self) -> u32 {
+        mem::take(&mut self.calls)
     }
 }
 
+fn update_descriptor_sets<'a>(
+    device: &Device,
+    entries: impl Iterator<
+        Item = (vk::DescriptorSet, u32, u32, &'a DescriptorSetUpdateEntry),
+    >,
+) {
+    let writes = write_infos(entries);
+    unsafe { device.update_descriptor_sets(&writes, &[]) };
+}
+
+/// one single descriptor write per entry, in the same order
+fn write_infos<'a>(
+    entries: impl Iterator<
+        Item = (vk::DescriptorSet, u32, u32, &'a DescriptorSetUpdateEntry),
+    >,
+) -> Box<[vk::WriteDescriptorSet<'a>]> {
+    entries
+        .map(|(set, binding, element, entry)| {
+            let base = vk::WriteDescriptorSet::default()
+                .dst_binding(binding)
+                .dst_set(set)
+                .dst_array_element(element)
+                .descriptor_count(1);
+
+            entry.fill(base)
+        })
+        .collect()
+}
+
 //
 
+#[derive(Clone, Copy)]
 pub enum DescriptorSetUpdateEntry {
     StorageImage(vk::DescriptorImageInfo),
     StorageBuffer(vk::DescriptorBufferInfo),
+    CombinedImageSampler(vk::DescriptorImageInfo),
 }
 
 impl DescriptorSetUpdateEntry {
@@ -93,6 +186,15 @@ impl DescriptorSetUpdateEntry {
         })
     }
 
+    /// `image` in the `SHADER_READ_ONLY_OPTIMAL` layout, read with `sampler`
+    pub fn sampled_image(image: &Image, sampler: vk::Sampler) -> Self {
+        Self::CombinedImageSampler(vk::DescriptorImageInfo {
+            sampler,
+            image_view: image.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        })
+    }
+
     fn fill<'a>(
         &'a self,
         info: vk::WriteDescriptorSet<'a>,
@@ -104,6 +206,9 @@ impl DescriptorSetUpdateEntry {
             DescriptorSetUpdateEntry::StorageBuffer(buffer_info) => info
                 .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
                 .buffer_info(slice::from_ref(buffer_info)),
+            DescriptorSetUpdateEntry::CombinedImageSampler(image_info) => info
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(slice::from_ref(image_info)),
         }
     }
 }
@@ -118,6 +223,8 @@ impl DescriptorSetLayout {
     pub const fn builder<'a>() -> DescriptorSetLayoutBuilder<'a> {
         DescriptorSetLayoutBuilder {
             bindings: Vec::new(),
+            binding_flags: Vec::new(),
+            name: None,
         }
     }
 }
@@ -126,6 +233,9 @@ impl DescriptorSetLayout {
 
 pub struct DescriptorSetLayoutBuilder<'a> {
     bindings: Vec<vk::DescriptorSetLayoutBinding<'a>>,
+    /// one per binding
+    binding_flags: Vec<vk::DescriptorBindingFlags>,
+    name: Option<&'static str>,
 }
 
 impl DescriptorSetLayoutBuilder<'_> {
@@ -142,19 +252,112 @@ impl DescriptorSetLayoutBuilder<'_> {
                 .descriptor_count(1)
                 .stage_flags(stages),
         );
+        self.binding_flags.push(vk::DescriptorBindingFlags::empty());
         self
     }
 
+    /// a bindless array of `count` descriptors, elements can be left
+    /// unwritten if the shader never reads them and can be written
+    /// while the set is bound in a pending command buffer,
+    /// as long as that command buffer does not use them
+    ///
+    /// uses `PARTIALLY_BOUND | UPDATE_AFTER_BIND |
+    /// UPDATE_UNUSED_WHILE_PENDING`, so the sets have
+    /// to come from a pool built with
+    /// [`DescriptorPoolBuilder::update_after_bind`], the array size
+    /// should be checked with [`Self::check_limits`]
+    pub fn add_array_binding(
+        mut self,
+        binding: u32,
+        ty: vk::DescriptorType,
+        count: u32,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.bindings.push(
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_type(ty)
+                .descriptor_count(count)
+                .stage_flags(stages),
+        );
+        self.binding_flags.push(
+            vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+                | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING,
+        );
+        self
+    }
+
+    /// shown in validation messages and RenderDoc captures
+    pub fn name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    fn update_after_bind(&self) -> bool {
+        self.binding_flags.iter().any(|flags| {
+            flags.contains(vk::DescriptorBindingFlags::UPDATE_AFTER_BIND)
+        })
+    }
+
+    /// make sure no shader stage uses more descriptors of a type
+    /// than the `maxPerStageDescriptor*` limits allow
+    pub fn check_limits(&self, limits: &DescriptorLimits) -> Result<()> {
+        let limits = if self.update_after_bind() {
+            &limits.per_stage_update_after_bind
+        } else {
+            &limits.per_stage
+        };
+
+        let all_stages = self
+            .bindings
+            .iter()
+            .fold(0, |stages, binding| stages | binding.stage_flags.as_raw());
+        for bit in (0..32).map(|i| 1 << i).filter(|bit| all_stages & bit != 0) {
+            let stage = vk::ShaderStageFlags::from_raw(bit);
+            for (name, max, types) in limits.per_type() {
+                let used = self
+                    .bindings
+                    .iter()
+                    .filter(|binding| {
+                        binding.stage_flags.contains(stage)
+                            && types.contains(&binding.descriptor_type)
+                    })
+                    .map(|binding| binding.descriptor_count as u64)
+                    .sum::<u64>();
+                if used > max as u64 {
+                    bail!(
+                        "{used} {name} descriptors in the {stage:?} stage, \
+                         the device allows at most {max}"
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn build(
         &self,
         device: &Device,
         delete_queue: &mut DeleteQueue,
     ) -> Result<DescriptorSetLayout> {
-        let create_info = vk::DescriptorSetLayoutCreateInfo::default()
-            .bindings(&self.bindings);
+        let mut binding_flags =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::default()
+                .binding_flags(&self.binding_flags);
+        let mut create_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .bindings(&self.bindings)
+            .push_next(&mut binding_flags);
+        if self.update_after_bind() {
+            create_info = create_info.flags(
+                vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+            );
+        }
         let layout =
             unsafe { device.create_descriptor_set_layout(&create_info, None)? };
         delete_queue.push(layout);
+        if let Some(name) = self.name {
+            set_object_name(device, layout, name);
+        }
         Ok(DescriptorSetLayout { layout })
     }
 }
@@ -170,6 +373,7 @@ impl DescriptorPool {
         DescriptorPoolBuilder {
             sizes: Vec::new(),
             max_sets: 10,
+            update_after_bind: false,
         }
     }
 
@@ -210,6 +414,7 @@ impl DescriptorPool {
 pub struct DescriptorPoolBuilder {
     sizes: Vec<vk::DescriptorPoolSize>,
     max_sets: u32,
+    update_after_bind: bool,
 }
 
 impl DescriptorPoolBuilder {
@@ -230,18 +435,335 @@ impl DescriptorPoolBuilder {
         self
     }
 
+    /// required for sets with [`DescriptorSetLayoutBuilder::add_array_binding`]
+    pub fn update_after_bind(mut self) -> Self {
+        self.update_after_bind = true;
+        self
+    }
+
     pub fn build(
         &self,
         device: &Device,
         delete_queue: &mut DeleteQueue,
     ) -> Result<DescriptorPool> {
+        let mut flags = vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET;
+        if self.update_after_bind {
+            flags |= vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND;
+        }
         let create_info = vk::DescriptorPoolCreateInfo::default()
             .pool_sizes(&self.sizes)
             .max_sets(self.max_sets)
-            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET);
+            .flags(flags);
         let pool =
             unsafe { device.create_descriptor_pool(&create_info, None)? };
         delete_queue.push(pool);
         Ok(DescriptorPool { pool })
     }
 }
+
+//
+
+/// per shader stage descriptor limits of a device,
+/// see [`DescriptorSetLayoutBuilder::check_limits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DescriptorLimits {
+    /// `maxPerStageDescriptor*`
+    pub per_stage: StageDescriptorLimits,
+    /// `maxPerStageDescriptorUpdateAfterBind*`, these apply to
+    /// every binding of a layout with an update-after-bind binding
+    pub per_stage_update_after_bind: StageDescriptorLimits,
+    /// `maxBoundDescriptorSets`, see [`PipelineLayout::check_limits`]
+    ///
+    /// [`PipelineLayout::check_limits`]: super::pipeline::PipelineLayout::check_limits
+    pub bound_sets: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StageDescriptorLimits {
+    pub samplers: u32,
+    pub sampled_images: u32,
+    pub storage_images: u32,
+    pub uniform_buffers: u32,
+    pub storage_buffers: u32,
+}
+
+impl DescriptorLimits {
+    pub fn query(instance: &Instance, gpu: vk::PhysicalDevice) -> Self {
+        let mut props12 = vk::PhysicalDeviceVulkan12Properties::default();
+        let mut props =
+            vk::PhysicalDeviceProperties2::default().push_next(&mut props12);
+        unsafe { instance.get_physical_device_properties2(gpu, &mut props) };
+        let limits = props.properties.limits;
+
+        Self {
+            per_stage: StageDescriptorLimits {
+                samplers: limits.max_per_stage_descriptor_samplers,
+                sampled_images: limits.max_per_stage_descriptor_sampled_images,
+                storage_images: limits.max_per_stage_descriptor_storage_images,
+                uniform_buffers: limits
+                    .max_per_stage_descriptor_uniform_buffers,
+                storage_buffers: limits
+                    .max_per_stage_descriptor_storage_buffers,
+            },
+            per_stage_update_after_bind: StageDescriptorLimits {
+                samplers: props12
+                    .max_per_stage_descriptor_update_after_bind_samplers,
+                sampled_images: props12
+                    .max_per_stage_descriptor_update_after_bind_sampled_images,
+                storage_images: props12
+                    .max_per_stage_descriptor_update_after_bind_storage_images,
+                uniform_buffers: props12
+                    .max_per_stage_descriptor_update_after_bind_uniform_buffers,
+                storage_buffers: props12
+                    .max_per_stage_descriptor_update_after_bind_storage_buffers,
+            },
+            bound_sets: limits.max_bound_descriptor_sets,
+        }
+    }
+}
+
+impl StageDescriptorLimits {
+    /// every limit with the descriptor types counted against it
+    fn per_type(
+        &self,
+    ) -> [(&'static str, u32, &'static [vk::DescriptorType]); 5] {
+        use vk::DescriptorType as T;
+        [
+            (
+                "sampler",
+                self.samplers,
+                &[T::SAMPLER, T::COMBINED_IMAGE_SAMPLER],
+            ),
+            (
+                "sampled image",
+                self.sampled_images,
+                &[
+                    T::SAMPLED_IMAGE,
+                    T::COMBINED_IMAGE_SAMPLER,
+                    T::UNIFORM_TEXEL_BUFFER,
+                ],
+            ),
+            (
+                "storage image",
+                self.storage_images,
+                &[T::STORAGE_IMAGE, T::STORAGE_TEXEL_BUFFER],
+            ),
+            (
+                "uniform buffer",
+                self.uniform_buffers,
+                &[T::UNIFORM_BUFFER, T::UNIFORM_BUFFER_DYNAMIC],
+            ),
+            (
+                "storage buffer",
+                self.storage_buffers,
+                &[T::STORAGE_BUFFER, T::STORAGE_BUFFER_DYNAMIC],
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+    use crate::graphics::pipeline::PipelineLayout;
+
+    fn buffer(raw: u64) -> DescriptorSetUpdateEntry {
+        DescriptorSetUpdateEntry::StorageBuffer(vk::DescriptorBufferInfo {
+            buffer: vk::Buffer::from_raw(raw),
+            offset: 0,
+            range: 64,
+        })
+    }
+
+    fn image(raw: u64) -> DescriptorSetUpdateEntry {
+        DescriptorSetUpdateEntry::StorageImage(vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: vk::ImageView::from_raw(raw),
+            image_layout: vk::ImageLayout::GENERAL,
+        })
+    }
+
+    #[test]
+    fn batcher_keeps_order() {
+        let a = DescriptorSet {
+            set: vk::DescriptorSet::from_raw(1),
+        };
+        let b = DescriptorSet {
+            set: vk::DescriptorSet::from_raw(2),
+        };
+
+        let mut batcher = DescriptorWriteBatcher::new();
+        batcher
+            .write(&a, 0, image(10))
+            .write(&b, 1, buffer(20))
+            .write(&a, 0, image(11));
+
+        let writes = write_infos(
+            batcher
+                .writes
+                .iter()
+                .map(|(set, binding, entry)| (*set, *binding, 0, entry)),
+        );
+        assert_eq!(writes.len(), 3);
+
+        let targets = writes
+            .iter()
+            .map(|w| (w.dst_set.as_raw(), w.dst_binding, w.descriptor_type))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            [
+                (1, 0, vk::DescriptorType::STORAGE_IMAGE),
+                (2, 1, vk::DescriptorType::STORAGE_BUFFER),
+                (1, 0, vk::DescriptorType::STORAGE_IMAGE),
+            ]
+        );
+
+        // the later write to the same binding wins, so it has to stay last
+        let view = unsafe { (*writes[2].p_image_info).image_view };
+        assert_eq!(view.as_raw(), 11);
+        let info = unsafe { *writes[1].p_buffer_info };
+        assert_eq!((info.buffer.as_raw(), info.range), (20, 64));
+        assert!(writes.iter().all(|w| w.descriptor_count == 1));
+    }
+
+    #[test]
+    fn mixed_entries() {
+        let set = vk::DescriptorSet::from_raw(1);
+        let sampled = DescriptorSetUpdateEntry::CombinedImageSampler(
+            vk::DescriptorImageInfo {
+                sampler: vk::Sampler::from_raw(40),
+                image_view: vk::ImageView::from_raw(30),
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+        );
+        let entries = [image(10), buffer(20), sampled];
+
+        // the sampled image goes into element 5 of an array binding
+        let writes = write_infos(
+            [(0, 0), (1, 0), (2, 5)].into_iter().zip(&entries).map(
+                |((binding, element), entry)| (set, binding, element, entry),
+            ),
+        );
+
+        let kinds = writes
+            .iter()
+            .map(|w| {
+                (
+                    w.descriptor_type,
+                    w.dst_array_element,
+                    w.p_image_info.is_null(),
+                    w.p_buffer_info.is_null(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (vk::DescriptorType::STORAGE_IMAGE, 0, false, true),
+                (vk::DescriptorType::STORAGE_BUFFER, 0, true, false),
+                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 5, false, true),
+            ]
+        );
+
+        let info = unsafe { *writes[2].p_image_info };
+        assert_eq!(info.sampler.as_raw(), 40);
+        assert_eq!(info.image_view.as_raw(), 30);
+        assert_eq!(
+            info.image_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+    }
+
+    fn limits(per_stage: u32, update_after_bind: u32) -> DescriptorLimits {
+        let stage = |max| StageDescriptorLimits {
+            samplers: max,
+            sampled_images: max,
+            storage_images: max,
+            uniform_buffers: max,
+            storage_buffers: max,
+        };
+        DescriptorLimits {
+            per_stage: stage(per_stage),
+            per_stage_update_after_bind: stage(update_after_bind),
+            bound_sets: 4,
+        }
+    }
+
+    #[test]
+    fn limits_per_stage() {
+        use vk::{DescriptorType as T, ShaderStageFlags as S};
+        let layout = DescriptorSetLayout::builder()
+            .add_binding(0, T::STORAGE_IMAGE, S::COMPUTE)
+            .add_binding(1, T::STORAGE_IMAGE, S::COMPUTE | S::FRAGMENT)
+            .add_binding(2, T::STORAGE_BUFFER, S::FRAGMENT);
+
+        // 2 storage images in the compute stage, only 1 in the fragment one
+        layout.check_limits(&limits(2, 0)).unwrap();
+        let err = layout.check_limits(&limits(1, 0)).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("2 storage image descriptors in the COMPUTE"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn limits_per_type() {
+        use vk::{DescriptorType as T, ShaderStageFlags as S};
+        // combined image samplers count as samplers and sampled images
+        let layout = DescriptorSetLayout::builder()
+            .add_binding(0, T::COMBINED_IMAGE_SAMPLER, S::FRAGMENT)
+            .add_binding(1, T::SAMPLED_IMAGE, S::FRAGMENT)
+            .add_binding(2, T::SAMPLER, S::FRAGMENT);
+
+        let mut device = limits(2, 0);
+        layout.check_limits(&device).unwrap();
+        device.per_stage.samplers = 1;
+        let err = layout.check_limits(&device).unwrap_err();
+        assert!(err.to_string().contains("2 sampler descriptors"), "{err}");
+        device.per_stage.samplers = 2;
+        device.per_stage.sampled_images = 1;
+        let err = layout.check_limits(&device).unwrap_err();
+        assert!(err.to_string().contains("sampled image"), "{err}");
+    }
+
+    #[test]
+    fn limits_update_after_bind() {
+        use vk::{DescriptorType as T, ShaderStageFlags as S};
+        let layout = DescriptorSetLayout::builder()
+            .add_binding(0, T::STORAGE_BUFFER, S::VERTEX)
+            .add_array_binding(1, T::COMBINED_IMAGE_SAMPLER, 64, S::FRAGMENT);
+        assert_eq!(
+            layout.binding_flags[1],
+            vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+                | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING
+        );
+
+        // the whole layout is checked against the update-after-bind limits
+        layout.check_limits(&limits(0, 64)).unwrap();
+        let err = layout.check_limits(&limits(1000, 63)).unwrap_err();
+        assert!(err.to_string().starts_with("64 sampler"), "{err}");
+
+        let plain = DescriptorSetLayout::builder().add_binding(
+            0,
+            T::STORAGE_BUFFER,
+            S::VERTEX,
+        );
+        assert!(plain.check_limits(&limits(0, 1000)).is_err());
+    }
+
+    #[test]
+    fn limits_bound_sets() {
+        let device = limits(0, 0);
+        PipelineLayout::<()>::check_limits(4, &device).unwrap();
+        let err = PipelineLayout::<()>::check_limits(5, &device).unwrap_err();
+        assert!(
+            err.to_string().contains("maxBoundDescriptorSets=4"),
+            "{err}"
+        );
+    }
+}
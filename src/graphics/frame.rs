@@ -1,15 +1,26 @@
 use core::slice;
 
-use ash::{Device, vk};
-use eyre::{Result, eyre};
+use ash::{
+    Device,
+    vk::{self, Handle},
+};
+use eyre::Result;
 use gpu_allocator::vulkan::Allocator;
 
-use super::{delete_queue::DeleteQueue, queues::QueueFamilies};
+use crate::profiling;
+
+use super::{
+    command_pool::{CommandPools, Lifetime},
+    debug::set_object_name,
+    delete_queue::DeleteQueue,
+    queues::QueueFamilies,
+};
 
 //
 
 pub struct FramesInFlight {
-
+    /// counts every [`Self::next`], the timeline value a frame signals
+    frame_index: u64,
     frame: usize,
     frames: [FrameInFlight; 2],
 }
@@ -18,14 +29,34 @@ impl FramesInFlight {
     pub fn new(
         device: &Device,
         queue_families: &QueueFamilies,
+        command_pools: &mut CommandPools,
         delete_queue: &mut DeleteQueue,
+        timeline_semaphore: bool,
     ) -> Result<Self> {
+        let timeline = if timeline_semaphore {
+            let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+                .semaphore_type(vk::SemaphoreType::TIMELINE)
+                .initial_value(0);
+            let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut type_info);
+            let timeline = unsafe { device.create_semaphore(&create_info, None)? };
+            delete_queue.push(timeline);
+            set_object_name(device, timeline, "frame_timeline");
+            Some(timeline)
+        } else {
+            None
+        };
+
+        let new_frame = |command_pools: &mut CommandPools, delete_queue: &mut DeleteQueue, i| {
+            FrameInFlight::new(device, queue_families, command_pools, delete_queue, timeline, i)
+        };
+
         Ok({
             Self {
+                frame_index: 0,
                 frame: 0,
                 frames: [
-                    FrameInFlight::new(device, queue_families, delete_queue)?,
-                    FrameInFlight::new(device, queue_families, delete_queue)?,
+                    new_frame(command_pools, delete_queue, 0)?,
+                    new_frame(command_pools, delete_queue, 1)?,
                 ],
             }
         })
@@ -34,6 +65,8 @@ impl FramesInFlight {
     pub fn next(&mut self) -> (&mut FrameInFlight, usize) {
         let idx = self.frame;
         self.increment();
+        self.frame_index += 1;
+        self.frames[idx].frame_index = self.frame_index;
         (&mut self.frames[idx], idx)
     }
 
@@ -42,11 +75,21 @@ impl FramesInFlight {
         (&mut self.frames[idx], idx)
     }
 
+    /// the frame handed out by the last [`Self::next`]
     pub fn previous(&mut self) -> (&mut FrameInFlight, usize) {
-        let idx = self.frame.wrapping_sub(1).min(self.frames.len() - 1);
+        let idx = previous_index(self.frame, self.frames.len());
         (&mut self.frames[idx], idx)
     }
 
+    /// the delete queue of the frame submitted last, flushed once its
+    /// fence signals, which is after every earlier submit has finished
+    ///
+    /// anything pushed here must not be used by commands submitted after
+    /// that frame, or it is freed while the GPU still uses it
+    pub fn deferred_delete_queue(&mut self) -> &mut DeleteQueue {
+        &mut self.previous().0.delete_queue
+    }
+
     pub fn increment(&mut self) {
         self.frame = (self.frame + 1) % self.frames.len();
     }
@@ -54,47 +97,77 @@ impl FramesInFlight {
     pub fn get(&mut self, i: usize) -> &mut FrameInFlight {
         &mut self.frames[i]
     }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+/// the frame before `frame` out of `len` frames in flight, wraps around
+fn previous_index(frame: usize, len: usize) -> usize {
+    (frame + len - 1) % len
+}
+
+/// the raymarch of one frame on the async compute queue
+pub struct AsyncCompute {
+    /// transient, only `cbuf` is allocated from it
+    command_pool: vk::CommandPool,
+    pub cbuf: vk::CommandBuffer,
+
+    /// the graphics cmds wait for the raymarch
+    pub compute_sema: vk::Semaphore,
+    /// signaled once the graphics cmds are done with the render target,
+    /// the next raymarch waits for it before overwriting it
+    pub graphics_sema: vk::Semaphore,
+
+    /// `compute_sema` is signaled by a submit that was not waited on yet
+    submitted: bool,
 }
 
 pub struct FrameInFlight {
+    /// transient, only `main_cbuf` is allocated from it
     pub command_pool: vk::CommandPool,
     pub main_cbuf: vk::CommandBuffer,
+    /// `None` when compute and graphics share a queue family,
+    /// then the raymarch is recorded into `main_cbuf`
+    pub compute: Option<AsyncCompute>,
 
     /// render cmds need to wait for the swapchain image
     pub swapchain_sema: vk::Semaphore,
     /// used to present the img once its rendered
     pub render_sema: vk::Semaphore,
-    /// used to wait for this frame to be complete
-    pub render_fence: vk::Fence,
+    /// used to wait for this frame to be complete,
+    /// only without a timeline semaphore
+    render_fence: vk::Fence,
+    /// shared by all frames, signaled with the frame index on submit
+    timeline: Option<vk::Semaphore>,
+    /// the frame index of the current use of this frame
+    frame_index: u64,
+    /// the timeline value of the last submit, 0 before the first one
+    submitted_index: u64,
 
     pub delete_queue: DeleteQueue,
+
+    /// key of the commands currently recorded into `main_cbuf`,
+    /// if it was recorded for reuse with [`Self::begin_static`]
+    static_key: Option<u64>,
 }
 
 impl FrameInFlight {
     pub fn new(
         device: &Device,
         queue_families: &QueueFamilies,
+        command_pools: &mut CommandPools,
         delete_queue: &mut DeleteQueue,
+        timeline: Option<vk::Semaphore>,
+        index: usize,
     ) -> Result<Self> {
-        let create_info = vk::CommandPoolCreateInfo::default()
-            .queue_family_index(queue_families.graphics)
-            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
-
-        let command_pool = unsafe { device.create_command_pool(&create_info, None)? };
-        delete_queue.push(command_pool);
-
-        let alloc_info = vk::CommandBufferAllocateInfo::default()
-            .command_pool(command_pool)
-            .level(vk::CommandBufferLevel::PRIMARY)
-            .command_buffer_count(1);
-
-        let main_cbuf = unsafe {
-            device
-                .allocate_command_buffers(&alloc_info)?
-                .into_iter()
-                .next()
-                .ok_or_else(|| eyre!("did not get any command buffers"))?
-        };
+        let (command_pool, main_cbuf) = command_pools.allocate(
+            device,
+            delete_queue,
+            queue_families.graphics,
+            Lifetime::Frame(index),
+        )?;
 
         let create_info = vk::SemaphoreCreateInfo::default();
         let swapchain_sema = unsafe { device.create_semaphore(&create_info, None)? };
@@ -102,70 +175,344 @@ impl FrameInFlight {
         let render_sema = unsafe { device.create_semaphore(&create_info, None)? };
         delete_queue.push(render_sema);
 
+        let compute = if queue_families.async_compute() {
+            let (command_pool, cbuf) = command_pools.allocate(
+                device,
+                delete_queue,
+                queue_families.compute,
+                Lifetime::Frame(index),
+            )?;
+            let compute_sema = unsafe { device.create_semaphore(&create_info, None)? };
+            delete_queue.push(compute_sema);
+            let graphics_sema = unsafe { device.create_semaphore(&create_info, None)? };
+            delete_queue.push(graphics_sema);
+
+            Some(AsyncCompute {
+                command_pool,
+                cbuf,
+                compute_sema,
+                graphics_sema,
+                submitted: false,
+            })
+        } else {
+            None
+        };
+
         let create_info = vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
         let render_fence = unsafe { device.create_fence(&create_info, None)? };
         delete_queue.push(render_fence);
 
+        name(device, index, command_pool, "command_pool");
+        name(device, index, main_cbuf, "main_cbuf");
+        name(device, index, swapchain_sema, "swapchain_sema");
+        name(device, index, render_sema, "render_sema");
+        name(device, index, render_fence, "render_fence");
+        if let Some(compute) = compute.as_ref() {
+            name(device, index, compute.command_pool, "compute_command_pool");
+            name(device, index, compute.cbuf, "compute_cbuf");
+            name(device, index, compute.compute_sema, "compute_sema");
+            name(device, index, compute.graphics_sema, "graphics_sema");
+        }
+
         Ok(Self {
             command_pool,
             main_cbuf,
+            compute,
             swapchain_sema,
             render_sema,
             render_fence,
+            timeline,
+            frame_index: 0,
+            submitted_index: 0,
             delete_queue: DeleteQueue::new(),
+            static_key: None,
         })
     }
 
     pub fn wait(&mut self, device: &Device, alloc: &mut Allocator) -> Result<()> {
-        unsafe { device.wait_for_fences(&[self.render_fence], true, 1_000_000_000)? };
-        unsafe { device.reset_fences(&[self.render_fence])? };
+        profiling::zone!("frame wait");
+        self.wait_submitted(device)?;
 
         self.delete_queue.flush(device, alloc);
 
         Ok(())
     }
 
+    /// wait for the last submit of this frame to finish on the GPU
+    pub fn wait_submitted(&self, device: &Device) -> Result<()> {
+        let Some(timeline) = self.timeline else {
+            // reset right before submitting, so a skipped frame leaves it signaled
+            unsafe { device.wait_for_fences(&[self.render_fence], true, 1_000_000_000)? };
+            return Ok(());
+        };
+
+        // a skipped frame did not signal anything, so the last submit is waited on
+        let wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(slice::from_ref(&timeline))
+            .values(slice::from_ref(&self.submitted_index));
+        unsafe { device.wait_semaphores(&wait_info, 1_000_000_000)? };
+
+        Ok(())
+    }
+
     pub fn begin(&mut self, device: &Device) -> Result<()> {
+        self.begin_with_usage(device, vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+    }
+
+    pub fn begin_with_usage(
+        &mut self,
+        device: &Device,
+        usage: vk::CommandBufferUsageFlags,
+    ) -> Result<()> {
+        self.static_key = None;
+
+        // the pool is reset here instead of in `wait`,
+        // so static commands survive frames that are not re-recorded
         unsafe {
-            device.reset_command_buffer(self.main_cbuf, vk::CommandBufferResetFlags::empty())?
+            device.reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())?
         };
 
-        let begin_info = vk::CommandBufferBeginInfo::default()
-            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        let begin_info = vk::CommandBufferBeginInfo::default().flags(usage);
         unsafe { device.begin_command_buffer(self.main_cbuf, &begin_info)? };
 
         Ok(())
     }
 
+    /// begin recording commands that can be resubmitted in later frames,
+    /// returns false if the commands recorded with the same `key` are still
+    /// in the command buffer, then recording and [`Self::end`] are skipped
+    ///
+    /// `key` has to change whenever anything used by the commands changes
+    pub fn begin_static(&mut self, device: &Device, key: u64) -> Result<bool> {
+        if self.static_key == Some(key) {
+            return Ok(false);
+        }
+
+        self.begin_with_usage(device, vk::CommandBufferUsageFlags::empty())?;
+        self.static_key = Some(key);
+
+        Ok(true)
+    }
+
     pub fn end(&mut self, device: &Device) -> Result<()> {
         unsafe { device.end_command_buffer(self.main_cbuf)? };
         Ok(())
     }
 
-    pub fn submit(&mut self, device: &Device, queue: vk::Queue) -> Result<()> {
+    /// begin recording the raymarch on the compute queue,
+    /// `None` if it goes into `main_cbuf`
+    pub fn begin_compute(&mut self, device: &Device) -> Result<Option<vk::CommandBuffer>> {
+        let Some(compute) = self.compute.as_mut() else {
+            return Ok(None);
+        };
+
+        // the render fence also covers the raymarch, the graphics cmds waited for it
+        unsafe {
+            device.reset_command_pool(compute.command_pool, vk::CommandPoolResetFlags::empty())?
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe { device.begin_command_buffer(compute.cbuf, &begin_info)? };
+
+        Ok(Some(compute.cbuf))
+    }
+
+    /// submit the raymarch recorded after [`Self::begin_compute`], it waits
+    /// for `render_target_release` from the previous graphics submit,
+    /// this has to be submitted before the graphics cmds of the frame
+    pub fn submit_compute(
+        &mut self,
+        device: &Device,
+        queue: vk::Queue,
+        render_target_release: Option<vk::Semaphore>,
+    ) -> Result<()> {
+        let Some(compute) = self.compute.as_mut() else {
+            return Ok(());
+        };
+        unsafe { device.end_command_buffer(compute.cbuf)? };
+
+        let wait_info = render_target_release.map(|sema| {
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(sema)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .device_index(0)
+                .value(1)
+        });
+
+        let signal_info = vk::SemaphoreSubmitInfo::default()
+            .semaphore(compute.compute_sema)
+            .stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .device_index(0)
+            .value(1);
+
+        let cmd_info = vk::CommandBufferSubmitInfo::default()
+            .command_buffer(compute.cbuf)
+            .device_mask(0);
+
+        let submit_info = vk::SubmitInfo2::default()
+            .wait_semaphore_infos(wait_info.as_slice())
+            .signal_semaphore_infos(slice::from_ref(&signal_info))
+            .command_buffer_infos(slice::from_ref(&cmd_info));
+
+        unsafe {
+            device.queue_submit2(queue, slice::from_ref(&submit_info), vk::Fence::null())?
+        };
+        compute.submitted = true;
+
+        Ok(())
+    }
+
+    /// returns the semaphore signaled once the render target is no longer used,
+    /// if the raymarch of this frame ran on the compute queue
+    pub fn submit(&mut self, device: &Device, queue: vk::Queue) -> Result<Option<vk::Semaphore>> {
         let wait_info = vk::SemaphoreSubmitInfo::default()
             .semaphore(self.swapchain_sema)
             .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
             .device_index(0)
             .value(1);
 
+        // the present transition of the swapchain image finishes at this stage
         let signal_info = vk::SemaphoreSubmitInfo::default()
             .semaphore(self.render_sema)
-            .stage_mask(vk::PipelineStageFlags2::ALL_GRAPHICS)
+            .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
             .device_index(0)
             .value(1);
 
+        self.submit_with(device, queue, Some(wait_info), Some(signal_info))
+    }
+
+    /// submit without waiting for a swapchain image or signaling
+    /// the render semaphore, for headless rendering
+    pub fn submit_without_present(
+        &mut self,
+        device: &Device,
+        queue: vk::Queue,
+    ) -> Result<Option<vk::Semaphore>> {
+        self.submit_with(device, queue, None, None)
+    }
+
+    fn submit_with(
+        &mut self,
+        device: &Device,
+        queue: vk::Queue,
+        wait_info: Option<vk::SemaphoreSubmitInfo<'static>>,
+        signal_info: Option<vk::SemaphoreSubmitInfo<'static>>,
+    ) -> Result<Option<vk::Semaphore>> {
+        let mut wait_infos = Vec::with_capacity(2);
+        let mut signal_infos = Vec::with_capacity(3);
+        wait_infos.extend(wait_info);
+        signal_infos.extend(signal_info);
+
+        let mut render_target_release = None;
+        if let Some(compute) = self.compute.as_mut().filter(|compute| compute.submitted) {
+            compute.submitted = false;
+            wait_infos.push(
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(compute.compute_sema)
+                    .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .device_index(0)
+                    .value(1),
+            );
+            signal_infos.push(
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(compute.graphics_sema)
+                    .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .device_index(0)
+                    .value(1),
+            );
+            render_target_release = Some(compute.graphics_sema);
+        }
+
+        // frame indices only increase, and frames are submitted in that order
+        let fence = if let Some(timeline) = self.timeline {
+            signal_infos.push(
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(timeline)
+                    .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .device_index(0)
+                    .value(self.frame_index),
+            );
+            vk::Fence::null()
+        } else {
+            unsafe { device.reset_fences(&[self.render_fence])? };
+            self.render_fence
+        };
+
         let cmd_info = vk::CommandBufferSubmitInfo::default()
             .command_buffer(self.main_cbuf)
             .device_mask(0);
 
         let submit_info = vk::SubmitInfo2::default()
-            .wait_semaphore_infos(slice::from_ref(&wait_info))
-            .signal_semaphore_infos(slice::from_ref(&signal_info))
+            .wait_semaphore_infos(&wait_infos)
+            .signal_semaphore_infos(&signal_infos)
             .command_buffer_infos(slice::from_ref(&cmd_info));
 
-        unsafe { device.queue_submit2(queue, slice::from_ref(&submit_info), self.render_fence)? };
+        unsafe { device.queue_submit2(queue, slice::from_ref(&submit_info), fence)? };
+        self.submitted_index = self.frame_index;
 
-        Ok(())
+        Ok(render_target_release)
+    }
+}
+
+/// `frame0.main_cbuf` style names, so validation messages tell
+/// the frames apart
+fn name<H: Handle>(device: &Device, index: usize, handle: H, object: &str) {
+    set_object_name(device, handle, &format!("frame{index}.{object}"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(i: u64) -> FrameInFlight {
+        FrameInFlight {
+            command_pool: vk::CommandPool::null(),
+            main_cbuf: vk::CommandBuffer::from_raw(i + 1),
+            compute: None,
+            swapchain_sema: vk::Semaphore::null(),
+            render_sema: vk::Semaphore::null(),
+            render_fence: vk::Fence::null(),
+            timeline: None,
+            frame_index: 0,
+            submitted_index: 0,
+            delete_queue: DeleteQueue::new(),
+            static_key: None,
+        }
+    }
+
+    #[test]
+    fn previous_after_wrap() {
+        let mut frames = FramesInFlight {
+            frame_index: 0,
+            frame: 0,
+            frames: [frame(0), frame(1)],
+        };
+
+        // past the end of the ring a few times
+        for n in 1..=5 {
+            let (current, i) = frames.next();
+            let cbuf = current.main_cbuf;
+            assert_eq!(i, (n as usize - 1) % 2);
+
+            let (previous, j) = frames.previous();
+            assert_eq!((j, previous.main_cbuf), (i, cbuf));
+            assert_eq!(previous.frame_index, n);
+
+            let deferred: *const DeleteQueue = frames.deferred_delete_queue();
+            assert!(std::ptr::eq(deferred, &frames.frames[i].delete_queue));
+        }
+    }
+
+    #[test]
+    fn previous() {
+        assert_eq!(previous_index(0, 1), 0);
+
+        assert_eq!(previous_index(0, 2), 1);
+        assert_eq!(previous_index(1, 2), 0);
+
+        assert_eq!(previous_index(0, 3), 2);
+        assert_eq!(previous_index(1, 3), 0);
+        assert_eq!(previous_index(2, 3), 1);
     }
 }
@@ -0,0 +1,77 @@
+#version 460
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+// a ping-pong pair, `flip` selects which one is read
+layout(rgba16f, set = 0, binding = 0) uniform image2D image0;
+layout(rgba16f, set = 0, binding = 1) uniform image2D image1;
+
+layout(push_constant) uniform PushConstant {
+    // 0 keeps the image as is, 1 fully blends across edges
+    float intensity;
+    // minimum local luminance contrast that counts as an edge
+    float edge_threshold;
+    // 0 reads image0 and writes image1, 1 is the other way around
+    uint flip;
+} push;
+
+//
+
+float luma(vec3 col) {
+    return dot(col, vec3(0.299, 0.587, 0.114));
+}
+
+ivec2 src_size() {
+    return push.flip == 0 ? imageSize(image0) : imageSize(image1);
+}
+
+vec4 load(ivec2 coord) {
+    coord = clamp(coord, ivec2(0), src_size() - 1);
+    return push.flip == 0 ? imageLoad(image0, coord) : imageLoad(image1, coord);
+}
+
+void store(ivec2 coord, vec4 col) {
+    if (push.flip == 0) {
+        imageStore(image1, coord, col);
+    } else {
+        imageStore(image0, coord, col);
+    }
+}
+
+void main() {
+    ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = src_size();
+
+    if (coord.x >= size.x || coord.y >= size.y) {
+        return;
+    }
+
+    vec4 center = load(coord);
+    vec4 n = load(coord + ivec2(0, -1));
+    vec4 s = load(coord + ivec2(0, 1));
+    vec4 e = load(coord + ivec2(1, 0));
+    vec4 w = load(coord + ivec2(-1, 0));
+
+    float luma_c = luma(center.rgb);
+    float luma_n = luma(n.rgb);
+    float luma_s = luma(s.rgb);
+    float luma_e = luma(e.rgb);
+    float luma_w = luma(w.rgb);
+
+    float luma_min = min(luma_c, min(min(luma_n, luma_s), min(luma_e, luma_w)));
+    float luma_max = max(luma_c, max(max(luma_n, luma_s), max(luma_e, luma_w)));
+    float contrast = luma_max - luma_min;
+
+    if (contrast < max(push.edge_threshold, luma_max * 0.125)) {
+        store(coord, center);
+        return;
+    }
+
+    // blend across the edge, so along the axis with the larger gradient
+    float horizontal = abs(luma_n + luma_s - 2.0 * luma_c);
+    float vertical = abs(luma_e + luma_w - 2.0 * luma_c);
+    vec4 across = horizontal >= vertical ? (n + s) * 0.5 : (e + w) * 0.5;
+
+    float blend = clamp(contrast / max(luma_max, 0.0001), 0.0, 1.0) * push.intensity;
+    store(coord, mix(center, across, blend * 0.5));
+}
@@ -0,0 +1,193 @@
+use ash::{Device, vk};
+use bytemuck::{Pod, Zeroable};
+use eyre::Result;
+use glam::UVec3;
+use gpu_allocator::vulkan::Allocator;
+
+use super::{
+    Graphics,
+    delete_queue::DeleteQueue,
+    descriptor::{
+        DescriptorPool, DescriptorSet, DescriptorSetLayout,
+        DescriptorSetUpdateEntry, DescriptorWriteBatcher,
+    },
+    image::Image,
+    pipeline::{ComputePipeline, PipelineCache, PipelineLayout},
+    shader::Shader,
+};
+
+//
+
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct FxaaPushConst {
+    /// 0 keeps the image as is, 1 fully blends across edges
+    pub intensity: f32,
+    /// minimum local luminance contrast that counts as an edge
+    pub edge_threshold: f32,
+}
+
+/// push constants of the pass, the settings and the ping-pong direction
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct PushConst {
+    settings: FxaaPushConst,
+    /// 0 reads binding 0 and writes binding 1, 1 is the other way around
+    flip: u32,
+}
+
+/// edge detecting anti-aliasing post pass,
+/// the render target and its own target are bound as a ping-pong pair
+/// at bindings 0 and 1, so the direction never rewrites descriptors
+pub struct Fxaa {
+    descriptor_set: DescriptorSet,
+    pipeline: ComputePipeline<PushConst>,
+
+    pub target: Image,
+    pub target_delete_queue: DeleteQueue,
+}
+
+impl Fxaa {
+    pub fn new(
+        device: &Device,
+        allocator: &mut Allocator,
+        descriptor_pool: &DescriptorPool,
+        delete_queue: &mut DeleteQueue,
+        pipeline_cache: &PipelineCache,
+        render_target: &Image,
+    ) -> Result<Self> {
+        let mut init_delete_queue = DeleteQueue::new();
+
+        let descriptor_set_layout = DescriptorSetLayout::builder()
+            .name("fxaa_set_layout")
+            .add_binding(
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .add_binding(
+                1,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .build(device, delete_queue)?;
+
+        let descriptor_set =
+            descriptor_pool.alloc(device, &descriptor_set_layout)?;
+
+        let pipeline_layout =
+            PipelineLayout::new(device, delete_queue, &descriptor_set_layout)?;
+
+        let shader =
+            Shader::new(device, &mut init_delete_queue, Shader::FXAA_COMP)?;
+        let pipeline = ComputePipeline::new(
+            device,
+            delete_queue,
+            pipeline_cache,
+            pipeline_layout,
+            &shader,
+        )?;
+
+        init_delete_queue.flush(device, allocator);
+
+        let mut target_delete_queue = DeleteQueue::new();
+        let target = Graphics::create_render_image(
+            device,
+            allocator,
+            &mut target_delete_queue,
+            render_target.extent,
+        )?;
+
+        let mut fxaa = Self {
+            descriptor_set,
+            pipeline,
+
+            target,
+            target_delete_queue,
+        };
+        fxaa.descriptor_set
+            .update(device)
+            .write(0, DescriptorSetUpdateEntry::storage_image(render_target))
+            .write(1, DescriptorSetUpdateEntry::storage_image(&fxaa.target));
+
+        Ok(fxaa)
+    }
+
+    /// match the size of a new render target,
+    /// the old target is deleted with `delete_queue`
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        allocator: &mut Allocator,
+        delete_queue: &mut DeleteQueue,
+        descriptor_writes: &mut DescriptorWriteBatcher,
+        render_target: &Image,
+    ) -> Result<()> {
+        delete_queue.append(&mut self.target_delete_queue);
+        self.target = Graphics::create_render_image(
+            device,
+            allocator,
+            &mut self.target_delete_queue,
+            render_target.extent,
+        )?;
+        descriptor_writes
+            .write(
+                &self.descriptor_set,
+                0,
+                DescriptorSetUpdateEntry::storage_image(render_target),
+            )
+            .write(
+                &self.descriptor_set,
+                1,
+                DescriptorSetUpdateEntry::storage_image(&self.target),
+            );
+        Ok(())
+    }
+
+    /// the image written by [`Self::dispatch`] with the same `flip`
+    pub fn output<'a>(
+        &'a self,
+        render_target: &'a Image,
+        flip: bool,
+    ) -> &'a Image {
+        if flip { render_target } else { &self.target }
+    }
+
+    /// filter the render target into the FXAA target,
+    /// or the FXAA target back into the render target if `flip` is set,
+    /// both have to be in the GENERAL layout
+    pub fn dispatch(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        settings: FxaaPushConst,
+        flip: bool,
+    ) {
+        let push_const = PushConst {
+            settings,
+            flip: flip as u32,
+        };
+        self.pipeline.bind(device, cbuf);
+        self.pipeline.bind_sets(
+            device,
+            cbuf,
+            0,
+            &[self.descriptor_set.set],
+            &[],
+        );
+        self.pipeline.write_push_constant(device, cbuf, &push_const);
+        self.pipeline.dispatch(
+            device,
+            cbuf,
+            UVec3::new(
+                self.target.extent.width.div_ceil(16),
+                self.target.extent.height.div_ceil(16),
+                1,
+            ),
+        );
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.target_delete_queue.flush(device, allocator);
+    }
+}
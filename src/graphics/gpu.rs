@@ -1,9 +1,10 @@
 use std::{
-    alloc::Layout, ffi::CStr, intrinsics::const_allocate, mem::MaybeUninit,
-    ptr, slice,
+    env,
+    ffi::{CStr, c_char},
+    ptr,
 };
 
-use ash::{Entry, Instance, khr, vk};
+use ash::{Entry, Instance, ext, khr, vk};
 
 use eyre::{Result, eyre};
 
@@ -11,45 +12,86 @@ use super::queues::QueueFamilies;
 
 //
 
+/// without a `surface` nothing is required to support presenting
 pub fn pick_gpu(
     entry: &Entry,
     instance: &Instance,
-    surface: vk::SurfaceKHR,
-) -> Result<(vk::PhysicalDevice, QueueFamilies)> {
+    surface: Option<vk::SurfaceKHR>,
+) -> Result<(vk::PhysicalDevice, QueueFamilies, Features)> {
     let surface_loader = khr::surface::Instance::new(entry, instance);
 
     let gpus = unsafe { instance.enumerate_physical_devices()? };
 
     if tracing::enabled!(tracing::Level::INFO) {
         tracing::info!("gpus:");
-        for gpu in gpus.iter().copied() {
+        for (i, gpu) in gpus.iter().copied().enumerate() {
             let props = unsafe { instance.get_physical_device_properties(gpu) };
-            tracing::info!(
-                " - {}",
-                props
-                    .device_name_as_c_str()
-                    .ok()
-                    .and_then(|s| s.to_str().ok())
-                    .unwrap_or("<invalid name>")
-            );
+            tracing::info!(" - {i}: {}", device_name(&props));
         }
     }
 
-    let (gpu, queue_families, props) = gpus
+    let suitable: Vec<_> = gpus
         .into_iter()
-        .filter_map(|gpu| is_suitable(instance, &surface_loader, gpu, surface))
-        .max_by_key(|(_, _, props)| score(props))
+        .enumerate()
+        .filter_map(|(i, gpu)| {
+            Some((i, is_suitable(instance, &surface_loader, gpu, surface)?))
+        })
+        .collect();
+
+    let forced = env::var("LUMINARY_GPU").ok().and_then(|wanted| {
+        let forced = find_forced(&suitable, &wanted);
+        if forced.is_none() {
+            tracing::warn!(
+                "LUMINARY_GPU={wanted} did not match any suitable GPU"
+            );
+        }
+        forced
+    });
+
+    let (gpu, queue_families, props, features) = forced
+        .or_else(|| {
+            suitable
+                .into_iter()
+                .map(|(_, suitable)| suitable)
+                .max_by_key(|(_, _, props, _)| score(props))
+        })
         .ok_or_else(|| eyre!("no suitable GPUs"))?;
 
-    let name = props
-        .device_name_as_c_str()
-        .ok()
-        .and_then(|s| s.to_str().ok())
-        .unwrap_or("<invalid name>");
+    let name = device_name(&props);
     tracing::info!("picked {name}");
     tracing::debug!("{queue_families:?}");
+    tracing::info!("{features:?}");
+
+    Ok((gpu, queue_families, features))
+}
+
+/// `wanted` is either the device index or a case-insensitive part of its name
+fn find_forced(
+    suitable: &[(usize, SuitableGpu)],
+    wanted: &str,
+) -> Option<SuitableGpu> {
+    if let Ok(index) = wanted.trim().parse::<usize>() {
+        return suitable
+            .iter()
+            .find(|(i, _)| *i == index)
+            .map(|(_, suitable)| suitable.clone());
+    }
+
+    let wanted = wanted.to_lowercase();
+    suitable
+        .iter()
+        .find(|(_, (_, _, props, _))| {
+            device_name(props).to_lowercase().contains(&wanted)
+        })
+        .map(|(_, suitable)| suitable.clone())
+}
 
-    Ok((gpu, queue_families))
+fn device_name(props: &vk::PhysicalDeviceProperties) -> &str {
+    props
+        .device_name_as_c_str()
+        .ok()
+        .and_then(|s| s.to_str().ok())
+        .unwrap_or("<invalid name>")
 }
 
 fn score(props: &vk::PhysicalDeviceProperties) -> usize {
@@ -63,41 +105,77 @@ fn score(props: &vk::PhysicalDeviceProperties) -> usize {
     }
 }
 
+type SuitableGpu = (
+    vk::PhysicalDevice,
+    QueueFamilies,
+    vk::PhysicalDeviceProperties,
+    Features,
+);
+
 fn is_suitable(
     instance: &Instance,
     surface_loader: &khr::surface::Instance,
     gpu: vk::PhysicalDevice,
-    surface: vk::SurfaceKHR,
-) -> Option<(
-    vk::PhysicalDevice,
-    QueueFamilies,
-    vk::PhysicalDeviceProperties,
-)> {
+    surface: Option<vk::SurfaceKHR>,
+) -> Option<SuitableGpu> {
     let props = unsafe { instance.get_physical_device_properties(gpu) };
     if props.api_version < vk::API_VERSION_1_3 {
         return None;
     }
 
-    if !has_extensions(instance, gpu) {
+    let res = unsafe { instance.enumerate_device_extension_properties(gpu) };
+    let avail_exts = res.ok()?;
+
+    // headless rendering works on devices without any presentation support
+    let swapchain = surface.is_some();
+    if swapchain && !has_extensions(&avail_exts, SWAPCHAIN_EXTS_CSTR) {
         return None;
     }
 
-    if !has_surface_support(surface_loader, gpu, surface) {
+    // the ray tracing structs can only be chained if the extensions exist
+    let ray_tracing_exts = has_extensions(&avail_exts, RAY_TRACING_EXTS_CSTR);
+    let mut features_as =
+        vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+    let mut features_rt =
+        vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default();
+    let mut features12 = vk::PhysicalDeviceVulkan12Features::default();
+    let mut features2 =
+        vk::PhysicalDeviceFeatures2::default().push_next(&mut features12);
+    if ray_tracing_exts {
+        features2 = features2
+            .push_next(&mut features_as)
+            .push_next(&mut features_rt);
+    }
+    unsafe { instance.get_physical_device_features2(gpu, &mut features2) };
+
+    let features = Features {
+        swapchain,
+        ray_tracing: ray_tracing_exts
+            && features_as.acceleration_structure == vk::TRUE
+            && features_rt.ray_tracing_pipeline == vk::TRUE,
+        timeline_semaphore: features12.timeline_semaphore == vk::TRUE,
+        global_priority: has_extensions(&avail_exts, GLOBAL_PRIORITY_EXTS_CSTR)
+            .then_some(vk::QueueGlobalPriorityKHR::MEDIUM),
+        memory_budget: has_extensions(&avail_exts, MEMORY_BUDGET_EXTS_CSTR),
+    };
+    tracing::debug!("{}: {features:?}", device_name(&props));
+
+    if let Some(surface) = surface
+        && !has_surface_support(surface_loader, gpu, surface)
+    {
         return None;
     }
 
     let queue_families = find_queues(instance, surface_loader, gpu, surface)?;
 
-    Some((gpu, queue_families, props))
+    Some((gpu, queue_families, props, features))
 }
 
-fn has_extensions(instance: &Instance, gpu: vk::PhysicalDevice) -> bool {
-    let res = unsafe { instance.enumerate_device_extension_properties(gpu) };
-    let Ok(avail_exts) = res else {
-        return false;
-    };
-
-    for required in REQUIRED_EXTS_CSTR {
+fn has_extensions(
+    avail_exts: &[vk::ExtensionProperties],
+    exts: &[&CStr],
+) -> bool {
+    for required in exts {
         if !avail_exts
             .iter()
             .any(|avail| avail.extension_name_as_c_str() == Ok(required))
@@ -156,56 +234,69 @@ fn find_queues(
     instance: &Instance,
     surface_loader: &khr::surface::Instance,
     gpu: vk::PhysicalDevice,
-    surface: vk::SurfaceKHR,
+    surface: Option<vk::SurfaceKHR>,
 ) -> Option<QueueFamilies> {
-    let mut queue_families =
+    let queue_families =
         unsafe { instance.get_physical_device_queue_family_properties(gpu) };
+    tracing::debug!("queue family count: {}", queue_families.len());
+
+    // without a surface, any queue can be the unused present queue
+    let has_present: Vec<bool> = (0..queue_families.len() as u32)
+        .map(|i| {
+            surface.is_none_or(|surface| {
+                unsafe {
+                    surface_loader
+                        .get_physical_device_surface_support(gpu, i, surface)
+                }
+                .unwrap_or(false)
+            })
+        })
+        .collect();
+
+    pick_queues(queue_families, &has_present)
+}
+
+/// the most specific family for each use, preferring families
+/// that are not picked yet, `has_present` is per family
+fn pick_queues(
+    mut queue_families: Vec<vk::QueueFamilyProperties>,
+    has_present: &[bool],
+) -> Option<QueueFamilies> {
     // use the timestamp_valid_bits field to count the times this queue is used
     for queue_family in queue_families.iter_mut() {
         queue_family.timestamp_valid_bits = 0;
     }
-    tracing::debug!("queue family count: {}", queue_families.len());
 
-    let present = find_queue(
-        surface_loader,
-        gpu,
-        surface,
-        &queue_families,
-        |_, has_present| has_present,
-    )?;
+    let present =
+        find_queue(&queue_families, has_present, |_, has_present| has_present)?;
     queue_families[present as usize].timestamp_valid_bits += 1;
-    let graphics = find_queue(
-        surface_loader,
-        gpu,
-        surface,
-        &queue_families,
-        |props, _| props.queue_flags.contains(vk::QueueFlags::GRAPHICS),
-    )?;
+    let graphics = find_queue(&queue_families, has_present, |props, _| {
+        props.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+    })?;
     queue_families[graphics as usize].timestamp_valid_bits += 1;
-    let transfer = find_queue(
-        surface_loader,
-        gpu,
-        surface,
-        &queue_families,
-        |props, _| props.queue_flags.contains(vk::QueueFlags::TRANSFER),
-    )?;
+    let transfer = find_queue(&queue_families, has_present, |props, _| {
+        props.queue_flags.contains(vk::QueueFlags::TRANSFER)
+    })?;
     queue_families[transfer as usize].timestamp_valid_bits += 1;
-    let compute = find_queue(
-        surface_loader,
-        gpu,
-        surface,
-        &queue_families,
-        |props, _| props.queue_flags.contains(vk::QueueFlags::COMPUTE),
-    )?;
+    let compute = find_queue(&queue_families, has_present, |props, _| {
+        props.queue_flags.contains(vk::QueueFlags::COMPUTE)
+    })?;
     queue_families[compute as usize].timestamp_valid_bits += 1;
 
     let mut families: Vec<vk::DeviceQueueCreateInfo<'static>> =
         [present, graphics, transfer, compute]
             .into_iter()
             .map(|i| {
+                // uploads should not delay frames sharing the hardware
+                let transfer_only =
+                    i == transfer && ![present, graphics, compute].contains(&i);
                 vk::DeviceQueueCreateInfo::default()
                     .queue_family_index(i)
-                    .queue_priorities(&[1.0])
+                    .queue_priorities(if transfer_only {
+                        &[0.5]
+                    } else {
+                        &[1.0]
+                    })
             })
             .collect();
 
@@ -221,26 +312,167 @@ fn find_queues(
     })
 }
 
-fn find_queue(
-    surface_loader: &khr::surface::Instance,
+/// global priorities queues of `family` can be created with,
+/// only valid if [`Features::global_priority`] is available
+pub fn global_priorities(
+    instance: &Instance,
     gpu: vk::PhysicalDevice,
-    surface: vk::SurfaceKHR,
+    family: u32,
+) -> Vec<vk::QueueGlobalPriorityKHR> {
+    let count = unsafe {
+        instance.get_physical_device_queue_family_properties2_len(gpu)
+    };
+    let mut priorities =
+        vec![vk::QueueFamilyGlobalPriorityPropertiesKHR::default(); count];
+    let mut props: Vec<_> = priorities
+        .iter_mut()
+        .map(|priorities| {
+            vk::QueueFamilyProperties2::default().push_next(priorities)
+        })
+        .collect();
+    unsafe {
+        instance.get_physical_device_queue_family_properties2(gpu, &mut props)
+    };
+    drop(props);
+
+    priorities
+        .get(family as usize)
+        .map(|family| {
+            family.priorities[..family.priority_count as usize].to_vec()
+        })
+        .unwrap_or_default()
+}
+
+/// a `LUMINARY_QUEUE_PRIORITY` value, unset means medium,
+/// unknown names are `None`
+pub fn parse_queue_priority(
+    name: Option<&str>,
+) -> Option<vk::QueueGlobalPriorityKHR> {
+    use vk::QueueGlobalPriorityKHR as P;
+    Some(match name {
+        Some("low") => P::LOW,
+        Some("medium") | None => P::MEDIUM,
+        Some("high") => P::HIGH,
+        Some("realtime") => P::REALTIME,
+        Some(_) => return None,
+    })
+}
+
+/// `wanted` if the queue family supports it, otherwise medium,
+/// which every queue family supports
+pub fn queue_priority(
+    wanted: vk::QueueGlobalPriorityKHR,
+    supported: &[vk::QueueGlobalPriorityKHR],
+) -> vk::QueueGlobalPriorityKHR {
+    const MEDIUM: vk::QueueGlobalPriorityKHR =
+        vk::QueueGlobalPriorityKHR::MEDIUM;
+    if wanted == MEDIUM || supported.contains(&wanted) {
+        wanted
+    } else {
+        MEDIUM
+    }
+}
+
+/// size, usage and budget of every memory heap, the usage counts the
+/// memory of the whole process, not just the allocator,
+/// only valid if [`Features::memory_budget`] is available
+pub fn memory_budget(
+    instance: &Instance,
+    gpu: vk::PhysicalDevice,
+) -> Vec<HeapBudget> {
+    let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let heaps = {
+        let mut props = vk::PhysicalDeviceMemoryProperties2::default()
+            .push_next(&mut budget);
+        unsafe {
+            instance.get_physical_device_memory_properties2(gpu, &mut props)
+        };
+        props.memory_properties.memory_heaps_as_slice().to_vec()
+    };
+
+    heaps
+        .iter()
+        .enumerate()
+        .map(|(i, heap)| HeapBudget {
+            size: heap.size,
+            device_local: heap
+                .flags
+                .contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+            usage: budget.heap_usage[i],
+            budget: budget.heap_budget[i],
+        })
+        .collect()
+}
+
+/// one memory heap from [`memory_budget`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapBudget {
+    pub size: u64,
+    /// VRAM, the other heaps are system memory
+    pub device_local: bool,
+    /// bytes used by this process
+    pub usage: u64,
+    /// bytes this process can use before allocations fail
+    /// or start to slow down, can change at any time
+    pub budget: u64,
+}
+
+/// how an allocation path responds to memory pressure,
+/// decided by [`memory_pressure`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAction {
+    /// go ahead
+    Allocate,
+    /// free memory that is only waiting for the GPU, then allocate
+    Reclaim,
+    /// reclaiming did not help, return the error
+    Fail,
+}
+
+/// the first heap that uses more than `fraction` of its budget,
+/// heaps without a budget are skipped
+pub fn heap_over_budget(
+    heaps: &[HeapBudget],
+    fraction: f32,
+) -> Option<(usize, &HeapBudget)> {
+    heaps.iter().enumerate().find(|(_, heap)| {
+        heap.budget != 0
+            && heap.usage as f64 > heap.budget as f64 * fraction as f64
+    })
+}
+
+/// the memory pressure policy, `failed` is the number of out of memory
+/// errors the allocation has already run into
+///
+/// memory is reclaimed before allocating when a heap is past `fraction`
+/// of its budget, or after the first failure, one retry is allowed
+pub fn memory_pressure(
+    heaps: &[HeapBudget],
+    fraction: f32,
+    failed: u32,
+) -> MemoryAction {
+    match failed {
+        0 if heap_over_budget(heaps, fraction).is_some() => {
+            MemoryAction::Reclaim
+        }
+        0 => MemoryAction::Allocate,
+        1 => MemoryAction::Reclaim,
+        _ => MemoryAction::Fail,
+    }
+}
+
+fn find_queue(
     queue_families: &[vk::QueueFamilyProperties],
+    has_present: &[bool],
     mut is_valid: impl FnMut(&vk::QueueFamilyProperties, bool) -> bool,
 ) -> Option<u32> {
     tracing::debug!("finding next queue");
     queue_families
         .iter()
+        .zip(has_present)
         .enumerate()
         .take(u32::MAX as _)
-        .map(|(i, p)| (i as u32, p))
-        .map(|(i, p)| {
-            tracing::debug!("i={i}");
-            let has_present =
-                unsafe { surface_loader.get_physical_device_surface_support(gpu, i, surface) }
-                    .unwrap_or(false);
-            (i, p, has_present)
-        })
+        .map(|(i, (p, has_present))| (i as u32, p, *has_present))
         .filter(|(i, props, has_present)| {
             let functions = props.queue_flags.as_raw().count_ones();
             let is_valid = is_valid(props, *has_present);
@@ -265,32 +497,250 @@ fn find_queue(
 
 //
 
-pub const REQUIRED_EXTS_CSTR: &[&CStr] = &[
-    khr::swapchain::NAME,
+/// only required when presenting to a surface
+pub const SWAPCHAIN_EXTS_CSTR: &[&CStr] = &[khr::swapchain::NAME];
+
+/// only enabled if all of them are available
+pub const RAY_TRACING_EXTS_CSTR: &[&CStr] = &[
     khr::acceleration_structure::NAME,
     khr::ray_tracing_pipeline::NAME,
     khr::deferred_host_operations::NAME,
 ];
-pub const REQUIRED_EXTS_PTRPTR: &[*const i8] = map(REQUIRED_EXTS_CSTR);
 
-const fn map(a: &[&CStr]) -> &'static [*const i8] {
-    let Ok((layout, _)) = Layout::new::<*const i8>().repeat(a.len()) else {
-        panic!("invalid layout for some reason");
-    };
-    let ptr = unsafe { const_allocate(layout.size(), layout.align()) };
+pub const GLOBAL_PRIORITY_EXTS_CSTR: &[&CStr] = &[khr::global_priority::NAME];
+
+pub const MEMORY_BUDGET_EXTS_CSTR: &[&CStr] = &[ext::memory_budget::NAME];
+
+/// optional device features that are enabled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features {
+    /// `VK_KHR_swapchain`, off for headless devices
+    pub swapchain: bool,
+    /// acceleration structures and ray tracing pipelines,
+    /// the compute raymarch works without them
+    pub ray_tracing: bool,
+    /// frames are tracked with one timeline semaphore instead of fences
+    pub timeline_semaphore: bool,
+    /// the global priority of the graphics queue,
+    /// `None` without `VK_KHR_global_priority`
+    pub global_priority: Option<vk::QueueGlobalPriorityKHR>,
+    /// per heap usage and budgets, see [`memory_budget`]
+    pub memory_budget: bool,
+}
+
+impl Features {
+    pub fn extension_names(&self) -> Vec<*const c_char> {
+        let mut exts = Vec::new();
+        if self.swapchain {
+            exts.extend(SWAPCHAIN_EXTS_CSTR.iter().map(|ext| ext.as_ptr()));
+        }
+        if self.ray_tracing {
+            exts.extend(RAY_TRACING_EXTS_CSTR.iter().map(|ext| ext.as_ptr()));
+        }
+        if self.global_priority.is_some() {
+            exts.extend(
+                GLOBAL_PRIORITY_EXTS_CSTR.iter().map(|ext| ext.as_ptr()),
+            );
+        }
+        if self.memory_budget {
+            exts.extend(MEMORY_BUDGET_EXTS_CSTR.iter().map(|ext| ext.as_ptr()));
+        }
+        exts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn heap(usage: u64, budget: u64) -> HeapBudget {
+        HeapBudget {
+            size: 2048 * MIB,
+            device_local: true,
+            usage,
+            budget,
+        }
+    }
+
+    #[test]
+    fn pressure_below_budget() {
+        let heaps = [heap(1000 * MIB, 2000 * MIB), heap(0, 8000 * MIB)];
+        assert_eq!(memory_pressure(&heaps, 0.9, 0), MemoryAction::Allocate);
+        // without memory_budget there is nothing to go by
+        assert_eq!(memory_pressure(&[], 0.9, 0), MemoryAction::Allocate);
+    }
 
-    if ptr.is_null() {
-        panic!("cannot run this in non-const context");
+    #[test]
+    fn pressure_near_budget() {
+        let heaps = [heap(100 * MIB, 8000 * MIB), heap(1900 * MIB, 2000 * MIB)];
+        assert_eq!(memory_pressure(&heaps, 0.9, 0), MemoryAction::Reclaim);
+        assert_eq!(memory_pressure(&heaps, 0.96, 0), MemoryAction::Allocate);
+        assert_eq!(heap_over_budget(&heaps, 0.9).map(|(i, _)| i), Some(1));
     }
 
-    let slice = ptr::slice_from_raw_parts_mut(ptr.cast(), a.len());
-    let slice = unsafe { slice.as_uninit_slice_mut().unwrap() };
+    #[test]
+    fn pressure_exactly_at_fraction() {
+        let heaps = [heap(500, 1000)];
+        assert_eq!(memory_pressure(&heaps, 0.5, 0), MemoryAction::Allocate);
+        assert_eq!(memory_pressure(&heaps, 0.499, 0), MemoryAction::Reclaim);
+    }
+
+    #[test]
+    fn pressure_without_budget() {
+        // a zero budget means the driver did not report one
+        let heaps = [heap(1000 * MIB, 0)];
+        assert_eq!(memory_pressure(&heaps, 0.0, 0), MemoryAction::Allocate);
+    }
+
+    #[test]
+    fn pressure_after_failures() {
+        let heaps = [heap(1000 * MIB, 2000 * MIB)];
+        assert_eq!(memory_pressure(&heaps, 0.9, 1), MemoryAction::Reclaim);
+        assert_eq!(memory_pressure(&heaps, 0.9, 2), MemoryAction::Fail);
+        assert_eq!(memory_pressure(&[], 0.9, 5), MemoryAction::Fail);
+    }
+
+    fn names(features: Features) -> Vec<&'static CStr> {
+        features
+            .extension_names()
+            .into_iter()
+            .map(|ext| unsafe { CStr::from_ptr(ext) })
+            .collect()
+    }
 
-    let mut i = 0;
-    while i < a.len() {
-        slice[i].write(a[i].as_ptr());
-        i += 1;
+    #[test]
+    fn headless_skips_swapchain() {
+        let headless = Features {
+            memory_budget: true,
+            ..Features::default()
+        };
+        assert_eq!(names(headless), [ext::memory_budget::NAME]);
+
+        let windowed = Features {
+            swapchain: true,
+            ..headless
+        };
+        assert_eq!(
+            names(windowed),
+            [khr::swapchain::NAME, ext::memory_budget::NAME]
+        );
     }
 
-    unsafe { MaybeUninit::slice_assume_init_ref(slice) }
+    #[test]
+    fn priority_names() {
+        use vk::QueueGlobalPriorityKHR as P;
+        assert_eq!(parse_queue_priority(None), Some(P::MEDIUM));
+        assert_eq!(parse_queue_priority(Some("low")), Some(P::LOW));
+        assert_eq!(parse_queue_priority(Some("realtime")), Some(P::REALTIME));
+        assert_eq!(parse_queue_priority(Some("HIGH")), None);
+        assert_eq!(parse_queue_priority(Some("")), None);
+    }
+
+    #[test]
+    fn priority_fallback() {
+        use vk::QueueGlobalPriorityKHR as P;
+        let supported = [P::LOW, P::MEDIUM, P::HIGH];
+        assert_eq!(queue_priority(P::HIGH, &supported), P::HIGH);
+        assert_eq!(queue_priority(P::LOW, &supported), P::LOW);
+        assert_eq!(queue_priority(P::REALTIME, &supported), P::MEDIUM);
+        // medium needs no support from the queue family
+        assert_eq!(queue_priority(P::MEDIUM, &[]), P::MEDIUM);
+        assert_eq!(queue_priority(P::HIGH, &[]), P::MEDIUM);
+    }
+
+    #[test]
+    fn priority_enables_extension() {
+        let features = Features {
+            global_priority: Some(vk::QueueGlobalPriorityKHR::MEDIUM),
+            ..Features::default()
+        };
+        assert_eq!(names(features), [khr::global_priority::NAME]);
+    }
+
+    #[test]
+    fn ray_tracing_is_optional() {
+        // a headless device without ray tracing needs no extensions
+        assert!(names(Features::default()).is_empty());
+
+        let features = Features {
+            swapchain: true,
+            ..Features::default()
+        };
+        assert_eq!(names(features), [khr::swapchain::NAME]);
+
+        let features = Features {
+            swapchain: true,
+            ray_tracing: true,
+            ..Features::default()
+        };
+        assert_eq!(
+            names(features),
+            [
+                khr::swapchain::NAME,
+                khr::acceleration_structure::NAME,
+                khr::ray_tracing_pipeline::NAME,
+                khr::deferred_host_operations::NAME,
+            ]
+        );
+    }
+
+    fn family(flags: vk::QueueFlags) -> vk::QueueFamilyProperties {
+        vk::QueueFamilyProperties::default()
+            .queue_flags(flags)
+            .queue_count(1)
+    }
+
+    fn priority(families: &QueueFamilies, index: u32) -> f32 {
+        let info = families
+            .families
+            .iter()
+            .find(|info| info.queue_family_index == index)
+            .unwrap();
+        unsafe { *info.p_queue_priorities }
+    }
+
+    #[test]
+    fn dedicated_transfer_family() {
+        use vk::QueueFlags as F;
+        // the usual discrete GPU layout
+        let props = vec![
+            family(F::GRAPHICS | F::COMPUTE | F::TRANSFER),
+            family(F::COMPUTE | F::TRANSFER),
+            family(F::TRANSFER),
+        ];
+        let families = pick_queues(props, &[true, true, false]).unwrap();
+        assert_eq!(
+            [families.graphics, families.transfer, families.compute],
+            [0, 2, 1]
+        );
+        assert_eq!(families.present, 1);
+        assert_eq!(families.families.len(), 3);
+        // uploads don't compete with the frames
+        assert_eq!(priority(&families, 2), 0.5);
+        assert_eq!(priority(&families, 0), 1.0);
+    }
+
+    #[test]
+    fn single_family() {
+        use vk::QueueFlags as F;
+        let props = vec![family(F::GRAPHICS | F::COMPUTE | F::TRANSFER)];
+        let families = pick_queues(props, &[true]).unwrap();
+        assert_eq!(
+            [
+                families.present,
+                families.graphics,
+                families.transfer,
+                families.compute
+            ],
+            [0; 4]
+        );
+        assert!(!families.async_compute());
+        assert_eq!(priority(&families, 0), 1.0);
+
+        // nothing can present
+        let props = vec![family(F::GRAPHICS | F::COMPUTE | F::TRANSFER)];
+        assert!(pick_queues(props, &[false]).is_none());
+    }
 }
@@ -0,0 +1,58 @@
+#version 460
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+// the render target and the FXAA target, `source` selects which one is read
+layout(rgba16f, set = 0, binding = 0) uniform readonly image2D image0;
+layout(rgba16f, set = 0, binding = 1) uniform readonly image2D image1;
+// blitted to the swapchain afterwards
+layout(rgba16f, set = 0, binding = 2) uniform writeonly image2D target;
+
+layout(push_constant) uniform PushConstant {
+    // 0 reads image0, 1 reads image1
+    uint source;
+    // 1 is linear scRGB, 2 is HDR10 (PQ with BT.2020 primaries)
+    uint encoding;
+    // luminance of a rendered 1.0 in nits
+    float white_nits;
+} push;
+
+//
+
+// the rendered colors are sRGB encoded, that is how SDR surfaces show them
+vec3 srgb_to_linear(vec3 col) {
+    return mix(col / 12.92, pow((col + 0.055) / 1.055, vec3(2.4)), step(0.04045, col));
+}
+
+// columns are the BT.2020 coordinates of the BT.709 primaries
+const mat3 BT709_TO_BT2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+// SMPTE ST 2084 inverse EOTF, absolute nits to [0, 1]
+vec3 pq(vec3 nits) {
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(0.1593017578125));
+    return pow((0.8359375 + 18.8515625 * y) / (1.0 + 18.6875 * y), vec3(78.84375));
+}
+
+void main() {
+    ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(coord, imageSize(target)))) {
+        return;
+    }
+
+    vec4 col = push.source == 0 ? imageLoad(image0, coord) : imageLoad(image1, coord);
+    vec3 linear = srgb_to_linear(max(col.rgb, vec3(0.0)));
+
+    vec3 encoded;
+    if (push.encoding == 2) {
+        encoded = pq(BT709_TO_BT2020 * linear * push.white_nits);
+    } else {
+        // scRGB 1.0 is 80 nits
+        encoded = linear * (push.white_nits / 80.0);
+    }
+
+    imageStore(target, coord, vec4(encoded, col.a));
+}
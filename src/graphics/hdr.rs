@@ -0,0 +1,235 @@
+use ash::{Device, vk};
+use bytemuck::{Pod, Zeroable};
+use eyre::Result;
+use glam::UVec3;
+use gpu_allocator::vulkan::Allocator;
+
+use super::{
+    Graphics,
+    delete_queue::DeleteQueue,
+    descriptor::{
+        DescriptorPool, DescriptorSet, DescriptorSetLayout,
+        DescriptorSetUpdateEntry, DescriptorWriteBatcher,
+    },
+    image::Image,
+    pipeline::{ComputePipeline, PipelineCache, PipelineLayout},
+    shader::Shader,
+};
+
+//
+
+/// how the presented images expect their values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputEncoding {
+    /// sRGB encoded values in 0..1, the rendered image as is
+    #[default]
+    Sdr,
+    /// linear BT.709 with 1.0 at 80 nits, values can go past 1
+    ScRgb,
+    /// PQ encoded BT.2020
+    Hdr10,
+}
+
+impl OutputEncoding {
+    /// HDR surface formats in the order they are preferred
+    pub const HDR_FORMATS: &[vk::SurfaceFormatKHR] = &[
+        vk::SurfaceFormatKHR {
+            format: vk::Format::R16G16B16A16_SFLOAT,
+            color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+        },
+        vk::SurfaceFormatKHR {
+            format: vk::Format::A2B10G10R10_UNORM_PACK32,
+            color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        },
+        vk::SurfaceFormatKHR {
+            format: vk::Format::A2R10G10B10_UNORM_PACK32,
+            color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        },
+    ];
+
+    pub fn of(format: vk::SurfaceFormatKHR) -> Self {
+        match format.color_space {
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Self::ScRgb,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => Self::Hdr10,
+            _ => Self::Sdr,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct PushConst {
+    /// 0 reads binding 0, 1 reads binding 1
+    source: u32,
+    /// 1 is [`OutputEncoding::ScRgb`], 2 is [`OutputEncoding::Hdr10`]
+    encoding: u32,
+    white_nits: f32,
+}
+
+/// converts the output to an HDR swapchain encoding before the blit,
+/// the render target and the FXAA target are both bound like for the HUD
+pub struct HdrEncode {
+    descriptor_set: DescriptorSet,
+    pipeline: ComputePipeline<PushConst>,
+
+    pub target: Image,
+    pub target_delete_queue: DeleteQueue,
+}
+
+impl HdrEncode {
+    /// luminance of a rendered 1.0, the BT.2408 reference white
+    pub const WHITE_NITS: f32 = 203.0;
+
+    pub fn new(
+        device: &Device,
+        allocator: &mut Allocator,
+        descriptor_pool: &DescriptorPool,
+        delete_queue: &mut DeleteQueue,
+        pipeline_cache: &PipelineCache,
+        render_target: &Image,
+        fxaa_target: &Image,
+    ) -> Result<Self> {
+        let mut init_delete_queue = DeleteQueue::new();
+
+        let descriptor_set_layout = DescriptorSetLayout::builder()
+            .name("hdr_encode_set_layout")
+            .add_binding(
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .add_binding(
+                1,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .add_binding(
+                2,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .build(device, delete_queue)?;
+
+        let descriptor_set =
+            descriptor_pool.alloc(device, &descriptor_set_layout)?;
+
+        let pipeline_layout =
+            PipelineLayout::new(device, delete_queue, &descriptor_set_layout)?;
+
+        let shader =
+            Shader::new(device, &mut init_delete_queue, Shader::HDR_COMP)?;
+        let pipeline = ComputePipeline::new(
+            device,
+            delete_queue,
+            pipeline_cache,
+            pipeline_layout,
+            &shader,
+        )?;
+
+        init_delete_queue.flush(device, allocator);
+
+        let mut target_delete_queue = DeleteQueue::new();
+        let target = Graphics::create_render_image(
+            device,
+            allocator,
+            &mut target_delete_queue,
+            render_target.extent,
+        )?;
+
+        let mut hdr = Self {
+            descriptor_set,
+            pipeline,
+
+            target,
+            target_delete_queue,
+        };
+        hdr.descriptor_set
+            .update(device)
+            .write(0, DescriptorSetUpdateEntry::storage_image(render_target))
+            .write(1, DescriptorSetUpdateEntry::storage_image(fxaa_target))
+            .write(2, DescriptorSetUpdateEntry::storage_image(&hdr.target));
+
+        Ok(hdr)
+    }
+
+    /// match the size of new render targets,
+    /// the old target is deleted with `delete_queue`
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        allocator: &mut Allocator,
+        delete_queue: &mut DeleteQueue,
+        descriptor_writes: &mut DescriptorWriteBatcher,
+        render_target: &Image,
+        fxaa_target: &Image,
+    ) -> Result<()> {
+        delete_queue.append(&mut self.target_delete_queue);
+        self.target = Graphics::create_render_image(
+            device,
+            allocator,
+            &mut self.target_delete_queue,
+            render_target.extent,
+        )?;
+        descriptor_writes
+            .write(
+                &self.descriptor_set,
+                0,
+                DescriptorSetUpdateEntry::storage_image(render_target),
+            )
+            .write(
+                &self.descriptor_set,
+                1,
+                DescriptorSetUpdateEntry::storage_image(fxaa_target),
+            )
+            .write(
+                &self.descriptor_set,
+                2,
+                DescriptorSetUpdateEntry::storage_image(&self.target),
+            );
+        Ok(())
+    }
+
+    /// encode the FXAA target if `fxaa` is set, the render target
+    /// otherwise, into [`Self::target`], all of them have to be in the
+    /// GENERAL layout, [`OutputEncoding::Sdr`] does nothing
+    pub fn dispatch(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        encoding: OutputEncoding,
+        fxaa: bool,
+    ) {
+        let encoding = match encoding {
+            OutputEncoding::Sdr => return,
+            OutputEncoding::ScRgb => 1,
+            OutputEncoding::Hdr10 => 2,
+        };
+        let push_const = PushConst {
+            source: fxaa as u32,
+            encoding,
+            white_nits: Self::WHITE_NITS,
+        };
+        self.pipeline.bind(device, cbuf);
+        self.pipeline.bind_sets(
+            device,
+            cbuf,
+            0,
+            &[self.descriptor_set.set],
+            &[],
+        );
+        self.pipeline.write_push_constant(device, cbuf, &push_const);
+        self.pipeline.dispatch(
+            device,
+            cbuf,
+            UVec3::new(
+                self.target.extent.width.div_ceil(16),
+                self.target.extent.height.div_ceil(16),
+                1,
+            ),
+        );
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.target_delete_queue.flush(device, allocator);
+    }
+}
@@ -0,0 +1,87 @@
+#version 460
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+// the render target and the FXAA target, `target` selects which one is drawn on
+layout(rgba16f, set = 0, binding = 0) uniform image2D image0;
+layout(rgba16f, set = 0, binding = 1) uniform image2D image1;
+
+layout(push_constant) uniform PushConstant {
+    // ASCII, 4 characters per uint with the first one in the low byte
+    uint text[8];
+    uint len;
+    // image pixels per font pixel
+    uint scale;
+    // 0 draws on image0, 1 on image1
+    uint target;
+} push;
+
+//
+
+const uint GLYPH_W = 3u;
+const uint GLYPH_H = 5u;
+// the glyph and the 1 pixel gap after it
+const uint CELL_W = GLYPH_W + 1u;
+// distance from the image corner, in font pixels
+const uint MARGIN = 2u;
+
+// 3x5 bitmaps, the top left pixel is bit 14 and the bottom right is bit 0
+uint glyph(uint c) {
+    switch (c) {
+    case 0x30u: return 0x7B6Fu; // 0
+    case 0x31u: return 0x2C97u; // 1
+    case 0x32u: return 0x73E7u; // 2
+    case 0x33u: return 0x73CFu; // 3
+    case 0x34u: return 0x5BC9u; // 4
+    case 0x35u: return 0x79CFu; // 5
+    case 0x36u: return 0x79EFu; // 6
+    case 0x37u: return 0x7249u; // 7
+    case 0x38u: return 0x7BEFu; // 8
+    case 0x39u: return 0x7BCFu; // 9
+    case 0x2Eu: return 0x0002u; // .
+    case 0x2Du: return 0x01C0u; // -
+    case 0x46u: return 0x79A4u; // F
+    case 0x4Du: return 0x5FEDu; // M
+    case 0x50u: return 0x7BE4u; // P
+    case 0x53u: return 0x79CFu; // S
+    default: return 0u;
+    }
+}
+
+uint char_at(uint i) {
+    return (push.text[i / 4] >> ((i % 4) * 8)) & 0xFFu;
+}
+
+void main() {
+    // font pixel coordinates inside the background box
+    uvec2 font = gl_GlobalInvocationID.xy / push.scale;
+    uvec2 box = uvec2(push.len * CELL_W + 1, GLYPH_H + 2);
+    if (font.x >= box.x || font.y >= box.y) {
+        return;
+    }
+
+    ivec2 coord = ivec2(gl_GlobalInvocationID.xy + MARGIN * push.scale);
+    ivec2 size = push.target == 0 ? imageSize(image0) : imageSize(image1);
+    if (coord.x >= size.x || coord.y >= size.y) {
+        return;
+    }
+
+    vec4 col = push.target == 0 ? imageLoad(image0, coord) : imageLoad(image1, coord);
+    col.rgb *= 0.25;
+
+    uvec2 inner = font - 1u;
+    uint cell = inner.x / CELL_W;
+    uvec2 pixel = uvec2(inner.x % CELL_W, inner.y);
+    if (font.x >= 1 && font.y >= 1 && cell < push.len && pixel.x < GLYPH_W && pixel.y < GLYPH_H) {
+        uint bit = GLYPH_W * GLYPH_H - 1 - (pixel.y * GLYPH_W + pixel.x);
+        if (((glyph(char_at(cell)) >> bit) & 1u) != 0u) {
+            col.rgb = vec3(1.0);
+        }
+    }
+
+    if (push.target == 0) {
+        imageStore(image0, coord, col);
+    } else {
+        imageStore(image1, coord, col);
+    }
+}
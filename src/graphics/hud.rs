@@ -0,0 +1,176 @@
+use ash::{Device, vk};
+use bytemuck::{Pod, Zeroable};
+use eyre::Result;
+use glam::UVec3;
+use gpu_allocator::vulkan::Allocator;
+
+use super::{
+    delete_queue::DeleteQueue,
+    descriptor::{
+        DescriptorPool, DescriptorSet, DescriptorSetLayout,
+        DescriptorSetUpdateEntry, DescriptorWriteBatcher,
+    },
+    image::Image,
+    pipeline::{ComputePipeline, PipelineCache, PipelineLayout},
+    shader::Shader,
+};
+
+//
+
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct PushConst {
+    /// ASCII, 4 characters per `u32` with the first one in the low byte
+    text: [u32; 8],
+    len: u32,
+    /// image pixels per font pixel
+    scale: u32,
+    /// 0 draws on binding 0, 1 on binding 1
+    target: u32,
+}
+
+/// a single line of text in the top left corner of the output,
+/// drawn with a tiny built-in font that only has digits, `.-FMPS`
+/// and spaces, other characters are left blank
+pub struct Hud {
+    descriptor_set: DescriptorSet,
+    pipeline: ComputePipeline<PushConst>,
+    push_const: PushConst,
+}
+
+impl Hud {
+    /// longest text that fits the push constants
+    pub const MAX_LEN: usize = 32;
+    const SCALE: u32 = 3;
+    // must match hud.glsl
+    const GLYPH_W: u32 = 3;
+    const GLYPH_H: u32 = 5;
+
+    pub fn new(
+        device: &Device,
+        allocator: &mut Allocator,
+        descriptor_pool: &DescriptorPool,
+        delete_queue: &mut DeleteQueue,
+        pipeline_cache: &PipelineCache,
+        render_target: &Image,
+        fxaa_target: &Image,
+    ) -> Result<Self> {
+        let mut init_delete_queue = DeleteQueue::new();
+
+        let descriptor_set_layout = DescriptorSetLayout::builder()
+            .name("hud_set_layout")
+            .add_binding(
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .add_binding(
+                1,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .build(device, delete_queue)?;
+
+        let descriptor_set =
+            descriptor_pool.alloc(device, &descriptor_set_layout)?;
+
+        let pipeline_layout =
+            PipelineLayout::new(device, delete_queue, &descriptor_set_layout)?;
+
+        let shader =
+            Shader::new(device, &mut init_delete_queue, Shader::HUD_COMP)?;
+        let pipeline = ComputePipeline::new(
+            device,
+            delete_queue,
+            pipeline_cache,
+            pipeline_layout,
+            &shader,
+        )?;
+
+        init_delete_queue.flush(device, allocator);
+
+        let mut hud = Self {
+            descriptor_set,
+            pipeline,
+            push_const: PushConst {
+                scale: Self::SCALE,
+                ..PushConst::zeroed()
+            },
+        };
+        hud.descriptor_set
+            .update(device)
+            .write(0, DescriptorSetUpdateEntry::storage_image(render_target))
+            .write(1, DescriptorSetUpdateEntry::storage_image(fxaa_target));
+
+        Ok(hud)
+    }
+
+    /// rebind the images after they were recreated
+    pub fn resize(
+        &self,
+        descriptor_writes: &mut DescriptorWriteBatcher,
+        render_target: &Image,
+        fxaa_target: &Image,
+    ) {
+        descriptor_writes
+            .write(
+                &self.descriptor_set,
+                0,
+                DescriptorSetUpdateEntry::storage_image(render_target),
+            )
+            .write(
+                &self.descriptor_set,
+                1,
+                DescriptorSetUpdateEntry::storage_image(fxaa_target),
+            );
+    }
+
+    /// replace the text, anything past [`Self::MAX_LEN`] is cut off
+    pub fn set_text(&mut self, text: &str) {
+        let mut bytes = [0u8; Self::MAX_LEN];
+        let len = text.len().min(Self::MAX_LEN);
+        bytes[..len].copy_from_slice(&text.as_bytes()[..len]);
+
+        self.push_const.text = bytemuck::cast(bytes);
+        self.push_const.len = len as u32;
+    }
+
+    /// the pushed text, for telling recorded frames apart
+    pub fn key(&self) -> &[u8] {
+        bytemuck::bytes_of(&self.push_const)
+    }
+
+    /// draw on the render target, or the FXAA target if `fxaa` is set,
+    /// the image has to be in the GENERAL layout
+    pub fn dispatch(
+        &mut self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        fxaa: bool,
+    ) {
+        if self.push_const.len == 0 {
+            return;
+        }
+        self.push_const.target = fxaa as u32;
+
+        let width =
+            (self.push_const.len * (Self::GLYPH_W + 1) + 1) * Self::SCALE;
+        let height = (Self::GLYPH_H + 2) * Self::SCALE;
+
+        self.pipeline.bind(device, cbuf);
+        self.pipeline.bind_sets(
+            device,
+            cbuf,
+            0,
+            &[self.descriptor_set.set],
+            &[],
+        );
+        self.pipeline
+            .write_push_constant(device, cbuf, &self.push_const);
+        self.pipeline.dispatch(
+            device,
+            cbuf,
+            UVec3::new(width.div_ceil(16), height.div_ceil(16), 1),
+        );
+    }
+}
@@ -5,7 +5,7 @@ use gpu_allocator::{
     vulkan::{AllocationCreateDesc, AllocationScheme, Allocator},
 };
 
-use super::delete_queue::DeleteQueue;
+use super::{debug::set_object_name, delete_queue::DeleteQueue};
 
 //
 
@@ -28,6 +28,10 @@ pub struct ImageBuilder {
     usage: vk::ImageUsageFlags,
     extent: vk::Extent2D,
     aspect_flags: vk::ImageAspectFlags,
+    /// queue families that share the image without ownership transfers
+    concurrent: Option<[u32; 2]>,
+    /// debug name of the image, the view gets a `.view` suffix
+    name: Option<&'static str>,
 }
 
 impl ImageBuilder {
@@ -50,16 +54,24 @@ impl ImageBuilder {
             .samples(vk::SampleCountFlags::TYPE_1)
             .tiling(vk::ImageTiling::OPTIMAL)
             .usage(self.usage)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .initial_layout(vk::ImageLayout::UNDEFINED);
+        let create_info = match self.concurrent.as_ref() {
+            Some(families) => create_info
+                .sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(families),
+            None => create_info.sharing_mode(vk::SharingMode::EXCLUSIVE),
+        };
 
         let image = unsafe { device.create_image(&create_info, None)? };
         delete_queue.push(image);
+        if let Some(name) = self.name {
+            set_object_name(device, image, name);
+        }
 
         let requirements = unsafe { device.get_image_memory_requirements(image) };
 
         let alloc_desc = AllocationCreateDesc {
-            name: "",
+            name: self.name.unwrap_or(""),
             requirements,
             location: MemoryLocation::GpuOnly,
             linear: false,
@@ -88,6 +100,9 @@ impl ImageBuilder {
 
         let view = unsafe { device.create_image_view(&create_info, None)? };
         delete_queue.push(view);
+        if let Some(name) = self.name {
+            set_object_name(device, view, &format!("{name}.view"));
+        }
 
         Ok(Image {
             image,
@@ -116,6 +131,19 @@ impl ImageBuilder {
         self.aspect_flags = aspect_flags;
         self
     }
+
+    /// share the image between two queue families,
+    /// it stays exclusive if they are the same family
+    pub fn concurrent(mut self, families: [u32; 2]) -> Self {
+        self.concurrent = (families[0] != families[1]).then_some(families);
+        self
+    }
+
+    /// shown in validation messages and RenderDoc captures
+    pub fn name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
 }
 
 impl Default for ImageBuilder {
@@ -132,6 +160,8 @@ impl Default for ImageBuilder {
                 height: 64,
             },
             aspect_flags: vk::ImageAspectFlags::COLOR,
+            concurrent: None,
+            name: None,
         }
     }
 }
@@ -1,59 +1,226 @@
-use std::slice;
+use std::{slice, time::Instant};
 
 use ash::{Device, vk};
 use eyre::Result;
 
+use super::{
+    command_pool::{CommandPools, Lifetime},
+    debug::CmdLabel,
+    delete_queue::DeleteQueue,
+    queues::{QueueTransfer, Resource},
+};
+
 //
 
 pub struct Immediate {
-    pool: vk::CommandPool,
     cbuf: vk::CommandBuffer,
     fence: vk::Fence,
+    /// signaled by the release half of an ownership transfer,
+    /// the acquire submit on the other queue waits for it
+    handoff: vk::Semaphore,
 
     // not owned
     queue: vk::Queue,
+    queue_family: u32,
 }
 
 impl Immediate {
     pub fn new(
         device: &Device,
+        command_pools: &mut CommandPools,
+        delete_queue: &mut DeleteQueue,
         queue: vk::Queue,
         queue_family: u32,
     ) -> Result<Self> {
-        let create_info = vk::CommandPoolCreateInfo::default()
-            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-            .queue_family_index(queue_family);
-
-        let pool = unsafe { device.create_command_pool(&create_info, None)? };
-
-        let allocate_info = vk::CommandBufferAllocateInfo::default()
-            .command_pool(pool)
-            .level(vk::CommandBufferLevel::PRIMARY)
-            .command_buffer_count(1);
-
-        let cbufs = unsafe { device.allocate_command_buffers(&allocate_info)? };
-        let cbuf = cbufs.into_iter().next().unwrap();
+        let (_, cbuf) = command_pools.allocate(
+            device,
+            delete_queue,
+            queue_family,
+            Lifetime::LongLived,
+        )?;
+        tracing::info!("immediate submits use queue family {queue_family}");
 
         let fence = unsafe {
             device.create_fence(&vk::FenceCreateInfo::default(), None)?
         };
+        delete_queue.push(fence);
+        let handoff = unsafe {
+            device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?
+        };
+        delete_queue.push(handoff);
 
         Ok(Self {
-            pool,
             cbuf,
             fence,
+            handoff,
             queue,
+            queue_family,
         })
     }
 
-    pub fn destroy(&self, device: &Device) {
-        unsafe { device.destroy_fence(self.fence, None) };
-        unsafe { device.destroy_command_pool(self.pool, None) };
+    pub fn submit<T>(
+        &self,
+        device: &Device,
+        f: impl FnOnce(vk::CommandBuffer) -> Result<T>,
+    ) -> Result<T> {
+        self.submit_with_usage(
+            device,
+            vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            f,
+        )
     }
 
-    pub fn submit<T>(
+    /// record copies into `resource` with `f` on this queue, then hand it
+    /// over to the queue family of `dst` if it is a different one,
+    /// `dst_stage` and `dst_access` are the first use of it on `dst`,
+    /// images are in `TRANSFER_DST_OPTIMAL` while `f` records
+    pub fn upload<T>(
+        &self,
+        device: &Device,
+        dst: &Immediate,
+        resource: Resource,
+        dst_stage: vk::PipelineStageFlags2,
+        dst_access: vk::AccessFlags2,
+        f: impl FnOnce(vk::CommandBuffer) -> Result<T>,
+    ) -> Result<T> {
+        use vk::{AccessFlags2 as A, PipelineStageFlags2 as S};
+
+        let transfer = QueueTransfer::new(self.queue_family, dst.queue_family);
+        let (to_copy, from_copy) = match resource {
+            Resource::Buffer(_) => (resource, resource),
+            Resource::Image {
+                image,
+                old_layout,
+                new_layout,
+            } => (
+                Resource::Image {
+                    image,
+                    old_layout,
+                    new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                },
+                Resource::Image {
+                    image,
+                    old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    new_layout,
+                },
+            ),
+        };
+        // take it back first, so the parts that are not copied stay valid,
+        // undefined images have nothing to keep
+        let take_back = transfer.is_needed()
+            && !matches!(
+                resource,
+                Resource::Image {
+                    old_layout: vk::ImageLayout::UNDEFINED,
+                    ..
+                }
+            );
+
+        if take_back {
+            dst.submit_handoff(device, None, true, |cbuf| {
+                let back = transfer.back();
+                back.release(device, cbuf, to_copy, dst_stage, A::NONE);
+                Ok(())
+            })?;
+        }
+
+        let start = Instant::now();
+        let wait = take_back.then_some(dst.handoff);
+        let signal = transfer.is_needed();
+        let val = self.submit_handoff(device, wait, signal, |cbuf| {
+            if take_back {
+                let back = transfer.back();
+                back.acquire(
+                    device,
+                    cbuf,
+                    to_copy,
+                    S::ALL_TRANSFER,
+                    A::TRANSFER_WRITE,
+                );
+            } else if let Resource::Image { .. } = to_copy {
+                // only the layout transition
+                QueueTransfer::new(self.queue_family, self.queue_family)
+                    .barrier(to_copy)
+                    .src(dst_stage, A::NONE)
+                    .dst(S::ALL_TRANSFER, A::TRANSFER_WRITE)
+                    .record(device, cbuf);
+            }
+
+            let label = CmdLabel::begin(cbuf, "upload", [0.9, 0.7, 0.2, 1.0]);
+            let val = f(cbuf)?;
+            label.end();
+
+            if transfer.is_needed() {
+                transfer.release(
+                    device,
+                    cbuf,
+                    from_copy,
+                    S::ALL_TRANSFER,
+                    A::TRANSFER_WRITE,
+                );
+            } else {
+                transfer
+                    .barrier(from_copy)
+                    .src(S::ALL_TRANSFER, A::TRANSFER_WRITE)
+                    .dst(dst_stage, dst_access)
+                    .record(device, cbuf);
+            }
+            Ok(val)
+        })?;
+        // on a separate transfer family the copies overlap the frames
+        // still in flight on the render queue instead of queueing behind them
+        tracing::debug!(
+            "upload on queue family {} took {:?}, used by family {}",
+            self.queue_family,
+            start.elapsed(),
+            dst.queue_family
+        );
+
+        if transfer.is_needed() {
+            dst.submit_handoff(device, Some(self.handoff), false, |cbuf| {
+                transfer
+                    .acquire(device, cbuf, from_copy, dst_stage, dst_access);
+                Ok(())
+            })?;
+        }
+
+        Ok(val)
+    }
+
+    pub fn submit_with_usage<T>(
+        &self,
+        device: &Device,
+        usage: vk::CommandBufferUsageFlags,
+        f: impl FnOnce(vk::CommandBuffer) -> Result<T>,
+    ) -> Result<T> {
+        self.submit_inner(device, usage, None, false, f)
+    }
+
+    /// a one time submit that waits for `wait` of another [`Immediate`]
+    /// and signals [`Self::handoff`] if `signal` is set
+    fn submit_handoff<T>(
+        &self,
+        device: &Device,
+        wait: Option<vk::Semaphore>,
+        signal: bool,
+        f: impl FnOnce(vk::CommandBuffer) -> Result<T>,
+    ) -> Result<T> {
+        self.submit_inner(
+            device,
+            vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            wait,
+            signal,
+            f,
+        )
+    }
+
+    fn submit_inner<T>(
         &self,
         device: &Device,
+        usage: vk::CommandBufferUsageFlags,
+        wait: Option<vk::Semaphore>,
+        signal: bool,
         f: impl FnOnce(vk::CommandBuffer) -> Result<T>,
     ) -> Result<T> {
         unsafe {
@@ -67,8 +234,7 @@ impl Immediate {
             )?;
         }
 
-        let begin_info = vk::CommandBufferBeginInfo::default()
-            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        let begin_info = vk::CommandBufferBeginInfo::default().flags(usage);
 
         unsafe {
             device.begin_command_buffer(self.cbuf, &begin_info)?;
@@ -83,8 +249,20 @@ impl Immediate {
         let cbuf_submit_info = vk::CommandBufferSubmitInfo::default()
             .command_buffer(self.cbuf)
             .device_mask(0);
+        let wait_info = wait.map(|semaphore| {
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(semaphore)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        });
+        let signal_info = signal.then(|| {
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(self.handoff)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        });
         let submit_info = vk::SubmitInfo2::default()
-            .command_buffer_infos(slice::from_ref(&cbuf_submit_info));
+            .wait_semaphore_infos(wait_info.as_slice())
+            .command_buffer_infos(slice::from_ref(&cbuf_submit_info))
+            .signal_semaphore_infos(signal_info.as_slice());
 
         unsafe {
             device.queue_submit2(
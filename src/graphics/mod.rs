@@ -1,46 +1,97 @@
 use core::slice;
 use std::{
-    mem::ManuallyDrop,
-    sync::Arc,
+    env, fmt, fs,
+    hash::{Hash, Hasher},
+    io::BufReader,
+    mem::{self, ManuallyDrop},
+    path::Path,
+    sync::{Arc, atomic::AtomicBool},
     time::{Duration, Instant},
 };
 
-use ash::{Device, Entry, Instance, ext, vk};
+use ash::{Device, Entry, Instance, ext, prelude::VkResult, vk};
 use bytemuck::{Pod, Zeroable};
-use eyre::Result;
-use glam::{Mat4, UVec3, Vec4};
-use gpu_allocator::vulkan::{Allocator, AllocatorCreateDesc};
+use eyre::{Result, bail};
+use glam::{Mat4, U64Vec3, UVec3, Vec3, Vec4};
+use gpu_allocator::{
+    AllocationError, MemoryLocation,
+    vulkan::{Allocator, AllocatorCreateDesc},
+};
+use rustc_hash::FxHasher;
 use winit::{raw_window_handle::HasDisplayHandle, window::Window};
 
-use crate::counter::Counter;
+use crate::{
+    counter::{
+        Counter, FrameStats, Metrics, MetricsRow, MetricsSink, Timer, Total,
+    },
+    profiling,
+};
 
 use self::{
-    debug::DebugUtils,
-    delete_queue::DeleteQueue,
+    buffer::{Buffer, TypedBuffer},
+    command_pool::CommandPools,
+    custom_pass::CustomPass,
+    debug::{CmdLabel, DebugUtils},
+    delete_queue::{DeleteQueue, Owned},
     descriptor::{
-        DescriptorPool, DescriptorSet, DescriptorSetLayout,
-        DescriptorSetUpdateEntry,
+        DescriptorLimits, DescriptorPool, DescriptorSet, DescriptorSetLayout,
+        DescriptorSetUpdateEntry, DescriptorWriteBatcher,
     },
     frame::FramesInFlight,
-    gpu::pick_gpu,
-    image::Image,
+    fxaa::Fxaa,
+    gpu::{
+        Features, MemoryAction, global_priorities, heap_over_budget,
+        memory_budget, memory_pressure, parse_queue_priority, pick_gpu,
+        queue_priority,
+    },
+    hdr::HdrEncode,
+    hud::Hud,
+    image::{Image, ImageBuilder},
     immediate::Immediate,
-    pipeline::{ComputePipeline, PipelineLayout},
+    pipeline::{
+        ComputePipeline, PipelineCache, PipelineLayout, Specialization,
+    },
     queues::{QueueFamilies, Queues},
     shader::Shader,
     surface::Surface,
     swapchain::Swapchain,
-    world::voxels::VoxelStructure,
+    timestamp::TimestampPool,
+    tonemap::Tonemap,
+    ui::Ui,
+    world::{import, save::WorldSave, voxels::VoxelStructure},
+};
+
+pub use self::{
+    custom_pass::{Access, CustomPassDesc, CustomPassId, InsertionPoint},
+    fxaa::FxaaPushConst,
+    gpu::HeapBudget,
+    hdr::OutputEncoding,
+    swapchain::PresentModePreference,
+    tonemap::TonemapSettings,
+    workarounds::Overrides,
+    world::{
+        history::History,
+        import::ImportLimits,
+        voxels::{
+            LoadTimings, MaterialInfo, NodeLayout, Scene, Stats as VoxelStats,
+            WorldEvent,
+        },
+    },
 };
 
 //
 
 mod buffer;
+mod command_pool;
+mod custom_pass;
 mod debug;
 mod delete_queue;
 mod descriptor;
 mod frame;
+mod fxaa;
 mod gpu;
+mod hdr;
+mod hud;
 mod image;
 mod immediate;
 mod pipeline;
@@ -48,6 +99,10 @@ mod queues;
 mod shader;
 mod surface;
 mod swapchain;
+mod timestamp;
+mod tonemap;
+mod ui;
+mod workarounds;
 
 pub mod world;
 
@@ -58,43 +113,510 @@ pub mod world;
 pub struct PushConst {
     pub projection_view: Mat4,
     pub mode_flags: u32,
-    pub _pad: [u32; 3],
+    /// filled in by [`Graphics::draw`], see [`Graphics::set_max_ray_distance`]
+    pub max_ray_distance: f32,
+    /// filled in by [`Graphics::draw`], see [`Graphics::set_min_ray_distance`]
+    pub min_ray_distance: f32,
+    /// filled in by [`Graphics::draw`], see [`Graphics::set_render_scale`]
+    pub render_extent: u32,
+    /// filled in by [`Graphics::draw`], see [`Graphics::set_grid`]
+    pub grid: GridPushConst,
+}
+
+impl PushConst {
+    /// bit of [`Self::mode_flags`] that makes the voxel world wrap around
+    /// at its bounds, flying off +X re-enters at -X
+    pub const WRAP_FLAG: u32 = 16;
+
+    /// [`Self::render_extent`] of `extent`, 16 bits per side
+    fn pack_extent(extent: vk::Extent2D) -> u32 {
+        extent.width.min(0xFFFF) | extent.height.min(0xFFFF) << 16
+    }
+}
+
+/// the ground grid on the y=0 plane, drawn where rays miss the voxels
+/// or reach the plane first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct GridPushConst {
+    /// world units between lines, 0 hides the grid
+    pub spacing: f32,
+    /// world units, lines thinner than a pixel fade instead
+    pub width: f32,
+    /// the grid fades out between half of this and this distance
+    pub fade_distance: f32,
+    /// RGBA8, alpha is the opacity of the lines
+    pub color: u32,
+}
+
+impl GridPushConst {
+    /// `spacing` has to be positive, `width` is clamped to it
+    /// and `fade_distance` to at least one cell
+    pub fn new(
+        spacing: f32,
+        width: f32,
+        fade_distance: f32,
+        color: Vec4,
+    ) -> Self {
+        if !(spacing > 0.0 && spacing.is_finite()) {
+            return Self::default();
+        }
+        let color = (color.clamp(Vec4::ZERO, Vec4::ONE) * 255.0).round();
+        Self {
+            spacing,
+            width: width.clamp(0.0, spacing),
+            fade_distance: fade_distance.clamp(spacing, f32::MAX),
+            color: u32::from_le_bytes(color.to_array().map(|c| c as u8)),
+        }
+    }
+}
+
+/// compile time selection of the optional renderer parts,
+/// the shader defines come from the same cargo features
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildConfig {
+    pub debug_visuals: bool,
+    pub voxel32: bool,
+}
+
+impl BuildConfig {
+    pub const CURRENT: Self = Self {
+        debug_visuals: cfg!(feature = "debug-visuals"),
+        voxel32: cfg!(feature = "voxel32"),
+    };
+
+    /// the octree node layout the shader was compiled for
+    pub const fn node_layout(&self) -> NodeLayout {
+        if self.voxel32 {
+            NodeLayout::Wide32
+        } else {
+            NodeLayout::Packed16
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VisionMode {
+    pub name: &'static str,
+    /// bits of [`PushConst::mode_flags`]
+    pub flags: u32,
+    /// only available with [`BuildConfig::debug_visuals`]
+    pub debug: bool,
+}
+
+impl VisionMode {
+    /// mask of all vision mode bits in [`PushConst::mode_flags`]
+    pub const MASK: u32 = 15;
+
+    pub const ALL: &[Self] = &[
+        Self::new("normal", 0, false),
+        Self::new("brightness", 1, true),
+        Self::new("depth", 2, true),
+        Self::new("normals", 4, true),
+        Self::new("step counter", 8, true),
+    ];
+
+    const fn new(name: &'static str, flags: u32, debug: bool) -> Self {
+        Self { name, flags, debug }
+    }
+
+    pub const fn is_available(&self, config: BuildConfig) -> bool {
+        !self.debug || config.debug_visuals
+    }
+}
+
+/// GPU memory usage from [`Graphics::memory_report`]
+#[derive(Debug, Clone, Default)]
+pub struct MemoryReport {
+    /// bytes used by live allocations
+    pub allocated_bytes: u64,
+    /// bytes of device memory reserved in blocks
+    pub reserved_bytes: u64,
+    pub allocations: usize,
+    pub blocks: Vec<BlockUsage>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockUsage {
+    pub size: u64,
+    pub used: u64,
+    pub allocations: usize,
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "allocated={}B reserved={}B allocations={} blocks={}",
+            self.allocated_bytes,
+            self.reserved_bytes,
+            self.allocations,
+            self.blocks.len()
+        )?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            write!(
+                f,
+                "\n - block {i}: {}/{}B in {} allocations",
+                block.used, block.size, block.allocations
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// GPU memory usage from [`Graphics::memory_stats`]
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStats {
+    /// bytes used by live allocations
+    pub allocated_bytes: u64,
+    /// bytes of device memory reserved in blocks
+    pub reserved_bytes: u64,
+    /// usage and budget of every memory heap as of the last FPS interval,
+    /// empty without `VK_EXT_memory_budget`
+    pub heaps: Vec<HeapBudget>,
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "allocated={}B reserved={}B",
+            self.allocated_bytes, self.reserved_bytes
+        )?;
+        for (i, heap) in self.heaps.iter().enumerate() {
+            write!(
+                f,
+                "\n - heap {i}{}: {}/{}B of {}B",
+                if heap.device_local { " (device)" } else { "" },
+                heap.usage,
+                heap.budget,
+                heap.size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Vulkan objects owned by the host application,
+/// see [`Graphics::from_external`]
+pub struct ExternalContext {
+    pub entry: Entry,
+    pub instance: Instance,
+    pub gpu: vk::PhysicalDevice,
+    pub device: Device,
+    /// a queue family with graphics, compute and transfer support,
+    /// and present support if rendering into a window
+    pub queue_family: u32,
+    /// moved into [`Graphics`], all allocations go through it
+    pub allocator: Allocator,
+}
+
+/// everything [`Graphics::init_with`] needs from the device setup
+struct Context {
+    entry: Entry,
+    instance: Instance,
+    debug_utils: Option<DebugUtils>,
+    surface: Option<Surface>,
+    gpu: vk::PhysicalDevice,
+    queue_families: QueueFamilies,
+    features: Features,
+    device: Device,
+    allocator: Allocator,
+    external: bool,
 }
 
 pub struct Graphics {
     // entry: Entry,
     instance: Instance,
-    debug_utils: DebugUtils,
-    surface: Surface,
+    /// `None` for an [`ExternalContext`]
+    debug_utils: Option<DebugUtils>,
+    /// `None` when headless
+    surface: Option<Surface>,
 
-    // gpu: vk::PhysicalDevice,
+    gpu: vk::PhysicalDevice,
     queue_families: QueueFamilies,
+    features: Features,
 
     device: Device,
+    /// the device and instance belong to the host application
+    external: bool,
+    /// driver bug workarounds in effect
+    overrides: Overrides,
     queues: Queues,
-    swapchain: Swapchain,
+    /// `None` when headless
+    swapchain: Option<Swapchain>,
+    /// the swapchain extent, or the fixed headless extent
+    output_extent: vk::Extent2D,
 
     allocator: ManuallyDrop<Allocator>,
 
+    command_pools: CommandPools,
     frames: FramesInFlight,
+    timestamps: TimestampPool,
 
+    /// staging uploads, on the transfer queue
     immediate: Immediate,
+    /// readbacks of images owned by the graphics queue
+    graphics_immediate: Immediate,
+    /// owns the voxel buffer when the raymarch runs on the async
+    /// compute queue, `None` if compute shares the graphics family
+    compute_immediate: Option<Immediate>,
+    /// signaled once the last graphics submit is done with the render
+    /// target, the next raymarch on the compute queue waits for it
+    render_target_release: Option<vk::Semaphore>,
 
     descriptor_pool: DescriptorPool,
 
     descriptor_set_layout: DescriptorSetLayout,
     descriptor_set: DescriptorSet,
+    /// runtime descriptor writes, flushed before recording each frame
+    descriptor_writes: DescriptorWriteBatcher,
+    /// `update_descriptor_sets` calls of the last frame
+    descriptor_write_calls: u32,
+    pipeline_cache: PipelineCache,
     pipeline_layout: PipelineLayout<PushConst>,
     pipeline: ComputePipeline<PushConst>,
+    /// the side of the square raymarch workgroups, see [`workgroup_size`]
+    workgroup_size: u32,
 
     render_target: Image,
     render_target_delete_queue: DeleteQueue,
+    /// the part of the render target that is drawn and shown,
+    /// the output extent times `render_scale`
+    render_extent: vk::Extent2D,
+    render_scale: f32,
+    /// the image the last recorded frame was blitted from, it is left in
+    /// `TRANSFER_SRC_OPTIMAL` while the other drawn images stay `GENERAL`
+    blit_source: vk::Image,
+
+    fxaa: Fxaa,
+    /// FXAA is skipped if this is `None`
+    fxaa_settings: Option<FxaaPushConst>,
+    /// embedder passes in the order they were added
+    custom_passes: Vec<CustomPass>,
+    /// only created for HDR swapchains, see [`Self::output_encoding`]
+    hdr_encode: Option<HdrEncode>,
+    tonemap: Tonemap,
+    /// skipped while it is the identity or the output is HDR
+    tonemap_settings: TonemapSettings,
+    /// FPS and frame time readout, updated every FPS interval
+    hud: Hud,
+    hud_enabled: bool,
+    /// egui meshes over the final output, see [`Self::set_ui`]
+    ui: Ui,
+    /// rays stop and miss after travelling this far in voxels
+    max_ray_distance: f32,
+    /// primary rays skip voxels closer than this
+    min_ray_distance: f32,
+    grid: GridPushConst,
 
     voxels: VoxelStructure,
 
+    /// points at the tile of [`Graphics::render_still`],
+    /// allocated on first use
+    still_descriptor_set: Option<DescriptorSet>,
+
+    /// reused by every [`Graphics::screenshot`], grown when needed
+    screenshot_buffer: Option<TypedBuffer<[u16; 4]>>,
+    screenshot_delete_queue: DeleteQueue,
+
     global_delete_queue: DeleteQueue,
     boot_time: Instant,
     fps: Counter,
+    /// reported together with the FPS
+    metrics: Metrics,
+    acquire_time: Arc<Timer>,
+    fence_wait_time: Arc<Timer>,
+    upload_bytes: Arc<Total>,
+    /// `LUMINARY_METRICS_CSV`, the JSON summary is written on drop
+    metrics_sink: Option<MetricsSink>,
+    static_recording: bool,
+    state: RenderState,
+    /// the window has no area, so nothing is rendered or presented
+    minimized: bool,
+    /// the driver lost the device, nothing but [`Graphics::rebuild`] works
+    device_lost: bool,
+    /// queried every FPS interval, empty without `VK_EXT_memory_budget`
+    heap_budgets: Vec<HeapBudget>,
+    /// warn when a heap uses more than this fraction of its budget
+    memory_warning_fraction: f32,
+    /// a heap was over the fraction at the last query
+    memory_warned: bool,
+}
+
+/// 8-bit RGBA pixels of a [`Graphics::screenshot`], rows are top to bottom
+#[derive(Debug, Clone)]
+pub struct ScreenshotData {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// robust values from a small neighborhood of render target pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Probe {
+    /// per channel median, ignores isolated outliers
+    pub median: [f32; 4],
+    /// the most common exact color
+    pub majority: [f32; 4],
+    /// fraction of the samples that are exactly [`Self::majority`],
+    /// low at edges between two surfaces
+    pub confidence: f32,
+}
+
+impl Probe {
+    /// pixels per side of the probed neighborhood
+    pub const SIZE: u32 = 5;
+
+    pub fn from_samples(samples: &[[f32; 4]]) -> Self {
+        if samples.is_empty() {
+            return Self {
+                median: [0.0; 4],
+                majority: [0.0; 4],
+                confidence: 0.0,
+            };
+        }
+
+        let median = [0, 1, 2, 3].map(|channel| {
+            let mut values: Vec<f32> =
+                samples.iter().map(|sample| sample[channel]).collect();
+            values.sort_unstable_by(f32::total_cmp);
+            values[values.len() / 2]
+        });
+
+        let (majority, count) = samples
+            .iter()
+            .map(|sample| {
+                let count = samples
+                    .iter()
+                    .filter(|other| {
+                        bytemuck::bytes_of(*other) == bytemuck::bytes_of(sample)
+                    })
+                    .count();
+                (*sample, count)
+            })
+            .max_by_key(|(_, count)| *count)
+            .unwrap();
+
+        Self {
+            median,
+            majority,
+            confidence: count as f32 / samples.len() as f32,
+        }
+    }
+}
+
+/// image layout transitions with the tightest masks for how they are used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageTransition {
+    /// render targets, the previous frame might still be reading from it
+    UndefinedToGeneral,
+    /// compute shader writes made visible to later compute shader reads
+    GeneralToGeneral,
+    /// compute shader writes made visible to blits and copies
+    GeneralToTransferSrc,
+    /// compute shader writes made visible to rendering over the image
+    GeneralToAttachment,
+    /// rendered colors made visible to compute shaders, the barrier
+    /// before a blit chains with this one, so it covers blits too
+    AttachmentToGeneral,
+    /// swapchain images after the acquire semaphore wait
+    UndefinedToTransferDst,
+    /// swapchain images, before the render semaphore signal
+    TransferDstToPresent,
+}
+
+impl ImageTransition {
+    fn barrier(self) -> vk::ImageMemoryBarrier2<'static> {
+        use vk::{
+            AccessFlags2 as A, ImageLayout as L, PipelineStageFlags2 as S,
+        };
+
+        let (src_stage, src_access, dst_stage, dst_access, from, to) =
+            match self {
+                Self::UndefinedToGeneral => (
+                    S::ALL_TRANSFER | S::COMPUTE_SHADER,
+                    A::NONE,
+                    S::COMPUTE_SHADER,
+                    A::SHADER_STORAGE_READ | A::SHADER_STORAGE_WRITE,
+                    L::UNDEFINED,
+                    L::GENERAL,
+                ),
+                // ping-pong passes may read or write either image next
+                Self::GeneralToGeneral => (
+                    S::COMPUTE_SHADER,
+                    A::SHADER_STORAGE_WRITE,
+                    S::COMPUTE_SHADER,
+                    A::SHADER_STORAGE_READ | A::SHADER_STORAGE_WRITE,
+                    L::GENERAL,
+                    L::GENERAL,
+                ),
+                Self::GeneralToTransferSrc => (
+                    S::COMPUTE_SHADER,
+                    A::SHADER_STORAGE_WRITE,
+                    S::ALL_TRANSFER,
+                    A::TRANSFER_READ,
+                    L::GENERAL,
+                    L::TRANSFER_SRC_OPTIMAL,
+                ),
+                // blending reads the image too
+                Self::GeneralToAttachment => (
+                    S::COMPUTE_SHADER,
+                    A::SHADER_STORAGE_WRITE,
+                    S::COLOR_ATTACHMENT_OUTPUT,
+                    A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE,
+                    L::GENERAL,
+                    L::GENERAL,
+                ),
+                Self::AttachmentToGeneral => (
+                    S::COLOR_ATTACHMENT_OUTPUT,
+                    A::COLOR_ATTACHMENT_WRITE,
+                    S::COMPUTE_SHADER,
+                    A::SHADER_STORAGE_READ | A::SHADER_STORAGE_WRITE,
+                    L::GENERAL,
+                    L::GENERAL,
+                ),
+                // the acquire semaphore is waited on
+                // at the color attachment output stage
+                Self::UndefinedToTransferDst => (
+                    S::COLOR_ATTACHMENT_OUTPUT,
+                    A::NONE,
+                    S::ALL_TRANSFER,
+                    A::TRANSFER_WRITE,
+                    L::UNDEFINED,
+                    L::TRANSFER_DST_OPTIMAL,
+                ),
+                // presentation engine accesses are made visible
+                // by the render semaphore, no access mask is needed
+                Self::TransferDstToPresent => (
+                    S::ALL_TRANSFER,
+                    A::TRANSFER_WRITE,
+                    S::COLOR_ATTACHMENT_OUTPUT,
+                    A::NONE,
+                    L::TRANSFER_DST_OPTIMAL,
+                    L::PRESENT_SRC_KHR,
+                ),
+            };
+
+        vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(src_stage)
+            .src_access_mask(src_access)
+            .dst_stage_mask(dst_stage)
+            .dst_access_mask(dst_access)
+            .old_layout(from)
+            .new_layout(to)
+    }
+}
+
+/// what [`Graphics::draw`] renders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderState {
+    /// only a dark splash screen is rendered
+    Initializing {
+        /// the splash can be held for longer with `LUMINARY_SPLASH_SECS`
+        until: Instant,
+    },
+    Ready,
 }
 
 impl Graphics {
@@ -105,69 +627,272 @@ impl Graphics {
             height: size.height,
         };
 
-        let mut global_delete_queue = DeleteQueue::new();
-        let mut init_delete_queue = DeleteQueue::new();
+        Self::init(Some(window), extent)
+    }
+
+    /// render into the render target only, without a window,
+    /// [`Self::screenshot`] reads the result
+    pub fn new_headless(extent: vk::Extent2D) -> Result<Self> {
+        Self::init(None, extent)
+    }
 
+    /// create the instance and device like [`Self::new_headless`] would,
+    /// for hosts that want to share them with luminary through
+    /// [`Self::from_external`], the host destroys them after the
+    /// [`Graphics`] using them is dropped
+    pub fn create_context() -> Result<ExternalContext> {
         let entry = ash::Entry::linked();
+        let instance = Self::create_instance(None, &entry, false)?;
+        let (gpu, queue_families, features) =
+            pick_gpu(&entry, &instance, None)?;
+        let (device, _) =
+            Self::create_device(&instance, gpu, &queue_families, features)?;
+        let allocator = Self::create_allocator(&instance, gpu, &device)?;
+
+        Ok(ExternalContext {
+            entry,
+            instance,
+            gpu,
+            device,
+            queue_family: queue_families.graphics,
+            allocator,
+        })
+    }
 
-        let instance = Self::create_instance(&window, &entry)?;
+    /// render with Vulkan objects created by the host application
+    ///
+    /// the device has to be created with the extensions and features
+    /// luminary requires, and the instance with the surface extensions
+    /// if `window` is given, the queue family of `context` is used for
+    /// everything, so the host has to synchronize its own use of it,
+    /// the device and instance are not destroyed on drop
+    pub fn from_external(
+        context: ExternalContext,
+        window: Option<Arc<Window>>,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let ExternalContext {
+            entry,
+            instance,
+            gpu,
+            device,
+            queue_family,
+            allocator,
+        } = context;
+
+        let surface = window
+            .clone()
+            .map(|window| Surface::new(window, &entry, &instance))
+            .transpose()?;
+
+        let queue_families = QueueFamilies {
+            present: queue_family,
+            graphics: queue_family,
+            transfer: queue_family,
+            compute: queue_family,
+            families: Box::new([]),
+        };
 
-        let debug_utils = DebugUtils::new(&entry, &instance)?;
+        Self::init_with(
+            Context {
+                entry,
+                instance,
+                debug_utils: None,
+                surface,
+                gpu,
+                queue_families,
+                features: Features::default(),
+                device,
+                allocator,
+                external: true,
+            },
+            window,
+            extent,
+        )
+    }
 
-        let surface = Surface::new(window.clone(), &entry, &instance)?;
+    fn init(window: Option<Arc<Window>>, extent: vk::Extent2D) -> Result<Self> {
+        let entry = ash::Entry::linked();
 
-        let (gpu, queue_families) = pick_gpu(&entry, &instance, surface.inner)?;
+        let hdr = window.is_some() && Self::hdr_requested();
+        let instance = Self::create_instance(window.as_deref(), &entry, hdr)?;
 
-        let device = Self::create_device(&instance, gpu, &queue_families)?;
+        // object names and validation messages are optional
+        let debug_utils = DebugUtils::new(&entry, &instance)
+            .inspect_err(|err| {
+                tracing::warn!("failed to create the debug messenger: {err}")
+            })
+            .ok();
 
-        let queues = Queues::new(&device, &queue_families);
+        let surface = window
+            .clone()
+            .map(|window| Surface::new(window, &entry, &instance))
+            .transpose()?;
 
-        let swapchain = Swapchain::new(
+        let (gpu, queue_families, features) = pick_gpu(
             &entry,
             &instance,
-            &device,
-            gpu,
-            &queue_families,
-            surface.inner,
-            extent,
-            window,
+            surface.as_ref().map(|surface| surface.inner),
         )?;
 
-        let mut allocator =
-            ManuallyDrop::new(Self::create_allocator(&instance, gpu, &device)?);
+        let (device, features) =
+            Self::create_device(&instance, gpu, &queue_families, features)?;
+
+        let allocator = Self::create_allocator(&instance, gpu, &device)?;
+
+        Self::init_with(
+            Context {
+                entry,
+                instance,
+                debug_utils,
+                surface,
+                gpu,
+                queue_families,
+                features,
+                device,
+                allocator,
+                external: false,
+            },
+            window,
+            extent,
+        )
+    }
+
+    fn init_with(
+        context: Context,
+        window: Option<Arc<Window>>,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let Context {
+            entry,
+            instance,
+            debug_utils,
+            surface,
+            gpu,
+            mut queue_families,
+            features,
+            device,
+            allocator,
+            external,
+        } = context;
+
+        let mut global_delete_queue = DeleteQueue::new();
+        let mut init_delete_queue = DeleteQueue::new();
+
+        let props = unsafe { instance.get_physical_device_properties(gpu) };
+        let overrides = Overrides::for_device(&props);
+        if overrides.disable_async_compute {
+            queue_families.disable_async_compute();
+        }
+
+        let queues = Queues::new(&device, &queue_families);
+        // external instances need VK_EXT_swapchain_colorspace themselves
+        let hdr = Self::hdr_requested();
+
+        let swapchain = surface
+            .as_ref()
+            .zip(window)
+            .map(|(surface, window)| {
+                Swapchain::new(
+                    &entry,
+                    &instance,
+                    &device,
+                    gpu,
+                    &queue_families,
+                    surface.inner,
+                    extent,
+                    window,
+                    if overrides.force_fifo {
+                        PresentModePreference::Fifo
+                    } else {
+                        PresentModePreference::default()
+                    },
+                    hdr,
+                )
+            })
+            .transpose()?;
+
+        let mut allocator = ManuallyDrop::new(allocator);
+
+        let mut command_pools = CommandPools::new();
 
         let frames = FramesInFlight::new(
             &device,
             &queue_families,
+            &mut command_pools,
             &mut global_delete_queue,
+            features.timeline_semaphore,
         )?;
 
-        let immediate =
-            Immediate::new(&device, queues.transfer, queue_families.transfer)?;
+        let timestamps = TimestampPool::new(
+            &instance,
+            &device,
+            gpu,
+            queue_families.graphics,
+            frames.len(),
+            4,
+            &mut global_delete_queue,
+        )?;
 
-        let mut render_target_delete_queue = DeleteQueue::new();
-        let render_target = Self::create_render_image(
+        let immediate = Immediate::new(
             &device,
-            &mut allocator,
-            &mut render_target_delete_queue,
-            extent,
+            &mut command_pools,
+            &mut global_delete_queue,
+            queues.transfer,
+            queue_families.transfer,
         )?;
+        let graphics_immediate = Immediate::new(
+            &device,
+            &mut command_pools,
+            &mut global_delete_queue,
+            queues.graphics,
+            queue_families.graphics,
+        )?;
+        let compute_immediate = queue_families
+            .async_compute()
+            .then(|| {
+                Immediate::new(
+                    &device,
+                    &mut command_pools,
+                    &mut global_delete_queue,
+                    queues.compute,
+                    queue_families.compute,
+                )
+            })
+            .transpose()?;
+        tracing::info!(
+            "raymarch runs on the {} queue",
+            if compute_immediate.is_some() {
+                "async compute"
+            } else {
+                "graphics"
+            }
+        );
+
+        let mut render_target_delete_queue = DeleteQueue::new();
+        let render_target = Self::render_image(extent)
+            .name("render_target")
+            .concurrent([queue_families.graphics, queue_families.compute])
+            .build(&device, &mut allocator, &mut render_target_delete_queue)?;
 
         let voxels = VoxelStructure::new(
             &instance,
             &device,
             &immediate,
+            compute_immediate.as_ref().unwrap_or(&graphics_immediate),
             &mut allocator,
             &mut global_delete_queue,
+            BuildConfig::CURRENT.node_layout(),
         )?;
 
         let descriptor_pool = DescriptorPool::builder()
-            .add_type_allocation(vk::DescriptorType::STORAGE_IMAGE, 10)
+            .add_type_allocation(vk::DescriptorType::STORAGE_IMAGE, 16)
             .add_type_allocation(vk::DescriptorType::STORAGE_BUFFER, 10)
             .max_sets(10)
             .build(&device, &mut global_delete_queue)?;
 
         let descriptor_set_layout = DescriptorSetLayout::builder()
+            .name("raymarch_set_layout")
             .add_binding(
                 0,
                 vk::DescriptorType::STORAGE_IMAGE,
@@ -194,223 +919,2070 @@ impl Graphics {
             &descriptor_set_layout,
         )?;
 
-        let shader =
-            Shader::new(&device, &mut init_delete_queue, Shader::DEFAULT_COMP)?;
-        let pipeline = ComputePipeline::new(
+        let pipeline_cache = PipelineCache::new(
+            &instance,
+            &device,
+            gpu,
+            &mut global_delete_queue,
+        )?;
+
+        let var = env::var("LUMINARY_WORKGROUP_SIZE").ok();
+        let max_invocations = props.limits.max_compute_work_group_invocations;
+        let workgroup_size = workgroup_size(var.as_deref(), max_invocations)
+            .unwrap_or_else(|| {
+                tracing::warn!(
+                    "LUMINARY_WORKGROUP_SIZE has to be a power of two with \
+                     a square of at most {max_invocations}"
+                );
+                16
+            });
+        let shader = Self::raymarch_shader(&device, &mut init_delete_queue)?;
+        let pipeline = ComputePipeline::with_specialization(
             &device,
             &mut global_delete_queue,
+            &pipeline_cache,
             pipeline_layout,
             &shader,
+            &Specialization::new()
+                .constant(0, workgroup_size)
+                .constant(1, workgroup_size),
+        )?;
+
+        let fxaa = Fxaa::new(
+            &device,
+            &mut allocator,
+            &descriptor_pool,
+            &mut global_delete_queue,
+            &pipeline_cache,
+            &render_target,
+        )?;
+
+        let mut hud = Hud::new(
+            &device,
+            &mut allocator,
+            &descriptor_pool,
+            &mut global_delete_queue,
+            &pipeline_cache,
+            &render_target,
+            &fxaa.target,
+        )?;
+        hud.set_text("--- FPS");
+
+        let tonemap = Tonemap::new(
+            &device,
+            &mut allocator,
+            &descriptor_pool,
+            &mut global_delete_queue,
+            &pipeline_cache,
+            &render_target,
+            &fxaa.target,
+        )?;
+
+        let hdr_encode = swapchain
+            .as_ref()
+            .filter(|swapchain| swapchain.encoding() != OutputEncoding::Sdr)
+            .map(|_| {
+                HdrEncode::new(
+                    &device,
+                    &mut allocator,
+                    &descriptor_pool,
+                    &mut global_delete_queue,
+                    &pipeline_cache,
+                    &render_target,
+                    &fxaa.target,
+                )
+            })
+            .transpose()?;
+
+        let ui = Ui::new(
+            &device,
+            &mut allocator,
+            &mut global_delete_queue,
+            &pipeline_cache,
+            &DescriptorLimits::query(&instance, gpu),
+            frames.len(),
         )?;
 
         init_delete_queue.flush(&device, &mut allocator);
 
-        Ok(Self {
+        let metrics = Metrics::new();
+
+        let mut graphics = Self {
             // entry,
             instance,
             debug_utils,
             surface,
 
-            // gpu,
+            gpu,
             queue_families,
+            features,
 
             device,
+            external,
+            overrides,
             queues,
             swapchain,
+            output_extent: extent,
 
             allocator,
 
+            command_pools,
             frames,
+            timestamps,
 
             immediate,
+            graphics_immediate,
+            compute_immediate,
+            render_target_release: None,
 
             descriptor_pool,
 
             descriptor_set_layout,
             descriptor_set,
+            descriptor_writes: DescriptorWriteBatcher::new(),
+            descriptor_write_calls: 0,
+            pipeline_cache,
             pipeline_layout,
             pipeline,
+            workgroup_size,
 
             render_target,
             render_target_delete_queue,
+            render_extent: extent,
+            render_scale: 1.0,
+            blit_source: vk::Image::null(),
+
+            fxaa,
+            hdr_encode,
+            tonemap,
+            tonemap_settings: TonemapSettings::default(),
+            fxaa_settings: None,
+            custom_passes: Vec::new(),
+            hud,
+            hud_enabled: false,
+            ui,
+            max_ray_distance: f32::INFINITY,
+            min_ray_distance: 0.0,
+            grid: GridPushConst::default(),
 
             voxels,
 
+            still_descriptor_set: None,
+
+            screenshot_buffer: None,
+            screenshot_delete_queue: DeleteQueue::new(),
+
             global_delete_queue,
             boot_time: Instant::now(),
-            fps: Counter::new(Duration::from_secs(3)),
-        })
+            fps: Counter::new(Self::fps_interval()),
+            acquire_time: metrics.timer("acquire"),
+            fence_wait_time: metrics.timer("fence_wait"),
+            upload_bytes: metrics.counter("upload_bytes"),
+            metrics,
+            metrics_sink: env::var_os("LUMINARY_METRICS_CSV")
+                .map(MetricsSink::new),
+            static_recording: false,
+            minimized: false,
+            device_lost: false,
+            heap_budgets: Vec::new(),
+            memory_warning_fraction: 0.9,
+            memory_warned: false,
+            state: RenderState::Initializing {
+                until: Instant::now() + Self::splash_duration(),
+            },
+        };
+        graphics.name_objects()?;
+        graphics.update_memory_budget();
+
+        Ok(graphics)
     }
 
-    pub fn draw(&mut self, push_const: PushConst) -> Result<()> {
-        let (frame, frame_i) = self.frames.next();
-        frame.wait(&self.device, &mut self.allocator)?;
+    /// the FPS log interval, `LUMINARY_FPS_INTERVAL_SECS` or 3 seconds
+    fn fps_interval() -> Duration {
+        env::var("LUMINARY_FPS_INTERVAL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse::<f32>().ok())
+            .map(Duration::from_secs_f32)
+            .unwrap_or(Duration::from_secs(3))
+    }
 
-        let swapchain_image = self.swapchain.acquire(
-            &self.device,
-            frame.swapchain_sema,
-            &self.queue_families,
-        )?;
+    fn log_fps(&mut self, stats: FrameStats) {
+        let FrameStats {
+            per_second,
+            average,
+            min,
+            p50,
+            p95,
+            p99,
+            max,
+        } = stats;
+        let gpu_averages = self.timestamps.take_averages();
+        let gpu = gpu_averages
+            .iter()
+            .map(|(label, ms)| format!(" {label}={ms:.3}ms"))
+            .collect::<String>();
+        let metrics = self.metrics.report();
+        tracing::info!(
+            "fps avg={per_second:.1} frame={average:.2}ms min={min:.2}ms \
+             p50={p50:.2}ms p95={p95:.2}ms p99={p99:.2}ms max={max:.2}ms{gpu} \
+             {metrics}"
+        );
 
-        frame.begin(&self.device)?;
+        self.hud
+            .set_text(&format!("{per_second:.0} FPS {average:.2} MS"));
+        self.update_memory_budget();
+
+        if let Some(sink) = self.metrics_sink.as_mut() {
+            sink.record(MetricsRow {
+                fps: per_second,
+                frame_ms: average,
+                p95_ms: p95,
+                gpu_ms: gpu_averages.iter().map(|(_, ms)| ms).sum(),
+                memory_bytes: self
+                    .allocator
+                    .generate_report()
+                    .total_allocated_bytes,
+            });
+        }
+    }
 
-        // make the main render target usable for rendering
-        Self::transition_image(
-            &self.device,
-            frame.main_cbuf,
-            self.render_target.image,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::GENERAL,
-        );
+    /// how often the average FPS is logged
+    pub fn set_fps_interval(&mut self, interval: Duration) {
+        self.fps.set_interval(interval);
+    }
 
-        // render everything
-        let cbuf = frame.main_cbuf;
-        self.draw_scene(cbuf, push_const);
+    /// reported and reset every FPS interval,
+    /// other subsystems can record into it too
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
 
-        let frame = self.frames.get(frame_i);
+    /// the average FPS of the current interval so far
+    pub fn fps_estimate(&self) -> Option<f32> {
+        self.fps.current_estimate()
+    }
 
-        // blit the render target image to swapchain
-        Self::transition_image(
-            &self.device,
-            frame.main_cbuf,
-            self.render_target.image,
-            vk::ImageLayout::GENERAL,
-            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-        );
-        Self::transition_image(
-            &self.device,
-            frame.main_cbuf,
-            swapchain_image.image,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        );
-        Self::blit_image(
-            &self.device,
-            frame.main_cbuf,
-            self.render_target.image,
-            self.render_target.extent,
-            swapchain_image.image,
-            self.swapchain.extent,
+    /// log the average FPS now and start a new interval
+    pub fn report_fps(&mut self) -> Option<FrameStats> {
+        let stats = self.fps.report();
+        if let Some(stats) = stats {
+            self.log_fps(stats);
+        }
+        stats
+    }
+
+    fn splash_duration() -> Duration {
+        env::var("LUMINARY_SPLASH_SECS")
+            .ok()
+            .and_then(|secs| secs.parse::<f32>().ok())
+            .map(Duration::from_secs_f32)
+            .unwrap_or_default()
+    }
+
+    /// all components are initialized once the state is [`RenderState::Ready`]
+    pub fn state(&mut self) -> RenderState {
+        if let RenderState::Initializing { until } = self.state
+            && Instant::now() >= until
+        {
+            tracing::debug!("initialization complete");
+            self.state = RenderState::Ready;
+        }
+
+        self.state
+    }
+
+    /// `false` if nothing was drawn this time, because the window is
+    /// minimized or the swapchain had no image ready
+    ///
+    /// check [`Self::is_device_lost`] if this fails
+    pub fn draw(&mut self, push_const: PushConst) -> Result<bool> {
+        let drawn = self.draw_frame(push_const);
+        if let Err(err) = drawn.as_ref()
+            && is_device_lost(err)
+        {
+            tracing::error!("the device was lost: {err}");
+            self.device_lost = true;
+        }
+        drawn
+    }
+
+    /// the driver reset or removed the device, [`Self::rebuild`]
+    /// has to be called before anything else
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost
+    }
+
+    /// recreate every device level object after [`Self::is_device_lost`],
+    /// the instance, surface and window are kept, the settings and voxels
+    /// carry over, but custom passes have to be added again
+    pub fn rebuild(&mut self) -> Result<()> {
+        if self.external {
+            bail!("the lost device belongs to the host application");
+        }
+        tracing::info!("rebuilding the renderer");
+        self.device_lost = true;
+
+        let present_mode = self.present_mode();
+        let swapchain_timeout = self.swapchain.as_ref().map(Swapchain::timeout);
+        // the surface can't get a new swapchain while the old one exists,
+        // destroying it is one of the few calls that work on a lost device
+        if let Some(mut swapchain) = self.swapchain.take() {
+            swapchain.destroy();
+        }
+        let surface = self.surface.take();
+        let window = surface.as_ref().map(|surface| surface.window().clone());
+
+        let entry = ash::Entry::linked();
+        let (gpu, queue_families, features) = pick_gpu(
+            &entry,
+            &self.instance,
+            surface.as_ref().map(|surface| surface.inner),
+        )?;
+        let (device, features) = Self::create_device(
+            &self.instance,
+            gpu,
+            &queue_families,
+            features,
+        )?;
+        let allocator = Self::create_allocator(&self.instance, gpu, &device)?;
+
+        let mut graphics = Self::init_with(
+            Context {
+                entry,
+                instance: self.instance.clone(),
+                debug_utils: self.debug_utils.take(),
+                surface,
+                gpu,
+                queue_families,
+                features,
+                device,
+                allocator,
+                external: false,
+            },
+            window,
+            self.output_extent,
+        )?;
+
+        graphics.fxaa_settings = self.fxaa_settings;
+        graphics.tonemap_settings = self.tonemap_settings;
+        graphics.hud_enabled = self.hud_enabled;
+        graphics.max_ray_distance = self.max_ray_distance;
+        graphics.min_ray_distance = self.min_ray_distance;
+        graphics.grid = self.grid;
+        graphics.static_recording = self.static_recording;
+        graphics.memory_warning_fraction = self.memory_warning_fraction;
+        if let Some(present_mode) = present_mode {
+            graphics.set_present_mode(present_mode);
+        }
+        if let Some(timeout) = swapchain_timeout {
+            graphics.set_swapchain_timeout(timeout);
+        }
+        graphics.set_render_scale(self.render_scale)?;
+
+        graphics.voxels.take_voxels(&mut self.voxels);
+        let uploaded = graphics.voxels.sync(
+            &graphics.device,
+            &graphics.immediate,
+            graphics
+                .compute_immediate
+                .as_ref()
+                .unwrap_or(&graphics.graphics_immediate),
+            &mut graphics.allocator,
         );
+        match uploaded {
+            Ok(bytes) => graphics.upload_bytes.add(bytes),
+            Err(err) => {
+                tracing::warn!("failed to restore the voxels: {err}");
+                graphics.load_scene(Scene::Demo)?;
+            }
+        }
+
+        // the allocator of the lost device is never dropped,
+        // see the `Drop` of `Graphics`
+        *self = graphics;
+
+        Ok(())
+    }
+
+    fn draw_frame(&mut self, mut push_const: PushConst) -> Result<bool> {
+        push_const.max_ray_distance = self.max_ray_distance;
+        push_const.min_ray_distance = self.min_ray_distance;
+        push_const.grid = self.grid;
+
+        let Some(swapchain) = self.swapchain.as_ref() else {
+            self.draw_headless(push_const)?;
+            return Ok(true);
+        };
+
+        let window_ext = swapchain.window_extent();
+        let was_minimized = self.minimized;
+        self.minimized = window_ext.width == 0 || window_ext.height == 0;
+        if self.minimized {
+            return Ok(false);
+        }
+        // not every platform sends a resize when the window is restored
+        if was_minimized {
+            self.resize()?;
+        }
+
+        if let Some(stats) = self.fps.next() {
+            self.log_fps(stats);
+        }
+
+        let ready = self.state() == RenderState::Ready;
+
+        let (frame, frame_i) = self.frames.next();
+        self.fence_wait_time
+            .time(|| frame.wait(&self.device, &mut self.allocator))?;
+        self.timestamps.read(&self.device, frame_i)?;
+
+        // the sets have to be up to date before they are bound
+        self.descriptor_writes.flush(&self.device);
+        self.descriptor_write_calls = self.descriptor_writes.take_calls();
+        self.ui
+            .prepare(&self.device, &mut self.allocator, frame_i)?;
+
+        let swapchain = self.swapchain.as_mut().unwrap();
+        let Some(swapchain_image) = self.acquire_time.time(|| {
+            swapchain.acquire(
+                &mut frame.delete_queue,
+                frame.swapchain_sema,
+                &self.queue_families,
+            )
+        })?
+        else {
+            return Ok(false);
+        };
+
+        // the raymarch is re-recorded every frame, even with static recording
+        let compute_cbuf = if ready {
+            frame.begin_compute(&self.device)?
+        } else {
+            None
+        };
+        if let Some(compute_cbuf) = compute_cbuf {
+            self.record_scene(compute_cbuf, None, push_const);
+            self.frames.get(frame_i).submit_compute(
+                &self.device,
+                self.queues.compute,
+                self.render_target_release.take(),
+            )?;
+        }
+
+        let frame = self.frames.get(frame_i);
+        let cbuf = frame.main_cbuf;
+        let record = if !ready {
+            frame.begin(&self.device)?;
+            self.record_splash(cbuf, swapchain_image.image);
+            self.frames.get(frame_i).end(&self.device)?;
+            false
+        } else if self.static_recording {
+            let key = self.static_key(swapchain_image.image, &push_const);
+            let frame = self.frames.get(frame_i);
+            frame.begin_static(&self.device, key)?
+        } else {
+            frame.begin(&self.device)?;
+            true
+        };
+
+        if record {
+            self.record_frame(
+                cbuf,
+                frame_i,
+                Some(swapchain_image.image),
+                push_const,
+                compute_cbuf.is_none(),
+            );
+            self.frames.get(frame_i).end(&self.device)?;
+        }
+
+        let frame = self.frames.get(frame_i);
+        if let Some(release) =
+            frame.submit(&self.device, self.queues.graphics)?
+        {
+            self.render_target_release = Some(release);
+        }
+
+        self.swapchain.as_mut().unwrap().present(
+            swapchain_image,
+            self.queues.present,
+            frame.render_sema,
+        )?;
+
+        Ok(true)
+    }
+
+    /// render one frame into the render target, nothing is presented
+    fn draw_headless(&mut self, push_const: PushConst) -> Result<()> {
+        if let Some(stats) = self.fps.next() {
+            self.log_fps(stats);
+        }
+
+        let (frame, frame_i) = self.frames.next();
+        self.fence_wait_time
+            .time(|| frame.wait(&self.device, &mut self.allocator))?;
+        self.timestamps.read(&self.device, frame_i)?;
+
+        self.descriptor_writes.flush(&self.device);
+        self.descriptor_write_calls = self.descriptor_writes.take_calls();
+        self.ui
+            .prepare(&self.device, &mut self.allocator, frame_i)?;
+
+        let compute_cbuf = frame.begin_compute(&self.device)?;
+        if let Some(compute_cbuf) = compute_cbuf {
+            self.record_scene(compute_cbuf, None, push_const);
+            self.frames.get(frame_i).submit_compute(
+                &self.device,
+                self.queues.compute,
+                self.render_target_release.take(),
+            )?;
+        }
+
+        let frame = self.frames.get(frame_i);
+        let cbuf = frame.main_cbuf;
+        frame.begin(&self.device)?;
+        self.record_frame(
+            cbuf,
+            frame_i,
+            None,
+            push_const,
+            compute_cbuf.is_none(),
+        );
+
+        let frame = self.frames.get(frame_i);
+        frame.end(&self.device)?;
+        if let Some(release) =
+            frame.submit_without_present(&self.device, self.queues.graphics)?
+        {
+            self.render_target_release = Some(release);
+        }
+
+        Ok(())
+    }
+
+    /// reuse the recorded frame commands while nothing changes,
+    /// useful for static scenes with a static camera
+    pub fn set_static_recording(&mut self, enabled: bool) {
+        self.static_recording = enabled;
+    }
+
+    fn static_key(
+        &self,
+        swapchain_image: vk::Image,
+        push_const: &PushConst,
+    ) -> u64 {
+        let mut hasher = FxHasher::default();
+        swapchain_image.hash(&mut hasher);
+        self.output_extent.hash(&mut hasher);
+        self.render_target.image.hash(&mut hasher);
+        self.render_target.extent.hash(&mut hasher);
+        self.render_extent.hash(&mut hasher);
+        bytemuck::bytes_of(push_const).hash(&mut hasher);
+        self.fxaa_settings
+            .map(|fxaa| bytemuck::bytes_of(&fxaa).to_vec())
+            .hash(&mut hasher);
+        self.hud_enabled.then(|| self.hud.key()).hash(&mut hasher);
+        self.ui.key().hash(&mut hasher);
+        self.tonemap_settings.operator.hash(&mut hasher);
+        self.tonemap_settings.exposure.to_bits().hash(&mut hasher);
+        for pass in &self.custom_passes {
+            pass.push().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// clear the swapchain image to a dark color, uses nothing
+    /// that could still be initializing
+    fn record_splash(
+        &self,
+        cbuf: vk::CommandBuffer,
+        swapchain_image: vk::Image,
+    ) {
+        Self::transition_image(
+            &self.device,
+            cbuf,
+            swapchain_image,
+            ImageTransition::UndefinedToTransferDst,
+        );
+
+        let clear_color = vk::ClearColorValue {
+            float32: [0.02, 0.02, 0.025, 1.0],
+        };
+        unsafe {
+            self.device.cmd_clear_color_image(
+                cbuf,
+                swapchain_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &clear_color,
+                &[Self::subresource_range(vk::ImageAspectFlags::COLOR)],
+            );
+        }
+
+        Self::transition_image(
+            &self.device,
+            cbuf,
+            swapchain_image,
+            ImageTransition::TransferDstToPresent,
+        );
+    }
+
+    /// without a `swapchain_image` the output is only
+    /// left in the transfer source layout for readbacks,
+    /// `scene` is false if the raymarch ran on the compute queue
+    fn record_frame(
+        &mut self,
+        cbuf: vk::CommandBuffer,
+        frame_i: usize,
+        swapchain_image: Option<vk::Image>,
+        push_const: PushConst,
+        scene: bool,
+    ) {
+        self.timestamps.reset(&self.device, cbuf, frame_i);
+        if scene {
+            self.record_scene(cbuf, Some(frame_i), push_const);
+        }
+        self.record_passes(cbuf, Some(frame_i));
+        let mut output = self
+            .fxaa
+            .output(&self.render_target, self.fxaa_settings.is_none());
+
+        let encoding = self.output_encoding();
+        if let Some(hdr_encode) = self.hdr_encode.as_ref()
+            && encoding != OutputEncoding::Sdr
+        {
+            // the encode expects the same values as the scene
+            self.record_ui(cbuf, frame_i, output);
+            self.timestamps.begin(&self.device, cbuf, frame_i, "hdr");
+            Self::transition_image(
+                &self.device,
+                cbuf,
+                output.image,
+                ImageTransition::GeneralToGeneral,
+            );
+            Self::transition_image(
+                &self.device,
+                cbuf,
+                hdr_encode.target.image,
+                ImageTransition::UndefinedToGeneral,
+            );
+            hdr_encode.dispatch(
+                &self.device,
+                cbuf,
+                encoding,
+                self.fxaa_settings.is_some(),
+            );
+            self.timestamps.end(&self.device, cbuf, frame_i);
+            output = &hdr_encode.target;
+        } else if self.tonemap_active() {
+            self.timestamps
+                .begin(&self.device, cbuf, frame_i, "tonemap");
+            Self::transition_image(
+                &self.device,
+                cbuf,
+                output.image,
+                ImageTransition::GeneralToGeneral,
+            );
+            Self::transition_image(
+                &self.device,
+                cbuf,
+                self.tonemap.target.image,
+                ImageTransition::UndefinedToGeneral,
+            );
+            self.tonemap.dispatch(
+                &self.device,
+                cbuf,
+                self.tonemap_settings,
+                self.fxaa_settings.is_some(),
+            );
+            self.timestamps.end(&self.device, cbuf, frame_i);
+            output = &self.tonemap.target;
+            self.record_ui(cbuf, frame_i, output);
+        } else {
+            self.record_ui(cbuf, frame_i, output);
+        }
+
+        // blit the render target image to swapchain
+        self.timestamps.begin(&self.device, cbuf, frame_i, "blit");
+        let label =
+            CmdLabel::begin(cbuf, "blit to swapchain", [0.4, 0.9, 0.4, 1.0]);
+        Self::transition_image(
+            &self.device,
+            cbuf,
+            output.image,
+            ImageTransition::GeneralToTransferSrc,
+        );
+        self.blit_source = output.image;
+        let Some(swapchain_image) = swapchain_image else {
+            CmdLabel::insert(cbuf, "no swapchain image", [1.0, 0.4, 0.2, 1.0]);
+            label.end();
+            self.timestamps.end(&self.device, cbuf, frame_i);
+            return;
+        };
+        Self::transition_image(
+            &self.device,
+            cbuf,
+            swapchain_image,
+            ImageTransition::UndefinedToTransferDst,
+        );
+        Self::blit_image(
+            &self.device,
+            cbuf,
+            output.image,
+            self.render_extent,
+            swapchain_image,
+            self.output_extent,
+        );
+        label.end();
+        self.timestamps.end(&self.device, cbuf, frame_i);
+
+        // make the swapchain image usable for presenting
+        let _label =
+            CmdLabel::begin(cbuf, "present transition", [0.5, 0.5, 0.5, 1.0]);
+        Self::transition_image(
+            &self.device,
+            cbuf,
+            swapchain_image,
+            ImageTransition::TransferDstToPresent,
+        );
+    }
+
+    /// the UI over `output`, after the tonemap so it keeps its colors,
+    /// the image stays in the GENERAL layout
+    fn record_ui(
+        &self,
+        cbuf: vk::CommandBuffer,
+        frame_i: usize,
+        output: &Image,
+    ) {
+        let _label = CmdLabel::begin(cbuf, "ui", [0.9, 0.6, 0.2, 1.0]);
+        self.ui.record(
+            &self.device,
+            cbuf,
+            frame_i,
+            output,
+            self.render_extent,
+            self.output_extent,
+        );
+    }
+
+    /// the raymarch into the render target, timestamps are only
+    /// written for a luminary owned `frame_i` on the graphics queue
+    fn record_scene(
+        &mut self,
+        cbuf: vk::CommandBuffer,
+        frame_i: Option<usize>,
+        push_const: PushConst,
+    ) {
+        // make the main render target usable for rendering
+        let label = CmdLabel::begin(
+            cbuf,
+            "transition render target",
+            [0.5, 0.5, 0.5, 1.0],
+        );
+        Self::transition_image(
+            &self.device,
+            cbuf,
+            self.render_target.image,
+            ImageTransition::UndefinedToGeneral,
+        );
+        label.end();
+
+        // render everything
+        self.begin_region(cbuf, frame_i, "compute");
+        let label =
+            CmdLabel::begin(cbuf, "raymarch dispatch", [0.2, 0.6, 1.0, 1.0]);
+        self.draw_scene(cbuf, push_const);
+        label.end();
+        self.end_region(cbuf, frame_i);
+    }
+
+    /// custom passes, FXAA and the HUD after [`Self::record_scene`],
+    /// the output image is left in the GENERAL layout, timestamps are
+    /// only written for a luminary owned `frame_i`
+    fn record_passes(
+        &mut self,
+        cbuf: vk::CommandBuffer,
+        frame_i: Option<usize>,
+    ) {
+        self.record_custom_passes(cbuf, InsertionPoint::PreFxaa, false);
+
+        if let Some(settings) = self.fxaa_settings {
+            self.begin_region(cbuf, frame_i, "fxaa");
+            // wait for the scene to be written
+            Self::transition_image(
+                &self.device,
+                cbuf,
+                self.render_target.image,
+                ImageTransition::GeneralToGeneral,
+            );
+            Self::transition_image(
+                &self.device,
+                cbuf,
+                self.fxaa.target.image,
+                ImageTransition::UndefinedToGeneral,
+            );
+            self.fxaa.dispatch(&self.device, cbuf, settings, false);
+            self.end_region(cbuf, frame_i);
+        }
+        self.record_custom_passes(
+            cbuf,
+            InsertionPoint::PreHud,
+            self.fxaa_settings.is_some(),
+        );
+
+        if self.hud_enabled {
+            self.begin_region(cbuf, frame_i, "hud");
+            let output = self
+                .fxaa
+                .output(&self.render_target, self.fxaa_settings.is_none());
+            Self::transition_image(
+                &self.device,
+                cbuf,
+                output.image,
+                ImageTransition::GeneralToGeneral,
+            );
+            self.hud
+                .dispatch(&self.device, cbuf, self.fxaa_settings.is_some());
+            self.end_region(cbuf, frame_i);
+        }
+    }
+
+    fn begin_region(
+        &mut self,
+        cbuf: vk::CommandBuffer,
+        frame_i: Option<usize>,
+        label: &'static str,
+    ) {
+        if let Some(frame_i) = frame_i {
+            self.timestamps.begin(&self.device, cbuf, frame_i, label);
+        }
+    }
+
+    fn end_region(&mut self, cbuf: vk::CommandBuffer, frame_i: Option<usize>) {
+        if let Some(frame_i) = frame_i {
+            self.timestamps.end(&self.device, cbuf, frame_i);
+        }
+    }
+
+    /// run the custom passes at `insertion`, each one waits for
+    /// the previous writes to the color image
+    fn record_custom_passes(
+        &self,
+        cbuf: vk::CommandBuffer,
+        insertion: InsertionPoint,
+        fxaa: bool,
+    ) {
+        let color = self.fxaa.output(&self.render_target, !fxaa);
+        for pass in self
+            .custom_passes
+            .iter()
+            .filter(|pass| pass.insertion == insertion)
+        {
+            Self::transition_image(
+                &self.device,
+                cbuf,
+                color.image,
+                ImageTransition::GeneralToGeneral,
+            );
+            for image in pass.private_images() {
+                Self::transition_image(
+                    &self.device,
+                    cbuf,
+                    image.image,
+                    ImageTransition::UndefinedToGeneral,
+                );
+            }
+            pass.dispatch(&self.device, cbuf, color.extent, fxaa);
+        }
+    }
+
+    /// how long to wait for a swapchain image before failing,
+    /// slow remote displays might need more than the default 1 second,
+    /// `Duration::MAX` waits forever
+    pub fn set_swapchain_timeout(&mut self, timeout: Duration) {
+        if let Some(swapchain) = self.swapchain.as_mut() {
+            swapchain.set_timeout(timeout);
+        }
+    }
+
+    /// switch the present mode with the next swapchain recreation,
+    /// does nothing when headless or while FIFO is forced by a workaround
+    pub fn set_present_mode(&mut self, preference: PresentModePreference) {
+        if self.overrides.force_fifo
+            && preference != PresentModePreference::Fifo
+        {
+            tracing::warn!("FIFO is forced, ignoring {preference:?}");
+            return;
+        }
+        if let Some(swapchain) = self.swapchain.as_mut() {
+            swapchain.set_present_mode_preference(preference);
+        }
+    }
+
+    /// `None` when headless
+    pub fn present_mode(&self) -> Option<PresentModePreference> {
+        self.swapchain
+            .as_ref()
+            .map(Swapchain::present_mode_preference)
+    }
+
+    /// format of the presented images, `None` when headless
+    pub fn output_format(&self) -> Option<vk::Format> {
+        self.swapchain.as_ref().map(Swapchain::format)
+    }
+
+    /// color space of the presented images, `None` when headless
+    pub fn output_color_space(&self) -> Option<vk::ColorSpaceKHR> {
+        self.swapchain.as_ref().map(Swapchain::color_space)
+    }
+
+    /// what the output is converted to before presenting,
+    /// HDR is opt-in with `LUMINARY_HDR=1`, always SDR when headless
+    pub fn output_encoding(&self) -> OutputEncoding {
+        self.swapchain
+            .as_ref()
+            .map_or(OutputEncoding::Sdr, Swapchain::encoding)
+    }
+
+    /// largest side of a [`Self::render_still`] tile, below the 4096
+    /// every Vulkan device supports, which also bounds the VRAM it uses
+    pub const STILL_TILE: u32 = 2048;
+
+    /// bounds of [`Self::set_render_scale`]
+    pub const MIN_RENDER_SCALE: f32 = 0.25;
+    pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+    /// build a compute pass supplied by the embedder and run it every frame
+    /// at its insertion point, after the passes added before it
+    pub fn add_custom_pass(
+        &mut self,
+        desc: &CustomPassDesc,
+    ) -> Result<CustomPassId> {
+        let pass = CustomPass::new(
+            &self.device,
+            &mut self.allocator,
+            &mut self.global_delete_queue,
+            &self.pipeline_cache,
+            desc,
+            &self.render_target,
+            &self.fxaa.target,
+        )?;
+        tracing::info!("added custom pass '{}'", pass.name);
+        self.custom_passes.push(pass);
+        Ok(CustomPassId(self.custom_passes.len() - 1))
+    }
+
+    /// the push constants of the next recorded frames,
+    /// at most [`CustomPass::PUSH_SIZE`] bytes
+    pub fn set_custom_pass_push_constants(
+        &mut self,
+        id: CustomPassId,
+        bytes: &[u8],
+    ) -> Result<()> {
+        self.custom_passes[id.0].set_push(bytes)
+    }
+
+    /// show the FPS and frame time in the top left corner,
+    /// for when the log is not visible
+    pub fn set_hud(&mut self, enabled: bool) {
+        self.hud_enabled = enabled;
+    }
+
+    pub fn hud(&self) -> bool {
+        self.hud_enabled
+    }
+
+    /// draw tessellated egui output over the final image,
+    /// `textures` are applied first, the meshes stay until the next call
+    pub fn set_ui(
+        &mut self,
+        primitives: &[egui::ClippedPrimitive],
+        textures: &egui::TexturesDelta,
+        pixels_per_point: f32,
+    ) -> Result<()> {
+        self.ui.set(
+            &self.device,
+            &mut self.allocator,
+            &self.immediate,
+            &self.graphics_immediate,
+            self.frames.deferred_delete_queue(),
+            primitives,
+            textures,
+            pixels_per_point,
+        )
+    }
+
+    /// only applies to SDR output, HDR output keeps the full range
+    pub fn set_tonemap(&mut self, settings: TonemapSettings) {
+        self.tonemap_settings = settings;
+    }
+
+    pub fn tonemap(&self) -> TonemapSettings {
+        self.tonemap_settings
+    }
+
+    fn tonemap_active(&self) -> bool {
+        !self.tonemap_settings.is_identity()
+            && self.output_encoding() == OutputEncoding::Sdr
+    }
+
+    /// run an edge blurring anti-aliasing pass after the scene,
+    /// `None` disables it
+    pub fn set_fxaa(&mut self, settings: Option<FxaaPushConst>) {
+        self.fxaa_settings = settings;
+    }
+
+    /// rays that travel further than `distance` voxels show the sky,
+    /// whichever of this and the step budget runs out first stops a ray,
+    /// `None` removes the limit
+    pub fn set_max_ray_distance(&mut self, distance: Option<f32>) {
+        self.max_ray_distance = distance.unwrap_or(f32::INFINITY);
+    }
+
+    /// camera rays skip voxels closer than `distance` voxels,
+    /// to see into a model, shadow rays are not clipped
+    pub fn set_min_ray_distance(&mut self, distance: f32) {
+        self.min_ray_distance = distance.max(0.0);
+    }
+
+    pub fn min_ray_distance(&self) -> f32 {
+        self.min_ray_distance
+    }
+
+    /// draw a ground grid with lines every `spacing` world units,
+    /// `width` wide and `color` (alpha is the opacity),
+    /// fading out towards `fade_distance`, a spacing of 0 hides it
+    pub fn set_grid(
+        &mut self,
+        spacing: f32,
+        width: f32,
+        fade_distance: f32,
+        color: Vec4,
+    ) {
+        self.grid = GridPushConst::new(spacing, width, fade_distance, color);
+    }
+
+    pub fn grid(&self) -> GridPushConst {
+        self.grid
+    }
+
+    /// draw at `scale` times the output resolution, clamped to
+    /// [`Self::MIN_RENDER_SCALE`]..=[`Self::MAX_RENDER_SCALE`],
+    /// the render target is only reallocated if it doesn't fit
+    pub fn set_render_scale(&mut self, scale: f32) -> Result<()> {
+        self.render_scale =
+            scale.clamp(Self::MIN_RENDER_SCALE, Self::MAX_RENDER_SCALE);
+        self.fit_render_target()
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// the resolution the scene is drawn at
+    pub fn render_extent(&self) -> vk::Extent2D {
+        self.render_extent
+    }
+
+    /// optional device features that are enabled
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
+    /// settings forced by driver workarounds or `LUMINARY_FORCE_*`
+    pub fn driver_overrides(&self) -> Overrides {
+        self.overrides
+    }
+
+    pub fn voxel_stats(&self) -> VoxelStats {
+        self.voxels.stats()
+    }
+
+    /// the time split of the last scene load
+    pub fn voxel_load_timings(&self) -> LoadTimings {
+        self.voxels.load_timings()
+    }
+
+    /// greedy mesh the voxels and save them to `path`,
+    /// as glTF for a `.gltf` extension and as OBJ otherwise
+    pub fn export_mesh(&self, path: &str) -> Result<()> {
+        let mesh = self.voxels.to_mesh();
+        if path.ends_with(".gltf") {
+            mesh.save_gltf(path)
+        } else {
+            mesh.save_obj(path)
+        }
+    }
+
+    /// the material of the voxel at `at`, from the CPU copy
+    pub fn material_of(&self, at: U64Vec3) -> MaterialInfo {
+        self.voxels.material_of(at)
+    }
+
+    /// voxel events since the last call, oldest first
+    pub fn take_world_events(&mut self) -> Vec<WorldEvent> {
+        self.voxels.take_events()
+    }
+
+    /// set every `(position, color)` in one edit and upload it,
+    /// the octree is compacted once it fills half of the voxel buffer
+    ///
+    /// returns the inverse changes, editing them restores the voxels
+    pub fn edit_voxels(
+        &mut self,
+        changes: impl IntoIterator<Item = (U64Vec3, u32)>,
+    ) -> Result<Vec<(U64Vec3, u32)>> {
+        let mut edit = self.voxels.begin_edit();
+        for (at, col) in changes {
+            edit.insert_voxel(at, col);
+        }
+        let inverse = edit.commit()?;
+
+        if self.voxels.stats().nodes > self.voxels.capacity() / 2 {
+            self.voxels.compact();
+        }
+        let bytes = self.voxels.sync(
+            &self.device,
+            &self.immediate,
+            self.compute_immediate
+                .as_ref()
+                .unwrap_or(&self.graphics_immediate),
+            &mut self.allocator,
+        )?;
+        self.upload_bytes.add(bytes);
+        Ok(inverse)
+    }
+
+    /// Vulkan validation errors since the process started,
+    /// always 0 without the validation layer
+    pub fn validation_errors(&self) -> u64 {
+        debug::validation_errors()
+    }
+
+    /// replace the voxels with a built-in scene and upload it,
+    /// the scene has to fit in the voxel buffer
+    pub fn load_scene(&mut self, scene: Scene) -> Result<()> {
+        let bytes = self.voxels.load(
+            scene,
+            &self.device,
+            &self.immediate,
+            self.compute_immediate
+                .as_ref()
+                .unwrap_or(&self.graphics_immediate),
+            &mut self.allocator,
+        )?;
+        self.upload_bytes.add(bytes);
+        Ok(())
+    }
+
+    /// write the voxels and `history` to the world save at `path`
+    pub fn save_world(&self, path: &str, history: &History) -> Result<()> {
+        let voxels = self.voxels.to_grid();
+        let mut save = WorldSave::default();
+        save.set_section(
+            WorldSave::VOXELS,
+            voxels.iter().flat_map(|col| col.to_le_bytes()).collect(),
+        );
+        save.set_section(
+            WorldSave::HISTORY,
+            history.to_bytes(|chunk| self.voxels.chunk_hash(chunk)),
+        );
+        save.write(path)
+    }
+
+    /// replace the voxels with the ones of the world save at `path`,
+    /// returns its history, which keeps at most `limit` operations
+    pub fn load_world(&mut self, path: &str, limit: usize) -> Result<History> {
+        let save = WorldSave::read(path)?;
+        let Some(voxels) = save.section(WorldSave::VOXELS) else {
+            bail!("{path} has no voxels");
+        };
+        let grid = voxels
+            .chunks_exact(4)
+            .map(|col| u32::from_le_bytes(col.try_into().unwrap()))
+            .collect::<Vec<_>>();
+
+        let bytes = self.voxels.load_grid(
+            &grid,
+            &self.device,
+            &self.immediate,
+            self.compute_immediate
+                .as_ref()
+                .unwrap_or(&self.graphics_immediate),
+            &mut self.allocator,
+        )?;
+        self.upload_bytes.add(bytes);
+
+        match save.section(WorldSave::HISTORY) {
+            Some(history) => History::from_bytes(history, limit, |chunk| {
+                self.voxels.chunk_hash(chunk)
+            }),
+            None => Ok(History::new(limit)),
+        }
+    }
+
+    /// replace the voxels with a MagicaVoxel `.vox` model or a square
+    /// `.r16` heightmap of little endian `u16` samples, the import is
+    /// refused if it could use too much memory, see [`ImportLimits`]
+    pub fn import_voxels(
+        &mut self,
+        path: &str,
+        limits: &ImportLimits,
+        cancel: &AtomicBool,
+    ) -> Result<()> {
+        let file = BufReader::new(fs::File::open(path)?);
+        let imported = match Path::new(path).extension() {
+            Some(ext) if ext == "vox" => {
+                import::import_vox(file, limits, cancel)?
+            }
+            Some(ext) if ext == "r16" => {
+                let samples = fs::metadata(path)?.len() / 2;
+                let side = samples.isqrt();
+                if side * side != samples {
+                    bail!("{path} is not a square heightmap");
+                }
+                import::import_heightmap(
+                    file,
+                    side as u32,
+                    side as u32,
+                    1,
+                    limits,
+                    cancel,
+                )?
+            }
+            _ => bail!("{path} is neither a .vox model nor a .r16 heightmap"),
+        };
+        tracing::info!(
+            "imported {path} with at most {}B, {} voxels did not fit",
+            imported.peak_bytes,
+            imported.clipped
+        );
+
+        let bytes = self.voxels.load_octree(
+            path,
+            imported.octree,
+            &self.device,
+            &self.immediate,
+            self.compute_immediate
+                .as_ref()
+                .unwrap_or(&self.graphics_immediate),
+            &mut self.allocator,
+        )?;
+        self.upload_bytes.add(bytes);
+        Ok(())
+    }
+
+    /// the first solid voxel the ray hits, from the CPU copy
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        dir: Vec3,
+        max_distance: f32,
+    ) -> Option<U64Vec3> {
+        self.voxels.raycast(origin, dir, max_distance)
+    }
+
+    /// command pools and command buffers allocated from them
+    pub fn command_buffer_counts(&self) -> (usize, usize) {
+        (
+            self.command_pools.pool_count(),
+            self.command_pools.buffer_count(),
+        )
+    }
+
+    /// usage of every memory block the allocator has reserved
+    pub fn memory_report(&self) -> MemoryReport {
+        let report = self.allocator.generate_report();
+
+        let blocks = report
+            .blocks
+            .iter()
+            .map(|block| BlockUsage {
+                size: block.size,
+                used: report.allocations[block.allocations.clone()]
+                    .iter()
+                    .map(|allocation| allocation.size)
+                    .sum(),
+                allocations: block.allocations.len(),
+            })
+            .collect();
+
+        MemoryReport {
+            allocated_bytes: report.total_allocated_bytes,
+            reserved_bytes: report.total_reserved_bytes,
+            allocations: report.allocations.len(),
+            blocks,
+        }
+    }
+
+    /// allocator totals and the heap budgets,
+    /// cheap enough to poll every frame, the budgets are cached
+    pub fn memory_stats(&self) -> MemoryStats {
+        let report = self.allocator.generate_report();
+        MemoryStats {
+            allocated_bytes: report.total_allocated_bytes,
+            reserved_bytes: report.total_reserved_bytes,
+            heaps: self.heap_budgets.clone(),
+        }
+    }
+
+    /// warn once a memory heap uses more than `fraction` of its budget,
+    /// 0.9 by default
+    pub fn set_memory_warning_fraction(&mut self, fraction: f32) {
+        self.memory_warning_fraction = fraction.clamp(0.0, 1.0);
+    }
+
+    /// query the heap budgets again and warn if one is close to running
+    /// out, allocations past the budget fail with a cryptic allocator error
+    fn update_memory_budget(&mut self) {
+        if !self.features.memory_budget {
+            return;
+        }
+
+        self.heap_budgets = memory_budget(&self.instance, self.gpu);
+        let over =
+            heap_over_budget(&self.heap_budgets, self.memory_warning_fraction);
+        if let Some((i, heap)) = over
+            && !self.memory_warned
+        {
+            tracing::warn!(
+                "memory heap {i} uses {}B of its {}B budget",
+                heap.usage,
+                heap.budget
+            );
+        }
+        self.memory_warned = over.is_some();
+    }
+
+    /// `update_descriptor_sets` calls made for the last frame
+    pub fn descriptor_write_calls(&self) -> u32 {
+        self.descriptor_write_calls
+    }
+
+    /// GPU time in milliseconds per labeled region of the last finished frame
+    pub fn timings(&self) -> &[(&'static str, f32)] {
+        self.timestamps.times()
+    }
+
+    /// record a frame into a command buffer of the host application
+    /// and blit it over all of `target`, for hosts that manage their
+    /// own frames, see [`Self::from_external`]
+    ///
+    /// `target` needs `TRANSFER_DST` usage and a format that supports
+    /// linear blits, it has to be in `TRANSFER_DST_OPTIMAL` when the
+    /// commands execute and is left in it, the caller synchronizes
+    /// everything else that uses it
+    ///
+    /// the internal images are reused by every recording, so the previous
+    /// submission has to finish before the next one starts executing,
+    /// no FPS stats or GPU timestamps are collected
+    pub fn record_into(
+        &mut self,
+        cbuf: vk::CommandBuffer,
+        target: vk::Image,
+        target_extent: vk::Extent2D,
+        mut push_const: PushConst,
+    ) {
+        push_const.max_ray_distance = self.max_ray_distance;
+        push_const.min_ray_distance = self.min_ray_distance;
+        push_const.grid = self.grid;
+
+        // the sets have to be up to date before they are bound
+        self.descriptor_writes.flush(&self.device);
+        self.descriptor_write_calls = self.descriptor_writes.take_calls();
+
+        self.record_scene(cbuf, None, push_const);
+        self.record_passes(cbuf, None);
+
+        let output = self
+            .fxaa
+            .output(&self.render_target, self.fxaa_settings.is_none());
+        Self::transition_image(
+            &self.device,
+            cbuf,
+            output.image,
+            ImageTransition::GeneralToTransferSrc,
+        );
+        self.blit_source = output.image;
+        Self::blit_image(
+            &self.device,
+            cbuf,
+            output.image,
+            self.render_extent,
+            target,
+            target_extent,
+        );
+    }
+
+    pub fn draw_scene(
+        &mut self,
+        cbuf: vk::CommandBuffer,
+        mut push_const: PushConst,
+    ) {
+        profiling::zone!("draw_scene");
+        // let t = self.boot_time.elapsed().as_secs_f32().sin() * 0.5 + 0.5;
+        // tracing::trace!("t={t}");
+        // let clear_color = vk::ClearColorValue {
+        //     float32: [t, t, t, 1.0],
+        // };
+
+        // unsafe {
+        //     self.device.cmd_clear_color_image(
+        //         cbuf,
+        //         self.render_target.image,
+        //         vk::ImageLayout::GENERAL,
+        //         &clear_color,
+        //         &[Self::subresource_range(vk::ImageAspectFlags::COLOR)],
+        //     );
+        // }
+
+        push_const.render_extent = PushConst::pack_extent(self.render_extent);
+
+        self.pipeline.bind(&self.device, cbuf);
+
+        self.pipeline.bind_sets(
+            &self.device,
+            cbuf,
+            0,
+            &[self.descriptor_set.set],
+            &[],
+        );
+
+        self.pipeline
+            .write_push_constant(&self.device, cbuf, &push_const);
+
+        // one more pixel per side for the repeated edge
+        self.pipeline.dispatch(
+            &self.device,
+            cbuf,
+            UVec3::new(
+                (self.render_extent.width + 1).div_ceil(self.workgroup_size),
+                (self.render_extent.height + 1).div_ceil(self.workgroup_size),
+                1,
+            ),
+        );
+    }
+
+    /// read back the exact color the shader wrote under the window pixel `x, y`,
+    /// the render target is read as it was left by the last [`Self::draw`]
+    pub fn read_pixel(&mut self, x: u32, y: u32) -> Result<[f32; 4]> {
+        let at = self.window_to_target(x, y)?;
+        let pixels = self.read_target_region(
+            at,
+            vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+        )?;
+        Ok(pixels[0])
+    }
+
+    /// read a [`Probe::SIZE`]² neighborhood around the window pixel `x, y`,
+    /// which is less noisy than [`Self::read_pixel`] at voxel edges
+    pub fn probe(&mut self, x: u32, y: u32) -> Result<Probe> {
+        let at = self.window_to_target(x, y)?;
+        let target_ext = self.render_extent;
+
+        // the neighborhood is moved inside the render target at its edges
+        let size = Probe::SIZE.min(target_ext.width).min(target_ext.height);
+        let half = (size / 2) as i32;
+        let offset = vk::Offset2D {
+            x: (at.x - half).clamp(0, (target_ext.width - size) as i32),
+            y: (at.y - half).clamp(0, (target_ext.height - size) as i32),
+        };
+
+        let samples = self.read_target_region(
+            offset,
+            vk::Extent2D {
+                width: size,
+                height: size,
+            },
+        )?;
+        Ok(Probe::from_samples(&samples))
+    }
+
+    /// the drawn part of the render target is stretched over the window,
+    /// so the window coordinates are scaled to match
+    fn window_to_target(&self, x: u32, y: u32) -> Result<vk::Offset2D> {
+        let window_ext = self.output_extent;
+        if x >= window_ext.width || y >= window_ext.height {
+            bail!(
+                "pixel {x},{y} is outside of the window {}x{}",
+                window_ext.width,
+                window_ext.height
+            );
+        }
+
+        let target_ext = self.render_extent;
+        Ok(vk::Offset2D {
+            x: (x as u64 * target_ext.width as u64 / window_ext.width as u64)
+                as i32,
+            y: (y as u64 * target_ext.height as u64 / window_ext.height as u64)
+                as i32,
+        })
+    }
+
+    /// rows of render target pixels, top to bottom
+    fn read_target_region(
+        &mut self,
+        offset: vk::Offset2D,
+        extent: vk::Extent2D,
+    ) -> Result<Vec<[f32; 4]>> {
+        // the last frame has to finish writing the render target
+        unsafe { self.device.device_wait_idle()? };
+
+        let texels = extent.width as usize * extent.height as usize;
+        let readback_buffer = Owned::new(
+            &self.device,
+            &mut self.allocator,
+            |allocator, delete_queue| {
+                Buffer::builder()
+                    .len::<[u16; 4]>(texels)
+                    .usage(vk::BufferUsageFlags::TRANSFER_DST)
+                    .location(MemoryLocation::GpuToCpu)
+                    .build_typed::<[u16; 4]>(
+                        &self.device,
+                        allocator,
+                        delete_queue,
+                    )
+            },
+        )?;
+
+        self.graphics_immediate.submit(&self.device, |cbuf| {
+            let region = vk::BufferImageCopy::default()
+                .buffer_offset(0)
+                .buffer_row_length(extent.width)
+                .buffer_image_height(extent.height)
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(0)
+                        .layer_count(1)
+                        .base_array_layer(0),
+                )
+                .image_offset(vk::Offset3D {
+                    x: offset.x,
+                    y: offset.y,
+                    z: 0,
+                })
+                .image_extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                });
+
+            unsafe {
+                self.device.cmd_copy_image_to_buffer(
+                    cbuf,
+                    self.render_target.image,
+                    layout_after_frame(
+                        self.render_target.image,
+                        self.blit_source,
+                    ),
+                    readback_buffer.buffer,
+                    slice::from_ref(&region),
+                );
+            }
+
+            Ok(())
+        })?;
+
+        Ok(readback_buffer
+            .as_typed_slice()
+            .expect("readback buffer should be CPU mappable")
+            .iter()
+            .map(|texel| texel.map(f16_to_f32))
+            .collect())
+    }
+
+    /// read back the last presented frame at the window resolution
+    pub fn screenshot(&mut self) -> Result<ScreenshotData> {
+        // the last frame has to finish writing the image
+        let (frame, _) = self.frames.previous();
+        frame.wait_submitted(&self.device)?;
+
+        let image = if self.tonemap_active() {
+            &self.tonemap.target
+        } else if self.fxaa_settings.is_some() {
+            self.fxaa.output(&self.render_target, false)
+        } else {
+            &self.render_target
+        };
+        let target_ext = self.render_extent;
+        let len = target_ext.width as usize * target_ext.height as usize;
+
+        if self
+            .screenshot_buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.len() < len)
+        {
+            self.screenshot_buffer = None;
+            self.screenshot_delete_queue
+                .flush(&self.device, &mut self.allocator);
+            self.screenshot_buffer = Some(
+                Buffer::builder()
+                    .len::<[u16; 4]>(len)
+                    .usage(vk::BufferUsageFlags::TRANSFER_DST)
+                    .location(MemoryLocation::GpuToCpu)
+                    .build_typed(
+                        &self.device,
+                        &mut self.allocator,
+                        &mut self.screenshot_delete_queue,
+                    )?,
+            );
+        }
+        let buffer = self.screenshot_buffer.as_ref().unwrap();
+
+        self.graphics_immediate.submit(&self.device, |cbuf| {
+            let region = vk::BufferImageCopy::default()
+                .buffer_offset(0)
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(0)
+                        .layer_count(1)
+                        .base_array_layer(0),
+                )
+                .image_extent(vk::Extent3D {
+                    width: target_ext.width,
+                    height: target_ext.height,
+                    depth: 1,
+                });
+
+            unsafe {
+                self.device.cmd_copy_image_to_buffer(
+                    cbuf,
+                    image.image,
+                    layout_after_frame(image.image, self.blit_source),
+                    buffer.buffer,
+                    slice::from_ref(&region),
+                );
+            }
 
-        // make the swapchain image usable for presenting
-        Self::transition_image(
-            &self.device,
-            frame.main_cbuf,
-            swapchain_image.image,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            vk::ImageLayout::PRESENT_SRC_KHR,
-        );
+            Ok(())
+        })?;
 
-        frame.end(&self.device)?;
-        frame.submit(&self.device, self.queues.graphics)?;
+        let texels = &buffer
+            .as_typed_slice()
+            .expect("screenshot buffer should be CPU mappable")[..len];
 
-        self.swapchain.present(
-            swapchain_image,
-            self.queues.present,
-            frame.render_sema,
-        )?;
+        // the drawn part of the render target is stretched over the window,
+        // so it is resampled the same way the blit does
+        let window_ext = self.output_extent;
+        let mut pixels = Vec::with_capacity(
+            window_ext.width as usize * window_ext.height as usize * 4,
+        );
+        for y in 0..window_ext.height as u64 {
+            let src_y = (y * 2 + 1) * target_ext.height as u64
+                / (window_ext.height as u64 * 2);
+            for x in 0..window_ext.width as u64 {
+                let src_x = (x * 2 + 1) * target_ext.width as u64
+                    / (window_ext.width as u64 * 2);
+                let texel =
+                    texels[(src_x + src_y * target_ext.width as u64) as usize];
+                pixels.extend(texel.map(|channel| {
+                    (f16_to_f32(channel).clamp(0.0, 1.0) * 255.0).round() as u8
+                }));
+            }
+        }
 
-        Ok(())
+        Ok(ScreenshotData {
+            width: window_ext.width,
+            height: window_ext.height,
+            pixels,
+        })
     }
 
-    pub fn draw_scene(
+    /// render one frame at `extent` independent of the window and the
+    /// live render target, in tiles of at most [`Self::STILL_TILE`]
+    /// pixels per side that are stitched together on the CPU,
+    /// blocks until done so nothing else is drawn meanwhile
+    pub fn render_still(
         &mut self,
-        cbuf: vk::CommandBuffer,
         push_const: PushConst,
-    ) {
-        if let Some(per_second) = self.fps.next() {
-            tracing::info!("average FPS={per_second:.1}");
+        extent: vk::Extent2D,
+    ) -> Result<ScreenshotData> {
+        self.render_still_tiled(push_const, extent, Self::STILL_TILE)
+    }
+
+    /// [`Self::render_still`] with tiles of at most `tile` pixels per side
+    pub fn render_still_tiled(
+        &mut self,
+        mut push_const: PushConst,
+        extent: vk::Extent2D,
+        tile: u32,
+    ) -> Result<ScreenshotData> {
+        if extent.width == 0 || extent.height == 0 || tile == 0 {
+            bail!("still extent and tile size have to be non-zero");
         }
 
-        // let t = self.boot_time.elapsed().as_secs_f32().sin() * 0.5 + 0.5;
-        // tracing::trace!("t={t}");
-        // let clear_color = vk::ClearColorValue {
-        //     float32: [t, t, t, 1.0],
-        // };
+        push_const.max_ray_distance = self.max_ray_distance;
+        push_const.min_ray_distance = self.min_ray_distance;
+        push_const.grid = self.grid;
+        let inverse_projection_view = push_const.projection_view;
 
-        // unsafe {
-        //     self.device.cmd_clear_color_image(
-        //         cbuf,
-        //         self.render_target.image,
-        //         vk::ImageLayout::GENERAL,
-        //         &clear_color,
-        //         &[Self::subresource_range(vk::ImageAspectFlags::COLOR)],
-        //     );
-        // }
+        unsafe { self.device.device_wait_idle()? };
 
-        self.pipeline.bind(&self.device, cbuf);
+        if self.still_descriptor_set.is_none() {
+            self.still_descriptor_set = Some(
+                self.descriptor_pool
+                    .alloc(&self.device, &self.descriptor_set_layout)?,
+            );
+        }
 
-        self.pipeline.bind_sets(
-            &self.device,
-            cbuf,
-            &[self.descriptor_set.set],
-            &[],
-        );
+        let tiles = still_tiles(extent, tile);
+        let mut pixels =
+            vec![0u8; extent.width as usize * extent.height as usize * 4];
 
-        self.pipeline
-            .write_push_constant(&self.device, cbuf, &push_const);
+        for (i, &(offset, tile_ext)) in tiles.iter().enumerate() {
+            push_const.projection_view =
+                inverse_projection_view * tile_ndc(extent, offset, tile_ext);
 
-        self.pipeline.dispatch(
+            let texels = self.render_still_tile(push_const, tile_ext)?;
+            stitch_tile(&mut pixels, extent, offset, tile_ext, &texels);
+
+            tracing::info!("still tile {}/{} done", i + 1, tiles.len());
+        }
+
+        Ok(ScreenshotData {
+            width: extent.width,
+            height: extent.height,
+            pixels,
+        })
+    }
+
+    /// render `push_const` into a temporary image of `extent`
+    /// and read it back, every temporary is destroyed before returning
+    fn render_still_tile(
+        &mut self,
+        push_const: PushConst,
+        extent: vk::Extent2D,
+    ) -> Result<Vec<[u16; 4]>> {
+        let mut delete_queue = DeleteQueue::new();
+        let result =
+            self.render_still_tile_with(push_const, extent, &mut delete_queue);
+        // the immediate submit already waited for the GPU
+        delete_queue.flush(&self.device, &mut self.allocator);
+        result
+    }
+
+    fn render_still_tile_with(
+        &mut self,
+        push_const: PushConst,
+        extent: vk::Extent2D,
+        delete_queue: &mut DeleteQueue,
+    ) -> Result<Vec<[u16; 4]>> {
+        let target = Self::create_render_image(
             &self.device,
-            cbuf,
-            UVec3::new(
-                self.render_target.extent.width.div_ceil(16),
-                self.render_target.extent.height.div_ceil(16),
+            &mut self.allocator,
+            delete_queue,
+            extent,
+        )?;
+        let buffer = Buffer::builder()
+            .len::<[u16; 4]>(extent.width as usize * extent.height as usize)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .location(MemoryLocation::GpuToCpu)
+            .build_typed::<[u16; 4]>(
+                &self.device,
+                &mut self.allocator,
+                delete_queue,
+            )?;
+
+        let descriptor_set = self.still_descriptor_set.as_mut().unwrap();
+        descriptor_set
+            .update(&self.device)
+            .write(0, DescriptorSetUpdateEntry::storage_image(&target))
+            .write(
                 1,
-            ),
-        );
+                DescriptorSetUpdateEntry::storage_buffer(&self.voxels.buffer),
+            );
+        let descriptor_set = descriptor_set.set;
+
+        // the voxel buffer belongs to the queue that runs the raymarch
+        let imm = self
+            .compute_immediate
+            .as_ref()
+            .unwrap_or(&self.graphics_immediate);
+        imm.submit(&self.device, |cbuf| {
+            Self::transition_image(
+                &self.device,
+                cbuf,
+                target.image,
+                ImageTransition::UndefinedToGeneral,
+            );
+
+            self.pipeline.bind(&self.device, cbuf);
+            self.pipeline.bind_sets(
+                &self.device,
+                cbuf,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            let push_const = PushConst {
+                render_extent: PushConst::pack_extent(extent),
+                ..push_const
+            };
+            self.pipeline
+                .write_push_constant(&self.device, cbuf, &push_const);
+            self.pipeline.dispatch(
+                &self.device,
+                cbuf,
+                UVec3::new(
+                    extent.width.div_ceil(self.workgroup_size),
+                    extent.height.div_ceil(self.workgroup_size),
+                    1,
+                ),
+            );
+
+            Self::transition_image(
+                &self.device,
+                cbuf,
+                target.image,
+                ImageTransition::GeneralToTransferSrc,
+            );
+            let region = vk::BufferImageCopy::default()
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(0)
+                        .layer_count(1)
+                        .base_array_layer(0),
+                )
+                .image_extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                });
+            unsafe {
+                self.device.cmd_copy_image_to_buffer(
+                    cbuf,
+                    target.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    buffer.buffer,
+                    slice::from_ref(&region),
+                );
+            }
+
+            Ok(())
+        })?;
+
+        Ok(buffer
+            .as_typed_slice()
+            .expect("still readback buffer should be CPU mappable")
+            .to_vec())
     }
 
+    /// true while the window is minimized or has zero size,
+    /// rendering is skipped until the next non-zero [`Self::resize`]
+    pub fn is_minimized(&self) -> bool {
+        self.minimized
+    }
+
+    /// does nothing when headless, the extent is fixed
     pub fn resize(&mut self) -> Result<()> {
-        self.swapchain
-            .recreate(&self.device, &self.queue_families)?;
+        let Some(swapchain) = self.swapchain.as_mut() else {
+            return Ok(());
+        };
+
+        let window_ext = swapchain.window_extent();
+        self.minimized = window_ext.width == 0 || window_ext.height == 0;
+        if self.minimized {
+            tracing::debug!("minimized, skipping swapchain recreation");
+            return Ok(());
+        }
+
+        swapchain.recreate(
+            self.frames.deferred_delete_queue(),
+            &self.queue_families,
+        )?;
+        self.output_extent = swapchain.extent;
+
+        self.fit_render_target()
+    }
 
+    /// reallocate the render target and the images sized like it
+    /// if the scaled output extent changed too much
+    fn fit_render_target(&mut self) -> Result<()> {
         let target_ext = self.render_target.extent;
-        let surface_ext = self.swapchain.extent;
+        let scaled = |side: u32| {
+            ((side as f32 * self.render_scale).round() as u32).max(1)
+        };
+        self.render_extent = vk::Extent2D {
+            width: scaled(self.output_extent.width),
+            height: scaled(self.output_extent.height),
+        };
+        let render_ext = self.render_extent;
 
-        // resize the render target if it cant fit the swapchain image at full res
-        // or when the render target is way bigger than the swapchain image
+        // resize the render target if it cant fit the scaled image
+        // or when the render target is way bigger than the scaled image
         const RENDER_TARGET_MULTIPLES: u32 = 256;
-        if target_ext.width >= surface_ext.width
-            && target_ext.height >= surface_ext.height
-            && target_ext.width.abs_diff(surface_ext.width)
+        if target_ext.width >= render_ext.width
+            && target_ext.height >= render_ext.height
+            && target_ext.width.abs_diff(render_ext.width)
                 <= RENDER_TARGET_MULTIPLES
-            && target_ext.width.abs_diff(surface_ext.width)
+            && target_ext.height.abs_diff(render_ext.height)
                 <= RENDER_TARGET_MULTIPLES
         {
             return Ok(());
         }
 
-        self.frames
-            .previous()
-            .0
-            .delete_queue
-            .append(&mut self.render_target_delete_queue);
-        self.render_target = Self::create_render_image(
-            &self.device,
-            &mut self.allocator,
+        // the descriptor sets pointing at the old images can't be
+        // rewritten while a frame in flight still uses them
+        unsafe { self.device.device_wait_idle()? };
+
+        let old_target = mem::replace(
             &mut self.render_target_delete_queue,
-            vk::Extent2D {
-                width: surface_ext
-                    .width
-                    .next_multiple_of(RENDER_TARGET_MULTIPLES),
-                height: surface_ext
-                    .height
-                    .next_multiple_of(RENDER_TARGET_MULTIPLES),
-            },
-        )?;
-        self.descriptor_set.update(&self.device).write(
+            DeleteQueue::new(),
+        );
+        self.defer_delete(old_target);
+        let extent = vk::Extent2D {
+            width: render_ext.width.next_multiple_of(RENDER_TARGET_MULTIPLES),
+            height: render_ext.height.next_multiple_of(RENDER_TARGET_MULTIPLES),
+        };
+        self.render_target = self.retry_out_of_memory(|this| {
+            // the raymarch writes it on the compute queue, if it has its own
+            Self::render_image(extent)
+                .name("render_target")
+                .concurrent([
+                    this.queue_families.graphics,
+                    this.queue_families.compute,
+                ])
+                .build(
+                    &this.device,
+                    &mut this.allocator,
+                    &mut this.render_target_delete_queue,
+                )
+        })?;
+        self.descriptor_writes.write(
+            &self.descriptor_set,
             0,
             DescriptorSetUpdateEntry::storage_image(&self.render_target),
         );
-
+        self.retry_out_of_memory(|this| {
+            this.fxaa.resize(
+                &this.device,
+                &mut this.allocator,
+                this.frames.deferred_delete_queue(),
+                &mut this.descriptor_writes,
+                &this.render_target,
+            )
+        })?;
+        self.retry_out_of_memory(|this| {
+            this.tonemap.resize(
+                &this.device,
+                &mut this.allocator,
+                this.frames.deferred_delete_queue(),
+                &mut this.descriptor_writes,
+                &this.render_target,
+                &this.fxaa.target,
+            )
+        })?;
+        if self.hdr_encode.is_some() {
+            self.retry_out_of_memory(|this| {
+                this.hdr_encode.as_mut().unwrap().resize(
+                    &this.device,
+                    &mut this.allocator,
+                    this.frames.deferred_delete_queue(),
+                    &mut this.descriptor_writes,
+                    &this.render_target,
+                    &this.fxaa.target,
+                )
+            })?;
+        }
+        self.hud.resize(
+            &mut self.descriptor_writes,
+            &self.render_target,
+            &self.fxaa.target,
+        );
+        for i in 0..self.custom_passes.len() {
+            self.retry_out_of_memory(|this| {
+                this.custom_passes[i].resize(
+                    &this.device,
+                    &mut this.allocator,
+                    this.frames.deferred_delete_queue(),
+                    &mut this.descriptor_writes,
+                    &this.render_target,
+                    &this.fxaa.target,
+                )
+            })?;
+        }
         Ok(())
     }
 
-    fn create_instance(window: &Window, entry: &Entry) -> Result<Instance> {
-        let window_handle = window.display_handle().unwrap().as_raw();
+    /// run `f`, reclaiming memory before it or retrying it
+    /// as [`memory_pressure`] decides
+    fn retry_out_of_memory<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let mut failed = 0;
+        loop {
+            let action = memory_pressure(
+                &self.heap_budgets,
+                self.memory_warning_fraction,
+                failed,
+            );
+            if action == MemoryAction::Reclaim {
+                self.reclaim_memory()?;
+            }
+
+            match f(self) {
+                Err(err) if is_out_of_memory(&err) => {
+                    failed += 1;
+                    let action = memory_pressure(
+                        &self.heap_budgets,
+                        self.memory_warning_fraction,
+                        failed,
+                    );
+                    if action == MemoryAction::Fail {
+                        return Err(err);
+                    }
+                    tracing::warn!(
+                        "out of GPU memory, reclaiming and retrying"
+                    );
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// free everything in `queue` once the frame submitted last has
+    /// finished on the GPU, none of it may be used by anything recorded
+    /// after that submit
+    fn defer_delete(&mut self, mut queue: DeleteQueue) {
+        self.frames.deferred_delete_queue().append(&mut queue);
+    }
+
+    /// free everything that is only waiting for the GPU to finish,
+    /// and the cached screenshot buffer
+    pub fn reclaim_memory(&mut self) -> Result<()> {
+        let before = self.allocator.generate_report().total_reserved_bytes;
+
+        unsafe { self.device.device_wait_idle()? };
+        for i in 0..self.frames.len() {
+            self.frames
+                .get(i)
+                .delete_queue
+                .flush(&self.device, &mut self.allocator);
+        }
+        self.screenshot_buffer = None;
+        self.screenshot_delete_queue
+            .flush(&self.device, &mut self.allocator);
+
+        let after = self.allocator.generate_report().total_reserved_bytes;
+        tracing::warn!(
+            "reclaimed {}B of GPU memory",
+            before.saturating_sub(after)
+        );
+        Ok(())
+    }
 
+    fn create_instance(
+        window: Option<&Window>,
+        entry: &Entry,
+        hdr: bool,
+    ) -> Result<Instance> {
         let layers = unsafe { entry.enumerate_instance_layer_properties()? };
         if tracing::enabled!(tracing::Level::DEBUG) {
             tracing::info!("layers:");
@@ -437,11 +3009,55 @@ impl Graphics {
         };
         tracing::debug!("enabled layers: {validation_layer_found} {layers:?}");
 
-        let mut extensions =
-            ash_window::enumerate_required_extensions(window_handle)
-                .unwrap()
-                .to_vec();
+        let mut extensions = match window {
+            Some(window) => ash_window::enumerate_required_extensions(
+                window.display_handle().unwrap().as_raw(),
+            )
+            .unwrap()
+            .to_vec(),
+            None => Vec::new(),
+        };
         extensions.push(ext::debug_utils::NAME.as_ptr());
+        // the HDR color spaces are only reported with it
+        if hdr {
+            let available =
+                unsafe { entry.enumerate_instance_extension_properties(None)? };
+            if available.iter().any(|ext| {
+                ext.extension_name_as_c_str()
+                    == Ok(ext::swapchain_colorspace::NAME)
+            }) {
+                extensions.push(ext::swapchain_colorspace::NAME.as_ptr());
+            } else {
+                tracing::warn!("HDR needs VK_EXT_swapchain_colorspace");
+            }
+        }
+        // the extra validation is provided by the validation layer itself
+        let mut validation_features = Self::requested_validation_features();
+        if !validation_features.is_empty() {
+            let available = if validation_layer_found {
+                unsafe {
+                    entry.enumerate_instance_extension_properties(Some(
+                        validation_layer,
+                    ))?
+                }
+            } else {
+                Vec::new()
+            };
+            if available.iter().any(|ext| {
+                ext.extension_name_as_c_str()
+                    == Ok(ext::validation_features::NAME)
+            }) {
+                extensions.push(ext::validation_features::NAME.as_ptr());
+            } else {
+                tracing::warn!(
+                    "LUMINARY_VALIDATION needs the validation layer \
+                    with VK_EXT_validation_features"
+                );
+                validation_features.clear();
+            }
+        }
+        let mut validation_info = vk::ValidationFeaturesEXT::default()
+            .enabled_validation_features(&validation_features);
 
         let app_info = vk::ApplicationInfo::default()
             .application_name(c"luminary")
@@ -450,24 +3066,156 @@ impl Graphics {
             .engine_version(0)
             .api_version(vk::make_api_version(0, 1, 3, 0));
 
-        let instance_info = vk::InstanceCreateInfo::default()
+        let mut instance_info = vk::InstanceCreateInfo::default()
             .application_info(&app_info)
             .enabled_layer_names(layers)
             .enabled_extension_names(&extensions);
+        if !validation_features.is_empty() {
+            instance_info = instance_info.push_next(&mut validation_info);
+        }
 
         let instance = unsafe { entry.create_instance(&instance_info, None) }?;
+        if !validation_features.is_empty() {
+            tracing::info!("validation features: {validation_features:?}");
+        }
         Ok(instance)
     }
 
+    /// `LUMINARY_VALIDATION=gpu,bestpractices,sync`, checks on top of
+    /// the default validation, only with the validation layer
+    fn requested_validation_features() -> Vec<vk::ValidationFeatureEnableEXT> {
+        use vk::ValidationFeatureEnableEXT as V;
+        let Ok(requested) = env::var("LUMINARY_VALIDATION") else {
+            return Vec::new();
+        };
+
+        let mut features = Vec::new();
+        for feature in requested.split(',').map(str::trim) {
+            match feature {
+                "" => {}
+                "gpu" => features.extend([
+                    V::GPU_ASSISTED,
+                    V::GPU_ASSISTED_RESERVE_BINDING_SLOT,
+                ]),
+                "bestpractices" => features.push(V::BEST_PRACTICES),
+                "sync" => features.push(V::SYNCHRONIZATION_VALIDATION),
+                other => {
+                    tracing::warn!(
+                        "unknown LUMINARY_VALIDATION feature {other}"
+                    )
+                }
+            }
+        }
+        features
+    }
+
+    /// `LUMINARY_SHADER=path` replaces the embedded raymarch shader with
+    /// a SPIR-V file, to try shader changes without rebuilding
+    fn raymarch_shader(
+        device: &Device,
+        delete_queue: &mut DeleteQueue,
+    ) -> Result<Shader> {
+        match env::var_os("LUMINARY_SHADER") {
+            Some(path) => {
+                tracing::info!("raymarch shader from {}", path.display());
+                Shader::from_spirv_file(device, delete_queue, path)
+            }
+            None => Shader::new(device, delete_queue, Shader::DEFAULT_COMP),
+        }
+    }
+
+    /// `LUMINARY_HDR=1` presents in an HDR format if the surface has one
+    fn hdr_requested() -> bool {
+        env::var("LUMINARY_HDR").is_ok_and(|hdr| hdr == "1")
+    }
+
+    /// `LUMINARY_QUEUE_PRIORITY=low|medium|high|realtime`,
+    /// the global priority requested for the graphics queue
+    fn requested_queue_priority() -> vk::QueueGlobalPriorityKHR {
+        let var = env::var("LUMINARY_QUEUE_PRIORITY").ok();
+        parse_queue_priority(var.as_deref()).unwrap_or_else(|| {
+            tracing::warn!(
+                "unknown LUMINARY_QUEUE_PRIORITY={}",
+                var.as_deref().unwrap_or_default()
+            );
+            vk::QueueGlobalPriorityKHR::MEDIUM
+        })
+    }
+
+    /// the returned features have the granted queue priority,
+    /// priorities the driver does not allow fall back to medium
     fn create_device(
         instance: &Instance,
         gpu: vk::PhysicalDevice,
         queue_families: &QueueFamilies,
-    ) -> Result<Device> {
+        mut features: Features,
+    ) -> Result<(Device, Features)> {
+        const MEDIUM: vk::QueueGlobalPriorityKHR =
+            vk::QueueGlobalPriorityKHR::MEDIUM;
+
+        if features.global_priority.is_some() {
+            let wanted = Self::requested_queue_priority();
+            let granted = queue_priority(
+                wanted,
+                &global_priorities(instance, gpu, queue_families.graphics),
+            );
+            if granted != wanted {
+                tracing::warn!(
+                    "the graphics queue does not support the {wanted:?} \
+                     priority, using {granted:?}"
+                );
+            }
+            features.global_priority = Some(granted);
+        } else if env::var_os("LUMINARY_QUEUE_PRIORITY").is_some() {
+            tracing::warn!(
+                "LUMINARY_QUEUE_PRIORITY needs VK_KHR_global_priority"
+            );
+        }
+
+        let device = match Self::try_create_device(
+            instance,
+            gpu,
+            queue_families,
+            features,
+        ) {
+            // elevated priorities can need privileges the process lacks
+            Err(vk::Result::ERROR_NOT_PERMITTED_KHR)
+                if features.global_priority != Some(MEDIUM) =>
+            {
+                tracing::warn!(
+                    "not permitted to use the {:?} queue priority, \
+                     using {MEDIUM:?}",
+                    features.global_priority.unwrap()
+                );
+                features.global_priority = Some(MEDIUM);
+                Self::try_create_device(
+                    instance,
+                    gpu,
+                    queue_families,
+                    features,
+                )?
+            }
+            res => res?,
+        };
+
+        if let Some(priority) = features.global_priority {
+            tracing::info!("graphics queue priority: {priority:?}");
+        }
+        Ok((device, features))
+    }
+
+    fn try_create_device(
+        instance: &Instance,
+        gpu: vk::PhysicalDevice,
+        queue_families: &QueueFamilies,
+        features: Features,
+    ) -> VkResult<Device> {
         let mut features_as =
             vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default()
-                .acceleration_structure(true)
-                .acceleration_structure_capture_replay(true);
+                .acceleration_structure(true);
+        let mut features_rt =
+            vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default()
+                .ray_tracing_pipeline(true);
 
         let mut features13 = vk::PhysicalDeviceVulkan13Features::default()
             .synchronization2(true)
@@ -477,23 +3225,51 @@ impl Graphics {
             .buffer_device_address(true)
             .buffer_device_address_capture_replay(true)
             .descriptor_indexing(true)
-            .uniform_and_storage_buffer8_bit_access(true);
+            // guaranteed by `descriptor_indexing`,
+            // used by `DescriptorSetLayoutBuilder::add_array_binding`
+            .runtime_descriptor_array(true)
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_sampled_image_update_after_bind(true)
+            .descriptor_binding_storage_image_update_after_bind(true)
+            .descriptor_binding_storage_buffer_update_after_bind(true)
+            .descriptor_binding_update_unused_while_pending(true)
+            .shader_sampled_image_array_non_uniform_indexing(true)
+            .uniform_and_storage_buffer8_bit_access(true)
+            .timeline_semaphore(features.timeline_semaphore);
 
         let mut features11 = vk::PhysicalDeviceVulkan11Features::default()
             .uniform_and_storage_buffer16_bit_access(true)
             .storage_buffer16_bit_access(true);
 
-        let create_info = vk::DeviceCreateInfo::default()
-            .push_next(&mut features_as)
+        let extensions = features.extension_names();
+
+        let mut global_priority =
+            vk::DeviceQueueGlobalPriorityCreateInfoKHR::default()
+                .global_priority(features.global_priority.unwrap_or_default());
+        let mut queue_infos = queue_families.families.to_vec();
+        if features.global_priority.is_some() {
+            for info in &mut queue_infos {
+                if info.queue_family_index == queue_families.graphics {
+                    *info = info.push_next(&mut global_priority);
+                    break;
+                }
+            }
+        }
+
+        let mut create_info = vk::DeviceCreateInfo::default()
             .push_next(&mut features13)
             .push_next(&mut features12)
             .push_next(&mut features11)
-            .enabled_extension_names(gpu::REQUIRED_EXTS_PTRPTR)
-            .queue_create_infos(&queue_families.families);
+            .enabled_extension_names(&extensions)
+            .queue_create_infos(&queue_infos);
 
-        let device =
-            unsafe { instance.create_device(gpu, &create_info, None)? };
-        Ok(device)
+        if features.ray_tracing {
+            create_info = create_info
+                .push_next(&mut features_as)
+                .push_next(&mut features_rt);
+        }
+
+        unsafe { instance.create_device(gpu, &create_info, None) }
     }
 
     fn create_allocator(
@@ -511,13 +3287,31 @@ impl Graphics {
         })?)
     }
 
+    /// give the long lived objects readable names in validation messages
+    /// and RenderDoc captures
+    fn name_objects(&self) -> Result<()> {
+        let Some(debug_utils) = self.debug_utils.as_ref() else {
+            return Ok(());
+        };
+
+        debug_utils.set_name(
+            &self.device,
+            self.pipeline.pipeline,
+            "raymarch_pipeline",
+        )
+    }
+
     fn create_render_image(
         device: &Device,
         allocator: &mut Allocator,
         delete_queue: &mut DeleteQueue,
         extent: vk::Extent2D,
     ) -> Result<Image> {
-        let render_target = Image::builder()
+        Self::render_image(extent).build(device, allocator, delete_queue)
+    }
+
+    fn render_image(extent: vk::Extent2D) -> ImageBuilder {
+        Image::builder()
             .format(vk::Format::R16G16B16A16_SFLOAT)
             .extent(extent)
             .usage(
@@ -527,38 +3321,22 @@ impl Graphics {
                     | vk::ImageUsageFlags::COLOR_ATTACHMENT,
             )
             .aspect_flags(vk::ImageAspectFlags::COLOR)
-            .build(device, allocator, delete_queue)?;
-        Ok(render_target)
     }
 
     fn transition_image(
         device: &Device,
         cbuf: vk::CommandBuffer,
         image: vk::Image,
-        from: vk::ImageLayout,
-        to: vk::ImageLayout,
+        transition: ImageTransition,
     ) {
-        let aspect = if to == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
-        {
-            vk::ImageAspectFlags::DEPTH
-        } else {
-            vk::ImageAspectFlags::COLOR
-        };
-
-        let image_barrier = vk::ImageMemoryBarrier2::default()
-            // the swapchain image is a copy destination
-            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
-            // the new layout is read+write render target
-            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-            .dst_access_mask(
-                vk::AccessFlags2::MEMORY_WRITE | vk::AccessFlags2::MEMORY_READ,
-            )
-            .old_layout(from)
-            .new_layout(to)
-            .src_queue_family_index(0)
-            .dst_queue_family_index(0)
-            .subresource_range(Self::subresource_range(aspect))
+        let image_barrier = transition
+            .barrier()
+            // not an ownership transfer
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(Self::subresource_range(
+                vk::ImageAspectFlags::COLOR,
+            ))
             .image(image);
 
         let dependency_info = vk::DependencyInfo::default()
@@ -630,19 +3408,332 @@ impl Graphics {
 
 impl Drop for Graphics {
     fn drop(&mut self) {
+        if self.device_lost {
+            // nothing can be destroyed on a lost device, so its objects
+            // are leaked, but the memory of the renderer itself is freed
+            tracing::warn!("leaking the objects of the lost device");
+            self.render_target_delete_queue.leak();
+            self.fxaa.target_delete_queue.leak();
+            self.tonemap.target_delete_queue.leak();
+            self.ui.leak();
+            if let Some(hdr_encode) = self.hdr_encode.as_mut() {
+                hdr_encode.target_delete_queue.leak();
+            }
+            for pass in &mut self.custom_passes {
+                pass.leak();
+            }
+            for i in 0..self.frames.len() {
+                self.frames.get(i).delete_queue.leak();
+            }
+            self.screenshot_delete_queue.leak();
+            self.global_delete_queue.leak();
+            return;
+        }
         _ = unsafe { self.device.device_wait_idle() };
 
         self.render_target_delete_queue
             .flush(&self.device, &mut self.allocator);
+        self.fxaa.destroy(&self.device, &mut self.allocator);
+        self.tonemap.destroy(&self.device, &mut self.allocator);
+        self.ui.destroy(&self.device, &mut self.allocator);
+        if let Some(hdr_encode) = self.hdr_encode.as_mut() {
+            hdr_encode.destroy(&self.device, &mut self.allocator);
+        }
+        for pass in &mut self.custom_passes {
+            pass.destroy(&self.device, &mut self.allocator);
+        }
+        self.screenshot_delete_queue
+            .flush(&self.device, &mut self.allocator);
+        self.pipeline_cache.save(&self.device);
         self.global_delete_queue
             .flush(&self.device, &mut self.allocator);
 
-        self.immediate.destroy(&self.device);
-
         unsafe { ManuallyDrop::drop(&mut self.allocator) };
-        self.swapchain.destroy();
-        unsafe { self.device.destroy_device(None) };
-        self.surface.destroy(&self.instance);
-        self.debug_utils.destroy(&self.instance);
+        if let Some(swapchain) = self.swapchain.as_mut() {
+            swapchain.destroy();
+        }
+        if !self.external {
+            unsafe { self.device.destroy_device(None) };
+        }
+        if let Some(surface) = self.surface.as_mut() {
+            surface.destroy(&self.instance);
+        }
+        if let Some(debug_utils) = self.debug_utils.as_mut() {
+            debug_utils.destroy(&self.instance);
+        }
+    }
+}
+
+/// the layout a recorded frame leaves one of its drawn images in,
+/// `blit_source` is the one it was blitted from
+fn layout_after_frame(
+    image: vk::Image,
+    blit_source: vk::Image,
+) -> vk::ImageLayout {
+    if image == blit_source {
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+    } else {
+        vk::ImageLayout::GENERAL
+    }
+}
+
+/// true if `err` comes from the allocator or the driver running out of memory
+fn is_out_of_memory(err: &eyre::Report) -> bool {
+    matches!(
+        err.downcast_ref::<AllocationError>(),
+        Some(AllocationError::OutOfMemory)
+    ) || matches!(
+        err.downcast_ref::<vk::Result>(),
+        Some(
+            &vk::Result::ERROR_OUT_OF_DEVICE_MEMORY
+                | &vk::Result::ERROR_OUT_OF_HOST_MEMORY
+        )
+    )
+}
+
+/// true if `err` comes from the driver losing the device
+fn is_device_lost(err: &eyre::Report) -> bool {
+    matches!(
+        err.downcast_ref::<vk::Result>(),
+        Some(&vk::Result::ERROR_DEVICE_LOST)
+    )
+}
+
+/// a `LUMINARY_WORKGROUP_SIZE` value, 16 if unset, `None` unless it is a
+/// power of two with a square of at most `max_invocations`
+fn workgroup_size(var: Option<&str>, max_invocations: u32) -> Option<u32> {
+    let Some(var) = var else {
+        return Some(16);
+    };
+    let size = var.parse::<u32>().ok()?;
+    let invocations = size.checked_mul(size)?;
+    (size.is_power_of_two() && invocations <= max_invocations).then_some(size)
+}
+
+/// the tiles of at most `tile` pixels per side covering `extent`,
+/// row by row
+fn still_tiles(
+    extent: vk::Extent2D,
+    tile: u32,
+) -> Vec<(vk::Offset2D, vk::Extent2D)> {
+    let tiles_x = extent.width.div_ceil(tile);
+    let tiles_y = extent.height.div_ceil(tile);
+    (0..tiles_y)
+        .flat_map(|tile_y| (0..tiles_x).map(move |tile_x| (tile_x, tile_y)))
+        .map(|(tile_x, tile_y)| {
+            let offset = vk::Offset2D {
+                x: (tile_x * tile) as i32,
+                y: (tile_y * tile) as i32,
+            };
+            let tile_ext = vk::Extent2D {
+                width: tile.min(extent.width - offset.x as u32),
+                height: tile.min(extent.height - offset.y as u32),
+            };
+            (offset, tile_ext)
+        })
+        .collect()
+}
+
+/// maps the NDC of a tile onto its part of the full frame
+fn tile_ndc(
+    extent: vk::Extent2D,
+    offset: vk::Offset2D,
+    tile_ext: vk::Extent2D,
+) -> Mat4 {
+    let (w, h) = (extent.width as f32, extent.height as f32);
+    Mat4::from_translation(Vec3::new(
+        (2.0 * offset.x as f32 + tile_ext.width as f32) / w - 1.0,
+        (2.0 * offset.y as f32 + tile_ext.height as f32) / h - 1.0,
+        0.0,
+    )) * Mat4::from_scale(Vec3::new(
+        tile_ext.width as f32 / w,
+        tile_ext.height as f32 / h,
+        1.0,
+    ))
+}
+
+/// copy the half float `texels` of a tile into its part of the
+/// 8 bit RGBA `pixels` of the full frame
+fn stitch_tile(
+    pixels: &mut [u8],
+    extent: vk::Extent2D,
+    offset: vk::Offset2D,
+    tile_ext: vk::Extent2D,
+    texels: &[[u16; 4]],
+) {
+    for (y, row) in texels.chunks_exact(tile_ext.width as usize).enumerate() {
+        let start = (offset.x as usize
+            + (offset.y as usize + y) * extent.width as usize)
+            * 4;
+        for (dst, texel) in pixels[start..].chunks_exact_mut(4).zip(row) {
+            dst.copy_from_slice(&texel.map(|channel| {
+                (f16_to_f32(channel).clamp(0.0, 1.0) * 255.0).round() as u8
+            }));
+        }
+    }
+}
+
+/// decode an IEEE 754 half precision float
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+
+    match exponent {
+        // subnormal
+        0 => sign * mantissa * 2.0f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2.0f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32) -> vk::Extent2D {
+        vk::Extent2D { width, height }
+    }
+
+    #[test]
+    fn still_tiles_cover_the_frame_once() {
+        let frame = extent(5, 3);
+        let tiles = still_tiles(frame, 2);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[2].0, vk::Offset2D { x: 4, y: 0 });
+        assert_eq!(tiles[2].1, extent(1, 2));
+        assert_eq!(tiles[5].1, extent(1, 1));
+
+        let mut covered = [0; 15];
+        for (offset, tile_ext) in tiles {
+            for y in 0..tile_ext.height as i32 {
+                for x in 0..tile_ext.width as i32 {
+                    covered[((offset.y + y) * 5 + offset.x + x) as usize] += 1;
+                }
+            }
+        }
+        assert_eq!(covered, [1; 15]);
+    }
+
+    #[test]
+    fn tile_ndc_maps_onto_the_frame() {
+        let frame = extent(400, 200);
+        let offset = vk::Offset2D { x: 100, y: 100 };
+        let ndc = tile_ndc(frame, offset, extent(100, 100));
+
+        // the corners of the tile land on its pixels in the full frame
+        let min = ndc.transform_point3(Vec3::new(-1.0, -1.0, 0.5));
+        let max = ndc.transform_point3(Vec3::new(1.0, 1.0, 0.5));
+        assert!(min.abs_diff_eq(Vec3::new(-0.5, 0.0, 0.5), 1e-6));
+        assert!(max.abs_diff_eq(Vec3::new(0.0, 1.0, 0.5), 1e-6));
+
+        // a single tile is the whole frame
+        let whole = tile_ndc(frame, vk::Offset2D::default(), frame);
+        assert!(whole.abs_diff_eq(Mat4::IDENTITY, 1e-6));
+    }
+
+    #[test]
+    fn tiled_matches_single_pass() {
+        let frame = extent(7, 5);
+        // half floats from 0.0 to 1.0 and some out of range values
+        let texel = |x: u32, y: u32| -> [u16; 4] {
+            let v = [0x0000, 0x3400, 0x3800, 0x3a00, 0x3c00, 0x4000, 0xbc00];
+            [
+                v[x as usize % 7],
+                v[y as usize % 7],
+                v[(x + y) as usize % 7],
+                0,
+            ]
+        };
+        let texels_of = |offset: vk::Offset2D, tile_ext: vk::Extent2D| {
+            (0..tile_ext.height)
+                .flat_map(|y| {
+                    (0..tile_ext.width).map(move |x| {
+                        texel(offset.x as u32 + x, offset.y as u32 + y)
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let render = |tile: u32| {
+            let mut pixels = vec![0u8; 7 * 5 * 4];
+            for (offset, tile_ext) in still_tiles(frame, tile) {
+                let texels = texels_of(offset, tile_ext);
+                stitch_tile(&mut pixels, frame, offset, tile_ext, &texels);
+            }
+            pixels
+        };
+
+        let single = render(8);
+        assert_eq!(&single[..8], &[0, 0, 0, 0, 64, 0, 64, 0]);
+        assert_eq!(render(2), single);
+        assert_eq!(render(3), single);
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x0001), 2.0f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn grid_settings() {
+        let grid =
+            GridPushConst::new(2.0, 5.0, 0.5, Vec4::new(1.0, 0.5, 0.0, 2.0));
+        assert_eq!(grid.width, 2.0);
+        assert_eq!(grid.fade_distance, 2.0);
+        assert_eq!(grid.color.to_le_bytes(), [255, 128, 0, 255]);
+
+        let hidden = GridPushConst::default();
+        assert_eq!(GridPushConst::new(0.0, 1.0, 1.0, Vec4::ONE), hidden);
+        assert_eq!(GridPushConst::new(-1.0, 1.0, 1.0, Vec4::ONE), hidden);
+        assert_eq!(GridPushConst::new(f32::NAN, 1.0, 1.0, Vec4::ONE), hidden);
+    }
+
+    #[test]
+    fn grid_push_constant_layout() {
+        // the shader reads the grid right after render_extent
+        assert_eq!(std::mem::offset_of!(PushConst, grid), 80);
+        assert_eq!(std::mem::size_of::<PushConst>(), 96);
+    }
+
+    #[test]
+    fn readback_layouts() {
+        use vk::{Handle, ImageLayout as L};
+        let render_target = vk::Image::from_raw(1);
+        let fxaa = vk::Image::from_raw(2);
+        let tonemap = vk::Image::from_raw(3);
+
+        // plain, only the render target is drawn and blitted
+        assert_eq!(
+            layout_after_frame(render_target, render_target),
+            L::TRANSFER_SRC_OPTIMAL
+        );
+        // FXAA, the render target stays where the raymarch left it
+        assert_eq!(layout_after_frame(render_target, fxaa), L::GENERAL);
+        assert_eq!(layout_after_frame(fxaa, fxaa), L::TRANSFER_SRC_OPTIMAL);
+        // tonemap after FXAA
+        assert_eq!(layout_after_frame(render_target, tonemap), L::GENERAL);
+        assert_eq!(layout_after_frame(fxaa, tonemap), L::GENERAL);
+        assert_eq!(
+            layout_after_frame(tonemap, tonemap),
+            L::TRANSFER_SRC_OPTIMAL
+        );
+    }
+
+    #[test]
+    fn workgroup_sizes() {
+        assert_eq!(workgroup_size(None, 256), Some(16));
+        assert_eq!(workgroup_size(Some("8"), 256), Some(8));
+        assert_eq!(workgroup_size(Some("32"), 1024), Some(32));
+        // over the device limit
+        assert_eq!(workgroup_size(Some("32"), 256), None);
+        assert_eq!(workgroup_size(Some("12"), 256), None);
+        assert_eq!(workgroup_size(Some("0"), 256), None);
+        assert_eq!(workgroup_size(Some("65536"), u32::MAX), None);
+        assert_eq!(workgroup_size(Some("big"), 256), None);
     }
 }
@@ -1,12 +1,21 @@
-use std::{marker::PhantomData, mem, slice};
+use std::{
+    env, fs,
+    marker::PhantomData,
+    mem,
+    path::{Path, PathBuf},
+    slice,
+};
 
-use ash::{Device, vk};
+use ash::{Device, Instance, vk};
 use bytemuck::{Pod, Zeroable};
-use eyre::Result;
+use eyre::{Result, bail};
 use glam::UVec3;
 
 use super::{
-    delete_queue::DeleteQueue, descriptor::DescriptorSetLayout, shader::Shader,
+    delete_queue::DeleteQueue,
+    descriptor::{DescriptorLimits, DescriptorSetLayout},
+    image::Image,
+    shader::Shader,
 };
 
 //
@@ -14,24 +23,58 @@ use super::{
 #[derive(Clone, Copy)]
 pub struct PipelineLayout<C = ()> {
     pub layout: vk::PipelineLayout,
+    /// the stages that can read the push constants
+    pub stages: vk::ShaderStageFlags,
     _p: PhantomData<C>,
 }
 
 impl<C: Sized> PipelineLayout<C> {
+    /// push constants for compute shaders
     pub fn new(
         device: &Device,
         delete_queue: &mut DeleteQueue,
         set_layout: &DescriptorSetLayout,
+    ) -> Result<Self> {
+        Self::with_stages(
+            device,
+            delete_queue,
+            set_layout,
+            vk::ShaderStageFlags::COMPUTE,
+        )
+    }
+
+    /// push constants for the given `stages`, like
+    /// `VERTEX | FRAGMENT` for a [`GraphicsPipeline`]
+    pub fn with_stages(
+        device: &Device,
+        delete_queue: &mut DeleteQueue,
+        set_layout: &DescriptorSetLayout,
+        stages: vk::ShaderStageFlags,
+    ) -> Result<Self> {
+        Self::with_set_layouts(device, delete_queue, &[set_layout], stages)
+    }
+
+    /// set `i` of the pipeline uses `set_layouts[i]`, the count
+    /// should be checked with [`Self::check_limits`]
+    pub fn with_set_layouts(
+        device: &Device,
+        delete_queue: &mut DeleteQueue,
+        set_layouts: &[&DescriptorSetLayout],
+        stages: vk::ShaderStageFlags,
     ) -> Result<Self> {
         let push_constant_size: u32 = size_of::<C>().try_into()?;
+        let set_layouts = set_layouts
+            .iter()
+            .map(|set_layout| set_layout.layout)
+            .collect::<Vec<_>>();
 
         let push_constant_range = vk::PushConstantRange::default()
             .offset(0)
             .size(push_constant_size)
-            .stage_flags(vk::ShaderStageFlags::COMPUTE); // TODO: specify the stage or something idk
+            .stage_flags(stages);
 
-        let mut create_info = vk::PipelineLayoutCreateInfo::default()
-            .set_layouts(slice::from_ref(&set_layout.layout));
+        let mut create_info =
+            vk::PipelineLayoutCreateInfo::default().set_layouts(&set_layouts);
 
         if push_constant_size != 0 {
             create_info = create_info
@@ -43,9 +86,168 @@ impl<C: Sized> PipelineLayout<C> {
         delete_queue.push(layout);
         Ok(Self {
             layout,
+            stages,
             _p: PhantomData,
         })
     }
+
+    /// make sure a pipeline can bind `set_count` sets at once,
+    /// `maxBoundDescriptorSets` is at least 4
+    pub fn check_limits(
+        set_count: usize,
+        limits: &DescriptorLimits,
+    ) -> Result<()> {
+        if set_count > limits.bound_sets as usize {
+            bail!(
+                "{set_count} descriptor sets exceed maxBoundDescriptorSets={}",
+                limits.bound_sets
+            );
+        }
+        Ok(())
+    }
+}
+
+//
+
+/// pipeline cache that is loaded from and saved to the platform cache dir
+pub struct PipelineCache {
+    pub cache: vk::PipelineCache,
+    path: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    /// size of `VkPipelineCacheHeaderVersionOne`
+    const HEADER_SIZE: usize = 32;
+
+    pub fn new(
+        instance: &Instance,
+        device: &Device,
+        gpu: vk::PhysicalDevice,
+        delete_queue: &mut DeleteQueue,
+    ) -> Result<Self> {
+        let path = cache_dir().map(|dir| dir.join("pipeline_cache.bin"));
+        let props = unsafe { instance.get_physical_device_properties(gpu) };
+
+        let data = path
+            .as_deref()
+            .and_then(|path| fs::read(path).ok())
+            .filter(|data| Self::is_compatible(data, &props))
+            .unwrap_or_default();
+        tracing::debug!("loaded {}B of pipeline cache", data.len());
+
+        let create_info =
+            vk::PipelineCacheCreateInfo::default().initial_data(&data);
+        let cache = unsafe { device.create_pipeline_cache(&create_info, None) }
+            .or_else(|_| {
+                // the driver can still reject data that looked valid
+                let create_info = vk::PipelineCacheCreateInfo::default();
+                unsafe { device.create_pipeline_cache(&create_info, None) }
+            })?;
+        delete_queue.push(cache);
+
+        Ok(Self { cache, path })
+    }
+
+    /// write the cache back to disk, failures are only logged
+    pub fn save(&self, device: &Device) {
+        let Some(path) = self.path.as_deref() else {
+            return;
+        };
+
+        let result = unsafe { device.get_pipeline_cache_data(self.cache) }
+            .map_err(eyre::Report::from)
+            .and_then(|data| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(path, &data)?;
+                Ok(data.len())
+            });
+
+        match result {
+            Ok(len) => tracing::debug!("saved {len}B of pipeline cache"),
+            Err(err) => {
+                tracing::warn!("failed to save the pipeline cache: {err}")
+            }
+        }
+    }
+
+    /// check the header against the current driver and GPU
+    fn is_compatible(
+        data: &[u8],
+        props: &vk::PhysicalDeviceProperties,
+    ) -> bool {
+        if data.len() < Self::HEADER_SIZE {
+            return false;
+        }
+
+        let u32_at =
+            |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+
+        let header_size = u32_at(0) as usize;
+        let version = u32_at(4);
+        header_size >= Self::HEADER_SIZE
+            && header_size <= data.len()
+            && version == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+            && u32_at(8) == props.vendor_id
+            && u32_at(12) == props.device_id
+            && data[16..32] == props.pipeline_cache_uuid
+    }
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME")
+            .map(|home| Path::new(&home).join("Library").join("Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME").map(|home| Path::new(&home).join(".cache"))
+            })
+    };
+
+    base.map(|base| base.join("luminary"))
+}
+
+//
+
+/// constant values baked into a pipeline, for shader
+/// `layout(constant_id = N)` constants, booleans are 4 byte `u32`s
+#[derive(Debug, Clone, Default)]
+pub struct Specialization {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl Specialization {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn constant<T: Pod>(mut self, constant_id: u32, value: T) -> Self {
+        let bytes = bytemuck::bytes_of(&value);
+        self.entries.push(
+            vk::SpecializationMapEntry::default()
+                .constant_id(constant_id)
+                .offset(self.data.len() as u32)
+                .size(bytes.len()),
+        );
+        self.data.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn info(&self) -> vk::SpecializationInfo<'_> {
+        vk::SpecializationInfo::default()
+            .map_entries(&self.entries)
+            .data(&self.data)
+    }
 }
 
 //
@@ -61,13 +263,38 @@ impl<C: Sized> ComputePipeline<C> {
     pub fn new(
         device: &Device,
         delete_queue: &mut DeleteQueue,
+        cache: &PipelineCache,
         layout: PipelineLayout<C>,
         compute_shader: &Shader,
     ) -> Result<Self> {
-        let stage_info = vk::PipelineShaderStageCreateInfo::default()
+        Self::with_specialization(
+            device,
+            delete_queue,
+            cache,
+            layout,
+            compute_shader,
+            &Specialization::new(),
+        )
+    }
+
+    /// [`Self::new`] with specialization constants, so one [`Shader`]
+    /// can be used for pipelines with different constants
+    pub fn with_specialization(
+        device: &Device,
+        delete_queue: &mut DeleteQueue,
+        cache: &PipelineCache,
+        layout: PipelineLayout<C>,
+        compute_shader: &Shader,
+        specialization: &Specialization,
+    ) -> Result<Self> {
+        let specialization_info = specialization.info();
+        let mut stage_info = vk::PipelineShaderStageCreateInfo::default()
             .stage(vk::ShaderStageFlags::COMPUTE)
             .module(compute_shader.module)
             .name(c"main");
+        if !specialization.is_empty() {
+            stage_info = stage_info.specialization_info(&specialization_info);
+        }
 
         let create_info = vk::ComputePipelineCreateInfo::default()
             .stage(stage_info)
@@ -75,7 +302,7 @@ impl<C: Sized> ComputePipeline<C> {
 
         let pipelines = unsafe {
             device.create_compute_pipelines(
-                vk::PipelineCache::null(),
+                cache.cache,
                 slice::from_ref(&create_info),
                 None,
             )
@@ -113,17 +340,19 @@ impl<C: Sized> ComputePipeline<C> {
             device.cmd_push_constants(
                 cbuf,
                 self.layout.layout,
-                vk::ShaderStageFlags::COMPUTE,
+                self.layout.stages,
                 0,
                 bytemuck::cast_slice(slice::from_ref(data)),
             );
         }
     }
 
+    /// bind `sets` to the set indices starting at `first_set`
     pub fn bind_sets(
         &self,
         device: &Device,
         cbuf: vk::CommandBuffer,
+        first_set: u32,
         sets: &[vk::DescriptorSet],
         offsets: &[u32],
     ) {
@@ -132,7 +361,7 @@ impl<C: Sized> ComputePipeline<C> {
                 cbuf,
                 vk::PipelineBindPoint::COMPUTE,
                 self.layout.layout,
-                0,
+                first_set,
                 sets,
                 offsets,
             );
@@ -155,3 +384,287 @@ impl<C: Sized> ComputePipeline<C> {
         }
     }
 }
+
+//
+
+/// a rasterization pipeline for dynamic rendering, without vertex
+/// buffers, the vertex shader builds its vertices from `gl_VertexIndex`
+/// or reads them from storage buffers
+///
+/// triangles are alpha blended over the color attachment,
+/// with a depth format they are also depth tested and written
+pub struct GraphicsPipeline<C = ()> {
+    pub pipeline: vk::Pipeline,
+    pub layout: PipelineLayout<C>,
+}
+
+impl<C: Sized> GraphicsPipeline<C> {
+    const PUSH_CONSTANT_SIZE: u32 = mem::size_of::<C>() as _;
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &Device,
+        delete_queue: &mut DeleteQueue,
+        cache: &PipelineCache,
+        layout: PipelineLayout<C>,
+        vertex_shader: &Shader,
+        fragment_shader: &Shader,
+        color_format: vk::Format,
+        depth_format: Option<vk::Format>,
+    ) -> Result<Self> {
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_shader.module)
+                .name(c"main"),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_shader.module)
+                .name(c"main"),
+        ];
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
+        let input_assembly =
+            vk::PipelineInputAssemblyStateCreateInfo::default()
+                .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        // the viewport and scissor are set by `begin_rendering`
+        let viewport = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .line_width(1.0);
+        let multisample = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(depth_format.is_some())
+            .depth_write_enable(depth_format.is_some())
+            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL);
+        let blend_attachment = vk::PipelineColorBlendAttachmentState::default()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(vk::ColorComponentFlags::RGBA);
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::default()
+            .attachments(slice::from_ref(&blend_attachment));
+        let dynamic_states =
+            [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic = vk::PipelineDynamicStateCreateInfo::default()
+            .dynamic_states(&dynamic_states);
+
+        let mut rendering = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(slice::from_ref(&color_format))
+            .depth_attachment_format(
+                depth_format.unwrap_or(vk::Format::UNDEFINED),
+            );
+
+        let create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic)
+            .layout(layout.layout)
+            .push_next(&mut rendering);
+
+        let pipelines = unsafe {
+            device.create_graphics_pipelines(
+                cache.cache,
+                slice::from_ref(&create_info),
+                None,
+            )
+        }
+        .map_err(|(_, err)| err)?;
+        let pipeline = pipelines.into_iter().next().unwrap();
+        delete_queue.push(pipeline);
+
+        Ok(Self { pipeline, layout })
+    }
+
+    /// render into the top left `extent` of `color`, like the render
+    /// target, which has to be in the `GENERAL` layout, its contents are
+    /// kept and drawn over, `depth` is cleared to 1.0 and has to be in the
+    /// `DEPTH_ATTACHMENT_OPTIMAL` layout
+    pub fn begin_rendering(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        color: &Image,
+        depth: Option<&Image>,
+        extent: vk::Extent2D,
+    ) {
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(color.view)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE);
+        let depth_attachment = depth.map(|depth| {
+            vk::RenderingAttachmentInfo::default()
+                .image_view(depth.view)
+                .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .clear_value(vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 1.0,
+                        stencil: 0,
+                    },
+                })
+        });
+
+        let mut rendering_info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent,
+            })
+            .layer_count(1)
+            .color_attachments(slice::from_ref(&color_attachment));
+        if let Some(depth_attachment) = depth_attachment.as_ref() {
+            rendering_info = rendering_info.depth_attachment(depth_attachment);
+        }
+
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent,
+        };
+
+        unsafe {
+            device.cmd_begin_rendering(cbuf, &rendering_info);
+            device.cmd_set_viewport(cbuf, 0, slice::from_ref(&viewport));
+            device.cmd_set_scissor(cbuf, 0, slice::from_ref(&scissor));
+        }
+    }
+
+    pub fn end_rendering(&self, device: &Device, cbuf: vk::CommandBuffer) {
+        unsafe { device.cmd_end_rendering(cbuf) };
+    }
+
+    pub fn bind(&self, device: &Device, cbuf: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                cbuf,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+        }
+    }
+
+    pub fn write_push_constant(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        data: &C,
+    ) where
+        C: Pod + Zeroable,
+    {
+        if Self::PUSH_CONSTANT_SIZE == 0 {
+            return;
+        }
+
+        unsafe {
+            device.cmd_push_constants(
+                cbuf,
+                self.layout.layout,
+                self.layout.stages,
+                0,
+                bytemuck::cast_slice(slice::from_ref(data)),
+            );
+        }
+    }
+
+    /// bind `sets` to the set indices starting at `first_set`
+    pub fn bind_sets(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        first_set: u32,
+        sets: &[vk::DescriptorSet],
+        offsets: &[u32],
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                cbuf,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout.layout,
+                first_set,
+                sets,
+                offsets,
+            );
+        }
+    }
+
+    /// only draw inside `scissor`, until the next [`Self::begin_rendering`]
+    pub fn set_scissor(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        scissor: vk::Rect2D,
+    ) {
+        unsafe { device.cmd_set_scissor(cbuf, 0, slice::from_ref(&scissor)) };
+    }
+
+    pub fn draw(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        vertex_count: u32,
+        instance_count: u32,
+    ) {
+        unsafe { device.cmd_draw(cbuf, vertex_count, instance_count, 0, 0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specialization_packing() {
+        let specialization = Specialization::new()
+            .constant(0, 16u32)
+            .constant(3, 0.5f32)
+            .constant(1, [1u8, 2])
+            .constant(2, u64::MAX);
+        assert!(!specialization.is_empty());
+
+        let layout: Vec<_> = specialization
+            .entries
+            .iter()
+            .map(|entry| (entry.constant_id, entry.offset, entry.size))
+            .collect();
+        assert_eq!(layout, [(0, 0, 4), (3, 4, 4), (1, 8, 2), (2, 10, 8)]);
+        assert_eq!(specialization.data.len(), 18);
+        assert_eq!(specialization.data[..4], 16u32.to_ne_bytes());
+        assert_eq!(specialization.data[4..8], 0.5f32.to_ne_bytes());
+        assert_eq!(specialization.data[8..10], [1, 2]);
+
+        let info = specialization.info();
+        assert_eq!(info.map_entry_count, 4);
+        assert_eq!(info.data_size, 18);
+    }
+
+    #[test]
+    fn specialization_empty() {
+        let specialization = Specialization::new();
+        assert!(specialization.is_empty());
+        assert_eq!(specialization.info().map_entry_count, 0);
+    }
+}
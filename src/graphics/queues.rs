@@ -1,3 +1,5 @@
+use std::slice;
+
 use ash::{
     Device,
     vk::{self, Queue},
@@ -30,7 +32,7 @@ impl Queues {
 
 //
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct QueueFamilies {
     pub present: u32,
     pub graphics: u32,
@@ -39,3 +41,285 @@ pub struct QueueFamilies {
 
     pub families: Box<[vk::DeviceQueueCreateInfo<'static>]>,
 }
+
+impl QueueFamilies {
+    /// the raymarch is submitted to its own compute queue,
+    /// instead of the graphics queue with everything else
+    pub fn async_compute(&self) -> bool {
+        self.compute != self.graphics
+    }
+
+    /// submit the raymarch to the graphics queue even if
+    /// a separate compute family was found
+    pub fn disable_async_compute(&mut self) {
+        self.compute = self.graphics;
+    }
+}
+
+//
+
+/// what a [`QueueTransfer`] hands over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// the whole buffer
+    Buffer(vk::Buffer),
+    /// every color mip and layer, the transfer also changes the layout
+    Image {
+        image: vk::Image,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    },
+}
+
+/// queue family ownership transfer of an exclusive [`Resource`],
+/// the release is recorded on the `src` family and the acquire on the `dst`
+/// family, in a submit that waits for a semaphore the release submit signals,
+/// within one family neither records anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueTransfer {
+    pub src: u32,
+    pub dst: u32,
+}
+
+impl QueueTransfer {
+    pub const fn new(src: u32, dst: u32) -> Self {
+        Self { src, dst }
+    }
+
+    pub const fn is_needed(&self) -> bool {
+        self.src != self.dst
+    }
+
+    /// the other direction
+    pub const fn back(&self) -> Self {
+        Self::new(self.dst, self.src)
+    }
+
+    /// the release half, the destination stage and access are ignored
+    pub fn release(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        resource: Resource,
+        src_stage: vk::PipelineStageFlags2,
+        src_access: vk::AccessFlags2,
+    ) {
+        if let Some(barrier) = self.release_barrier(resource, src_stage, src_access) {
+            barrier.record(device, cbuf);
+        }
+    }
+
+    /// the acquire half, the source stage and access are ignored
+    pub fn acquire(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        resource: Resource,
+        dst_stage: vk::PipelineStageFlags2,
+        dst_access: vk::AccessFlags2,
+    ) {
+        if let Some(barrier) = self.acquire_barrier(resource, dst_stage, dst_access) {
+            barrier.record(device, cbuf);
+        }
+    }
+
+    fn release_barrier(
+        &self,
+        resource: Resource,
+        src_stage: vk::PipelineStageFlags2,
+        src_access: vk::AccessFlags2,
+    ) -> Option<Barrier> {
+        self.is_needed().then(|| {
+            self.barrier(resource)
+                .src(src_stage, src_access)
+                .dst(vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE)
+        })
+    }
+
+    fn acquire_barrier(
+        &self,
+        resource: Resource,
+        dst_stage: vk::PipelineStageFlags2,
+        dst_access: vk::AccessFlags2,
+    ) -> Option<Barrier> {
+        self.is_needed().then(|| {
+            self.barrier(resource)
+                .src(vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE)
+                .dst(dst_stage, dst_access)
+        })
+    }
+
+    /// a plain barrier if both families are the same
+    pub fn barrier(&self, resource: Resource) -> Barrier {
+        let (src, dst) = if self.is_needed() {
+            (self.src, self.dst)
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        };
+        match resource {
+            Resource::Buffer(buffer) => Barrier::Buffer(
+                vk::BufferMemoryBarrier2::default()
+                    .src_queue_family_index(src)
+                    .dst_queue_family_index(dst)
+                    .buffer(buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE),
+            ),
+            Resource::Image {
+                image,
+                old_layout,
+                new_layout,
+            } => Barrier::Image(
+                vk::ImageMemoryBarrier2::default()
+                    .src_queue_family_index(src)
+                    .dst_queue_family_index(dst)
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .image(image)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::default()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .level_count(vk::REMAINING_MIP_LEVELS)
+                            .layer_count(vk::REMAINING_ARRAY_LAYERS),
+                    ),
+            ),
+        }
+    }
+}
+
+/// a barrier of a [`QueueTransfer`], see [`Barrier::src`] and [`Barrier::dst`]
+#[derive(Debug, Clone, Copy)]
+pub enum Barrier {
+    Buffer(vk::BufferMemoryBarrier2<'static>),
+    Image(vk::ImageMemoryBarrier2<'static>),
+}
+
+impl Barrier {
+    pub fn src(self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        match self {
+            Self::Buffer(b) => Self::Buffer(b.src_stage_mask(stage).src_access_mask(access)),
+            Self::Image(b) => Self::Image(b.src_stage_mask(stage).src_access_mask(access)),
+        }
+    }
+
+    pub fn dst(self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        match self {
+            Self::Buffer(b) => Self::Buffer(b.dst_stage_mask(stage).dst_access_mask(access)),
+            Self::Image(b) => Self::Image(b.dst_stage_mask(stage).dst_access_mask(access)),
+        }
+    }
+
+    pub fn record(&self, device: &Device, cbuf: vk::CommandBuffer) {
+        let dependency_info = match self {
+            Self::Buffer(b) => {
+                vk::DependencyInfo::default().buffer_memory_barriers(slice::from_ref(b))
+            }
+            Self::Image(b) => {
+                vk::DependencyInfo::default().image_memory_barriers(slice::from_ref(b))
+            }
+        };
+        unsafe { device.cmd_pipeline_barrier2(cbuf, &dependency_info) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vk::{AccessFlags2 as A, Handle, PipelineStageFlags2 as S};
+
+    use super::*;
+
+    fn buffer() -> Resource {
+        Resource::Buffer(vk::Buffer::from_raw(7))
+    }
+
+    #[test]
+    fn async_compute_override() {
+        let mut families = QueueFamilies {
+            present: 0,
+            graphics: 0,
+            transfer: 2,
+            compute: 1,
+            families: Box::new([]),
+        };
+        assert!(families.async_compute());
+
+        families.disable_async_compute();
+        assert!(!families.async_compute());
+        assert_eq!((families.graphics, families.compute, families.transfer), (0, 0, 2));
+    }
+
+    #[test]
+    fn same_family_records_nothing() {
+        let transfer = QueueTransfer::new(2, 2);
+        assert!(!transfer.is_needed());
+        assert!(transfer.release_barrier(buffer(), S::ALL_TRANSFER, A::TRANSFER_WRITE).is_none());
+        assert!(transfer.acquire_barrier(buffer(), S::COMPUTE_SHADER, A::SHADER_READ).is_none());
+
+        // plain barriers leave the families alone
+        let Barrier::Buffer(barrier) = transfer.barrier(buffer()) else {
+            panic!("not a buffer barrier");
+        };
+        assert_eq!(barrier.src_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+        assert_eq!(barrier.dst_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+    }
+
+    #[test]
+    fn buffer_handoff() {
+        let transfer = QueueTransfer::new(1, 0);
+        let Some(Barrier::Buffer(release)) =
+            transfer.release_barrier(buffer(), S::ALL_TRANSFER, A::TRANSFER_WRITE)
+        else {
+            panic!("no buffer release");
+        };
+        let Some(Barrier::Buffer(acquire)) =
+            transfer.acquire_barrier(buffer(), S::COMPUTE_SHADER, A::SHADER_STORAGE_READ)
+        else {
+            panic!("no buffer acquire");
+        };
+
+        for barrier in [release, acquire] {
+            assert_eq!(barrier.src_queue_family_index, 1);
+            assert_eq!(barrier.dst_queue_family_index, 0);
+            assert_eq!(barrier.buffer, vk::Buffer::from_raw(7));
+            assert_eq!(barrier.size, vk::WHOLE_SIZE);
+        }
+        // each half only has its own side of the dependency
+        assert_eq!(
+            (release.src_stage_mask, release.src_access_mask),
+            (S::ALL_TRANSFER, A::TRANSFER_WRITE)
+        );
+        assert_eq!((release.dst_stage_mask, release.dst_access_mask), (S::NONE, A::NONE));
+        assert_eq!((acquire.src_stage_mask, acquire.src_access_mask), (S::NONE, A::NONE));
+        assert_eq!(
+            (acquire.dst_stage_mask, acquire.dst_access_mask),
+            (S::COMPUTE_SHADER, A::SHADER_STORAGE_READ)
+        );
+
+        assert_eq!(transfer.back(), QueueTransfer::new(0, 1));
+    }
+
+    #[test]
+    fn image_handoff() {
+        let image = Resource::Image {
+            image: vk::Image::from_raw(9),
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let transfer = QueueTransfer::new(1, 0);
+        let release = transfer.release_barrier(image, S::ALL_TRANSFER, A::TRANSFER_WRITE);
+        let acquire = transfer.acquire_barrier(image, S::FRAGMENT_SHADER, A::SHADER_SAMPLED_READ);
+
+        // both halves have to do the same layout transition
+        for barrier in [release, acquire] {
+            let Some(Barrier::Image(barrier)) = barrier else {
+                panic!("no image barrier");
+            };
+            assert_eq!(barrier.old_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+            assert_eq!(barrier.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+            assert_eq!(barrier.src_queue_family_index, 1);
+            assert_eq!(barrier.dst_queue_family_index, 0);
+            assert_eq!(barrier.subresource_range.aspect_mask, vk::ImageAspectFlags::COLOR);
+            assert_eq!(barrier.subresource_range.level_count, vk::REMAINING_MIP_LEVELS);
+        }
+    }
+}
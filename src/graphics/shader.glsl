@@ -4,19 +4,27 @@
 // #extension GL_EXT_shader_8bit_storage : enable
 #extension GL_EXT_shader_explicit_arithmetic_types : enable
 
-layout(local_size_x = 16, local_size_y = 16) in;
+// specialized to LUMINARY_WORKGROUP_SIZE, 16 by default
+layout(local_size_x_id = 0, local_size_y_id = 1) in;
 
 layout(rgba16f, set = 0, binding = 0) uniform image2D image;
 
+// same as NodeLayout
 struct Voxel {
     uint32_t col;
-    // 15 bit pointer (relative to this block) to 8 children within the current block,
-    // the last 1 bit tells if it is actually a pointer to a far pointer
+    // index of the first of 8 consecutive children
+#ifdef VOXEL32
+    uint32_t child_pointer;
+#else
     uint16_t child_pointer;
+#endif
     // which children are non-leaf voxels
     uint8_t valid_mask;
     // which children are leaf voxels
     uint8_t leaf_mask;
+#ifdef VOXEL32
+    uint16_t _pad;
+#endif
 };
 
 layout(std430, set = 0, binding = 1) readonly buffer VoxelStorage {
@@ -26,8 +34,26 @@ layout(std430, set = 0, binding = 1) readonly buffer VoxelStorage {
 layout(push_constant) uniform PushConstant {
     mat4x4 projection_view;
     uint mode_flags;
+    // rays stop and miss after travelling this far, can be infinite
+    float max_ray_distance;
+    // primary rays skip voxels closer than this
+    float min_ray_distance;
+    // the part of the image that is shown, width in the low 16 bits
+    uint render_extent;
+    // same as GridPushConst, a spacing of 0 hides the grid
+    float grid_spacing;
+    float grid_width;
+    float grid_fade_distance;
+    uint grid_color;
 } push;
 
+// same as PushConst::WRAP_FLAG
+const uint WRAP_FLAG = 16;
+
+bool is_wrapping() {
+    return (push.mode_flags & WRAP_FLAG) != 0;
+}
+
 //
 
 uint get_voxel_linear(ivec3 world_pos) {
@@ -44,6 +70,13 @@ uint get_voxel_linear(ivec3 world_pos) {
 }
 
 uint get_voxel(ivec3 world_pos) {
+    if (is_wrapping()) {
+        // euclidean modulo, also for negative coordinates
+        world_pos &= 31;
+    } else if (any(lessThan(world_pos, ivec3(0))) || any(greaterThanEqual(world_pos, ivec3(32)))) {
+        return 0;
+    }
+
     vec3 center = vec3(16.0);
     float half_span = 8.0;
 
@@ -118,17 +151,23 @@ bool ray_aabb(
     // return (sign(t_close_f) > 0.0 && t_close_f <= t_far_f) || (all(lessThanEqual(low, ray_origin)) && all(lessThanEqual(ray_origin, high)));
 }
 
-void ray_cast_linear(vec3 ray_origin, vec3 ray_dir, bool skip_first, out HitData hit_data) {
+// voxels closer than `clip` are skipped
+void ray_cast_linear(vec3 ray_origin, vec3 ray_dir, bool skip_first, float clip, out HitData hit_data) {
     float t_close_f, t_far_f;
-    if (!ray_aabb(ray_origin, ray_dir, vec3(0.0), vec3(32.0), t_close_f, t_far_f)) {
+    if (is_wrapping()) {
+        // the world repeats forever, so the ray starts where it is
+        t_close_f = 0.0;
+    } else if (!ray_aabb(ray_origin, ray_dir, vec3(0.0), vec3(32.0), t_close_f, t_far_f)) {
         hit_data.position = ray_origin;
         hit_data.hit = false;
         hit_data.steps = 0;
         return;
     }
 
-    // start DDA from the voxel AABB edge, if it starts outside
-    ray_origin += ray_dir * max(t_close_f, 0.0);
+    // start DDA from the voxel AABB edge, if it starts outside,
+    // or from the clip distance, if that is further
+    float t_start = max(max(t_close_f, 0.0), clip);
+    ray_origin += ray_dir * t_start;
         
     vec3 ray_origin_grid = floor(ray_origin);
     ivec3 world_pos = ivec3(ray_origin_grid);
@@ -156,19 +195,33 @@ void ray_cast_linear(vec3 ray_origin, vec3 ray_dir, bool skip_first, out HitData
         }
 
         mask = lessThanEqual(next_dist.xyz, min(next_dist.yzx, next_dist.zxy));
+        if (min(next_dist.x, min(next_dist.y, next_dist.z)) + t_start > push.max_ray_distance) {
+            break;
+        }
         next_dist += vec3(mask) * ray_dist;
         world_pos += ivec3(vec3(mask) * ray_sign);
 
-        if (!(all(lessThanEqual(ivec3(0), world_pos)) && all(lessThanEqual(world_pos, ivec3(32))))) {
+        if (!is_wrapping() && !(all(lessThanEqual(ivec3(0), world_pos)) && all(lessThanEqual(world_pos, ivec3(32))))) {
             break;
         }
     }
 
+    if (hit_data.hit && hit_data.steps == 0 && clip > 0.0 && t_start == clip) {
+        // started inside a clipped voxel, so the cut is shaded like a face
+        vec3 dir_abs = abs(ray_dir);
+        mask = greaterThanEqual(dir_abs, max(dir_abs.yzx, dir_abs.zxy));
+        hit_data.voxel = world_pos;
+        hit_data.normal = -vec3(ray_sign) * vec3(mask);
+        hit_data.distance = t_start;
+        hit_data.position = ray_origin;
+        return;
+    }
+
     hit_data.voxel = world_pos;
     hit_data.normal = -vec3(ray_sign) * vec3(mask);
     hit_data.distance = length(vec3(mask) * (next_dist - ray_dist));
     hit_data.position = ray_origin + ray_dir * hit_data.distance;
-    hit_data.distance += max(t_close_f, 0.0);
+    hit_data.distance += t_start;
 }
 
 uint select_child(vec3 t_coeff, vec3 t_bias, vec3 center, vec3 point) {
@@ -177,7 +230,8 @@ uint select_child(vec3 t_coeff, vec3 t_bias, vec3 center, vec3 point) {
     return uint(bitmask.x) | (uint(bitmask.y) << 1) | (uint(bitmask.z) << 2);
 }
 
-void ray_cast(vec3 ray_origin, vec3 ray_dir, bool skip_first, out HitData hit_data) {
+// voxels closer than `clip` are skipped
+void ray_cast(vec3 ray_origin, vec3 ray_dir, bool skip_first, float clip, out HitData hit_data) {
     // float t_close_f, t_far_f;
     // if (!ray_aabb(ray_origin, ray_dir, vec3(0.0), vec3(32.0), t_close_f, t_far_f)) {
     //     hit_data.position = ray_origin;
@@ -195,19 +249,20 @@ void ray_cast(vec3 ray_origin, vec3 ray_dir, bool skip_first, out HitData hit_da
     vec3 t_high = t_coeff * 32.0 + t_bias;
     vec3 t_close = min(t_low, t_high);
     vec3 t_far = max(t_low, t_high);
-    float t_min = max(t_close.x, max(t_close.y, t_close.z));
+    float t_min = max(max(t_close.x, max(t_close.y, t_close.z)), clip);
     float t_max = min(t_far.x, min(t_far.y, t_far.z));
 
-    hit_data.hit = t_min <= t_max;
+    hit_data.hit = t_min <= t_max && t_min <= push.max_ray_distance;
     hit_data.distance = t_min;
 
-    float stop = t_max;
+    float stop = min(t_max, push.max_ray_distance);
     uint parent = 0; // root node
     uint idx = select_child(t_coeff, t_bias, vec3(16), vec3(t_min));
 
     hit_data.distance = float(idx) * 5.0;
 }
 
+// MaterialInfo::PALETTE in voxels.rs has the same colors
 vec4 palette[] = {
     vec4(0.000, 0.000, 0.000, 0.0),
     vec4(0.000, 0.453, 0.668, 1.0),
@@ -215,46 +270,115 @@ vec4 palette[] = {
     vec4(0.746, 0.914, 1.000, 1.0),
 };
 
+// the camera ray through `pixel` of an image of `size` pixels
+void primary_ray(vec2 pixel, vec2 size, out vec3 origin, out vec3 dir) {
+    vec2 plane_pos = pixel / size * 2.0 - 1.0;
+    vec4 ray_origin = push.projection_view * vec4(plane_pos, 0.0, 1.0);
+    vec4 ray_target = push.projection_view * vec4(plane_pos, 1.0, 1.0);
+    ray_origin.xyz /= ray_origin.w;
+    ray_target.xyz /= ray_target.w;
+    origin = ray_origin.xyz;
+    dir = normalize(ray_target.xyz - ray_origin.xyz);
+}
+
+// where the ray of `pixel` crosses the y=0 plane,
+// w is the distance along the ray, not positive if it never does
+vec4 ground_hit(vec2 pixel, vec2 size) {
+    vec3 origin;
+    vec3 dir;
+    primary_ray(pixel, size, origin, dir);
+    float t = -origin.y / dir.y;
+    return vec4(origin + dir * t, t);
+}
+
+// coverage of the grid lines at `pos`, `footprint` is the size of the
+// pixel on the plane, analytic antialiasing after Ben Golus' pristine grid
+float grid_coverage(vec2 pos, vec2 footprint) {
+    vec2 uv = pos / push.grid_spacing;
+    vec2 uv_deriv = footprint / push.grid_spacing;
+    vec2 line_width = vec2(push.grid_width / push.grid_spacing);
+    vec2 draw_width = clamp(line_width, uv_deriv, vec2(0.5));
+    vec2 line_aa = uv_deriv * 1.5;
+    vec2 grid_uv = 1.0 - abs(fract(uv) * 2.0 - 1.0);
+    vec2 lines = 1.0 - smoothstep(draw_width - line_aa, draw_width + line_aa, grid_uv);
+    // lines thinner than a pixel get fainter instead
+    lines *= clamp(line_width / draw_width, 0.0, 1.0);
+    // pixels covering whole cells get the average coverage,
+    // which is what stops the shimmer at grazing angles
+    lines = mix(lines, line_width, clamp(uv_deriv * 2.0 - 1.0, 0.0, 1.0));
+    return mix(lines.x, 1.0, lines.y);
+}
+
+// the ground grid blended over `col`, if the plane is closer than `depth`
+vec4 add_grid(vec4 col, vec2 pixel, vec2 size, float depth) {
+    if (push.grid_spacing <= 0.0) {
+        return col;
+    }
+    vec4 ground = ground_hit(pixel, size);
+    if (ground.w <= 0.0 || ground.w >= depth) {
+        return col;
+    }
+
+    // compute shaders have no screen-space derivatives,
+    // so they come from the rays of the neighbouring pixels,
+    // a neighbour that misses the plane counts as a whole cell
+    vec4 ground_x = ground_hit(pixel + vec2(1.0, 0.0), size);
+    vec4 ground_y = ground_hit(pixel + vec2(0.0, 1.0), size);
+    vec3 dx = ground_x.w > 0.0 ? ground_x.xyz - ground.xyz : vec3(push.grid_spacing);
+    vec3 dy = ground_y.w > 0.0 ? ground_y.xyz - ground.xyz : vec3(push.grid_spacing);
+    vec2 footprint = vec2(length(vec2(dx.x, dy.x)), length(vec2(dx.z, dy.z)));
+
+    vec4 grid_col = unpackUnorm4x8(push.grid_color);
+    float fade = 1.0 - smoothstep(0.5 * push.grid_fade_distance, push.grid_fade_distance, ground.w);
+    float alpha = grid_coverage(ground.xz, footprint) * grid_col.a * fade;
+    return vec4(mix(col.rgb, grid_col.rgb, alpha), col.a);
+}
+
 void main() {
     vec3 sun_dir = normalize(vec3(0.5, 1.0, 0.75));
     ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
-    ivec2 size = imageSize(image);
+    ivec2 size = ivec2(push.render_extent & 0xFFFF, push.render_extent >> 16);
 
-    if (coord.x >= size.x || coord.y >= size.y) {
+    // one extra column and row repeat the edge, so filtering
+    // just past the shown part doesn't pick up stale pixels
+    if (coord.x > size.x || coord.y > size.y
+        || any(greaterThanEqual(coord, imageSize(image)))) {
         return;
     }
 
-    vec2 plane_pos = vec2(coord.xy) / vec2(size.xy) * 2.0 - 1.0;
-    vec4 ray_origin = push.projection_view * vec4(plane_pos, 0.0, 1.0);
-    vec4 ray_target = push.projection_view * vec4(plane_pos, 1.0, 1.0);
-    ray_origin.xyz /= ray_origin.w;
-    ray_target.xyz /= ray_target.w;
-    vec3 ray_dir = normalize(ray_target.xyz - ray_origin.xyz);
+    vec2 pixel = vec2(min(coord, size - 1));
+    vec3 ray_origin;
+    vec3 ray_dir;
+    primary_ray(pixel, vec2(size), ray_origin, ray_dir);
 
     // camera to world cast
     HitData hit_data;
-    ray_cast(ray_origin.xyz, ray_dir, false, hit_data);
+    ray_cast(ray_origin, ray_dir, false, push.min_ray_distance, hit_data);
 
+#ifdef DEBUG_VISUALS
     if ((push.mode_flags & 8) != 0) {
         imageStore(image, coord, vec4(vec3(float(hit_data.steps) / 50), 1.0));
         return;
     }
+#endif
 
     if (!hit_data.hit) {
         float sky = smoothstep(0.998, 1.0, dot(sun_dir, ray_dir));
-        imageStore(image, coord, vec4(vec3(sky), 1.0));
+        vec4 col = add_grid(vec4(vec3(sky), 1.0), pixel, vec2(size), push.max_ray_distance);
+        imageStore(image, coord, col);
         return;
     }
 
     // shadow cast
     HitData light_hit_data;
-    ray_cast(hit_data.position + hit_data.normal * 0.005, sun_dir, true, light_hit_data);
+    ray_cast(hit_data.position + hit_data.normal * 0.005, sun_dir, true, 0.0, light_hit_data);
     float brightness = float(light_hit_data.hit) * 0.05 + float(!light_hit_data.hit) * dot(sun_dir, hit_data.normal);
 
     uint voxel_col = get_voxel(hit_data.voxel);
     vec4 col = palette[voxel_col];
     col.xyz *= brightness;
 
+#ifdef DEBUG_VISUALS
     if ((push.mode_flags & 1) != 0) {
         col = vec4(vec3(brightness), 1.0);
     } else if ((push.mode_flags & 2) != 0) {
@@ -262,6 +386,8 @@ void main() {
     } else if ((push.mode_flags & 4) != 0) {
         col = vec4(vec3(hit_data.normal), 1.0);
     }  
-    
+#endif
+
+    col = add_grid(col, pixel, vec2(size), hit_data.distance);
     imageStore(image, coord, col);
 }
@@ -1,7 +1,7 @@
-use std::{intrinsics::const_allocate, slice};
+use std::{fs, intrinsics::const_allocate, path::Path, slice};
 
 use ash::{Device, vk};
-use eyre::Result;
+use eyre::{Result, bail, eyre};
 
 use super::delete_queue::DeleteQueue;
 
@@ -12,11 +12,36 @@ pub struct Shader {
 }
 
 impl Shader {
+    /// the first word of every SPIR-V module
+    pub const SPIRV_MAGIC: u32 = 0x0723_0203;
+    /// magic, version, generator, bound and schema
+    pub const SPIRV_HEADER_WORDS: usize = 5;
+
     pub const DEFAULT_COMP: &[u32] = read_shader(include_bytes!(concat!(
         env!("OUT_DIR"),
         "/shader.comp.spirv"
     )));
 
+    pub const FXAA_COMP: &[u32] = read_shader(include_bytes!(concat!(
+        env!("OUT_DIR"),
+        "/fxaa.comp.spirv"
+    )));
+
+    pub const HUD_COMP: &[u32] = read_shader(include_bytes!(concat!(
+        env!("OUT_DIR"),
+        "/hud.comp.spirv"
+    )));
+
+    pub const HDR_COMP: &[u32] = read_shader(include_bytes!(concat!(
+        env!("OUT_DIR"),
+        "/hdr.comp.spirv"
+    )));
+
+    pub const TONEMAP_COMP: &[u32] = read_shader(include_bytes!(concat!(
+        env!("OUT_DIR"),
+        "/tonemap.comp.spirv"
+    )));
+
     pub fn new(device: &Device, delete_queue: &mut DeleteQueue, code: &[u32]) -> Result<Self> {
         tracing::debug!("shader module size {}", code.len());
 
@@ -26,10 +51,57 @@ impl Shader {
 
         Ok(Self { module })
     }
+
+    /// load a SPIR-V module at runtime instead of embedding it,
+    /// files that are not SPIR-V are rejected before reaching the driver
+    pub fn from_spirv_file(
+        device: &Device,
+        delete_queue: &mut DeleteQueue,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)
+            .map_err(|err| eyre!("failed to read shader '{}': {err}", path.display()))?;
+        let code = parse_spirv(&bytes)
+            .map_err(|err| eyre!("invalid shader '{}': {err}", path.display()))?;
+
+        Self::new(device, delete_queue, &code)
+    }
 }
 
+// `Shader::<NAME>_<STAGE>` for every `.vert`, `.frag` and `.comp` file
+include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+
 //
 
+/// SPIR-V words from bytes in either byte order
+fn parse_spirv(bytes: &[u8]) -> Result<Vec<u32>> {
+    if !bytes.len().is_multiple_of(4) {
+        bail!("the length {}B is not a multiple of 4", bytes.len());
+    }
+    if bytes.len() < Shader::SPIRV_HEADER_WORDS * 4 {
+        bail!("truncated, {}B is shorter than the SPIR-V header", bytes.len());
+    }
+
+    let magic = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    let from_bytes = if u32::from_le_bytes(magic) == Shader::SPIRV_MAGIC {
+        u32::from_le_bytes
+    } else if u32::from_be_bytes(magic) == Shader::SPIRV_MAGIC {
+        u32::from_be_bytes
+    } else {
+        bail!(
+            "bad magic number {:#010x}, expected {:#010x}",
+            u32::from_le_bytes(magic),
+            Shader::SPIRV_MAGIC
+        );
+    };
+
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|word| from_bytes([word[0], word[1], word[2], word[3]]))
+        .collect())
+}
+
 const fn read_shader(bytes: &[u8]) -> &[u32] {
     if bytes.is_empty() {
         return &[];
@@ -51,3 +123,58 @@ const fn read_shader(bytes: &[u8]) -> &[u32] {
 
     unsafe { slice::from_raw_parts(ptr, size) }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn module(words: &[u32], to_bytes: fn(u32) -> [u8; 4]) -> Vec<u8> {
+        words.iter().flat_map(|word| to_bytes(*word)).collect()
+    }
+
+    const WORDS: [u32; 6] = [Shader::SPIRV_MAGIC, 0x0001_0600, 0, 8, 0, 0x0002_0011];
+
+    #[test]
+    fn either_byte_order() {
+        assert_eq!(parse_spirv(&module(&WORDS, u32::to_le_bytes)).unwrap(), WORDS);
+        assert_eq!(parse_spirv(&module(&WORDS, u32::to_be_bytes)).unwrap(), WORDS);
+    }
+
+    #[test]
+    fn bad_header() {
+        let bytes = module(&WORDS, u32::to_le_bytes);
+        let err = |bytes: &[u8]| parse_spirv(bytes).unwrap_err().to_string();
+
+        assert!(err(&bytes[..bytes.len() - 1]).contains("multiple of 4"));
+        assert!(err(&bytes[..Shader::SPIRV_HEADER_WORDS * 4 - 4]).contains("truncated"));
+        assert!(err(&[]).contains("truncated"));
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] ^= 0xFF;
+        assert!(err(&wrong_magic).contains("bad magic number"));
+        // GLSL source is the usual mistake
+        assert!(err(b"#version 460\n\0\0\0\0\0\0\0").contains("bad magic number"));
+    }
+
+    #[test]
+    fn bad_file() {
+        // the device is only used once the file checks out
+        let device = unsafe { Device::load_with(|_| std::ptr::null(), vk::Device::null()) };
+        let mut delete_queue = DeleteQueue::new();
+        let dir = env::temp_dir().join(format!("luminary-shader-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let missing = dir.join("missing.spv");
+        let err = Shader::from_spirv_file(&device, &mut delete_queue, &missing).err().unwrap();
+        assert!(err.to_string().contains("failed to read shader"), "{err}");
+
+        let truncated = dir.join("truncated.spv");
+        fs::write(&truncated, &module(&WORDS, u32::to_le_bytes)[..8]).unwrap();
+        let err = Shader::from_spirv_file(&device, &mut delete_queue, &truncated).err().unwrap();
+        assert!(err.to_string().contains("invalid shader"), "{err}");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -20,7 +20,6 @@ pub struct Surface {
     destroy_fp: vk::PFN_vkDestroySurfaceKHR,
 
     /// keeps the surface alive
-    #[allow(dead_code)]
     window: Arc<Window>,
 }
 
@@ -44,6 +43,10 @@ impl Surface {
         })
     }
 
+    pub fn window(&self) -> &Arc<Window> {
+        &self.window
+    }
+
     pub fn destroy(&mut self, instance: &Instance) {
         if self.inner.is_null() {
             cold();
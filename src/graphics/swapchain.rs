@@ -1,16 +1,20 @@
 use core::slice;
-use std::sync::Arc;
+use std::{sync::Arc, thread, time::Duration};
 
 use ash::{
     Device, Entry, Instance, khr,
     vk::{self, Handle},
 };
-use eyre::{Result, bail};
+use eyre::Result;
 use winit::window::Window;
 
-use crate::cold;
+use crate::{cold, profiling};
 
-use super::queues::QueueFamilies;
+use super::{
+    delete_queue::{DeleteQueue, DeletionEntry},
+    hdr::OutputEncoding,
+    queues::QueueFamilies,
+};
 
 //
 
@@ -22,14 +26,28 @@ pub struct Swapchain {
     gpu: vk::PhysicalDevice,
     pub extent: vk::Extent2D,
     format: vk::Format,
+    color_space: vk::ColorSpaceKHR,
+    present_mode: vk::PresentModeKHR,
+    /// picks `present_mode` from the available modes
+    preference: PresentModePreference,
+    /// the selection is logged again on the next recreate
+    preference_changed: bool,
+    /// pick one of [`OutputEncoding::HDR_FORMATS`] if available
+    hdr: bool,
     images: Box<[vk::Image]>,
     suboptimal: bool,
+    /// acquire timeout in nanoseconds, `u64::MAX` waits forever
+    timeout: u64,
 
     surface_loader: khr::surface::Instance,
     swapchain_loader: khr::swapchain::Device,
 }
 
 impl Swapchain {
+    pub const DEFAULT_TIMEOUT: u64 = 1_000_000_000; // 1 sec
+    /// out of date acquires in a row before the frame is skipped
+    const MAX_RECREATES: u32 = 4;
+
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         entry: &Entry,
@@ -40,6 +58,8 @@ impl Swapchain {
         surface: vk::SurfaceKHR,
         extent: vk::Extent2D,
         window: Arc<Window>,
+        preference: PresentModePreference,
+        hdr: bool,
     ) -> Result<Self> {
         let surface_loader = khr::surface::Instance::new(entry, instance);
         let swapchain_loader = khr::swapchain::Device::new(instance, device);
@@ -52,21 +72,33 @@ impl Swapchain {
             surface,
             extent,
             window,
+            Self::DEFAULT_TIMEOUT,
+            preference,
+            None,
+            hdr,
+            vk::SwapchainKHR::null(),
         )?;
         Ok(res)
     }
 
-    pub fn recreate(&mut self, device: &Device, queue_families: &QueueFamilies) -> Result<()> {
-        _ = unsafe { device.device_wait_idle() };
-
-        let size = self.window.inner_size();
-        let extent = vk::Extent2D {
-            width: size.width,
-            height: size.height,
-        };
-
-        self.destroy();
+    /// keeps the old swapchain while the window has no area,
+    /// it is recreated again once the window has a size
+    ///
+    /// the old swapchain is retired into `delete_queue`, which has to be
+    /// flushed only after the frames that used its images have finished
+    pub fn recreate(
+        &mut self,
+        delete_queue: &mut DeleteQueue,
+        queue_families: &QueueFamilies,
+    ) -> Result<()> {
+        let extent = self.window_extent();
+        if extent.width == 0 || extent.height == 0 {
+            tracing::debug!("zero window extent, keeping the swapchain");
+            self.suboptimal = true;
+            return Ok(());
+        }
 
+        let old_swapchain = self.inner;
         *self = Self::create(
             self.surface_loader.clone(),
             self.swapchain_loader.clone(),
@@ -75,26 +107,89 @@ impl Swapchain {
             self.surface,
             extent,
             self.window.clone(),
+            self.timeout,
+            self.preference,
+            (!self.preference_changed).then_some(self.present_mode),
+            self.hdr,
+            old_swapchain,
         )?;
+        if !old_swapchain.is_null() {
+            delete_queue
+                .push(DeletionEntry::Swapchain(old_swapchain, self.swapchain_loader.clone()));
+        }
 
         Ok(())
     }
 
+    /// current size of the window, zero if it is minimized
+    pub fn window_extent(&self) -> vk::Extent2D {
+        let size = self.window.inner_size();
+        vk::Extent2D {
+            width: size.width,
+            height: size.height,
+        }
+    }
+
+    /// format of the swapchain images, UNORM formats expect
+    /// the values to be encoded for [`Self::color_space`] already
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn color_space(&self) -> vk::ColorSpaceKHR {
+        self.color_space
+    }
+
+    /// what the presented images have to be converted to
+    pub fn encoding(&self) -> OutputEncoding {
+        OutputEncoding::of(vk::SurfaceFormatKHR {
+            format: self.format,
+            color_space: self.color_space,
+        })
+    }
+
+    /// `u64::MAX` nanoseconds waits forever
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout.as_nanos().try_into().unwrap_or(u64::MAX);
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_nanos(self.timeout)
+    }
+
+    /// marks the swapchain suboptimal, so the next [`Self::acquire`]
+    /// recreates it with the new present mode
+    pub fn set_present_mode_preference(&mut self, preference: PresentModePreference) {
+        if preference == self.preference {
+            return;
+        }
+
+        self.preference = preference;
+        self.preference_changed = true;
+        self.suboptimal = true;
+    }
+
+    pub fn present_mode_preference(&self) -> PresentModePreference {
+        self.preference
+    }
+
+    /// `None` if no image could be acquired this time and the frame should be skipped
     pub fn acquire(
         &mut self,
-        device: &Device,
+        delete_queue: &mut DeleteQueue,
         on_acquire: vk::Semaphore,
         queue_families: &QueueFamilies,
-    ) -> Result<SwapchainImage> {
-        loop {
+    ) -> Result<Option<SwapchainImage>> {
+        profiling::zone!("acquire");
+        for _ in 0..Self::MAX_RECREATES {
             if self.suboptimal {
-                self.recreate(device, queue_families)?;
+                self.recreate(delete_queue, queue_families)?;
             }
 
             let res = unsafe {
                 self.swapchain_loader.acquire_next_image(
                     self.inner,
-                    1_000_000_000, // 1 sec
+                    self.timeout,
                     on_acquire,
                     vk::Fence::null(),
                 )
@@ -103,23 +198,31 @@ impl Swapchain {
             match res {
                 Ok((index, suboptimal)) => {
                     self.suboptimal |= suboptimal;
-                    return Ok(SwapchainImage {
+                    return Ok(Some(SwapchainImage {
                         image: self.images[index as usize],
                         index,
-                    });
+                    }));
+                }
+                // nothing is signaled, the frame is skipped and acquired again next time
+                Err(vk::Result::NOT_READY) => {
+                    thread::yield_now();
+                    return Ok(None);
                 }
-                Err(vk::Result::NOT_READY) => continue,
                 Err(vk::Result::TIMEOUT) => {
-                    bail!("swapchain timeout")
+                    tracing::warn!("swapchain timeout ({:?}), skipping a frame", self.timeout());
+                    return Ok(None);
                 }
                 Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                    self.recreate(device, queue_families)?;
+                    self.suboptimal = true;
                 }
                 Err(err) => {
                     eyre::bail!("failed to acquire next image: {err}")
                 }
             }
         }
+
+        tracing::warn!("swapchain still out of date after {} recreates", Self::MAX_RECREATES);
+        Ok(None)
     }
 
     pub fn present(
@@ -128,6 +231,7 @@ impl Swapchain {
         queue: vk::Queue,
         wait_for: vk::Semaphore,
     ) -> Result<()> {
+        profiling::zone!("present");
         let present_info = vk::PresentInfoKHR::default()
             .wait_semaphores(slice::from_ref(&wait_for))
             .swapchains(slice::from_ref(&self.inner))
@@ -147,6 +251,7 @@ impl Swapchain {
         self.inner = vk::SwapchainKHR::null();
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn create(
         surface_loader: khr::surface::Instance,
         swapchain_loader: khr::swapchain::Device,
@@ -155,14 +260,27 @@ impl Swapchain {
         surface: vk::SurfaceKHR,
         extent: vk::Extent2D,
         window: Arc<Window>,
+        timeout: u64,
+        preference: PresentModePreference,
+        previous_present_mode: Option<vk::PresentModeKHR>,
+        hdr: bool,
+        old_swapchain: vk::SwapchainKHR,
     ) -> Result<Self> {
         let surface_formats =
             unsafe { surface_loader.get_physical_device_surface_formats(gpu, surface)? };
         let surface_present_modes =
             unsafe { surface_loader.get_physical_device_surface_present_modes(gpu, surface)? };
 
-        let surface_format = Self::preferred_format(&surface_formats);
-        let present_mode = Self::preferred_present_mode(&surface_present_modes);
+        let surface_format =
+            Self::preferred_format(&surface_formats, hdr, previous_present_mode.is_none());
+        let present_mode = Self::preferred_present_mode(
+            preference,
+            &surface_present_modes,
+            previous_present_mode.is_none(),
+        );
+        if previous_present_mode.is_some_and(|previous| previous != present_mode) {
+            tracing::warn!("present mode changed to {present_mode:?}");
+        }
 
         let caps =
             unsafe { surface_loader.get_physical_device_surface_capabilities(gpu, surface)? };
@@ -205,7 +323,8 @@ impl Swapchain {
             .image_usage(vk::ImageUsageFlags::TRANSFER_DST)
             .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .clipped(true);
+            .clipped(true)
+            .old_swapchain(old_swapchain);
 
         let inner = unsafe { swapchain_loader.create_swapchain(&create_info, None)? };
 
@@ -219,15 +338,45 @@ impl Swapchain {
             gpu,
             extent,
             format: surface_format.format,
+            color_space: surface_format.color_space,
+            present_mode,
+            preference,
+            preference_changed: false,
+            hdr,
             images,
             suboptimal: false,
+            timeout,
 
             surface_loader,
             swapchain_loader,
         })
     }
 
-    fn preferred_format(formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
+    /// with `hdr` the first available of [`OutputEncoding::HDR_FORMATS`],
+    /// 8-bit sRGB otherwise or if none of them are available,
+    /// the selection is only logged with `log`
+    fn preferred_format(
+        formats: &[vk::SurfaceFormatKHR],
+        hdr: bool,
+        log: bool,
+    ) -> vk::SurfaceFormatKHR {
+        let hdr_format = OutputEncoding::HDR_FORMATS.iter().copied().find(|hdr_format| {
+            formats.iter().any(|f| {
+                f.format == hdr_format.format && f.color_space == hdr_format.color_space
+            })
+        });
+        if hdr && hdr_format.is_none() && log {
+            tracing::warn!("no HDR surface format in {formats:?}, using SDR");
+        }
+
+        let format = hdr_format.filter(|_| hdr).unwrap_or_else(|| Self::sdr_format(formats));
+        if log {
+            tracing::info!("swapchain format {:?} {:?}", format.format, format.color_space);
+        }
+        format
+    }
+
+    fn sdr_format(formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
         formats
             .iter()
             .copied()
@@ -238,12 +387,73 @@ impl Swapchain {
             .unwrap_or(formats[0])
     }
 
-    fn preferred_present_mode(present_modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
-        present_modes
+    /// the first available of [`PresentModePreference::fallbacks`],
+    /// the selection is only logged with `log`
+    fn preferred_present_mode(
+        preference: PresentModePreference,
+        present_modes: &[vk::PresentModeKHR],
+        log: bool,
+    ) -> vk::PresentModeKHR {
+        let requested = preference.mode();
+        let selected = preference
+            .fallbacks()
             .iter()
             .copied()
-            .find(|mode| *mode == vk::PresentModeKHR::MAILBOX)
-            .unwrap_or(vk::PresentModeKHR::FIFO)
+            .find(|mode| present_modes.contains(mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO);
+
+        if log && selected == requested {
+            tracing::info!("available present modes: {present_modes:?}, using {selected:?}");
+        } else if log {
+            tracing::warn!(
+                "present mode {requested:?} is not available in {present_modes:?}, falling back to {selected:?}"
+            );
+        }
+
+        selected
+    }
+}
+
+//
+
+/// the present mode [`Swapchain`] asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresentModePreference {
+    /// uncapped framerate without tearing
+    #[default]
+    Mailbox,
+    /// vsync, always available
+    Fifo,
+    /// uncapped framerate, might tear
+    Immediate,
+}
+
+impl PresentModePreference {
+    pub const ALL: [Self; 3] = [Self::Mailbox, Self::Fifo, Self::Immediate];
+
+    /// the next one of [`Self::ALL`], wrapping around
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|pref| *pref == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    pub fn mode(self) -> vk::PresentModeKHR {
+        self.fallbacks()[0]
+    }
+
+    /// the requested mode, then the ones tried if it is unavailable,
+    /// uncapped modes fall back to each other before FIFO
+    /// but MAILBOX never falls back to tearing
+    pub fn fallbacks(self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            Self::Mailbox => &[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO],
+            Self::Fifo => &[vk::PresentModeKHR::FIFO],
+            Self::Immediate => &[
+                vk::PresentModeKHR::IMMEDIATE,
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::FIFO,
+            ],
+        }
     }
 }
 
@@ -0,0 +1,212 @@
+use ash::{Device, Instance, vk};
+use eyre::Result;
+
+use super::delete_queue::DeleteQueue;
+use crate::profiling;
+
+//
+
+/// GPU timers for labeled regions of a command buffer,
+/// each frame in flight has its own range of queries
+pub struct TimestampPool {
+    /// null if the queue family does not support timestamps
+    pool: vk::QueryPool,
+    /// nanoseconds per timestamp tick
+    period: f32,
+    valid_mask: u64,
+    regions: u32,
+
+    frames: Box<[FrameQueries]>,
+    times: Vec<(&'static str, f32)>,
+    /// summed milliseconds and sample count per label,
+    /// since the last [`TimestampPool::take_averages`]
+    sums: Vec<(&'static str, f32, u32)>,
+}
+
+#[derive(Default)]
+struct FrameQueries {
+    labels: Vec<&'static str>,
+    /// number of timestamps written since the last reset
+    written: u32,
+}
+
+impl TimestampPool {
+    pub fn new(
+        instance: &Instance,
+        device: &Device,
+        gpu: vk::PhysicalDevice,
+        queue_family: u32,
+        frames: usize,
+        regions: u32,
+        delete_queue: &mut DeleteQueue,
+    ) -> Result<Self> {
+        let props = unsafe { instance.get_physical_device_properties(gpu) };
+        let valid_bits = unsafe {
+            instance.get_physical_device_queue_family_properties(gpu)
+        }[queue_family as usize]
+            .timestamp_valid_bits;
+
+        let pool = if valid_bits == 0 {
+            tracing::warn!("queue family {queue_family} has no timestamps");
+            vk::QueryPool::null()
+        } else {
+            let create_info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(regions * 2 * frames as u32);
+            let pool = unsafe { device.create_query_pool(&create_info, None)? };
+            delete_queue.push(pool);
+            pool
+        };
+
+        Ok(Self {
+            pool,
+            period: props.limits.timestamp_period,
+            valid_mask: u64::MAX >> (64 - valid_bits.clamp(1, 64)),
+            regions,
+
+            frames: (0..frames).map(|_| FrameQueries::default()).collect(),
+            times: Vec::new(),
+            sums: Vec::new(),
+        })
+    }
+
+    /// milliseconds per labeled region,
+    /// from the last frame that was read back
+    pub fn times(&self) -> &[(&'static str, f32)] {
+        &self.times
+    }
+
+    /// average milliseconds per labeled region since the last call
+    pub fn take_averages(&mut self) -> Vec<(&'static str, f32)> {
+        self.sums
+            .drain(..)
+            .map(|(label, sum, n)| (label, sum / n as f32))
+            .collect()
+    }
+
+    /// read the results written by `frame`,
+    /// the frame's fence has to be waited on before calling this
+    pub fn read(&mut self, device: &Device, frame: usize) -> Result<()> {
+        let queries = &self.frames[frame];
+        // unfinished regions are not read
+        let regions = queries.written as usize / 2;
+        if self.pool == vk::QueryPool::null() || regions == 0 {
+            return Ok(());
+        }
+
+        let mut results = vec![0u64; regions * 2];
+        unsafe {
+            device.get_query_pool_results(
+                self.pool,
+                self.first_query(frame),
+                &mut results,
+                vk::QueryResultFlags::TYPE_64,
+            )?
+        };
+
+        self.times.clear();
+        self.times.extend(
+            queries.labels.iter().zip(results.chunks_exact(2)).map(
+                |(label, pair)| {
+                    let ticks = pair[1].wrapping_sub(pair[0]) & self.valid_mask;
+                    (*label, ticks as f32 * self.period / 1_000_000.0)
+                },
+            ),
+        );
+
+        for &(label, ms) in &self.times {
+            profiling::gpu_zone(label, ms);
+            match self.sums.iter_mut().find(|(l, ..)| *l == label) {
+                Some((_, sum, n)) => {
+                    *sum += ms;
+                    *n += 1;
+                }
+                None => self.sums.push((label, ms, 1)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// reset the queries of `frame`, has to be recorded before any regions
+    pub fn reset(
+        &mut self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        self.frames[frame] = FrameQueries::default();
+        if self.pool == vk::QueryPool::null() {
+            return;
+        }
+
+        unsafe {
+            device.cmd_reset_query_pool(
+                cbuf,
+                self.pool,
+                self.first_query(frame),
+                self.regions * 2,
+            )
+        };
+    }
+
+    pub fn begin(
+        &mut self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        frame: usize,
+        label: &'static str,
+    ) {
+        let first_query = self.first_query(frame);
+        let queries = &mut self.frames[frame];
+        if self.pool == vk::QueryPool::null()
+            || !queries.written.is_multiple_of(2)
+            || queries.written >= self.regions * 2
+        {
+            return;
+        }
+
+        let query = first_query + queries.written;
+        queries.labels.push(label);
+        queries.written += 1;
+        unsafe {
+            device.cmd_write_timestamp2(
+                cbuf,
+                vk::PipelineStageFlags2::TOP_OF_PIPE,
+                self.pool,
+                query,
+            )
+        };
+    }
+
+    /// end the region that was last started with [`Self::begin`]
+    pub fn end(
+        &mut self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        let first_query = self.first_query(frame);
+        let queries = &mut self.frames[frame];
+        if self.pool == vk::QueryPool::null()
+            || queries.written.is_multiple_of(2)
+        {
+            return;
+        }
+
+        let query = first_query + queries.written;
+        queries.written += 1;
+        unsafe {
+            device.cmd_write_timestamp2(
+                cbuf,
+                vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+                self.pool,
+                query,
+            )
+        };
+    }
+
+    fn first_query(&self, frame: usize) -> u32 {
+        frame as u32 * self.regions * 2
+    }
+}
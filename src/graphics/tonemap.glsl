@@ -0,0 +1,52 @@
+#version 460
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+// the render target and the FXAA target, `source` selects which one is read
+layout(rgba16f, set = 0, binding = 0) uniform readonly image2D image0;
+layout(rgba16f, set = 0, binding = 1) uniform readonly image2D image1;
+// LDR output, blitted to the swapchain afterwards
+layout(rgba16f, set = 0, binding = 2) uniform writeonly image2D target;
+
+layout(push_constant) uniform PushConstant {
+    // 0 reads image0, 1 reads image1
+    uint source;
+    // 0 passes the color through, 1 is Reinhard, 2 is the ACES fit
+    uint operator;
+    // linear multiplier applied before the operator
+    float exposure;
+} push;
+
+//
+
+vec3 srgb_to_linear(vec3 col) {
+    return mix(col / 12.92, pow((col + 0.055) / 1.055, vec3(2.4)), step(0.04045, col));
+}
+
+vec3 linear_to_srgb(vec3 col) {
+    return mix(col * 12.92, 1.055 * pow(col, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, col));
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x) {
+    return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+void main() {
+    ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(coord, imageSize(target)))) {
+        return;
+    }
+
+    vec4 col = push.source == 0 ? imageLoad(image0, coord) : imageLoad(image1, coord);
+    // the operators work on linear light
+    vec3 linear = srgb_to_linear(max(col.rgb, vec3(0.0))) * push.exposure;
+
+    if (push.operator == 1) {
+        linear = linear / (1.0 + linear);
+    } else if (push.operator == 2) {
+        linear = aces(linear);
+    }
+
+    imageStore(target, coord, vec4(linear_to_srgb(clamp(linear, 0.0, 1.0)), col.a));
+}
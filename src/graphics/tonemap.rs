@@ -0,0 +1,235 @@
+use ash::{Device, vk};
+use bytemuck::{Pod, Zeroable};
+use eyre::Result;
+use glam::UVec3;
+use gpu_allocator::vulkan::Allocator;
+
+use super::{
+    Graphics,
+    delete_queue::DeleteQueue,
+    descriptor::{
+        DescriptorPool, DescriptorSet, DescriptorSetLayout,
+        DescriptorSetUpdateEntry, DescriptorWriteBatcher,
+    },
+    image::Image,
+    pipeline::{ComputePipeline, PipelineCache, PipelineLayout},
+    shader::Shader,
+};
+
+//
+
+/// curve that maps linear light into 0..1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(u32)]
+pub enum TonemapOperator {
+    /// clips everything above 1, the image as rendered
+    #[default]
+    PassThrough = 0,
+    Reinhard = 1,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces = 2,
+}
+
+impl TonemapOperator {
+    pub const ALL: [Self; 3] = [Self::PassThrough, Self::Reinhard, Self::Aces];
+
+    /// the next one of [`Self::ALL`], wrapping around
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TonemapSettings {
+    pub operator: TonemapOperator,
+    /// linear multiplier applied before the operator
+    pub exposure: f32,
+}
+
+impl TonemapSettings {
+    /// with these the pass is skipped, it would not change anything
+    pub fn is_identity(&self) -> bool {
+        self.operator == TonemapOperator::PassThrough && self.exposure == 1.0
+    }
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self {
+            operator: TonemapOperator::default(),
+            exposure: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct PushConst {
+    /// 0 reads binding 0, 1 reads binding 1
+    source: u32,
+    /// [`TonemapOperator`] as `u32`
+    operator: u32,
+    exposure: f32,
+}
+
+/// maps the output into the displayable range before an SDR blit,
+/// the render target and the FXAA target are both bound like for the HUD,
+/// the result goes to an intermediate image that only holds 0..1
+pub struct Tonemap {
+    descriptor_set: DescriptorSet,
+    pipeline: ComputePipeline<PushConst>,
+
+    pub target: Image,
+    pub target_delete_queue: DeleteQueue,
+}
+
+impl Tonemap {
+    pub fn new(
+        device: &Device,
+        allocator: &mut Allocator,
+        descriptor_pool: &DescriptorPool,
+        delete_queue: &mut DeleteQueue,
+        pipeline_cache: &PipelineCache,
+        render_target: &Image,
+        fxaa_target: &Image,
+    ) -> Result<Self> {
+        let mut init_delete_queue = DeleteQueue::new();
+
+        let descriptor_set_layout = DescriptorSetLayout::builder()
+            .name("tonemap_set_layout")
+            .add_binding(
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .add_binding(
+                1,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .add_binding(
+                2,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .build(device, delete_queue)?;
+
+        let descriptor_set =
+            descriptor_pool.alloc(device, &descriptor_set_layout)?;
+
+        let pipeline_layout =
+            PipelineLayout::new(device, delete_queue, &descriptor_set_layout)?;
+
+        let shader =
+            Shader::new(device, &mut init_delete_queue, Shader::TONEMAP_COMP)?;
+        let pipeline = ComputePipeline::new(
+            device,
+            delete_queue,
+            pipeline_cache,
+            pipeline_layout,
+            &shader,
+        )?;
+
+        init_delete_queue.flush(device, allocator);
+
+        let mut target_delete_queue = DeleteQueue::new();
+        let target = Graphics::create_render_image(
+            device,
+            allocator,
+            &mut target_delete_queue,
+            render_target.extent,
+        )?;
+
+        let mut tonemap = Self {
+            descriptor_set,
+            pipeline,
+
+            target,
+            target_delete_queue,
+        };
+        tonemap
+            .descriptor_set
+            .update(device)
+            .write(0, DescriptorSetUpdateEntry::storage_image(render_target))
+            .write(1, DescriptorSetUpdateEntry::storage_image(fxaa_target))
+            .write(2, DescriptorSetUpdateEntry::storage_image(&tonemap.target));
+
+        Ok(tonemap)
+    }
+
+    /// match the size of new render targets,
+    /// the old target is deleted with `delete_queue`
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        allocator: &mut Allocator,
+        delete_queue: &mut DeleteQueue,
+        descriptor_writes: &mut DescriptorWriteBatcher,
+        render_target: &Image,
+        fxaa_target: &Image,
+    ) -> Result<()> {
+        delete_queue.append(&mut self.target_delete_queue);
+        self.target = Graphics::create_render_image(
+            device,
+            allocator,
+            &mut self.target_delete_queue,
+            render_target.extent,
+        )?;
+        descriptor_writes
+            .write(
+                &self.descriptor_set,
+                0,
+                DescriptorSetUpdateEntry::storage_image(render_target),
+            )
+            .write(
+                &self.descriptor_set,
+                1,
+                DescriptorSetUpdateEntry::storage_image(fxaa_target),
+            )
+            .write(
+                &self.descriptor_set,
+                2,
+                DescriptorSetUpdateEntry::storage_image(&self.target),
+            );
+        Ok(())
+    }
+
+    /// tone map the FXAA target if `fxaa` is set, the render target
+    /// otherwise, into [`Self::target`], all of them have to be in the
+    /// GENERAL layout
+    pub fn dispatch(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        settings: TonemapSettings,
+        fxaa: bool,
+    ) {
+        let push_const = PushConst {
+            source: fxaa as u32,
+            operator: settings.operator as u32,
+            exposure: settings.exposure,
+        };
+        self.pipeline.bind(device, cbuf);
+        self.pipeline.bind_sets(
+            device,
+            cbuf,
+            0,
+            &[self.descriptor_set.set],
+            &[],
+        );
+        self.pipeline.write_push_constant(device, cbuf, &push_const);
+        self.pipeline.dispatch(
+            device,
+            cbuf,
+            UVec3::new(
+                self.target.extent.width.div_ceil(16),
+                self.target.extent.height.div_ceil(16),
+                1,
+            ),
+        );
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.target_delete_queue.flush(device, allocator);
+    }
+}
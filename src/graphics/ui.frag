@@ -0,0 +1,29 @@
+#version 460
+
+#extension GL_EXT_nonuniform_qualifier : require
+
+// every egui texture, sRGB bytes with premultiplied alpha
+layout(set = 1, binding = 0) uniform sampler2D textures[];
+
+layout(push_constant) uniform PushConstant {
+    vec2 screen_size;
+    uint first_index;
+    uint vertex_offset;
+    uint texture;
+} push;
+
+layout(location = 0) in vec2 uv;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 frag_color;
+
+//
+
+void main() {
+    // the output holds sRGB encoded colors too,
+    // so egui blends in the same space it was designed for
+    vec4 col = color * texture(textures[push.texture], uv);
+
+    // the pipeline blends with straight alpha
+    frag_color = vec4(col.a > 0.0 ? col.rgb / col.a : vec3(0.0), col.a);
+}
@@ -0,0 +1,826 @@
+use std::slice;
+
+use ash::{Device, vk};
+use bytemuck::{Pod, Zeroable};
+use egui::{
+    ClippedPrimitive, Color32, ImageData, Rect, TextureFilter, TextureId,
+    TexturesDelta,
+    epaint::{ImageDelta, Primitive, Vertex},
+};
+use eyre::{Result, bail};
+use glam::Vec2;
+use gpu_allocator::{MemoryLocation, vulkan::Allocator};
+use rustc_hash::FxHashMap;
+
+use super::{
+    Graphics, ImageTransition,
+    buffer::{Buffer, TypedBuffer},
+    delete_queue::{DeleteQueue, Owned},
+    descriptor::{
+        DescriptorLimits, DescriptorPool, DescriptorSet, DescriptorSetLayout,
+        DescriptorSetUpdateEntry,
+    },
+    image::Image,
+    immediate::Immediate,
+    pipeline::{GraphicsPipeline, PipelineCache, PipelineLayout},
+    queues::Resource,
+    shader::Shader,
+};
+
+//
+
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct PushConst {
+    /// the output size in points
+    screen_size: [f32; 2],
+    first_index: u32,
+    vertex_offset: u32,
+    /// element of the texture array
+    texture: u32,
+}
+
+/// one mesh of the tessellated UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Draw {
+    /// in points
+    clip_rect: Rect,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: u32,
+    texture: u32,
+}
+
+struct Texture {
+    slot: u32,
+    /// owns the image
+    delete_queue: DeleteQueue,
+    /// partial updates are applied to this copy,
+    /// which is uploaded as a new image
+    size: [usize; 2],
+    pixels: Vec<Color32>,
+}
+
+/// the vertices and indices of one frame in flight
+struct FrameBuffers {
+    set: DescriptorSet,
+    vertices: Option<TypedBuffer<Vertex>>,
+    indices: Option<TypedBuffer<u32>>,
+    delete_queue: DeleteQueue,
+    /// the [`Ui::generation`] in the buffers
+    generation: u64,
+}
+
+/// egui meshes drawn over the output, every texture is an element
+/// of one bindless array, so all meshes share the same sets
+pub struct Ui {
+    pipeline: GraphicsPipeline<PushConst>,
+    texture_set: DescriptorSet,
+    /// linear and nearest filtering
+    samplers: [vk::Sampler; 2],
+    textures: FxHashMap<TextureId, Texture>,
+    /// freed by egui, but still used by the current meshes
+    pending_free: Vec<TextureId>,
+    free_slots: Vec<u32>,
+    /// slots of deleted textures, reusable from the given frame on
+    retired_slots: Vec<(u32, u64)>,
+    next_slot: u32,
+
+    frames: Vec<FrameBuffers>,
+    /// counts [`Self::prepare`] calls
+    frame: u64,
+
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    draws: Vec<Draw>,
+    pixels_per_point: f32,
+    /// bumped by every [`Self::set`]
+    generation: u64,
+}
+
+impl Ui {
+    /// egui needs the font atlas and a few images
+    const MAX_TEXTURES: u32 = 64;
+    /// elements the frame buffers start with
+    const MIN_CAPACITY: usize = 1 << 12;
+
+    pub fn new(
+        device: &Device,
+        allocator: &mut Allocator,
+        delete_queue: &mut DeleteQueue,
+        pipeline_cache: &PipelineCache,
+        limits: &DescriptorLimits,
+        frames: usize,
+    ) -> Result<Self> {
+        let mut init_delete_queue = DeleteQueue::new();
+
+        let buffer_set_layout = DescriptorSetLayout::builder()
+            .name("ui_buffer_set_layout")
+            .add_binding(
+                0,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::VERTEX,
+            )
+            .add_binding(
+                1,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::VERTEX,
+            );
+        buffer_set_layout.check_limits(limits)?;
+        let buffer_set_layout =
+            buffer_set_layout.build(device, delete_queue)?;
+
+        let texture_set_layout = DescriptorSetLayout::builder()
+            .name("ui_texture_set_layout")
+            .add_array_binding(
+                0,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                Self::MAX_TEXTURES,
+                vk::ShaderStageFlags::FRAGMENT,
+            );
+        texture_set_layout.check_limits(limits)?;
+        let texture_set_layout =
+            texture_set_layout.build(device, delete_queue)?;
+
+        // pools of its own, the textures need an update-after-bind one
+        let buffer_pool = DescriptorPool::builder()
+            .add_type_allocation(
+                vk::DescriptorType::STORAGE_BUFFER,
+                2 * frames as u32,
+            )
+            .max_sets(frames as u32)
+            .build(device, delete_queue)?;
+        let texture_pool = DescriptorPool::builder()
+            .add_type_allocation(
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                Self::MAX_TEXTURES,
+            )
+            .max_sets(1)
+            .update_after_bind()
+            .build(device, delete_queue)?;
+
+        PipelineLayout::<PushConst>::check_limits(2, limits)?;
+        let pipeline_layout = PipelineLayout::with_set_layouts(
+            device,
+            delete_queue,
+            &[&buffer_set_layout, &texture_set_layout],
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        )?;
+        let vertex_shader =
+            Shader::new(device, &mut init_delete_queue, Shader::UI_VERT)?;
+        let fragment_shader =
+            Shader::new(device, &mut init_delete_queue, Shader::UI_FRAG)?;
+        let pipeline = GraphicsPipeline::new(
+            device,
+            delete_queue,
+            pipeline_cache,
+            pipeline_layout,
+            &vertex_shader,
+            &fragment_shader,
+            vk::Format::R16G16B16A16_SFLOAT,
+            None,
+        )?;
+        init_delete_queue.flush(device, allocator);
+
+        let samplers =
+            [vk::Filter::LINEAR, vk::Filter::NEAREST].map(|filter| {
+                let create_info = vk::SamplerCreateInfo::default()
+                    .mag_filter(filter)
+                    .min_filter(filter)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+                unsafe { device.create_sampler(&create_info, None) }
+            });
+        let samplers = samplers.map(|sampler| {
+            sampler.inspect(|sampler| delete_queue.push(*sampler))
+        });
+        let samplers = [samplers[0]?, samplers[1]?];
+
+        let frames = (0..frames)
+            .map(|_| {
+                Ok(FrameBuffers {
+                    set: buffer_pool.alloc(device, &buffer_set_layout)?,
+                    vertices: None,
+                    indices: None,
+                    delete_queue: DeleteQueue::new(),
+                    generation: 0,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            pipeline,
+            texture_set: texture_pool.alloc(device, &texture_set_layout)?,
+            samplers,
+            textures: FxHashMap::default(),
+            pending_free: Vec::new(),
+            free_slots: Vec::new(),
+            retired_slots: Vec::new(),
+            next_slot: 0,
+
+            frames,
+            frame: 0,
+
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            draws: Vec::new(),
+            pixels_per_point: 1.0,
+            generation: 0,
+        })
+    }
+
+    /// replace the meshes and apply the texture changes, replaced
+    /// and freed textures are deleted with `delete_queue`, which has to
+    /// be flushed only after every frame submitted so far has finished
+    #[allow(clippy::too_many_arguments)]
+    pub fn set(
+        &mut self,
+        device: &Device,
+        allocator: &mut Allocator,
+        imm: &Immediate,
+        render_imm: &Immediate,
+        delete_queue: &mut DeleteQueue,
+        primitives: &[ClippedPrimitive],
+        textures: &TexturesDelta,
+        pixels_per_point: f32,
+    ) -> Result<()> {
+        // egui frees textures after the frame that used them last
+        for id in self.pending_free.drain(..) {
+            if let Some(texture) = self.textures.remove(&id) {
+                Self::retire(
+                    texture,
+                    self.frame,
+                    self.frames.len(),
+                    &mut self.retired_slots,
+                    delete_queue,
+                );
+            }
+        }
+        self.pending_free.extend_from_slice(&textures.free);
+
+        for (id, delta) in &textures.set {
+            self.set_texture(
+                device,
+                allocator,
+                imm,
+                render_imm,
+                delete_queue,
+                *id,
+                delta,
+            )?;
+        }
+
+        (self.vertices, self.indices, self.draws) = flatten(primitives, |id| {
+            self.textures.get(&id).map(|texture| texture.slot)
+        });
+        self.pixels_per_point = pixels_per_point;
+        self.generation += 1;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn set_texture(
+        &mut self,
+        device: &Device,
+        allocator: &mut Allocator,
+        imm: &Immediate,
+        render_imm: &Immediate,
+        delete_queue: &mut DeleteQueue,
+        id: TextureId,
+        delta: &ImageDelta,
+    ) -> Result<()> {
+        let mut size = delta.image.size();
+        let mut pixels = match &delta.image {
+            ImageData::Color(image) => image.pixels.clone(),
+            ImageData::Font(image) => image.srgba_pixels(None).collect(),
+        };
+        if let Some(pos) = delta.pos {
+            let Some(texture) = self.textures.get(&id) else {
+                bail!("partial update of the unknown texture {id:?}");
+            };
+            let mut whole = texture.pixels.clone();
+            patch(&mut whole, texture.size[0], &pixels, size, pos);
+            (size, pixels) = (texture.size, whole);
+        }
+        if size[0] == 0 || size[1] == 0 {
+            bail!("empty texture {id:?}");
+        }
+
+        // the old image might still be sampled by frames in flight,
+        // so every update goes to a new image in a new slot
+        let mut texture_delete_queue = DeleteQueue::new();
+        let image = match Self::upload(
+            device,
+            allocator,
+            imm,
+            render_imm,
+            &mut texture_delete_queue,
+            size,
+            &pixels,
+        ) {
+            Ok(image) => image,
+            Err(err) => {
+                texture_delete_queue.flush(device, allocator);
+                return Err(err);
+            }
+        };
+
+        let Some(slot) = self.free_slots.pop().or_else(|| {
+            (self.next_slot < Self::MAX_TEXTURES).then(|| {
+                self.next_slot += 1;
+                self.next_slot - 1
+            })
+        }) else {
+            delete_queue.append(&mut texture_delete_queue);
+            bail!("more than {} UI textures", Self::MAX_TEXTURES);
+        };
+        let sampler = match delta.options.magnification {
+            TextureFilter::Linear => self.samplers[0],
+            TextureFilter::Nearest => self.samplers[1],
+        };
+        self.texture_set.update(device).write_array(
+            0,
+            slot,
+            &[DescriptorSetUpdateEntry::sampled_image(&image, sampler)],
+        );
+
+        let texture = Texture {
+            slot,
+            delete_queue: texture_delete_queue,
+            size,
+            pixels,
+        };
+        if let Some(old) = self.textures.insert(id, texture) {
+            Self::retire(
+                old,
+                self.frame,
+                self.frames.len(),
+                &mut self.retired_slots,
+                delete_queue,
+            );
+        }
+        Ok(())
+    }
+
+    /// an image holding `pixels`, copied through a staging buffer on the
+    /// queue of `imm` and handed over to the queue of `render_imm`
+    fn upload(
+        device: &Device,
+        allocator: &mut Allocator,
+        imm: &Immediate,
+        render_imm: &Immediate,
+        delete_queue: &mut DeleteQueue,
+        size: [usize; 2],
+        pixels: &[Color32],
+    ) -> Result<Image> {
+        let extent = vk::Extent2D {
+            width: size[0] as u32,
+            height: size[1] as u32,
+        };
+        let image = Image::builder()
+            .name("ui_texture")
+            .format(vk::Format::R8G8B8A8_UNORM)
+            .extent(extent)
+            .usage(
+                vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+            .build(device, allocator, delete_queue)?;
+
+        let mut staging =
+            Owned::new(device, allocator, |allocator, delete_queue| {
+                Buffer::builder()
+                    .len::<Color32>(pixels.len())
+                    .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+                    .location(MemoryLocation::CpuToGpu)
+                    .name("ui_staging")
+                    .build_typed::<Color32>(device, allocator, delete_queue)
+            })?;
+        staging.write_slice(pixels)?;
+
+        imm.upload(
+            device,
+            render_imm,
+            Resource::Image {
+                image: image.image,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+            |cbuf| {
+                let copy = vk::BufferImageCopy::default()
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::default()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .layer_count(1),
+                    )
+                    .image_extent(vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    });
+                unsafe {
+                    device.cmd_copy_buffer_to_image(
+                        cbuf,
+                        staging.buffer,
+                        image.image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        slice::from_ref(&copy),
+                    );
+                }
+                Ok(())
+            },
+        )?;
+
+        Ok(image)
+    }
+
+    /// the frames in flight can still use the slot,
+    /// it is reused once they all finished
+    fn retire(
+        mut texture: Texture,
+        frame: u64,
+        frames: usize,
+        retired_slots: &mut Vec<(u32, u64)>,
+        delete_queue: &mut DeleteQueue,
+    ) {
+        delete_queue.append(&mut texture.delete_queue);
+        retired_slots.push((texture.slot, frame + frames as u64));
+    }
+
+    /// copy the meshes to the buffers of `frame_i` if they are outdated,
+    /// the frame has to be waited for
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        allocator: &mut Allocator,
+        frame_i: usize,
+    ) -> Result<()> {
+        self.frame += 1;
+        let frame = self.frame;
+        self.free_slots.extend(
+            self.retired_slots
+                .extract_if(.., |(_, reusable)| *reusable <= frame)
+                .map(|(slot, _)| slot),
+        );
+
+        let buffers = &mut self.frames[frame_i];
+        if buffers.generation == self.generation || self.draws.is_empty() {
+            buffers.generation = self.generation;
+            return Ok(());
+        }
+
+        let fits = |len: Option<usize>, needed: usize| {
+            len.is_some_and(|len| len >= needed)
+        };
+        if !fits(
+            buffers.vertices.as_ref().map(|b| b.len()),
+            self.vertices.len(),
+        ) || !fits(
+            buffers.indices.as_ref().map(|b| b.len()),
+            self.indices.len(),
+        ) {
+            // nothing uses the old buffers after the frame wait
+            buffers.vertices = None;
+            buffers.indices = None;
+            buffers.delete_queue.flush(device, allocator);
+
+            let capacity = |needed: usize| {
+                needed.next_power_of_two().max(Self::MIN_CAPACITY)
+            };
+            let vertices = Buffer::builder()
+                .len::<Vertex>(capacity(self.vertices.len()))
+                .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                .location(MemoryLocation::CpuToGpu)
+                .name("ui_vertices")
+                .build_typed(device, allocator, &mut buffers.delete_queue)?;
+            let indices = Buffer::builder()
+                .len::<u32>(capacity(self.indices.len()))
+                .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                .location(MemoryLocation::CpuToGpu)
+                .name("ui_indices")
+                .build_typed(device, allocator, &mut buffers.delete_queue)?;
+            buffers
+                .set
+                .update(device)
+                .write(0, DescriptorSetUpdateEntry::storage_buffer(&vertices))
+                .write(1, DescriptorSetUpdateEntry::storage_buffer(&indices));
+            buffers.vertices = Some(vertices);
+            buffers.indices = Some(indices);
+        }
+
+        buffers
+            .vertices
+            .as_mut()
+            .unwrap()
+            .write_slice(&self.vertices)?;
+        buffers
+            .indices
+            .as_mut()
+            .unwrap()
+            .write_slice(&self.indices)?;
+        buffers.generation = self.generation;
+        Ok(())
+    }
+
+    /// changes with every [`Self::set`], for telling recorded frames apart
+    pub fn key(&self) -> u64 {
+        self.generation
+    }
+
+    /// draw over the top left `extent` of `color`, which has to be in the
+    /// GENERAL layout and stays in it, the UI is laid out for a window
+    /// of `output_extent` pixels and scaled to `extent`
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        device: &Device,
+        cbuf: vk::CommandBuffer,
+        frame_i: usize,
+        color: &Image,
+        extent: vk::Extent2D,
+        output_extent: vk::Extent2D,
+    ) {
+        if self.draws.is_empty() {
+            return;
+        }
+
+        Graphics::transition_image(
+            device,
+            cbuf,
+            color.image,
+            ImageTransition::GeneralToAttachment,
+        );
+        self.pipeline
+            .begin_rendering(device, cbuf, color, None, extent);
+        self.pipeline.bind(device, cbuf);
+        self.pipeline.bind_sets(
+            device,
+            cbuf,
+            0,
+            &[self.frames[frame_i].set.set, self.texture_set.set],
+            &[],
+        );
+
+        let screen_size = extent_vec(output_extent) / self.pixels_per_point;
+        // the output can be rendered at a different scale
+        let scale = extent_vec(extent) / screen_size;
+        for draw in &self.draws {
+            let Some(rect) = scissor(draw.clip_rect, scale, extent) else {
+                continue;
+            };
+            self.pipeline.set_scissor(device, cbuf, rect);
+            self.pipeline.write_push_constant(
+                device,
+                cbuf,
+                &PushConst {
+                    screen_size: screen_size.to_array(),
+                    first_index: draw.first_index,
+                    vertex_offset: draw.vertex_offset,
+                    texture: draw.texture,
+                },
+            );
+            self.pipeline.draw(device, cbuf, draw.index_count, 1);
+        }
+
+        self.pipeline.end_rendering(device, cbuf);
+        Graphics::transition_image(
+            device,
+            cbuf,
+            color.image,
+            ImageTransition::AttachmentToGeneral,
+        );
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        for frame in &mut self.frames {
+            frame.delete_queue.flush(device, allocator);
+        }
+        for texture in self.textures.values_mut() {
+            texture.delete_queue.flush(device, allocator);
+        }
+    }
+
+    /// [`Self::destroy`] for a lost device
+    pub fn leak(&mut self) {
+        for frame in &mut self.frames {
+            frame.delete_queue.leak();
+        }
+        for texture in self.textures.values_mut() {
+            texture.delete_queue.leak();
+        }
+    }
+}
+
+fn extent_vec(extent: vk::Extent2D) -> Vec2 {
+    Vec2::new(extent.width as f32, extent.height as f32)
+}
+
+/// concatenates the meshes, the indices stay relative to their mesh,
+/// meshes with unknown textures and paint callbacks are skipped
+fn flatten(
+    primitives: &[ClippedPrimitive],
+    slot: impl Fn(TextureId) -> Option<u32>,
+) -> (Vec<Vertex>, Vec<u32>, Vec<Draw>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut draws = Vec::new();
+
+    for primitive in primitives {
+        let Primitive::Mesh(mesh) = &primitive.primitive else {
+            continue;
+        };
+        if mesh.indices.is_empty() {
+            continue;
+        }
+        let Some(texture) = slot(mesh.texture_id) else {
+            tracing::warn!("UI mesh with the unknown {:?}", mesh.texture_id);
+            continue;
+        };
+
+        draws.push(Draw {
+            clip_rect: primitive.clip_rect,
+            first_index: indices.len() as u32,
+            index_count: mesh.indices.len() as u32,
+            vertex_offset: vertices.len() as u32,
+            texture,
+        });
+        vertices.extend_from_slice(&mesh.vertices);
+        indices.extend_from_slice(&mesh.indices);
+    }
+
+    (vertices, indices, draws)
+}
+
+/// `clip_rect` in points to a scissor in the pixels of `extent`,
+/// `None` if nothing of it is visible
+fn scissor(
+    clip_rect: Rect,
+    scale: Vec2,
+    extent: vk::Extent2D,
+) -> Option<vk::Rect2D> {
+    let min = (Vec2::new(clip_rect.min.x, clip_rect.min.y) * scale)
+        .round()
+        .max(Vec2::ZERO);
+    let max = (Vec2::new(clip_rect.max.x, clip_rect.max.y) * scale)
+        .round()
+        .min(extent_vec(extent));
+    if max.x <= min.x || max.y <= min.y {
+        return None;
+    }
+
+    Some(vk::Rect2D {
+        offset: vk::Offset2D {
+            x: min.x as i32,
+            y: min.y as i32,
+        },
+        extent: vk::Extent2D {
+            width: (max.x - min.x) as u32,
+            height: (max.y - min.y) as u32,
+        },
+    })
+}
+
+/// write the `size` sized `src` into `dst` at `pos`,
+/// `dst` is `width` pixels wide
+fn patch(
+    dst: &mut [Color32],
+    width: usize,
+    src: &[Color32],
+    size: [usize; 2],
+    pos: [usize; 2],
+) {
+    for (y, row) in src.chunks_exact(size[0]).enumerate() {
+        let start = (pos[1] + y) * width + pos[0];
+        dst[start..start + size[0]].copy_from_slice(row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::{Mesh, Pos2, pos2};
+
+    use super::*;
+
+    fn mesh(texture: u64, triangles: usize) -> Mesh {
+        let mut mesh = Mesh::with_texture(TextureId::Managed(texture));
+        for i in 0..triangles as u32 {
+            for _ in 0..3 {
+                mesh.vertices.push(Vertex {
+                    pos: pos2(i as f32, 0.0),
+                    uv: Pos2::ZERO,
+                    color: Color32::WHITE,
+                });
+            }
+            mesh.add_triangle(i * 3, i * 3 + 1, i * 3 + 2);
+        }
+        mesh
+    }
+
+    fn primitive(clip_rect: Rect, mesh: Mesh) -> ClippedPrimitive {
+        ClippedPrimitive {
+            clip_rect,
+            primitive: Primitive::Mesh(mesh),
+        }
+    }
+
+    #[test]
+    fn flatten_offsets() {
+        let clip = Rect::from_min_max(pos2(1.0, 2.0), pos2(3.0, 4.0));
+        let primitives = [
+            primitive(Rect::EVERYTHING, mesh(0, 2)),
+            primitive(clip, mesh(1, 1)),
+            primitive(clip, mesh(0, 3)),
+        ];
+        let (vertices, indices, draws) = flatten(&primitives, |id| match id {
+            TextureId::Managed(0) => Some(5),
+            TextureId::Managed(1) => Some(7),
+            _ => None,
+        });
+
+        assert_eq!((vertices.len(), indices.len()), (18, 18));
+        assert_eq!(
+            draws
+                .iter()
+                .map(|draw| (
+                    draw.first_index,
+                    draw.index_count,
+                    draw.vertex_offset,
+                    draw.texture
+                ))
+                .collect::<Vec<_>>(),
+            [(0, 6, 0, 5), (6, 3, 6, 7), (9, 9, 9, 5)]
+        );
+        assert_eq!(draws[1].clip_rect, clip);
+        // the indices stay relative to their own mesh
+        assert_eq!(&indices[6..9], &[0, 1, 2]);
+    }
+
+    #[test]
+    fn flatten_skips_unknown_and_empty() {
+        let primitives = [
+            primitive(Rect::EVERYTHING, mesh(9, 1)),
+            primitive(Rect::EVERYTHING, mesh(0, 0)),
+            primitive(Rect::EVERYTHING, mesh(0, 1)),
+        ];
+        let (vertices, indices, draws) = flatten(&primitives, |id| {
+            (id == TextureId::Managed(0)).then_some(0)
+        });
+
+        assert_eq!((vertices.len(), indices.len()), (3, 3));
+        assert_eq!(draws.len(), 1);
+        assert_eq!((draws[0].first_index, draws[0].vertex_offset), (0, 0));
+    }
+
+    #[test]
+    fn scissor_scales_and_clamps() {
+        let extent = vk::Extent2D {
+            width: 200,
+            height: 100,
+        };
+        let rect = |min: (f32, f32), max: (f32, f32)| {
+            Rect::from_min_max(pos2(min.0, min.1), pos2(max.0, max.1))
+        };
+
+        // 2 pixels per point
+        let scissor_of = |clip| scissor(clip, Vec2::splat(2.0), extent);
+        assert_eq!(
+            scissor_of(rect((10.0, 5.0), (20.0, 30.0))),
+            Some(vk::Rect2D {
+                offset: vk::Offset2D { x: 20, y: 10 },
+                extent: vk::Extent2D {
+                    width: 20,
+                    height: 50,
+                },
+            })
+        );
+        assert_eq!(
+            scissor_of(Rect::EVERYTHING),
+            Some(vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent,
+            })
+        );
+        assert_eq!(scissor_of(rect((120.0, 0.0), (150.0, 10.0))), None);
+        assert_eq!(scissor_of(rect((10.0, 10.0), (10.0, 20.0))), None);
+    }
+
+    #[test]
+    fn patch_writes_the_region() {
+        let mut dst = vec![Color32::BLACK; 4 * 3];
+        let src = [Color32::RED, Color32::GREEN, Color32::BLUE, Color32::WHITE];
+        patch(&mut dst, 4, &src, [2, 2], [1, 1]);
+
+        let b = Color32::BLACK;
+        assert_eq!(
+            dst,
+            [
+                b, b, b, b, //
+                b, src[0], src[1], b, //
+                b, src[2], src[3], b,
+            ]
+        );
+    }
+}
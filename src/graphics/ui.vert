@@ -0,0 +1,35 @@
+#version 460
+
+// egui vertices, 5 words each, the position in points, the uv
+// and the color as sRGB bytes with premultiplied alpha
+layout(std430, set = 0, binding = 0) readonly buffer Vertices {
+    uint vertices[];
+};
+// relative to the first vertex of their mesh
+layout(std430, set = 0, binding = 1) readonly buffer Indices {
+    uint indices[];
+};
+
+layout(push_constant) uniform PushConstant {
+    // the output size in points
+    vec2 screen_size;
+    uint first_index;
+    uint vertex_offset;
+    // element of the texture array
+    uint texture;
+} push;
+
+layout(location = 0) out vec2 uv;
+layout(location = 1) out vec4 color;
+
+//
+
+void main() {
+    uint i = (push.vertex_offset + indices[push.first_index + gl_VertexIndex]) * 5u;
+    vec2 pos = uintBitsToFloat(uvec2(vertices[i], vertices[i + 1u]));
+
+    uv = uintBitsToFloat(uvec2(vertices[i + 2u], vertices[i + 3u]));
+    color = unpackUnorm4x8(vertices[i + 4u]);
+    // the top left corner is 0,0 in egui and -1,-1 in Vulkan
+    gl_Position = vec4(pos / push.screen_size * 2.0 - 1.0, 0.0, 1.0);
+}
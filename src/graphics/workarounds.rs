@@ -0,0 +1,284 @@
+use std::{env, fmt, ops::RangeInclusive};
+
+use ash::vk;
+
+//
+
+/// renderer settings forced to avoid driver bugs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Overrides {
+    /// FIFO instead of MAILBOX presentation
+    pub force_fifo: bool,
+    /// the raymarch runs on the graphics queue
+    pub disable_async_compute: bool,
+}
+
+/// a known driver bug and the overrides that avoid it
+struct Workaround {
+    name: &'static str,
+    vendor_id: u32,
+    device_ids: RangeInclusive<u32>,
+    driver_versions: RangeInclusive<DriverVersion>,
+    overrides: Overrides,
+}
+
+/// confirmed driver bugs, matched against the selected GPU
+const WORKAROUNDS: &[Workaround] = &[];
+
+impl Overrides {
+    /// overrides of every matching [`WORKAROUNDS`] entry,
+    /// then the `LUMINARY_FORCE_*` environment variables on top
+    pub fn for_device(props: &vk::PhysicalDeviceProperties) -> Self {
+        Self::matching(WORKAROUNDS, props).with_env(|name| env::var(name).ok())
+    }
+
+    fn matching(
+        workarounds: &[Workaround],
+        props: &vk::PhysicalDeviceProperties,
+    ) -> Self {
+        let version =
+            DriverVersion::decode(props.vendor_id, props.driver_version);
+        let mut overrides = Self::default();
+
+        for workaround in workarounds.iter().filter(|workaround| {
+            workaround.vendor_id == props.vendor_id
+                && workaround.device_ids.contains(&props.device_id)
+                && workaround.driver_versions.contains(&version)
+        }) {
+            tracing::warn!(
+                "applying driver workaround '{}' for driver {version}: {:?}",
+                workaround.name,
+                workaround.overrides
+            );
+            overrides = overrides.merge(workaround.overrides);
+        }
+
+        overrides
+    }
+
+    /// `var` reads an environment variable, anything but `0` forces
+    /// the override and `0` disables it even if a workaround set it
+    fn with_env(mut self, var: impl Fn(&str) -> Option<String>) -> Self {
+        let env_flag = |name: &str, value: &mut bool| {
+            if let Some(var) = var(name) {
+                *value = var != "0";
+                tracing::warn!("{name}={} from the environment", *value);
+            }
+        };
+        env_flag("LUMINARY_FORCE_FIFO", &mut self.force_fifo);
+        env_flag(
+            "LUMINARY_FORCE_NO_ASYNC_COMPUTE",
+            &mut self.disable_async_compute,
+        );
+
+        self
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            force_fifo: self.force_fifo || other.force_fifo,
+            disable_async_compute: self.disable_async_compute
+                || other.disable_async_compute,
+        }
+    }
+}
+
+/// `driverVersion` decoded with the vendor specific bit layout,
+/// ordered from the most to the least significant part
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DriverVersion(pub [u32; 4]);
+
+impl DriverVersion {
+    pub const NVIDIA: u32 = 0x10de;
+    pub const INTEL: u32 = 0x8086;
+    /// MoltenVK on Apple GPUs
+    pub const APPLE: u32 = 0x106b;
+
+    pub fn decode(vendor_id: u32, version: u32) -> Self {
+        match vendor_id {
+            // 10.8.8.6 bits
+            Self::NVIDIA => Self([
+                version >> 22,
+                (version >> 14) & 0xff,
+                (version >> 6) & 0xff,
+                version & 0x3f,
+            ]),
+            // 18.14 bits, only on Windows, Mesa uses the Vulkan layout
+            Self::INTEL if cfg!(windows) => {
+                Self([version >> 14, version & 0x3fff, 0, 0])
+            }
+            // MoltenVK writes its own version as decimal digits,
+            // 1.2.5 is 10205
+            Self::APPLE => {
+                Self([version / 10000, version / 100 % 100, version % 100, 0])
+            }
+            // AMD, Mesa and the rest use VK_MAKE_API_VERSION
+            _ => Self([
+                vk::api_version_major(version),
+                vk::api_version_minor(version),
+                vk::api_version_patch(version),
+                0,
+            ]),
+        }
+    }
+}
+
+impl fmt::Display for DriverVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{a}.{b}.{c}.{d}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AMD: u32 = 0x1002;
+
+    fn decode(vendor_id: u32, version: u32) -> [u32; 4] {
+        DriverVersion::decode(vendor_id, version).0
+    }
+
+    #[test]
+    fn nvidia_versions() {
+        // 535.104.05 and 472.12
+        assert_eq!(
+            decode(DriverVersion::NVIDIA, 0x85da_0140),
+            [535, 104, 5, 0]
+        );
+        assert_eq!(decode(DriverVersion::NVIDIA, 0x7603_0000), [472, 12, 0, 0]);
+    }
+
+    #[test]
+    fn amd_and_mesa_versions() {
+        // AMDVLK 2.0.279 and RADV from Mesa 23.1.5
+        assert_eq!(decode(AMD, 0x0080_0117), [2, 0, 279, 0]);
+        assert_eq!(decode(AMD, 0x05c0_1005), [23, 1, 5, 0]);
+        // ANV from Mesa 24.0.3, the Windows layout is not used on Linux
+        if !cfg!(windows) {
+            assert_eq!(
+                decode(DriverVersion::INTEL, 0x0600_0003),
+                [24, 0, 3, 0]
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn intel_windows_versions() {
+        // 101.4146
+        assert_eq!(
+            decode(DriverVersion::INTEL, 0x0019_5032),
+            [101, 4146, 0, 0]
+        );
+    }
+
+    #[test]
+    fn moltenvk_versions() {
+        assert_eq!(decode(DriverVersion::APPLE, 10205), [1, 2, 5, 0]);
+        assert_eq!(decode(DriverVersion::APPLE, 10300), [1, 3, 0, 0]);
+    }
+
+    #[test]
+    fn versions_are_ordered() {
+        let v = |version| DriverVersion::decode(DriverVersion::NVIDIA, version);
+        // 535.104.05 < 535.113.01 < 545.23.06
+        assert!(v(0x85da_0140) < v(0x85dc_4040));
+        assert!(v(0x85dc_4040) < v(0x8845_c180));
+    }
+
+    fn props(
+        vendor_id: u32,
+        device_id: u32,
+        driver_version: u32,
+    ) -> vk::PhysicalDeviceProperties {
+        vk::PhysicalDeviceProperties {
+            vendor_id,
+            device_id,
+            driver_version,
+            ..Default::default()
+        }
+    }
+
+    const NONE: Overrides = Overrides {
+        force_fifo: false,
+        disable_async_compute: false,
+    };
+
+    const TABLE: &[Workaround] = &[
+        Workaround {
+            name: "old nvidia async compute",
+            vendor_id: DriverVersion::NVIDIA,
+            device_ids: 0x2000..=0x2fff,
+            driver_versions: DriverVersion([470, 0, 0, 0])
+                ..=DriverVersion([535, 255, 255, 63]),
+            overrides: Overrides {
+                disable_async_compute: true,
+                ..NONE
+            },
+        },
+        Workaround {
+            name: "radv mailbox",
+            vendor_id: AMD,
+            device_ids: 0..=u32::MAX,
+            driver_versions: DriverVersion([23, 1, 0, 0])
+                ..=DriverVersion([23, 1, 255, 0]),
+            overrides: Overrides {
+                force_fifo: true,
+                ..NONE
+            },
+        },
+    ];
+
+    #[test]
+    fn matching_workarounds() {
+        let matched = Overrides::matching(
+            TABLE,
+            &props(DriverVersion::NVIDIA, 0x2684, 0x85da_0140),
+        );
+        assert!(matched.disable_async_compute && !matched.force_fifo);
+
+        // a newer driver, another device and another vendor
+        let newer = Overrides::matching(
+            TABLE,
+            &props(DriverVersion::NVIDIA, 0x2684, 0x8845_c180),
+        );
+        let device = Overrides::matching(
+            TABLE,
+            &props(DriverVersion::NVIDIA, 0x1b80, 0x85da_0140),
+        );
+        let vendor = Overrides::matching(
+            TABLE,
+            &props(DriverVersion::INTEL, 0x2684, 0x85da_0140),
+        );
+        assert_eq!(newer, Overrides::default());
+        assert_eq!(device, Overrides::default());
+        assert_eq!(vendor, Overrides::default());
+
+        let radv = Overrides::matching(TABLE, &props(AMD, 0x73bf, 0x05c0_1005));
+        assert!(radv.force_fifo);
+    }
+
+    #[test]
+    fn environment_takes_precedence() {
+        let forced = Overrides {
+            force_fifo: true,
+            ..Overrides::default()
+        };
+        let env = |name: &str| match name {
+            "LUMINARY_FORCE_FIFO" => Some("0".to_owned()),
+            "LUMINARY_FORCE_NO_ASYNC_COMPUTE" => Some("1".to_owned()),
+            _ => None,
+        };
+
+        assert_eq!(
+            forced.with_env(env),
+            Overrides {
+                disable_async_compute: true,
+                ..Overrides::default()
+            }
+        );
+        assert_eq!(forced.with_env(|_| None), forced);
+    }
+}
@@ -0,0 +1,522 @@
+use std::collections::VecDeque;
+
+use eyre::{Result, bail};
+use glam::U64Vec3;
+use rustc_hash::FxHashSet;
+
+use super::voxels::Octree;
+
+//
+
+/// bounded undo and redo stacks of committed edits,
+/// stored in the world save so that they survive restarts
+pub struct History {
+    /// oldest first
+    undo: VecDeque<Operation>,
+    /// the next redo is last
+    redo: Vec<Operation>,
+    /// the oldest operations are evicted past this many
+    limit: usize,
+}
+
+/// one restorable edit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    /// seconds since the unix epoch, when the edit was committed
+    pub time: u64,
+    /// the inverse changes from [`Edit::commit`], committing them
+    /// restores the voxels from before the edit
+    ///
+    /// [`Edit::commit`]: super::voxels::Edit::commit
+    pub changes: Vec<(U64Vec3, u32)>,
+}
+
+impl Operation {
+    /// the chunks the changes are in, without duplicates
+    pub fn chunks(&self) -> impl Iterator<Item = U64Vec3> {
+        let mut seen = FxHashSet::default();
+        self.changes
+            .iter()
+            .map(|(at, _)| *at / History::CHUNK)
+            .filter(move |chunk| seen.insert(*chunk))
+    }
+}
+
+impl History {
+    /// voxels per chunk side, the unit of the hash based invalidation
+    pub const CHUNK: u64 = 8;
+    /// `HIST` section version
+    const VERSION: u32 = 1;
+
+    pub fn new(limit: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+        }
+    }
+
+    /// a new edit, the redo stack is cleared
+    /// and the oldest operation is evicted if there are too many
+    pub fn record(&mut self, time: u64, inverse: Vec<(U64Vec3, u32)>) {
+        self.redo.clear();
+        self.push_undo(Operation {
+            time,
+            changes: inverse,
+        });
+    }
+
+    fn push_undo(&mut self, op: Operation) {
+        self.undo.push_back(op);
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+
+    /// revert the last operation with `apply`, which commits the changes
+    /// and returns their inverse, returns the time of the operation
+    pub fn undo(
+        &mut self,
+        apply: impl FnOnce(Vec<(U64Vec3, u32)>) -> Result<Vec<(U64Vec3, u32)>>,
+    ) -> Result<Option<u64>> {
+        let Some(op) = self.undo.pop_back() else {
+            return Ok(None);
+        };
+        let time = op.time;
+        let changes = apply(op.changes)?;
+        self.redo.push(Operation { time, changes });
+        Ok(Some(time))
+    }
+
+    /// like [`Self::undo`], but the other way around
+    pub fn redo(
+        &mut self,
+        apply: impl FnOnce(Vec<(U64Vec3, u32)>) -> Result<Vec<(U64Vec3, u32)>>,
+    ) -> Result<Option<u64>> {
+        let Some(op) = self.redo.pop() else {
+            return Ok(None);
+        };
+        let time = op.time;
+        let changes = apply(op.changes)?;
+        self.push_undo(Operation { time, changes });
+        Ok(Some(time))
+    }
+
+    /// undoable operations, the next undo first
+    pub fn undoable(&self) -> impl Iterator<Item = &Operation> {
+        self.undo.iter().rev()
+    }
+
+    /// redoable operations, the next redo first
+    pub fn redoable(&self) -> impl Iterator<Item = &Operation> {
+        self.redo.iter().rev()
+    }
+
+    /// the `HIST` section of the world save, the changes are grouped
+    /// by chunk and `chunk_hash` of every referenced chunk is stored
+    /// to detect edits made while the history was not loaded
+    pub fn to_bytes(&self, chunk_hash: impl Fn(U64Vec3) -> u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_u32(&mut out, Self::VERSION);
+
+        let mut chunks = Vec::new();
+        for stack in [
+            self.undo.iter().collect::<Vec<_>>(),
+            self.redo.iter().collect(),
+        ] {
+            put_u32(&mut out, stack.len() as u32);
+            for op in stack {
+                put_u64(&mut out, op.time);
+                Self::put_changes(&mut out, op);
+                chunks.extend(op.chunks());
+            }
+        }
+
+        chunks.sort_unstable_by_key(|chunk| chunk.to_array());
+        chunks.dedup();
+        put_u32(&mut out, chunks.len() as u32);
+        for chunk in chunks {
+            put_chunk(&mut out, chunk);
+            put_u64(&mut out, chunk_hash(chunk));
+        }
+        out
+    }
+
+    /// changes of each chunk in their original order,
+    /// with chunk local voxel positions
+    fn put_changes(out: &mut Vec<u8>, op: &Operation) {
+        let chunks = op.chunks().collect::<Vec<_>>();
+        put_u32(out, chunks.len() as u32);
+        for chunk in chunks {
+            let changes = op
+                .changes
+                .iter()
+                .filter(|(at, _)| *at / Self::CHUNK == chunk)
+                .collect::<Vec<_>>();
+            put_chunk(out, chunk);
+            put_u32(out, changes.len() as u32);
+            for (at, col) in changes {
+                let local = *at % Self::CHUNK;
+                let local = local.x
+                    + local.y * Self::CHUNK
+                    + local.z * Self::CHUNK * Self::CHUNK;
+                out.extend_from_slice(&(local as u16).to_le_bytes());
+                put_u32(out, *col);
+            }
+        }
+    }
+
+    /// the inverse of [`Self::to_bytes`], operations referencing chunks
+    /// whose `chunk_hash` changed since the save are dropped,
+    /// their changes would overwrite voxels they do not know about
+    pub fn from_bytes(
+        bytes: &[u8],
+        limit: usize,
+        chunk_hash: impl Fn(U64Vec3) -> u64,
+    ) -> Result<Self> {
+        let mut r = Reader(bytes);
+        let version = r.u32()?;
+        if version != Self::VERSION {
+            bail!("unsupported history version {version}");
+        }
+
+        let mut stacks = [Vec::new(), Vec::new()];
+        for stack in &mut stacks {
+            for _ in 0..r.u32()? {
+                let time = r.u64()?;
+                let mut changes = Vec::new();
+                for _ in 0..r.u32()? {
+                    let chunk = r.chunk()?;
+                    for _ in 0..r.u32()? {
+                        let local = r.u16()? as u64;
+                        let col = r.u32()?;
+                        let offset = U64Vec3::new(
+                            local % Self::CHUNK,
+                            local / Self::CHUNK % Self::CHUNK,
+                            local / (Self::CHUNK * Self::CHUNK),
+                        );
+                        changes.push((chunk * Self::CHUNK + offset, col));
+                    }
+                }
+                stack.push(Operation { time, changes });
+            }
+        }
+
+        let mut stale = FxHashSet::default();
+        for _ in 0..r.u32()? {
+            let chunk = r.chunk()?;
+            if r.u64()? != chunk_hash(chunk) {
+                stale.insert(chunk);
+            }
+        }
+
+        let [undo, redo] = stacks;
+        let total = undo.len() + redo.len();
+        let is_valid =
+            |op: &Operation| !op.chunks().any(|chunk| stale.contains(&chunk));
+        let undo = undo.into_iter().filter(is_valid).collect::<Vec<_>>();
+        let redo = redo.into_iter().filter(is_valid).collect::<Vec<_>>();
+
+        let dropped = total - undo.len() - redo.len();
+        if dropped != 0 {
+            tracing::warn!(
+                "{} chunks changed outside of the history, \
+                 dropped {dropped} operations",
+                stale.len()
+            );
+        }
+
+        let mut history = Self::new(limit);
+        for op in undo {
+            history.push_undo(op);
+        }
+        history.redo = redo;
+        Ok(history)
+    }
+}
+
+/// `YYYY-MM-DD HH:MM:SS` UTC of seconds since the unix epoch
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let (hour, min, sec) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    // days to a civil date, from Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!("{year:04}-{month:02}-{day:02} {hour:02}:{min:02}:{sec:02}")
+}
+
+/// content hash of one [`History::CHUNK`]³ chunk of `octree`
+pub fn chunk_hash(octree: &Octree, chunk: U64Vec3) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = rustc_hash::FxHasher::default();
+    let origin = chunk * History::CHUNK;
+    for z in 0..History::CHUNK {
+        for y in 0..History::CHUNK {
+            for x in 0..History::CHUNK {
+                let at = origin + U64Vec3::new(x, y, z);
+                let col = if at.cmplt(U64Vec3::splat(Octree::SIZE as u64)).all()
+                {
+                    octree.get_voxel(at)
+                } else {
+                    0
+                };
+                col.hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, v: u64) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_chunk(out: &mut Vec<u8>, chunk: U64Vec3) {
+    for v in chunk.to_array() {
+        put_u32(out, v as u32);
+    }
+}
+
+/// little endian values from the front of a byte slice
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let Some((bytes, rest)) = self.0.split_first_chunk::<N>() else {
+            bail!("the history section is truncated");
+        };
+        self.0 = rest;
+        Ok(*bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn chunk(&mut self) -> Result<U64Vec3> {
+        Ok(U64Vec3::new(
+            self.u32()? as u64,
+            self.u32()? as u64,
+            self.u32()? as u64,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Changes = Vec<(U64Vec3, u32)>;
+
+    /// applies the changes to `octree` like [`Edit::commit`] would
+    ///
+    /// [`Edit::commit`]: super::super::voxels::Edit::commit
+    fn apply(
+        octree: &mut Octree,
+    ) -> impl FnOnce(Changes) -> Result<Changes> + '_ {
+        |changes| {
+            let mut inverse = Vec::new();
+            for (at, col) in changes {
+                inverse.push((at, octree.get_voxel(at)));
+                octree.insert_voxel(at, col);
+            }
+            inverse.reverse();
+            Ok(inverse)
+        }
+    }
+
+    fn edit(
+        history: &mut History,
+        octree: &mut Octree,
+        time: u64,
+        changes: Vec<(U64Vec3, u32)>,
+    ) {
+        let inverse = apply(octree)(changes).unwrap();
+        history.record(time, inverse);
+    }
+
+    fn times<'a>(ops: impl Iterator<Item = &'a Operation>) -> Vec<u64> {
+        ops.map(|op| op.time).collect()
+    }
+
+    #[test]
+    fn eviction_keeps_the_newest() {
+        let mut octree = Octree::new();
+        let mut history = History::new(3);
+        for time in 0..5 {
+            edit(
+                &mut history,
+                &mut octree,
+                time,
+                vec![(U64Vec3::splat(time), 1)],
+            );
+        }
+        assert_eq!(times(history.undoable()), [4, 3, 2]);
+
+        // redoing pushes back onto the full undo stack
+        history.undo(apply(&mut octree)).unwrap();
+        assert_eq!(times(history.undoable()), [3, 2]);
+        history.redo(apply(&mut octree)).unwrap();
+        assert_eq!(times(history.undoable()), [4, 3, 2]);
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut octree = Octree::new();
+        let mut history = History::new(8);
+        edit(&mut history, &mut octree, 1, vec![(U64Vec3::ZERO, 1)]);
+        edit(&mut history, &mut octree, 2, vec![(U64Vec3::ONE, 1)]);
+
+        assert_eq!(history.undo(apply(&mut octree)).unwrap(), Some(2));
+        assert_eq!(octree.get_voxel(U64Vec3::ONE), 0);
+        assert_eq!(times(history.redoable()), [2]);
+
+        edit(&mut history, &mut octree, 3, vec![(U64Vec3::X, 1)]);
+        assert_eq!(times(history.redoable()), []);
+        assert_eq!(history.redo(apply(&mut octree)).unwrap(), None);
+    }
+
+    #[test]
+    fn undo_and_redo_restore_voxels() {
+        let mut octree = Octree::new();
+        octree.insert_voxel(U64Vec3::ZERO, 5);
+        let mut history = History::new(8);
+        edit(
+            &mut history,
+            &mut octree,
+            1,
+            vec![(U64Vec3::ZERO, 0), (U64Vec3::ZERO, 7)],
+        );
+
+        history.undo(apply(&mut octree)).unwrap();
+        assert_eq!(octree.get_voxel(U64Vec3::ZERO), 5);
+        history.redo(apply(&mut octree)).unwrap();
+        assert_eq!(octree.get_voxel(U64Vec3::ZERO), 7);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut octree = Octree::new();
+        let mut history = History::new(8);
+        edit(
+            &mut history,
+            &mut octree,
+            10,
+            // the inverse is grouped by chunk, same as in the section
+            vec![
+                (U64Vec3::new(1, 2, 3), 1),
+                (U64Vec3::new(1, 2, 3), 3),
+                (U64Vec3::new(31, 30, 9), 2),
+            ],
+        );
+        edit(&mut history, &mut octree, 20, vec![(U64Vec3::splat(8), 4)]);
+        history.undo(apply(&mut octree)).unwrap();
+
+        let hash = |chunk| chunk_hash(&octree, chunk);
+        let bytes = history.to_bytes(hash);
+        let loaded = History::from_bytes(&bytes, 8, hash).unwrap();
+        assert_eq!(
+            loaded.undoable().collect::<Vec<_>>(),
+            history.undoable().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            loaded.redoable().collect::<Vec<_>>(),
+            history.redoable().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn load_applies_the_limit() {
+        let mut octree = Octree::new();
+        let mut history = History::new(8);
+        for time in 0..4 {
+            edit(
+                &mut history,
+                &mut octree,
+                time,
+                vec![(U64Vec3::splat(time), 1)],
+            );
+        }
+
+        let hash = |chunk| chunk_hash(&octree, chunk);
+        let loaded =
+            History::from_bytes(&history.to_bytes(hash), 2, hash).unwrap();
+        assert_eq!(times(loaded.undoable()), [3, 2]);
+    }
+
+    #[test]
+    fn changed_chunks_invalidate() {
+        let mut octree = Octree::new();
+        let mut history = History::new(8);
+        // chunk 0,0,0 and chunk 3,3,3
+        edit(&mut history, &mut octree, 1, vec![(U64Vec3::ZERO, 1)]);
+        edit(&mut history, &mut octree, 2, vec![(U64Vec3::splat(30), 1)]);
+        edit(
+            &mut history,
+            &mut octree,
+            3,
+            vec![(U64Vec3::ONE, 1), (U64Vec3::splat(31), 1)],
+        );
+        let bytes = history.to_bytes(|chunk| chunk_hash(&octree, chunk));
+
+        // an edit that bypassed the history
+        octree.insert_voxel(U64Vec3::new(7, 0, 0), 2);
+        let loaded =
+            History::from_bytes(&bytes, 8, |chunk| chunk_hash(&octree, chunk))
+                .unwrap();
+        assert_eq!(times(loaded.undoable()), [2]);
+    }
+
+    #[test]
+    fn unchanged_chunks_stay_valid() {
+        let mut octree = Octree::new();
+        let mut history = History::new(8);
+        edit(&mut history, &mut octree, 1, vec![(U64Vec3::ZERO, 1)]);
+        let bytes = history.to_bytes(|chunk| chunk_hash(&octree, chunk));
+
+        // another chunk
+        octree.insert_voxel(U64Vec3::new(8, 0, 0), 2);
+        let loaded =
+            History::from_bytes(&bytes, 8, |chunk| chunk_hash(&octree, chunk))
+                .unwrap();
+        assert_eq!(times(loaded.undoable()), [1]);
+    }
+
+    #[test]
+    fn truncated_fails() {
+        let history = History::new(8);
+        let bytes = history.to_bytes(|_| 0);
+        assert!(
+            History::from_bytes(&bytes[..bytes.len() - 1], 8, |_| 0).is_err()
+        );
+    }
+
+    #[test]
+    fn format_times() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00");
+        assert_eq!(format_time(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_time(1_700_000_000), "2023-11-14 22:13:20");
+    }
+}
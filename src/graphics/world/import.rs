@@ -0,0 +1,496 @@
+use std::{
+    env, fs,
+    io::{self, Read},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use eyre::{Result, bail};
+use glam::U64Vec3;
+
+use super::voxels::{Octree, Voxel};
+
+//
+
+/// how much memory an import may use
+#[derive(Debug, Clone, Copy)]
+pub struct ImportLimits {
+    /// import even if the estimate is over the limit
+    pub force: bool,
+    /// largest share of the system memory the estimate may use,
+    /// `LUMINARY_IMPORT_MEMORY_FRACTION`, 0.5 by default
+    pub memory_fraction: f64,
+    /// total system memory, `None` if it is unknown
+    pub system_memory: Option<u64>,
+}
+
+impl ImportLimits {
+    pub fn new(force: bool) -> Self {
+        Self {
+            force,
+            memory_fraction: env::var("LUMINARY_IMPORT_MEMORY_FRACTION")
+                .ok()
+                .and_then(|fraction| fraction.parse().ok())
+                .unwrap_or(0.5),
+            system_memory: system_memory(),
+        }
+    }
+
+    /// refuse imports estimated to need more than the limit,
+    /// nothing is refused if the system memory is unknown
+    fn check(&self, what: &str, estimate: u64) -> Result<()> {
+        let Some(system_memory) = self.system_memory else {
+            return Ok(());
+        };
+        let limit = (system_memory as f64 * self.memory_fraction) as u64;
+        if estimate <= limit {
+            return Ok(());
+        }
+        if self.force {
+            tracing::warn!(
+                "importing {what} needs about {estimate}B, \
+                 over the {limit}B limit, forced"
+            );
+            return Ok(());
+        }
+        bail!(
+            "importing {what} needs about {estimate}B, more than {:.0}% of \
+             the {system_memory}B of system memory, use --force to import \
+             it anyways",
+            self.memory_fraction * 100.0
+        );
+    }
+}
+
+/// `MemTotal` of /proc/meminfo
+fn system_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let kib = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// the voxels of an import, parts outside of the octree are cut off
+pub struct Imported {
+    pub octree: Octree,
+    /// voxels that did not fit the octree
+    pub clipped: u64,
+    /// the most memory the import held at once, counted from the
+    /// capacities of its own buffers and not the OS, so it is exact
+    pub peak_bytes: u64,
+}
+
+/// counts the bytes held by the importer's buffers
+#[derive(Default)]
+struct PeakCounter {
+    peak: u64,
+}
+
+impl PeakCounter {
+    fn update(&mut self, buffer: usize, octree: &Octree) {
+        let bytes = buffer + octree.node_capacity() * size_of::<Voxel>();
+        self.peak = self.peak.max(bytes as u64);
+    }
+}
+
+/// the most a growing node `Vec` can hold for `voxels` distinct voxels,
+/// each level has at most one group of 8 children per voxel,
+/// doubled because a `Vec` grows by doubling its capacity
+fn octree_estimate(voxels: u64) -> u64 {
+    let nodes = 1
+        + (0..Octree::DEPTH)
+            .map(|level| 8 * 8u64.pow(level).min(voxels))
+            .sum::<u64>();
+    2 * nodes * size_of::<Voxel>() as u64
+}
+
+//
+
+/// voxels read from a `.vox` at once
+const VOX_BATCH: usize = 4096;
+
+/// import a MagicaVoxel `.vox` chunk by chunk, the voxels go straight
+/// into the octree without a dense grid, so the memory used does not
+/// depend on the model size
+///
+/// models are placed at the origin with Z up turned into Y up,
+/// transforms of the scene graph are ignored, so are the palette colors,
+/// color indices are kept as they are
+pub fn import_vox(
+    mut reader: impl Read,
+    limits: &ImportLimits,
+    cancel: &AtomicBool,
+) -> Result<Imported> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"VOX " {
+        bail!("not a .vox file");
+    }
+    let (id, content, _) = read_chunk_header(&mut reader)?;
+    if &id != b"MAIN" {
+        bail!("the .vox file does not start with a MAIN chunk");
+    }
+    skip(&mut reader, content)?;
+
+    let mut octree = Octree::new();
+    let mut counter = PeakCounter::default();
+    let mut batch = Vec::with_capacity(VOX_BATCH * 4);
+    let mut size = None;
+    let mut clipped = 0;
+
+    loop {
+        let (id, content, children) = match read_chunk_header(&mut reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        match &id {
+            b"SIZE" if content >= 12 => {
+                let mut dims = [0u8; 12];
+                reader.read_exact(&mut dims)?;
+                skip(&mut reader, content - 12)?;
+                let dims = U64Vec3::from_array(
+                    [0, 4, 8].map(|i| read_u32(&dims[i..]) as u64),
+                );
+                // the voxels of this model within the octree
+                let fits = dims.min(U64Vec3::splat(Octree::SIZE as u64));
+                let estimate = octree_estimate(fits.element_product())
+                    + batch.capacity() as u64;
+                limits.check(&format!("a {dims} .vox model"), estimate)?;
+                size = Some(dims);
+            }
+            b"XYZI" if content >= 4 => {
+                let Some(dims) = size.take() else {
+                    bail!("XYZI chunk without a SIZE chunk");
+                };
+                let mut count = [0u8; 4];
+                reader.read_exact(&mut count)?;
+                let count = read_u32(&count) as u64;
+                if content < 4 + count * 4 {
+                    bail!("the XYZI chunk is too short for {count} voxels");
+                }
+
+                let mut done = 0;
+                while done < count {
+                    if cancel.load(Ordering::Relaxed) {
+                        bail!("import cancelled");
+                    }
+                    let n = (count - done).min(VOX_BATCH as u64) as usize;
+                    batch.resize(n * 4, 0);
+                    reader.read_exact(&mut batch)?;
+                    for voxel in batch.chunks_exact(4) {
+                        let [x, y, z, col] =
+                            [voxel[0], voxel[1], voxel[2], voxel[3]]
+                                .map(|v| v as u64);
+                        let at = U64Vec3::new(x, z, y);
+                        if at.cmplt(U64Vec3::splat(Octree::SIZE as u64)).all()
+                            && col != 0
+                        {
+                            octree.insert_voxel(at, col as u32);
+                        } else {
+                            clipped += 1;
+                        }
+                    }
+                    counter.update(batch.capacity(), &octree);
+
+                    let before = done * 10 / count;
+                    done += n as u64;
+                    if done * 10 / count != before {
+                        tracing::info!(
+                            "importing a {dims} .vox model, {}%",
+                            done * 100 / count
+                        );
+                    }
+                }
+                skip(&mut reader, content - 4 - count * 4)?;
+            }
+            // the palette, materials and the scene graph
+            _ => skip(&mut reader, content)?,
+        }
+        skip(&mut reader, children)?;
+    }
+
+    octree.compact();
+    counter.update(batch.capacity(), &octree);
+    Ok(Imported {
+        octree,
+        clipped,
+        peak_bytes: counter.peak,
+    })
+}
+
+/// the id, content size and children size of a chunk
+fn read_chunk_header(
+    reader: &mut impl Read,
+) -> io::Result<([u8; 4], u64, u64)> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    Ok((
+        [header[0], header[1], header[2], header[3]],
+        read_u32(&header[4..]) as u64,
+        read_u32(&header[8..]) as u64,
+    ))
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn skip(reader: &mut impl Read, bytes: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(bytes), &mut io::sink())?;
+    if skipped != bytes {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+//
+
+/// heightmap rows read at once
+const HEIGHTMAP_STRIP: usize = 64;
+
+/// import a heightmap of little endian `u16` samples, `width` per row,
+/// in strips of rows, each octree column is as high as the highest
+/// sample that falls into it and has the color `col`
+pub fn import_heightmap(
+    mut reader: impl Read,
+    width: u32,
+    height: u32,
+    col: u32,
+    limits: &ImportLimits,
+    cancel: &AtomicBool,
+) -> Result<Imported> {
+    if width == 0 || height == 0 {
+        bail!("the heightmap is empty");
+    }
+    let (width, height) = (width as usize, height as usize);
+    let columns = Octree::SIZE * Octree::SIZE;
+    let strip_len = width * HEIGHTMAP_STRIP.min(height) * 2;
+    let estimate = octree_estimate(Octree::SIZE.pow(3) as u64)
+        + (strip_len + columns * 2) as u64;
+    limits.check(&format!("a {width}x{height} heightmap"), estimate)?;
+
+    let mut octree = Octree::new();
+    let mut counter = PeakCounter::default();
+    let mut strip = Vec::with_capacity(strip_len);
+    let mut highest = vec![0u16; columns];
+
+    for first_row in (0..height).step_by(HEIGHTMAP_STRIP) {
+        if cancel.load(Ordering::Relaxed) {
+            bail!("import cancelled");
+        }
+        let rows = HEIGHTMAP_STRIP.min(height - first_row);
+        strip.resize(width * rows * 2, 0);
+        reader.read_exact(&mut strip)?;
+
+        for (row, samples) in strip.chunks_exact(width * 2).enumerate() {
+            let z = (first_row + row) * Octree::SIZE / height;
+            for (x, sample) in samples.chunks_exact(2).enumerate() {
+                let column =
+                    &mut highest[z * Octree::SIZE + x * Octree::SIZE / width];
+                *column =
+                    (*column).max(u16::from_le_bytes([sample[0], sample[1]]));
+            }
+        }
+        counter.update(strip.capacity() + highest.capacity() * 2, &octree);
+
+        tracing::info!(
+            "importing a {width}x{height} heightmap, {}%",
+            (first_row + rows) * 100 / height
+        );
+    }
+
+    for (i, &sample) in highest.iter().enumerate() {
+        let top =
+            (sample as usize * Octree::SIZE).div_ceil(u16::MAX as usize + 1);
+        let (x, z) = ((i % Octree::SIZE) as u64, (i / Octree::SIZE) as u64);
+        for y in 0..top as u64 {
+            octree.insert_voxel(U64Vec3::new(x, y, z), col);
+        }
+    }
+    counter.update(strip.capacity() + highest.capacity() * 2, &octree);
+    octree.compact();
+
+    Ok(Imported {
+        octree,
+        clipped: 0,
+        peak_bytes: counter.peak,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+        out.extend(id);
+        out.extend((content.len() as u32).to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out.extend(content);
+    }
+
+    /// a `.vox` with one model of `size` and `voxels`
+    fn vox(size: [u32; 3], voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut children = Vec::new();
+        chunk(&mut children, b"SIZE", bytemuck::cast_slice(&size));
+        let mut xyzi = (voxels.len() as u32).to_le_bytes().to_vec();
+        xyzi.extend(voxels.as_flattened());
+        chunk(&mut children, b"XYZI", &xyzi);
+        chunk(&mut children, b"RGBA", &[0xff; 1024]);
+
+        let mut out = b"VOX \x96\0\0\0MAIN".to_vec();
+        out.extend(0u32.to_le_bytes());
+        out.extend((children.len() as u32).to_le_bytes());
+        out.extend(children);
+        out
+    }
+
+    fn unlimited() -> ImportLimits {
+        ImportLimits {
+            force: false,
+            memory_fraction: 0.5,
+            system_memory: None,
+        }
+    }
+
+    #[test]
+    fn vox_voxels() {
+        let file =
+            vox([40, 40, 40], &[[1, 2, 3, 7], [0, 0, 0, 1], [35, 0, 0, 2]]);
+        let imported =
+            import_vox(&file[..], &unlimited(), &AtomicBool::new(false))
+                .unwrap();
+
+        // Z up becomes Y up
+        assert_eq!(imported.octree.get_voxel(U64Vec3::new(1, 3, 2)), 7);
+        assert_eq!(imported.octree.get_voxel(U64Vec3::ZERO), 1);
+        assert_eq!(imported.octree.get_voxel(U64Vec3::new(1, 2, 3)), 0);
+        assert_eq!(imported.clipped, 1);
+
+        assert!(
+            import_vox(&b"RIFF"[..], &unlimited(), &AtomicBool::new(false))
+                .is_err()
+        );
+        let truncated = &file[..file.len() - 1100];
+        assert!(
+            import_vox(truncated, &unlimited(), &AtomicBool::new(false))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn large_sparse_vox_stays_small() {
+        // a huge model, a dense grid of it would need terabytes
+        let voxels = (0..200_000u32)
+            .map(|i| {
+                let [a, b, c, _] = i.to_le_bytes();
+                [a, b.wrapping_mul(7), c ^ a, 1 + (i % 3) as u8]
+            })
+            .collect::<Vec<_>>();
+        let file = vox([2048, 2048, 2048], &voxels);
+        let imported =
+            import_vox(&file[..], &unlimited(), &AtomicBool::new(false))
+                .unwrap();
+
+        assert!(imported.clipped > 150_000, "{}", imported.clipped);
+        let estimate = octree_estimate(32 * 32 * 32) + (VOX_BATCH * 4) as u64;
+        assert!(
+            imported.peak_bytes <= estimate,
+            "{} > {estimate}",
+            imported.peak_bytes
+        );
+        assert!(imported.peak_bytes < 1 << 20, "{}", imported.peak_bytes);
+    }
+
+    #[test]
+    fn refused_unless_forced() {
+        let file = vox([32, 32, 32], &[[0, 0, 0, 1]]);
+        let mut limits = ImportLimits {
+            force: false,
+            memory_fraction: 0.5,
+            system_memory: Some(64 * 1024),
+        };
+        let err = import_vox(&file[..], &limits, &AtomicBool::new(false))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("--force"), "{err}");
+
+        limits.force = true;
+        import_vox(&file[..], &limits, &AtomicBool::new(false)).unwrap();
+        let err = import_heightmap(
+            &[0; 8][..],
+            2,
+            2,
+            1,
+            &ImportLimits {
+                force: false,
+                ..limits
+            },
+            &AtomicBool::new(false),
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("2x2 heightmap"), "{err}");
+    }
+
+    #[test]
+    fn cancelled() {
+        let file = vox([8, 8, 8], &[[0, 0, 0, 1]]);
+        let cancel = AtomicBool::new(true);
+        let err = import_vox(&file[..], &unlimited(), &cancel).err().unwrap();
+        assert_eq!(err.to_string(), "import cancelled");
+        let err = import_heightmap(&[0; 8][..], 2, 2, 1, &unlimited(), &cancel)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "import cancelled");
+    }
+
+    #[test]
+    fn heightmap_columns() {
+        // 100 rows, so there are two strips, the samples rise along x
+        let (width, height) = (64u32, 100u32);
+        let samples = (0..width * height)
+            .flat_map(|i| ((i % width) as u16 * 1024).to_le_bytes())
+            .collect::<Vec<_>>();
+        let imported = import_heightmap(
+            &samples[..],
+            width,
+            height,
+            2,
+            &unlimited(),
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let octree = &imported.octree;
+
+        // two samples per column, the higher one counts,
+        // 65535 would be all 32 voxels
+        let column = |x: u64, z: u64| {
+            (0..32)
+                .take_while(|&y| octree.get_voxel(U64Vec3::new(x, y, z)) == 2)
+                .count()
+        };
+        assert_eq!(column(0, 0), 1);
+        assert_eq!(column(10, 31), 11);
+        assert_eq!(column(31, 17), 32);
+        assert_eq!(octree.get_voxel(U64Vec3::new(10, 11, 5)), 0);
+
+        assert!(
+            import_heightmap(
+                &samples[..10],
+                width,
+                height,
+                2,
+                &unlimited(),
+                &AtomicBool::new(false)
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use std::{fmt::Write as _, fs, path::Path};
+
+use eyre::{Result, bail};
+use glam::{IVec3, Vec3};
+use rustc_hash::FxHashMap;
+
+//
+
+/// axis aligned voxel face rectangle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quad {
+    /// counter-clockwise when looking against the normal
+    pub corners: [Vec3; 4],
+    pub normal: Vec3,
+    pub col: u32,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Mesh {
+    pub quads: Vec<Quad>,
+}
+
+impl Mesh {
+    /// greedy mesh a dense `size`³ grid of voxel colors, 0 is air,
+    /// the grid is indexed with `x + y * size + z * size * size`
+    pub fn greedy(grid: &[u32], size: usize) -> Self {
+        assert_eq!(grid.len(), size * size * size);
+
+        let get = |p: IVec3| -> u32 {
+            if p.cmplt(IVec3::ZERO).any()
+                || p.cmpge(IVec3::splat(size as _)).any()
+            {
+                return 0;
+            }
+            grid[p.x as usize
+                + p.y as usize * size
+                + p.z as usize * size * size]
+        };
+
+        let mut quads = Vec::new();
+        let mut mask = vec![0u32; size * size];
+
+        for axis in 0..3 {
+            let u = (axis + 1) % 3;
+            let v = (axis + 2) % 3;
+
+            for dir in [1i32, -1] {
+                let mut normal = IVec3::ZERO;
+                normal[axis] = dir;
+
+                for slice in 0..size {
+                    // faces of this slice that are not covered by a neighbour
+                    for j in 0..size {
+                        for i in 0..size {
+                            let mut p = IVec3::ZERO;
+                            p[axis] = slice as _;
+                            p[u] = i as _;
+                            p[v] = j as _;
+
+                            let col = get(p);
+                            mask[i + j * size] =
+                                if col != 0 && get(p + normal) == 0 {
+                                    col
+                                } else {
+                                    0
+                                };
+                        }
+                    }
+
+                    for j in 0..size {
+                        let mut i = 0;
+                        while i < size {
+                            let col = mask[i + j * size];
+                            if col == 0 {
+                                i += 1;
+                                continue;
+                            }
+
+                            let mut w = 1;
+                            while i + w < size && mask[i + w + j * size] == col
+                            {
+                                w += 1;
+                            }
+
+                            let mut h = 1;
+                            while j + h < size
+                                && (i..i + w)
+                                    .all(|i| mask[i + (j + h) * size] == col)
+                            {
+                                h += 1;
+                            }
+
+                            for y in j..j + h {
+                                mask[i + y * size..i + w + y * size].fill(0);
+                            }
+
+                            let mut origin = Vec3::ZERO;
+                            origin[axis] =
+                                slice as f32 + (dir == 1) as u8 as f32;
+                            origin[u] = i as f32;
+                            origin[v] = j as f32;
+                            let mut du = Vec3::ZERO;
+                            du[u] = w as f32;
+                            let mut dv = Vec3::ZERO;
+                            dv[v] = h as f32;
+
+                            let corners = if dir == 1 {
+                                [
+                                    origin,
+                                    origin + du,
+                                    origin + du + dv,
+                                    origin + dv,
+                                ]
+                            } else {
+                                [
+                                    origin,
+                                    origin + dv,
+                                    origin + du + dv,
+                                    origin + du,
+                                ]
+                            };
+
+                            quads.push(Quad {
+                                corners,
+                                normal: normal.as_vec3(),
+                                col,
+                            });
+
+                            i += w;
+                        }
+                    }
+                }
+            }
+        }
+
+        Self { quads }
+    }
+
+    /// Wavefront OBJ with one `usemtl col_N` group per voxel color
+    pub fn save_obj(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut obj = String::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+
+        for quad in self.quads.iter() {
+            for corner in quad.corners {
+                writeln!(obj, "v {} {} {}", corner.x, corner.y, corner.z)?;
+            }
+            if !normals.contains(&quad.normal) {
+                normals.push(quad.normal);
+            }
+        }
+        for normal in normals.iter() {
+            writeln!(obj, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+        }
+
+        for (col, quads) in self.quads_by_col() {
+            writeln!(obj, "usemtl col_{col}")?;
+            for i in quads {
+                let quad = &self.quads[i];
+                // obj indices start from 1
+                let v = i * 4 + 1;
+                let n =
+                    normals.iter().position(|n| *n == quad.normal).unwrap() + 1;
+                writeln!(
+                    obj,
+                    "f {}//{n} {}//{n} {}//{n} {}//{n}",
+                    v,
+                    v + 1,
+                    v + 2,
+                    v + 3
+                )?;
+            }
+        }
+
+        fs::write(path, obj)?;
+        Ok(())
+    }
+
+    /// glTF 2.0 with an embedded buffer and one primitive per voxel color,
+    /// the material index of a primitive is its index in the sorted colors,
+    /// fails for empty meshes, glTF meshes need at least one primitive
+    pub fn save_gltf(&self, path: impl AsRef<Path>) -> Result<()> {
+        if self.quads.is_empty() {
+            bail!("there are no voxel faces to export");
+        }
+
+        let mut bin: Vec<u8> = Vec::new();
+        let mut buffer_views = Vec::new();
+        let mut accessors = Vec::new();
+        let mut primitives = Vec::new();
+        let mut materials = Vec::new();
+
+        for (material, (col, quads)) in
+            self.quads_by_col().into_iter().enumerate()
+        {
+            let mut positions: Vec<Vec3> = Vec::new();
+            let mut normals: Vec<Vec3> = Vec::new();
+            let mut indices: Vec<u32> = Vec::new();
+            for i in quads {
+                let quad = &self.quads[i];
+                let first = positions.len() as u32;
+                positions.extend(quad.corners);
+                normals.extend([quad.normal; 4]);
+                indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+            }
+
+            let min = positions.iter().fold(Vec3::INFINITY, |a, b| a.min(*b));
+            let max =
+                positions.iter().fold(Vec3::NEG_INFINITY, |a, b| a.max(*b));
+
+            let attributes = [
+                (bytemuck::cast_slice::<_, u8>(&positions), 34962),
+                (bytemuck::cast_slice::<_, u8>(&normals), 34962),
+                (bytemuck::cast_slice::<_, u8>(&indices), 34963),
+            ];
+            let first_view = buffer_views.len();
+            for (bytes, target) in attributes {
+                buffer_views.push(format!(
+                    r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{target}}}"#,
+                    bin.len(),
+                    bytes.len()
+                ));
+                bin.extend_from_slice(bytes);
+            }
+
+            let first_accessor = accessors.len();
+            accessors.push(format!(
+                r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+                first_view,
+                positions.len(),
+                min.x,
+                min.y,
+                min.z,
+                max.x,
+                max.y,
+                max.z
+            ));
+            accessors.push(format!(
+                r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC3"}}"#,
+                first_view + 1,
+                normals.len()
+            ));
+            accessors.push(format!(
+                r#"{{"bufferView":{},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+                first_view + 2,
+                indices.len()
+            ));
+
+            primitives.push(format!(
+                r#"{{"attributes":{{"POSITION":{},"NORMAL":{}}},"indices":{},"material":{material}}}"#,
+                first_accessor,
+                first_accessor + 1,
+                first_accessor + 2
+            ));
+            materials.push(format!(r#"{{"name":"col_{col}"}}"#));
+        }
+
+        let gltf = format!(
+            r#"{{"asset":{{"version":"2.0","generator":"luminary"}},"scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0}}],"meshes":[{{"primitives":[{}]}}],"materials":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{},"uri":"data:application/octet-stream;base64,{}"}}]}}"#,
+            primitives.join(","),
+            materials.join(","),
+            accessors.join(","),
+            buffer_views.join(","),
+            bin.len(),
+            base64(&bin)
+        );
+
+        fs::write(path, gltf)?;
+        Ok(())
+    }
+
+    /// quad indices grouped by color, sorted by color
+    fn quads_by_col(&self) -> Vec<(u32, Vec<usize>)> {
+        let mut groups: FxHashMap<u32, Vec<usize>> = FxHashMap::default();
+        for (i, quad) in self.quads.iter().enumerate() {
+            groups.entry(quad.col).or_default().push(i);
+        }
+
+        let mut groups: Vec<_> = groups.into_iter().collect();
+        groups.sort_unstable_by_key(|(col, _)| *col);
+        groups
+    }
+}
+
+//
+
+fn base64(bytes: &[u8]) -> String {
+    const TABLE: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - i * 6)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 4;
+
+    fn grid(voxels: &[(usize, usize, usize, u32)]) -> Vec<u32> {
+        let mut grid = vec![0; SIZE.pow(3)];
+        for &(x, y, z, col) in voxels {
+            grid[x + y * SIZE + z * SIZE * SIZE] = col;
+        }
+        grid
+    }
+
+    /// the corners wind counter-clockwise around the normal
+    fn assert_winding(mesh: &Mesh) {
+        for quad in mesh.quads.iter() {
+            let [a, b, _, d] = quad.corners;
+            let normal = (b - a).cross(d - a).normalize();
+            assert_eq!(normal, quad.normal, "{quad:?}");
+        }
+    }
+
+    #[test]
+    fn single_voxel() {
+        let mesh = Mesh::greedy(&grid(&[(1, 2, 3, 5)]), SIZE);
+        assert_eq!(mesh.quads.len(), 6);
+        assert!(mesh.quads.iter().all(|quad| quad.col == 5));
+        assert_winding(&mesh);
+
+        let top = mesh.quads.iter().find(|quad| quad.normal == Vec3::Y);
+        let mut corners = top.unwrap().corners;
+        corners
+            .sort_by(|a, b| a.to_array().partial_cmp(&b.to_array()).unwrap());
+        assert_eq!(
+            corners,
+            [
+                Vec3::new(1.0, 3.0, 3.0),
+                Vec3::new(1.0, 3.0, 4.0),
+                Vec3::new(2.0, 3.0, 3.0),
+                Vec3::new(2.0, 3.0, 4.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn bar_is_merged() {
+        let mesh = Mesh::greedy(&grid(&[(0, 0, 0, 1), (1, 0, 0, 1)]), SIZE);
+        // two end caps and four merged 2x1 sides
+        assert_eq!(mesh.quads.len(), 6);
+        assert_winding(&mesh);
+    }
+
+    #[test]
+    fn colors_are_not_merged() {
+        let mesh = Mesh::greedy(&grid(&[(0, 0, 0, 1), (1, 0, 0, 2)]), SIZE);
+        assert_eq!(mesh.quads.len(), 10);
+        assert_winding(&mesh);
+        let groups = mesh.quads_by_col();
+        assert_eq!(
+            groups.iter().map(|(col, _)| *col).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(groups[0].1.len(), 5);
+    }
+
+    #[test]
+    fn empty_gltf_fails() {
+        let path = std::env::temp_dir().join("luminary-empty-mesh.gltf");
+        let mesh = Mesh::greedy(&grid(&[]), SIZE);
+        assert!(mesh.quads.is_empty());
+        assert!(mesh.save_gltf(&path).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+    }
+}
@@ -1 +1,5 @@
+pub mod history;
+pub mod import;
+pub mod mesh;
+pub mod save;
 pub mod voxels;
@@ -0,0 +1,130 @@
+use std::{fs, path::Path};
+
+use eyre::{Result, bail};
+
+//
+
+/// a world save, tagged sections that are read independently,
+/// so a reader skips the ones it does not know
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct WorldSave {
+    sections: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl WorldSave {
+    const MAGIC: [u8; 4] = *b"LUMW";
+    /// the dense voxel grid, little endian `u32` colors
+    pub const VOXELS: [u8; 4] = *b"VOXL";
+    /// the undo history, see [`History::to_bytes`]
+    ///
+    /// [`History::to_bytes`]: super::history::History::to_bytes
+    pub const HISTORY: [u8; 4] = *b"HIST";
+
+    pub fn section(&self, tag: [u8; 4]) -> Option<&[u8]> {
+        self.sections
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, bytes)| bytes.as_slice())
+    }
+
+    /// replaces an existing section with the same tag
+    pub fn set_section(&mut self, tag: [u8; 4], bytes: Vec<u8>) {
+        match self.sections.iter_mut().find(|(t, _)| *t == tag) {
+            Some((_, section)) => *section = bytes,
+            None => self.sections.push((tag, bytes)),
+        }
+    }
+
+    /// `LUMW`, then each section as its tag, `u32` length and bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Self::MAGIC.to_vec();
+        for (tag, bytes) in &self.sections {
+            out.extend_from_slice(tag);
+            out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            out.extend_from_slice(bytes);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let Some(mut rest) = bytes.strip_prefix(&Self::MAGIC) else {
+            bail!("not a world save");
+        };
+
+        let mut save = Self::default();
+        while !rest.is_empty() {
+            let Some((tag, after)) = rest.split_first_chunk::<4>() else {
+                bail!("truncated section tag");
+            };
+            let Some((len, after)) = after.split_first_chunk::<4>() else {
+                bail!("truncated section length");
+            };
+            let len = u32::from_le_bytes(*len) as usize;
+            if after.len() < len {
+                bail!(
+                    "section {} needs {len}B, only {}B are left",
+                    String::from_utf8_lossy(tag),
+                    after.len()
+                );
+            }
+            save.sections.push((*tag, after[..len].to_vec()));
+            rest = &after[len..];
+        }
+        Ok(save)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        // a crash while writing must not destroy the previous save
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.to_bytes())?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut save = WorldSave::default();
+        save.set_section(WorldSave::VOXELS, vec![1, 2, 3]);
+        save.set_section(WorldSave::HISTORY, vec![]);
+        save.set_section(WorldSave::VOXELS, vec![4]);
+
+        let loaded = WorldSave::from_bytes(&save.to_bytes()).unwrap();
+        assert_eq!(loaded, save);
+        assert_eq!(loaded.section(WorldSave::VOXELS), Some(&[4][..]));
+        assert_eq!(loaded.section(WorldSave::HISTORY), Some(&[][..]));
+        assert_eq!(loaded.section(*b"NONE"), None);
+    }
+
+    #[test]
+    fn unknown_sections_are_skipped() {
+        let mut bytes = b"LUMWXTRA".to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&[9, 9]);
+        bytes.extend_from_slice(b"VOXL");
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.push(7);
+
+        let save = WorldSave::from_bytes(&bytes).unwrap();
+        assert_eq!(save.section(WorldSave::VOXELS), Some(&[7][..]));
+    }
+
+    #[test]
+    fn invalid_saves_fail() {
+        assert!(WorldSave::from_bytes(b"PNG").is_err());
+
+        let mut bytes = b"LUMWVOXL".to_vec();
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.push(1);
+        assert!(WorldSave::from_bytes(&bytes).is_err());
+    }
+}
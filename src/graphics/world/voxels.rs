@@ -1,83 +1,69 @@
-use std::{mem, slice};
+use std::{
+    fmt, mem,
+    ops::Range,
+    slice,
+    time::{Duration, Instant},
+};
 
 use ash::{Device, Instance, vk};
 use bytemuck::{Pod, Zeroable};
-use eyre::Result;
-use glam::{U64Vec3, UVec3};
+use eyre::{Result, bail};
+use glam::{U64Vec3, UVec3, Vec3};
 use gpu_allocator::{MemoryLocation, vulkan::Allocator};
+use rustc_hash::FxHashMap;
 
+use super::{history, mesh::Mesh};
 use crate::graphics::{
     buffer::Buffer, delete_queue::DeleteQueue, immediate::Immediate,
+    queues::Resource,
 };
 
 //
 
 pub struct VoxelStructure {
     pub buffer: Buffer,
+    /// CPU copy of the octree
+    octree: Octree,
+    /// how the nodes are stored in `buffer`
+    layout: NodeLayout,
+    /// emitted by committed edits, drained with [`Self::take_events`]
+    events: Vec<WorldEvent>,
+    /// how long the last scene took to load
+    load_timings: LoadTimings,
 }
 
 impl VoxelStructure {
+    /// upper bound of the staging buffer used for uploads
+    const STAGING_SIZE: u64 = 16 * 1024 * 1024;
+    /// the buffer holds at least this many nodes, so edits have room
+    /// to copy paths until the next compaction
+    pub const MIN_CAPACITY: usize = 1 << 16;
+
     pub fn new(
         instance: &Instance,
         device: &Device,
         imm: &Immediate,
+        render_imm: &Immediate,
         allocator: &mut Allocator,
         delete_queue: &mut DeleteQueue,
+        layout: NodeLayout,
     ) -> Result<Self> {
-        let mut octree_data: Vec<Voxel> = vec![Voxel {
-            col: 0,
-            child_pointer: 0,
-            valid_mask: 0,
-            leaf_mask: 0,
-        }];
-
-        for i in 0..(32 * 32 * 32) as usize {
-            let x = i & 31;
-            let y = (i >> 5) & 31;
-            let z = (i >> 10) & 31;
+        let start = Instant::now();
+        let mut octree = Scene::Demo.build();
+        // everything gets uploaded anyways
+        octree.dirty.clear();
+        let built = Instant::now();
 
-            let is_corner = (x == 0 || x == 31)
-                && (y == 0 || y == 31)
-                && (z == 0 || z == 31);
+        let octree_data = &octree.nodes;
+        let octree_size = octree_data.len() as u64 * layout.node_size();
 
-            let is_ball = (x.abs_diff(16).pow(2)
-                + y.abs_diff(16).pow(2)
-                + z.abs_diff(16).pow(2))
-                <= 120;
-
-            let is_cross = (x.abs_diff(16) <= 1 && y.abs_diff(16) <= 1)
-                || (x.abs_diff(16) <= 1 && z.abs_diff(16) <= 1)
-                || (y.abs_diff(16) <= 1 && z.abs_diff(16) <= 1);
-
-            let is_solid = (is_corner || is_ball) && !is_cross;
-            // let is_solid = is_corner;
-
-            if !is_solid {
-                continue;
-            }
-
-            let col = 1 + (i % 3) as u8;
-
-            Self::insert_voxel(
-                &mut octree_data,
-                U64Vec3::new(x as _, y as _, z as _),
-                col as u32,
-            );
-
-            // if is_solid {
-            //     tracing::info!("i={i:05} x={x:02} y={y:02} z={z:02}");
-            // }
-        }
-
-        // tracing::info!("octree: {octree_data:#?}");
-
-        tracing::info!(
-            "voxel data = {}B",
-            octree_data.len() * mem::size_of::<Voxel>()
-        );
+        tracing::info!("voxel data = {octree_size}B ({layout:?})");
 
+        let capacity = octree_data.len().max(Self::MIN_CAPACITY) as u64
+            * layout.node_size();
         let voxel_buffer = Buffer::builder()
-            .capacity(octree_data.len() * mem::size_of::<Voxel>())
+            .name("voxels")
+            .capacity(capacity as usize)
             .usage(
                 vk::BufferUsageFlags::STORAGE_BUFFER
                     | vk::BufferUsageFlags::TRANSFER_DST,
@@ -85,40 +71,21 @@ impl VoxelStructure {
             .location(MemoryLocation::GpuOnly)
             .build(device, allocator, delete_queue)?;
 
-        let mut tmp_delete_queue = DeleteQueue::new();
-
-        let mut stage_buffer = Buffer::builder()
-            .capacity(octree_data.len() * mem::size_of::<Voxel>())
-            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
-            .location(MemoryLocation::CpuToGpu)
-            .build(device, allocator, &mut tmp_delete_queue)?;
-
-        let stage_buffer_memory = stage_buffer
-            .as_slice_mut()
-            .expect("stage buffer should be CPU mappable");
-
-        stage_buffer_memory[..octree_data.len() * mem::size_of::<Voxel>()]
-            .copy_from_slice(bytemuck::cast_slice(&octree_data));
-
-        imm.submit(device, |cbuf| {
-            let copy = vk::BufferCopy::default()
-                .src_offset(0)
-                .dst_offset(0)
-                .size(stage_buffer.size);
-
-            unsafe {
-                device.cmd_copy_buffer(
-                    cbuf,
-                    stage_buffer.buffer,
-                    voxel_buffer.buffer,
-                    slice::from_ref(&copy),
-                );
-            }
-
-            Ok(())
-        })?;
+        Self::upload_ranges(
+            device,
+            imm,
+            render_imm,
+            allocator,
+            &voxel_buffer,
+            |range, out| layout.encode(octree_data, range, out),
+            slice::from_ref(&(0..octree_size)),
+        )?;
 
-        tmp_delete_queue.flush(device, allocator);
+        let load_timings = LoadTimings {
+            build: built - start,
+            upload: built.elapsed(),
+        };
+        tracing::info!("loaded {:?}: {load_timings}", Scene::Demo);
 
         // TODO: make one AABB per voxel octree,
         // then use the intersection shader to run DDA algorithm
@@ -132,60 +99,1393 @@ impl VoxelStructure {
 
         Ok(Self {
             buffer: voxel_buffer,
+            octree,
+            layout,
+            events: Vec::new(),
+            load_timings,
+        })
+    }
+
+    /// how many nodes fit in the buffer
+    pub fn capacity(&self) -> usize {
+        (self.buffer.size / self.layout.node_size()) as usize
+    }
+
+    /// replace every voxel with `scene` and upload it,
+    /// returns the number of uploaded bytes
+    pub fn load(
+        &mut self,
+        scene: Scene,
+        device: &Device,
+        imm: &Immediate,
+        render_imm: &Immediate,
+        allocator: &mut Allocator,
+    ) -> Result<u64> {
+        self.replace(
+            &format!("{scene:?}"),
+            || scene.build(),
+            device,
+            imm,
+            render_imm,
+            allocator,
+        )
+    }
+
+    /// like [`Self::load`], but from a dense grid of [`Octree::to_grid`]
+    pub fn load_grid(
+        &mut self,
+        grid: &[u32],
+        device: &Device,
+        imm: &Immediate,
+        render_imm: &Immediate,
+        allocator: &mut Allocator,
+    ) -> Result<u64> {
+        if grid.len() != Octree::SIZE.pow(3) {
+            bail!(
+                "the voxel grid has {} voxels instead of {}",
+                grid.len(),
+                Octree::SIZE.pow(3)
+            );
+        }
+        self.replace(
+            "saved voxels",
+            || Octree::from_grid(grid),
+            device,
+            imm,
+            render_imm,
+            allocator,
+        )
+    }
+
+    /// like [`Self::load`], but with an imported octree
+    pub fn load_octree(
+        &mut self,
+        name: &str,
+        octree: Octree,
+        device: &Device,
+        imm: &Immediate,
+        render_imm: &Immediate,
+        allocator: &mut Allocator,
+    ) -> Result<u64> {
+        self.replace(name, || octree, device, imm, render_imm, allocator)
+    }
+
+    fn replace(
+        &mut self,
+        name: &str,
+        build: impl FnOnce() -> Octree,
+        device: &Device,
+        imm: &Immediate,
+        render_imm: &Immediate,
+        allocator: &mut Allocator,
+    ) -> Result<u64> {
+        let start = Instant::now();
+        self.octree = build();
+        let built = Instant::now();
+        let bytes = self.sync(device, imm, render_imm, allocator)?;
+
+        self.load_timings = LoadTimings {
+            build: built - start,
+            upload: built.elapsed(),
+        };
+        tracing::info!("loaded {name}: {}", self.load_timings);
+        Ok(bytes)
+    }
+
+    /// the time split of the last [`Self::new`] or [`Self::load`]
+    pub fn load_timings(&self) -> LoadTimings {
+        self.load_timings
+    }
+
+    /// move the voxels of `other` over, all of them are uploaded
+    /// by the next [`Self::sync`], for when the device is recreated
+    pub fn take_voxels(&mut self, other: &mut VoxelStructure) {
+        self.octree = mem::replace(&mut other.octree, Octree::new());
+        self.octree.compact();
+    }
+
+    /// merge identical subtrees and drop unreachable nodes,
+    /// only the CPU copy is modified until the next [`Self::sync`]
+    pub fn compact(&mut self) {
+        self.octree.compact();
+    }
+
+    /// collect voxel changes that are applied all at once with
+    /// [`Edit::commit`], dropping the edit discards them
+    pub fn begin_edit(&mut self) -> Edit<'_> {
+        Edit {
+            octree: &mut self.octree,
+            events: &mut self.events,
+            layout: self.layout,
+            changes: Vec::new(),
+        }
+    }
+
+    /// see [`Octree::to_grid`]
+    pub fn to_grid(&self) -> Vec<u32> {
+        self.octree.to_grid()
+    }
+
+    /// see [`history::chunk_hash`]
+    pub fn chunk_hash(&self, chunk: U64Vec3) -> u64 {
+        history::chunk_hash(&self.octree, chunk)
+    }
+
+    /// the first solid voxel along the ray, see [`Octree::raycast`]
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        dir: Vec3,
+        max_distance: f32,
+    ) -> Option<U64Vec3> {
+        self.octree.raycast(origin, dir, max_distance)
+    }
+
+    /// the material of the voxel at `at`, [`MaterialInfo::AIR`] if empty
+    pub fn material_of(&self, at: U64Vec3) -> MaterialInfo {
+        MaterialInfo::get(self.octree.get_voxel(at))
+    }
+
+    /// events of the edits committed since the last call, oldest first
+    pub fn take_events(&mut self) -> Vec<WorldEvent> {
+        mem::take(&mut self.events)
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            gpu_bytes: self.buffer.size,
+            ..self.octree.stats()
+        }
+    }
+
+    /// greedy meshed exposed voxel faces, for exporting
+    pub fn to_mesh(&self) -> Mesh {
+        Mesh::greedy(&self.octree.to_grid(), Octree::SIZE)
+    }
+
+    /// how the voxel buffer is used by the raymarch
+    fn first_use() -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+        (
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_READ,
+        )
+    }
+
+    /// upload the node ranges modified since the last sync,
+    /// `imm` does the copies and `render_imm`, on the queue
+    /// that runs the raymarch, takes the buffer back,
+    /// returns the number of uploaded bytes
+    pub fn sync(
+        &mut self,
+        device: &Device,
+        imm: &Immediate,
+        render_imm: &Immediate,
+        allocator: &mut Allocator,
+    ) -> Result<u64> {
+        let dirty = self.octree.dirty.clone();
+        let res = self.upload_dirty(device, imm, render_imm, allocator);
+        if res.is_err() {
+            // the CPU copy is still valid, so the upload can be retried
+            self.octree.dirty = dirty;
+        }
+        res
+    }
+
+    fn upload_dirty(
+        &mut self,
+        device: &Device,
+        imm: &Immediate,
+        render_imm: &Immediate,
+        allocator: &mut Allocator,
+    ) -> Result<u64> {
+        let node_size = self.layout.node_size();
+        let ranges = self
+            .octree
+            .take_dirty_ranges()
+            .into_iter()
+            .map(|range| {
+                range.start as u64 * node_size..range.end as u64 * node_size
+            })
+            .collect::<Vec<_>>();
+        let Some(last) = ranges.last() else {
+            return Ok(0);
+        };
+
+        if self.octree.nodes.len() > self.layout.max_nodes() {
+            bail!(
+                "{} octree nodes do not fit the {:?} node layout, \
+                 build with the `voxel32` feature",
+                self.octree.nodes.len(),
+                self.layout
+            );
+        }
+        if last.end > self.buffer.size {
+            bail!(
+                "voxel buffer is too small ({}B) for the octree ({}B)",
+                self.buffer.size,
+                last.end
+            );
+        }
+
+        Self::upload_ranges(
+            device,
+            imm,
+            render_imm,
+            allocator,
+            &self.buffer,
+            |range, out| self.layout.encode(&self.octree.nodes, range, out),
+            &ranges,
+        )?;
+
+        Ok(ranges.iter().map(|range| range.end - range.start).sum())
+    }
+
+    /// copy byte `ranges` written by `read` to the same offsets in `dst`,
+    /// through a staging buffer of at most [`Self::STAGING_SIZE`] bytes,
+    /// so large octrees never need one giant host allocation
+    fn upload_ranges(
+        device: &Device,
+        imm: &Immediate,
+        render_imm: &Immediate,
+        allocator: &mut Allocator,
+        dst: &Buffer,
+        read: impl Fn(Range<u64>, &mut [u8]),
+        ranges: &[Range<u64>],
+    ) -> Result<()> {
+        let total: u64 =
+            ranges.iter().map(|range| range.end - range.start).sum();
+        if total == 0 {
+            return Ok(());
+        }
+
+        let mut stage_buffer = Buffer::builder()
+            .capacity(total.min(Self::STAGING_SIZE) as usize)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .location(MemoryLocation::CpuToGpu)
+            .build_owned(device, allocator)?;
+
+        // ranges larger than the staging buffer are split
+        let mut pieces = ranges.iter().flat_map(|range| {
+            (range.start..range.end)
+                .step_by(Self::STAGING_SIZE as usize)
+                .map(|start| start..(start + Self::STAGING_SIZE).min(range.end))
+        });
+        let mut next = pieces.next();
+
+        let (dst_stage, dst_access) = Self::first_use();
+        while next.is_some() {
+            let stage_size = stage_buffer.size;
+            let stage_memory = stage_buffer
+                .as_slice_mut()
+                .expect("stage buffer should be CPU mappable");
+
+            let mut copies = Vec::new();
+            let mut src_offset = 0;
+            while let Some(piece) = next.clone() {
+                let size = piece.end - piece.start;
+                if src_offset + size > stage_size {
+                    break;
+                }
+
+                read(
+                    piece.clone(),
+                    &mut stage_memory
+                        [src_offset as usize..(src_offset + size) as usize],
+                );
+                copies.push(
+                    vk::BufferCopy::default()
+                        .src_offset(src_offset)
+                        .dst_offset(piece.start)
+                        .size(size),
+                );
+                src_offset += size;
+                next = pieces.next();
+            }
+
+            // the submit waits, so the staging buffer can be refilled
+            imm.upload(
+                device,
+                render_imm,
+                Resource::Buffer(dst.buffer),
+                dst_stage,
+                dst_access,
+                |cbuf| {
+                    unsafe {
+                        device.cmd_copy_buffer(
+                            cbuf,
+                            stage_buffer.buffer,
+                            dst.buffer,
+                            &copies,
+                        );
+                    }
+                    Ok(())
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+//
+
+/// built-in voxel scenes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scene {
+    /// corners and a ball with a cross cut out of it
+    Demo,
+    /// exactly one voxel, at [`Scene::SINGLE_VOXEL`],
+    /// the smallest possible traversal check
+    SingleVoxel,
+}
+
+impl Scene {
+    pub const SINGLE_VOXEL: U64Vec3 = U64Vec3::ZERO;
+    /// color index of the single voxel, the brightest palette entry
+    pub const SINGLE_VOXEL_COL: u32 = 3;
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "demo" => Some(Self::Demo),
+            "single-voxel" => Some(Self::SingleVoxel),
+            _ => None,
+        }
+    }
+
+    /// compacted octree, every node is marked dirty
+    fn build(self) -> Octree {
+        let mut octree = Octree::new();
+
+        match self {
+            Scene::Demo => {
+                for i in 0..(32 * 32 * 32) as usize {
+                    let x = i & 31;
+                    let y = (i >> 5) & 31;
+                    let z = (i >> 10) & 31;
+
+                    let is_corner = (x == 0 || x == 31)
+                        && (y == 0 || y == 31)
+                        && (z == 0 || z == 31);
+
+                    let is_ball = (x.abs_diff(16).pow(2)
+                        + y.abs_diff(16).pow(2)
+                        + z.abs_diff(16).pow(2))
+                        <= 120;
+
+                    let is_cross = (x.abs_diff(16) <= 1 && y.abs_diff(16) <= 1)
+                        || (x.abs_diff(16) <= 1 && z.abs_diff(16) <= 1)
+                        || (y.abs_diff(16) <= 1 && z.abs_diff(16) <= 1);
+
+                    let is_solid = (is_corner || is_ball) && !is_cross;
+                    // let is_solid = is_corner;
+
+                    if !is_solid {
+                        continue;
+                    }
+
+                    let col = 1 + (i % 3) as u8;
+
+                    octree.insert_voxel(
+                        U64Vec3::new(x as _, y as _, z as _),
+                        col as u32,
+                    );
+
+                    // if is_solid {
+                    //     tracing::info!("i={i:05} x={x:02} y={y:02} z={z:02}");
+                    // }
+                }
+
+                // tracing::info!("octree: {octree:#?}");
+            }
+            Scene::SingleVoxel => {
+                octree.insert_voxel(Self::SINGLE_VOXEL, Self::SINGLE_VOXEL_COL);
+            }
+        }
+
+        octree.compact();
+        octree
+    }
+}
+
+//
+
+/// gameplay data of a palette entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialInfo {
+    /// index into the palette, 0 is empty
+    pub col: u32,
+    /// linear RGBA, has to match the palette in shader.glsl
+    pub color: [f32; 4],
+    /// `TAG_*` bits
+    pub tags: u32,
+    /// which `BREAK_*` effect plays when the voxel is removed
+    pub break_effect: u8,
+}
+
+impl MaterialInfo {
+    pub const TAG_SOLID: u32 = 1 << 0;
+    pub const TAG_STONE: u32 = 1 << 1;
+    pub const TAG_GLASS: u32 = 1 << 2;
+
+    pub const BREAK_NONE: u8 = 0;
+    pub const BREAK_STONE: u8 = 1;
+    pub const BREAK_GLASS: u8 = 2;
+
+    pub const AIR: Self = Self {
+        col: 0,
+        color: [0.0; 4],
+        tags: 0,
+        break_effect: Self::BREAK_NONE,
+    };
+
+    /// indexed by the voxel color
+    pub const PALETTE: &[Self] = &[
+        Self::AIR,
+        Self {
+            col: 1,
+            color: [0.000, 0.453, 0.668, 1.0],
+            tags: Self::TAG_SOLID | Self::TAG_STONE,
+            break_effect: Self::BREAK_STONE,
+        },
+        Self {
+            col: 2,
+            color: [0.000, 0.316, 0.469, 1.0],
+            tags: Self::TAG_SOLID | Self::TAG_STONE,
+            break_effect: Self::BREAK_STONE,
+        },
+        Self {
+            col: 3,
+            color: [0.746, 0.914, 1.000, 1.0],
+            tags: Self::TAG_SOLID | Self::TAG_GLASS,
+            break_effect: Self::BREAK_GLASS,
+        },
+    ];
+
+    /// unknown colors are solid and have no break effect
+    pub fn get(col: u32) -> Self {
+        Self::PALETTE.get(col as usize).copied().unwrap_or(Self {
+            col,
+            color: [1.0; 4],
+            tags: Self::TAG_SOLID,
+            break_effect: Self::BREAK_NONE,
         })
     }
+}
+
+/// something that happened to the voxels, for gameplay hooks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WorldEvent {
+    /// a solid voxel was replaced with an empty one,
+    /// once per removed voxel even within a single edit
+    VoxelBroken { at: U64Vec3, material: MaterialInfo },
+}
+
+//
+
+/// pending voxel changes, nothing is modified before [`Self::commit`]
+#[must_use = "edits are discarded unless committed"]
+pub struct Edit<'a> {
+    octree: &'a mut Octree,
+    events: &'a mut Vec<WorldEvent>,
+    layout: NodeLayout,
+    changes: Vec<(U64Vec3, u32)>,
+}
+
+impl Edit<'_> {
+    pub fn insert_voxel(&mut self, at: U64Vec3, col: u32) -> &mut Self {
+        self.changes.push((at, col));
+        self
+    }
+
+    /// apply every change or none of them,
+    /// the GPU copy is updated on the next [`VoxelStructure::sync`]
+    ///
+    /// returns the inverse changes, committing them in another edit
+    /// restores the previous voxels
+    pub fn commit(self) -> Result<Vec<(U64Vec3, u32)>> {
+        let size = U64Vec3::splat(Octree::SIZE as u64);
+        if let Some((at, _)) =
+            self.changes.iter().find(|(at, _)| !at.cmplt(size).all())
+        {
+            bail!("voxel {at} is outside of the octree");
+        }
+
+        // worst case every change copies a whole path
+        let new_nodes = self.changes.len() * Octree::DEPTH as usize * 8;
+        let max_nodes = self.layout.max_nodes();
+        if self.octree.nodes.len() + new_nodes > max_nodes {
+            bail!(
+                "the edit could grow the octree past the {max_nodes} nodes \
+                 of the {:?} node layout",
+                self.layout
+            );
+        }
+
+        let mut inverse = Vec::with_capacity(self.changes.len());
+        for (at, col) in self.changes {
+            let previous = self.octree.get_voxel(at);
+            inverse.push((at, previous));
+            self.octree.insert_voxel(at, col);
+
+            if previous != 0 && col == 0 {
+                self.events.push(WorldEvent::VoxelBroken {
+                    at,
+                    material: MaterialInfo::get(previous),
+                });
+            }
+        }
+        // the last change of a voxel has to be undone first
+        inverse.reverse();
+
+        Ok(inverse)
+    }
+}
+
+/// CPU side sparse voxel octree
+pub struct Octree {
+    nodes: Vec<Voxel>,
+    /// node index ranges modified since the last sync
+    dirty: Vec<Range<usize>>,
+    /// set after compaction, subtrees can have multiple parents
+    shared: bool,
+}
+
+impl Octree {
+    /// levels below the root node
+    pub const DEPTH: u32 = 5;
+    /// voxels per side
+    pub const SIZE: usize = 1 << Self::DEPTH;
+
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Voxel::EMPTY],
+            dirty: vec![Range { start: 0, end: 1 }],
+            shared: false,
+        }
+    }
+
+    /// merge identical subtrees and drop unreachable nodes
+    pub fn compact(&mut self) {
+        let before = self.nodes.len();
+
+        let mut compacted = vec![Voxel::EMPTY];
+        let mut groups = FxHashMap::default();
+        compacted[0] = Self::compact_node(
+            &self.nodes,
+            self.nodes[0],
+            &mut compacted,
+            &mut groups,
+        );
+        self.nodes = compacted;
+        self.dirty = vec![Range {
+            start: 0,
+            end: self.nodes.len(),
+        }];
+        self.shared = true;
+
+        tracing::info!(
+            "compacted octree from {before} to {} nodes",
+            self.nodes.len()
+        );
+    }
+
+    /// rebuilds `node`s subtree bottom-up into `compacted`,
+    /// each unique group of 8 children is stored only once
+    fn compact_node(
+        octree: &[Voxel],
+        node: Voxel,
+        compacted: &mut Vec<Voxel>,
+        groups: &mut FxHashMap<[Voxel; 8], u32>,
+    ) -> Voxel {
+        if node.valid_mask == 0 {
+            return Voxel {
+                child_pointer: 0,
+                ..node
+            };
+        }
 
-    fn insert_voxel(octree: &mut Vec<Voxel>, at: U64Vec3, col: u32) {
+        // children that are not in the valid mask are never read,
+        // so they are zeroed to make more groups identical
+        let mut children = [Voxel::EMPTY; 8];
+        for (i, child) in children.iter_mut().enumerate() {
+            if node.valid_mask & (1 << i) != 0 {
+                *child = Self::compact_node(
+                    octree,
+                    octree[node.child_pointer as usize + i],
+                    compacted,
+                    groups,
+                );
+            }
+        }
+
+        let child_pointer = *groups.entry(children).or_insert_with(|| {
+            let child_pointer = compacted
+                .len()
+                .try_into()
+                .expect("more than 2^32 octree nodes");
+            compacted.extend(children);
+            child_pointer
+        });
+
+        Voxel {
+            child_pointer,
+            ..node
+        }
+    }
+
+    /// how many nodes fit without growing
+    pub fn node_capacity(&self) -> usize {
+        self.nodes.capacity()
+    }
+
+    /// the color at `at`, 0 if it is empty
+    pub fn get_voxel(&self, at: U64Vec3) -> u32 {
         let mut current = 0usize;
-        let mut center = U64Vec3::splat(16);
-        let mut span = 16usize;
+        let mut center = U64Vec3::splat(Self::SIZE as u64 / 2);
+        let mut span = Self::SIZE as u64 / 2;
 
-        for i in 0..5 {
+        for _ in 0..Self::DEPTH {
+            let child_idx = child_index(center, at);
+            if self.nodes[current].valid_mask & (1 << child_idx) == 0 {
+                return 0;
+            }
+
+            span /= 2;
+            center = child_center(center, span, child_idx);
+            current =
+                self.nodes[current].child_pointer as usize + child_idx as usize;
+        }
+
+        self.nodes[current].col
+    }
+
+    pub fn insert_voxel(&mut self, at: U64Vec3, col: u32) {
+        assert!(
+            at.cmplt(U64Vec3::splat(Self::SIZE as u64)).all(),
+            "voxel {at} is outside of the octree"
+        );
+
+        let octree = &mut self.nodes;
+        let mut current = 0usize;
+        let mut center = U64Vec3::splat(Self::SIZE as u64 / 2);
+        let mut span = Self::SIZE as u64 / 2;
+
+        for _ in 0..Self::DEPTH {
             if octree[current].valid_mask == 0 {
                 octree[current].child_pointer = octree
                     .len()
                     .try_into()
-                    .expect("todo: blocks & far pointers");
-                octree.extend(
-                    [Voxel {
-                        col: 0,
-                        child_pointer: 0,
-                        valid_mask: 0,
-                        leaf_mask: 0,
-                    }]
-                    .iter()
-                    .cycle()
-                    .take(8),
-                );
+                    .expect("more than 2^32 octree nodes");
+                self.dirty.push(octree.len()..octree.len() + 8);
+                octree.extend([Voxel::EMPTY; 8]);
+            } else if self.shared {
+                // the children might be shared with other parents,
+                // so the path is copied instead of modified in place
+                let children = octree[current].child_pointer as usize;
+                octree[current].child_pointer = octree
+                    .len()
+                    .try_into()
+                    .expect("more than 2^32 octree nodes");
+                self.dirty.push(octree.len()..octree.len() + 8);
+                octree.extend_from_within(children..children + 8);
             }
 
-            tracing::info!("center = {center} point = {at}");
-            let cmpge = at.cmpge(center);
-            let child_idx = cmpge.bitmask();
-            tracing::info!("child_idx = {child_idx}");
+            let child_idx = child_index(center, at);
             span /= 2;
-            center -= U64Vec3::splat(span as _);
-            center += U64Vec3::splat(span as u64 * 2)
-                * U64Vec3::new(cmpge.x as _, cmpge.y as _, cmpge.z as _);
+            center = child_center(center, span, child_idx);
+            tracing::trace!("child_idx={child_idx} center={center} at={at}");
 
             octree[current].valid_mask |= 1 << child_idx;
+            self.dirty.push(current..current + 1);
             current =
                 octree[current].child_pointer as usize + child_idx as usize;
         }
 
         octree[current].col = col;
+        self.dirty.push(current..current + 1);
+    }
+
+    /// walks every node reachable from the root,
+    /// shared subtrees are counted once
+    pub fn stats(&self) -> Stats {
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut stack = vec![(0usize, 0u32)];
+        let mut stats = Stats {
+            nodes: self.nodes.len(),
+            cpu_bytes: (self.nodes.capacity() * mem::size_of::<Voxel>()) as u64,
+            ..Stats::default()
+        };
+
+        while let Some((node, depth)) = stack.pop() {
+            if mem::replace(&mut reachable[node], true) {
+                continue;
+            }
+
+            let voxel = self.nodes[node];
+            if depth == Self::DEPTH {
+                stats.leaves += (voxel.col != 0) as usize;
+                continue;
+            }
+
+            for i in 0..8 {
+                if voxel.valid_mask & (1 << i) != 0 {
+                    stack.push((voxel.child_pointer as usize + i, depth + 1));
+                }
+            }
+        }
+
+        stats.wasted_nodes = reachable.iter().filter(|r| !**r).count();
+        stats
+    }
+
+    /// dense grid of voxel colors, indexed with `x + y * SIZE + z * SIZE²`
+    pub fn to_grid(&self) -> Vec<u32> {
+        let mut grid = vec![0; Self::SIZE.pow(3)];
+        self.fill_grid(
+            &mut grid,
+            self.nodes[0],
+            UVec3::ZERO,
+            Self::SIZE as u32,
+        );
+        grid
+    }
+
+    fn fill_grid(
+        &self,
+        grid: &mut [u32],
+        node: Voxel,
+        origin: UVec3,
+        span: u32,
+    ) {
+        if span == 1 {
+            let size = Self::SIZE as u32;
+            grid[(origin.x + origin.y * size + origin.z * size * size)
+                as usize] = node.col;
+            return;
+        }
+
+        let span = span / 2;
+        for i in 0..8 {
+            if node.valid_mask & (1 << i) == 0 {
+                continue;
+            }
+
+            let child = self.nodes[node.child_pointer as usize + i as usize];
+            let offset = UVec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1) * span;
+            self.fill_grid(grid, child, origin + offset, span);
+        }
+    }
+
+    /// the inverse of [`Self::to_grid`], compacted
+    pub fn from_grid(grid: &[u32]) -> Self {
+        let mut octree = Self::new();
+        let size = Self::SIZE as u64;
+        for (i, col) in grid.iter().enumerate() {
+            if *col != 0 {
+                let i = i as u64;
+                let at =
+                    U64Vec3::new(i % size, i / size % size, i / size / size);
+                octree.insert_voxel(at, *col);
+            }
+        }
+        octree.compact();
+        octree
+    }
+
+    /// the first solid voxel along the ray from `origin` towards `dir`,
+    /// voxel by voxel up to `max_distance`, the origin can be outside
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        dir: Vec3,
+        max_distance: f32,
+    ) -> Option<U64Vec3> {
+        let dir = dir.normalize_or_zero();
+        if dir == Vec3::ZERO {
+            return None;
+        }
+
+        // Amanatides & Woo, `t` is the distance to the next voxel boundary
+        let mut cell = origin.floor();
+        let step = dir.signum();
+        let delta = dir.recip().abs();
+        let next = (cell + step.max(Vec3::ZERO) - origin) / dir;
+        let mut t = Vec3::select(dir.cmpeq(Vec3::ZERO), Vec3::INFINITY, next);
+
+        let size = Self::SIZE as f32;
+        loop {
+            if cell.cmpge(Vec3::ZERO).all()
+                && cell.cmplt(Vec3::splat(size)).all()
+            {
+                let at = cell.as_u64vec3();
+                if self.get_voxel(at) != 0 {
+                    return Some(at);
+                }
+            }
+
+            let axis = if t.x <= t.y && t.x <= t.z {
+                0
+            } else if t.y <= t.z {
+                1
+            } else {
+                2
+            };
+            if t[axis] > max_distance {
+                return None;
+            }
+            cell[axis] += step[axis];
+            t[axis] += delta[axis];
+        }
+    }
+
+    /// changes that set every voxel within `radius` of `center` to `col`,
+    /// clipped to the octree
+    pub fn ball(center: U64Vec3, radius: u64, col: u32) -> Vec<(U64Vec3, u32)> {
+        let size = Self::SIZE as u64;
+        let mut changes = Vec::new();
+        let low = center.saturating_sub(U64Vec3::splat(radius));
+        let high = (center + radius).min(U64Vec3::splat(size - 1));
+        for z in low.z..=high.z {
+            for y in low.y..=high.y {
+                for x in low.x..=high.x {
+                    let at = U64Vec3::new(x, y, z);
+                    let d = at.as_ivec3() - center.as_ivec3();
+                    if d.length_squared() <= (radius * radius) as i32 {
+                        changes.push((at, col));
+                    }
+                }
+            }
+        }
+        changes
+    }
+
+    /// merges the dirty node ranges into sorted copy regions
+    pub fn take_dirty_ranges(&mut self) -> Vec<Range<usize>> {
+        let mut dirty = mem::take(&mut self.dirty);
+        dirty.sort_unstable_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(dirty.len());
+        for range in dirty {
+            match merged.last_mut() {
+                // touching and overlapping ranges are merged
+                Some(last) if range.start <= last.end => {
+                    last.end = last.end.max(range.end);
+                }
+                _ => merged.push(range),
+            }
+        }
+
+        merged
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// all stored nodes, including unreachable ones
+    pub nodes: usize,
+    /// unique solid voxels at the bottom level
+    pub leaves: usize,
+    pub cpu_bytes: u64,
+    /// size of the voxel storage buffer
+    pub gpu_bytes: u64,
+    /// nodes that are not reachable from the root anymore,
+    /// [`Octree::compact`] drops them
+    pub wasted_nodes: usize,
+}
+
+/// where the time of loading a scene went
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadTimings {
+    /// constructing the CPU octree
+    pub build: Duration,
+    /// staging and copying the nodes to the GPU
+    pub upload: Duration,
+}
+
+impl fmt::Display for LoadTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "build={:.2?} upload={:.2?}", self.build, self.upload)
     }
 }
 
-#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "nodes={} leaves={} wasted={} cpu={}B gpu={}B",
+            self.nodes,
+            self.leaves,
+            self.wasted_nodes,
+            self.cpu_bytes,
+            self.gpu_bytes
+        )
+    }
+}
+
+/// index of the child octant of a node centered at `center` that contains
+/// `at`, bit 0 is set for the upper x half, bit 1 for y and bit 2 for z
+pub fn child_index(center: U64Vec3, at: U64Vec3) -> u8 {
+    at.cmpge(center).bitmask() as u8
+}
+
+/// center of the child octant `child_idx` of a node centered at `center`,
+/// `child_span` is the half size of the child octant
+pub fn child_center(
+    center: U64Vec3,
+    child_span: u64,
+    child_idx: u8,
+) -> U64Vec3 {
+    let upper = U64Vec3::new(
+        (child_idx & 1) as u64,
+        ((child_idx >> 1) & 1) as u64,
+        ((child_idx >> 2) & 1) as u64,
+    );
+    center - U64Vec3::splat(child_span) + upper * child_span * 2
+}
+
+/// CPU side octree node, also the GPU layout of [`NodeLayout::Wide32`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Pod, Zeroable)]
 #[repr(C)]
 pub struct Voxel {
     col: u32,
-    /// 15 bit pointer (relative to this block) to 8 children within the current block,
-    /// the last 1 bit tells if it is actually a pointer to a far pointer
-    child_pointer: u16,
+    /// index of the first of 8 consecutive children
+    child_pointer: u32,
     /// which children are non-leaf voxels
     valid_mask: u8,
     /// which children are leaf voxels
     leaf_mask: u8,
+    _pad: [u8; 2],
+}
+
+impl Voxel {
+    pub const EMPTY: Self = Self {
+        col: 0,
+        child_pointer: 0,
+        valid_mask: 0,
+        leaf_mask: 0,
+        _pad: [0; 2],
+    };
+}
+
+/// GPU layout of [`NodeLayout::Packed16`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Pod, Zeroable)]
+#[repr(C)]
+struct PackedVoxel {
+    col: u32,
+    child_pointer: u16,
+    valid_mask: u8,
+    leaf_mask: u8,
+}
+
+impl From<Voxel> for PackedVoxel {
+    fn from(voxel: Voxel) -> Self {
+        Self {
+            col: voxel.col,
+            child_pointer: voxel.child_pointer as u16,
+            valid_mask: voxel.valid_mask,
+            leaf_mask: voxel.leaf_mask,
+        }
+    }
+}
+
+/// how octree nodes are stored in the voxel buffer, has to match
+/// the shader, which is compiled for [`BuildConfig::node_layout`]
+///
+/// [`BuildConfig::node_layout`]: crate::graphics::BuildConfig::node_layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NodeLayout {
+    /// 8 byte nodes with 16 bit child pointers
+    #[default]
+    Packed16,
+    /// 12 byte nodes with 32 bit child pointers,
+    /// more memory but no node count limit in practice
+    Wide32,
+}
+
+impl NodeLayout {
+    pub const fn node_size(self) -> u64 {
+        match self {
+            Self::Packed16 => mem::size_of::<PackedVoxel>() as u64,
+            Self::Wide32 => mem::size_of::<Voxel>() as u64,
+        }
+    }
+
+    /// the most nodes the child pointers can address
+    pub const fn max_nodes(self) -> usize {
+        match self {
+            Self::Packed16 => 1 << 16,
+            Self::Wide32 => u32::MAX as usize,
+        }
+    }
+
+    /// write the `bytes` range of the encoded `nodes` to `out`,
+    /// the range does not have to start or end at a node boundary
+    fn encode(self, nodes: &[Voxel], bytes: Range<u64>, out: &mut [u8]) {
+        let (start, end) = (bytes.start as usize, bytes.end as usize);
+        match self {
+            Self::Packed16 => {
+                let size = mem::size_of::<PackedVoxel>();
+                for i in start / size..end.div_ceil(size) {
+                    let packed = PackedVoxel::from(nodes[i]);
+                    let node = bytemuck::bytes_of(&packed);
+                    let from = (i * size).max(start);
+                    let to = ((i + 1) * size).min(end);
+                    out[from - start..to - start]
+                        .copy_from_slice(&node[from - i * size..to - i * size]);
+                }
+            }
+            Self::Wide32 => {
+                out.copy_from_slice(&bytemuck::cast_slice(nodes)[start..end])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(col: u32) -> Octree {
+        let mut octree = Octree::new();
+        for z in 0..Octree::SIZE as u64 {
+            for y in 0..Octree::SIZE as u64 {
+                for x in 0..Octree::SIZE as u64 {
+                    octree.insert_voxel(U64Vec3::new(x, y, z), col);
+                }
+            }
+        }
+        octree
+    }
+
+    #[test]
+    fn compact_solid() {
+        let mut octree = solid(1);
+        assert!(octree.nodes.len() > 32 * 32 * 32);
+
+        octree.compact();
+        // the root and one shared group of 8 children per level
+        assert_eq!(octree.nodes.len(), 1 + Octree::DEPTH as usize * 8);
+        assert_eq!(octree.stats().wasted_nodes, 0);
+        assert_eq!(octree.get_voxel(U64Vec3::new(5, 17, 31)), 1);
+    }
+
+    #[test]
+    fn compact_keeps_voxels() {
+        let mut octree = Scene::Demo.build();
+        let before = octree.to_grid();
+        octree.compact();
+        assert_eq!(octree.to_grid(), before);
+    }
+
+    #[test]
+    fn insert_copies_shared_paths() {
+        let mut octree = Octree::new();
+        // both halves end up as the same shared subtree
+        octree.insert_voxel(U64Vec3::new(0, 0, 0), 2);
+        octree.insert_voxel(U64Vec3::new(16, 0, 0), 2);
+        octree.compact();
+        let root = octree.nodes[0];
+        assert_eq!(root.valid_mask, 0b11);
+
+        octree.insert_voxel(U64Vec3::new(1, 0, 0), 3);
+        assert_eq!(octree.get_voxel(U64Vec3::new(1, 0, 0)), 3);
+        assert_eq!(octree.get_voxel(U64Vec3::new(0, 0, 0)), 2);
+        assert_eq!(octree.get_voxel(U64Vec3::new(16, 0, 0)), 2);
+        assert_eq!(octree.get_voxel(U64Vec3::new(17, 0, 0)), 0);
+    }
+
+    #[test]
+    fn insert_overwrites() {
+        let mut octree = solid(1);
+        octree.compact();
+        octree.insert_voxel(U64Vec3::new(31, 0, 31), 0);
+        assert_eq!(octree.get_voxel(U64Vec3::new(31, 0, 31)), 0);
+        assert_eq!(octree.get_voxel(U64Vec3::new(30, 0, 31)), 1);
+        let solid = octree.to_grid().iter().filter(|col| **col != 0).count();
+        assert_eq!(solid, Octree::SIZE.pow(3) - 1);
+    }
+
+    #[test]
+    fn dirty_ranges_merge() {
+        let mut octree = Octree::new();
+        octree.dirty = vec![5..6, 0..1, 1..3, 8..9, 6..7, 2..3];
+        assert_eq!(octree.take_dirty_ranges(), [0..3, 5..7, 8..9]);
+        assert!(octree.take_dirty_ranges().is_empty());
+    }
+
+    #[test]
+    fn dirty_ranges_cover_edits() {
+        let mut octree = Scene::Demo.build();
+        octree.take_dirty_ranges();
+
+        let before = octree.nodes.clone();
+        let edits = [(1, 2, 3), (1, 2, 4), (30, 30, 30), (16, 0, 9)];
+        for (x, y, z) in edits {
+            octree.insert_voxel(U64Vec3::new(x, y, z), 2);
+        }
+        let ranges = octree.take_dirty_ranges();
+
+        let covered = |i: usize| ranges.iter().any(|range| range.contains(&i));
+        for (i, node) in octree.nodes.iter().enumerate() {
+            if before.get(i) != Some(node) {
+                assert!(covered(i), "node {i} changed but is not uploaded");
+            }
+        }
+        for pair in ranges.windows(2) {
+            assert!(pair[0].end < pair[1].start, "{pair:?} are not merged");
+        }
+        assert!(ranges.last().unwrap().end <= octree.nodes.len());
+    }
+
+    #[test]
+    fn dirty_ranges_of_shared_edit() {
+        let mut octree = solid(1);
+        octree.compact();
+        octree.take_dirty_ranges();
+
+        let len = octree.nodes.len();
+        octree.insert_voxel(U64Vec3::new(7, 8, 9), 2);
+        // the root points to the copied path, nothing else is touched
+        let copied = len..len + Octree::DEPTH as usize * 8;
+        assert_eq!(octree.take_dirty_ranges(), [0..1, copied]);
+    }
+
+    fn edit<'a>(
+        octree: &'a mut Octree,
+        events: &'a mut Vec<WorldEvent>,
+    ) -> Edit<'a> {
+        Edit {
+            octree,
+            events,
+            layout: NodeLayout::Packed16,
+            changes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn edit_commit_and_undo() {
+        let mut octree = Scene::SingleVoxel.build();
+        let mut events = Vec::new();
+        let before = octree.to_grid();
+
+        let mut e = edit(&mut octree, &mut events);
+        e.insert_voxel(Scene::SINGLE_VOXEL, 0)
+            .insert_voxel(U64Vec3::new(4, 5, 6), 1)
+            .insert_voxel(U64Vec3::new(4, 5, 6), 2);
+        let inverse = e.commit().unwrap();
+
+        assert_eq!(octree.get_voxel(Scene::SINGLE_VOXEL), 0);
+        assert_eq!(octree.get_voxel(U64Vec3::new(4, 5, 6)), 2);
+        assert_eq!(
+            events,
+            [WorldEvent::VoxelBroken {
+                at: Scene::SINGLE_VOXEL,
+                material: MaterialInfo::get(Scene::SINGLE_VOXEL_COL),
+            }]
+        );
+
+        let mut e = edit(&mut octree, &mut events);
+        for (at, col) in inverse {
+            e.insert_voxel(at, col);
+        }
+        e.commit().unwrap();
+        assert_eq!(octree.to_grid(), before);
+    }
+
+    #[test]
+    fn edit_aborted() {
+        let mut octree = Scene::Demo.build();
+        octree.take_dirty_ranges();
+        let mut events = Vec::new();
+        let before = octree.nodes.clone();
+
+        // out of bounds, nothing is applied
+        let mut e = edit(&mut octree, &mut events);
+        e.insert_voxel(U64Vec3::new(16, 16, 16), 0)
+            .insert_voxel(U64Vec3::new(0, 32, 0), 1);
+        assert!(e.commit().is_err());
+
+        // dropped without committing
+        let mut e = edit(&mut octree, &mut events);
+        e.insert_voxel(U64Vec3::new(1, 1, 1), 3);
+        drop(e);
+
+        assert_eq!(octree.nodes, before);
+        assert!(octree.take_dirty_ranges().is_empty());
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn edit_events_per_broken_voxel() {
+        let mut octree = Octree::new();
+        let stone = U64Vec3::new(1, 2, 3);
+        let glass = U64Vec3::new(3, 2, 1);
+        octree.insert_voxel(stone, 1);
+        octree.insert_voxel(glass, 3);
+        let mut events = Vec::new();
+
+        // clearing air and recoloring voxels breaks nothing
+        let mut e = edit(&mut octree, &mut events);
+        e.insert_voxel(U64Vec3::new(9, 9, 9), 0)
+            .insert_voxel(stone, 2)
+            .insert_voxel(glass, 0)
+            .insert_voxel(stone, 0);
+        e.commit().unwrap();
+
+        assert_eq!(
+            events,
+            [
+                WorldEvent::VoxelBroken {
+                    at: glass,
+                    material: MaterialInfo::PALETTE[3],
+                },
+                // the color right before it was cleared
+                WorldEvent::VoxelBroken {
+                    at: stone,
+                    material: MaterialInfo::PALETTE[2],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn material_lookup() {
+        assert_eq!(MaterialInfo::get(0), MaterialInfo::AIR);
+        let glass = MaterialInfo::get(3);
+        assert_eq!(glass.col, 3);
+        assert_eq!(glass.break_effect, MaterialInfo::BREAK_GLASS);
+        assert_ne!(glass.tags & MaterialInfo::TAG_GLASS, 0);
+
+        // outside of the palette
+        let unknown = MaterialInfo::get(200);
+        assert_eq!(unknown.col, 200);
+        assert_eq!(unknown.tags, MaterialInfo::TAG_SOLID);
+        assert_eq!(unknown.break_effect, MaterialInfo::BREAK_NONE);
+    }
+
+    #[test]
+    fn child_octants() {
+        let center = U64Vec3::splat(16);
+        assert_eq!(child_index(center, U64Vec3::ZERO), 0);
+        assert_eq!(child_index(center, U64Vec3::new(16, 0, 0)), 0b001);
+        assert_eq!(child_index(center, U64Vec3::new(15, 16, 0)), 0b010);
+        assert_eq!(child_index(center, U64Vec3::new(0, 0, 31)), 0b100);
+        assert_eq!(child_index(center, U64Vec3::splat(31)), 0b111);
+
+        assert_eq!(child_center(center, 8, 0), U64Vec3::splat(8));
+        assert_eq!(child_center(center, 8, 0b111), U64Vec3::splat(24));
+        assert_eq!(child_center(center, 8, 0b101), U64Vec3::new(24, 8, 24));
+        // the last level has a span of 0.5 rounded down
+        assert_eq!(child_center(U64Vec3::ONE, 0, 0b011), U64Vec3::ONE);
+    }
+
+    #[test]
+    fn insert_corners_and_boundaries() {
+        let max = Octree::SIZE as u64 - 1;
+        let mut positions = Vec::new();
+        for i in 0..8 {
+            let corner = |bit: u64| if i & bit != 0 { max } else { 0 };
+            positions.push(U64Vec3::new(corner(1), corner(2), corner(4)));
+        }
+        // both sides of every octant split
+        for split in [1, 2, 4, 8, 16] {
+            positions.push(U64Vec3::new(split - 1, split, split - 1));
+            positions.push(U64Vec3::new(split, split - 1, split));
+        }
+
+        let mut octree = Octree::new();
+        for (i, at) in positions.iter().enumerate() {
+            octree.insert_voxel(*at, i as u32 + 1);
+        }
+        for (i, at) in positions.iter().enumerate() {
+            assert_eq!(octree.get_voxel(*at), i as u32 + 1, "{at}");
+        }
+
+        let grid = octree.to_grid();
+        assert_eq!(
+            grid.iter().filter(|col| **col != 0).count(),
+            positions.len()
+        );
+        let size = Octree::SIZE as u64;
+        for at in positions {
+            let i = at.x + at.y * size + at.z * size * size;
+            assert_ne!(grid[i as usize], 0, "{at}");
+        }
+    }
+
+    #[test]
+    #[should_panic = "outside of the octree"]
+    fn insert_out_of_bounds() {
+        Octree::new().insert_voxel(U64Vec3::new(0, 0, Octree::SIZE as u64), 1);
+    }
+
+    #[test]
+    fn grid_round_trip() {
+        let mut octree = Octree::new();
+        octree.insert_voxel(U64Vec3::new(1, 2, 3), 4);
+        octree.insert_voxel(U64Vec3::splat(31), 5);
+
+        let grid = octree.to_grid();
+        assert_eq!(Octree::from_grid(&grid).to_grid(), grid);
+    }
+
+    #[test]
+    fn raycast_from_outside() {
+        let mut octree = Octree::new();
+        octree.insert_voxel(U64Vec3::new(10, 5, 5), 1);
+        octree.insert_voxel(U64Vec3::new(20, 5, 5), 1);
+
+        let origin = Vec3::new(-4.5, 5.5, 5.5);
+        assert_eq!(
+            octree.raycast(origin, Vec3::X, 100.0),
+            Some(U64Vec3::new(10, 5, 5))
+        );
+        assert_eq!(
+            octree.raycast(Vec3::new(40.5, 5.5, 5.5), Vec3::NEG_X, 100.0),
+            Some(U64Vec3::new(20, 5, 5))
+        );
+        // too short and missing
+        assert_eq!(octree.raycast(origin, Vec3::X, 10.0), None);
+        assert_eq!(octree.raycast(origin, Vec3::Y, 100.0), None);
+    }
+
+    #[test]
+    fn raycast_diagonal() {
+        let mut octree = Octree::new();
+        octree.insert_voxel(U64Vec3::splat(16), 1);
+
+        let hit = octree.raycast(Vec3::splat(0.5), Vec3::ONE, 100.0);
+        assert_eq!(hit, Some(U64Vec3::splat(16)));
+    }
+
+    #[test]
+    fn ball_is_clipped() {
+        let ball = Octree::ball(U64Vec3::splat(16), 1, 2);
+        // the center and its 6 neighbors
+        assert_eq!(ball.len(), 7);
+        assert!(ball.iter().all(|(_, col)| *col == 2));
+
+        let corner = Octree::ball(U64Vec3::ZERO, 2, 0);
+        assert!(corner.iter().all(|(at, _)| at.max_element() <= 2));
+        assert_eq!(corner.len(), 11);
+    }
+
+    #[test]
+    fn scene_names() {
+        assert_eq!(Scene::from_name("demo"), Some(Scene::Demo));
+        assert_eq!(Scene::from_name("single-voxel"), Some(Scene::SingleVoxel));
+        assert_eq!(Scene::from_name("single_voxel"), None);
+    }
+
+    #[test]
+    fn single_voxel_scene() {
+        let grid = Scene::SingleVoxel.build().to_grid();
+        let solid = grid
+            .iter()
+            .enumerate()
+            .filter(|(_, col)| **col != 0)
+            .collect::<Vec<_>>();
+        // the origin is index 0 of the grid
+        assert_eq!(solid, [(0, &Scene::SINGLE_VOXEL_COL)]);
+    }
 }
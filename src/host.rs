@@ -0,0 +1,288 @@
+use ash::{Device, Instance, vk};
+use eyre::{Result, eyre};
+
+use crate::graphics::{Graphics, PushConst, ScreenshotData};
+
+//
+
+/// what a host application embedding luminary does with
+/// [`Graphics::record_into`], for `--headless --external`
+///
+/// records a frame into its own command buffer and image,
+/// then copies the image into its own buffer and reads it back
+pub struct Host {
+    device: Device,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    image: vk::Image,
+    image_memory: vk::DeviceMemory,
+    buffer: vk::Buffer,
+    buffer_memory: vk::DeviceMemory,
+    extent: vk::Extent2D,
+}
+
+impl Host {
+    const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+    pub fn new(
+        instance: &Instance,
+        gpu: vk::PhysicalDevice,
+        device: &Device,
+        queue_family: u32,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let memory_props =
+            unsafe { instance.get_physical_device_memory_properties(gpu) };
+        let alloc = |requirements: vk::MemoryRequirements,
+                     flags: vk::MemoryPropertyFlags|
+         -> Result<vk::DeviceMemory> {
+            let memory_type = memory_props.memory_types
+                [..memory_props.memory_type_count as usize]
+                .iter()
+                .enumerate()
+                .position(|(i, ty)| {
+                    requirements.memory_type_bits & (1 << i) != 0
+                        && ty.property_flags.contains(flags)
+                })
+                .ok_or_else(|| eyre!("no {flags:?} memory type"))?;
+            let info = vk::MemoryAllocateInfo::default()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type as u32);
+            Ok(unsafe { device.allocate_memory(&info, None)? })
+        };
+
+        // every handle is null until created, so `destroy` can clean up
+        // after a failure half way through
+        let mut host = Self {
+            device: device.clone(),
+            queue: unsafe { device.get_device_queue(queue_family, 0) },
+            command_pool: vk::CommandPool::null(),
+            image: vk::Image::null(),
+            image_memory: vk::DeviceMemory::null(),
+            buffer: vk::Buffer::null(),
+            buffer_memory: vk::DeviceMemory::null(),
+            extent,
+        };
+        let res = (|| -> Result<()> {
+            host.command_pool = unsafe {
+                device.create_command_pool(
+                    &vk::CommandPoolCreateInfo::default()
+                        .queue_family_index(queue_family),
+                    None,
+                )?
+            };
+
+            host.image = unsafe {
+                device.create_image(
+                    &vk::ImageCreateInfo::default()
+                        .image_type(vk::ImageType::TYPE_2D)
+                        .format(Self::FORMAT)
+                        .extent(extent.into())
+                        .mip_levels(1)
+                        .array_layers(1)
+                        .samples(vk::SampleCountFlags::TYPE_1)
+                        .tiling(vk::ImageTiling::OPTIMAL)
+                        .usage(
+                            vk::ImageUsageFlags::TRANSFER_DST
+                                | vk::ImageUsageFlags::TRANSFER_SRC,
+                        ),
+                    None,
+                )?
+            };
+            host.image_memory = alloc(
+                unsafe { device.get_image_memory_requirements(host.image) },
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?;
+            unsafe {
+                device.bind_image_memory(host.image, host.image_memory, 0)?
+            };
+
+            host.buffer = unsafe {
+                device.create_buffer(
+                    &vk::BufferCreateInfo::default()
+                        .size(extent.width as u64 * extent.height as u64 * 4)
+                        .usage(vk::BufferUsageFlags::TRANSFER_DST),
+                    None,
+                )?
+            };
+            host.buffer_memory = alloc(
+                unsafe { device.get_buffer_memory_requirements(host.buffer) },
+                vk::MemoryPropertyFlags::HOST_VISIBLE
+                    | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            unsafe {
+                device.bind_buffer_memory(host.buffer, host.buffer_memory, 0)?
+            };
+            Ok(())
+        })();
+
+        match res {
+            Ok(()) => Ok(host),
+            Err(err) => {
+                host.destroy();
+                Err(err)
+            }
+        }
+    }
+
+    /// record a frame with `graphics` into the host image,
+    /// submit it on the host queue and read the image back
+    pub fn render(
+        &self,
+        graphics: &mut Graphics,
+        push_const: PushConst,
+    ) -> Result<ScreenshotData> {
+        let device = &self.device;
+        let cbuf = unsafe {
+            device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(self.command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1),
+            )?[0]
+        };
+        let fence = unsafe {
+            device.create_fence(&vk::FenceCreateInfo::default(), None)?
+        };
+
+        let res = self.submit(graphics, push_const, cbuf, fence);
+
+        unsafe {
+            device.destroy_fence(fence, None);
+            device.free_command_buffers(self.command_pool, &[cbuf]);
+        }
+        res?;
+
+        let len = self.extent.width as usize * self.extent.height as usize * 4;
+        let mut pixels = vec![0u8; len];
+        unsafe {
+            let mapped = device.map_memory(
+                self.buffer_memory,
+                0,
+                len as u64,
+                vk::MemoryMapFlags::empty(),
+            )?;
+            mapped
+                .cast::<u8>()
+                .copy_to_nonoverlapping(pixels.as_mut_ptr(), len);
+            device.unmap_memory(self.buffer_memory);
+        }
+
+        Ok(ScreenshotData {
+            width: self.extent.width,
+            height: self.extent.height,
+            pixels,
+        })
+    }
+
+    fn submit(
+        &self,
+        graphics: &mut Graphics,
+        push_const: PushConst,
+        cbuf: vk::CommandBuffer,
+        fence: vk::Fence,
+    ) -> Result<()> {
+        let device = &self.device;
+        let range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+        let barrier =
+            |src_stage, src_access, dst_stage, dst_access, old, new| {
+                vk::ImageMemoryBarrier2::default()
+                    .src_stage_mask(src_stage)
+                    .src_access_mask(src_access)
+                    .dst_stage_mask(dst_stage)
+                    .dst_access_mask(dst_access)
+                    .old_layout(old)
+                    .new_layout(new)
+                    .image(self.image)
+                    .subresource_range(range)
+            };
+
+        unsafe {
+            device.begin_command_buffer(
+                cbuf,
+                &vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+
+            // record_into wants the target in TRANSFER_DST_OPTIMAL
+            device.cmd_pipeline_barrier2(
+                cbuf,
+                &vk::DependencyInfo::default().image_memory_barriers(&[
+                    barrier(
+                        vk::PipelineStageFlags2::NONE,
+                        vk::AccessFlags2::NONE,
+                        vk::PipelineStageFlags2::ALL_TRANSFER,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    ),
+                ]),
+            );
+        }
+
+        graphics.record_into(cbuf, self.image, self.extent, push_const);
+
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                cbuf,
+                &vk::DependencyInfo::default().image_memory_barriers(&[
+                    barrier(
+                        vk::PipelineStageFlags2::ALL_TRANSFER,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                        vk::PipelineStageFlags2::ALL_TRANSFER,
+                        vk::AccessFlags2::TRANSFER_READ,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    ),
+                ]),
+            );
+            device.cmd_copy_image_to_buffer(
+                cbuf,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.buffer,
+                &[vk::BufferImageCopy::default()
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::default()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .layer_count(1),
+                    )
+                    .image_extent(self.extent.into())],
+            );
+            // the fence does not make the copy visible to the host
+            device.cmd_pipeline_barrier2(
+                cbuf,
+                &vk::DependencyInfo::default().memory_barriers(&[
+                    vk::MemoryBarrier2::default()
+                        .src_stage_mask(vk::PipelineStageFlags2::ALL_TRANSFER)
+                        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                        .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+                        .dst_access_mask(vk::AccessFlags2::HOST_READ),
+                ]),
+            );
+            device.end_command_buffer(cbuf)?;
+
+            let cbufs = [cbuf];
+            device.queue_submit(
+                self.queue,
+                &[vk::SubmitInfo::default().command_buffers(&cbufs)],
+                fence,
+            )?;
+            device.wait_for_fences(&[fence], true, u64::MAX)?;
+        }
+        Ok(())
+    }
+
+    pub fn destroy(&mut self) {
+        unsafe {
+            self.device.destroy_buffer(self.buffer, None);
+            self.device.free_memory(self.buffer_memory, None);
+            self.device.destroy_image(self.image, None);
+            self.device.free_memory(self.image_memory, None);
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::{fs, io, path::Path};
+
+use eyre::Result;
+use rustc_hash::FxHashMap;
+use winit::keyboard::KeyCode;
+
+//
+
+macro_rules! actions {
+    ($($action:ident = $name:literal, $key:ident;)*) => {
+        /// logical inputs that keys are bound to
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Action {
+            $($action,)*
+        }
+
+        impl Action {
+            pub const ALL: &[Self] = &[$(Self::$action,)*];
+
+            /// the name used in the key map file
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $(Self::$action => $name,)*
+                }
+            }
+
+            pub const fn default_key(self) -> KeyCode {
+                match self {
+                    $(Self::$action => KeyCode::$key,)*
+                }
+            }
+        }
+    };
+}
+
+actions! {
+    MoveLeft = "move_left", KeyA;
+    MoveRight = "move_right", KeyD;
+    MoveBack = "move_back", KeyS;
+    MoveForward = "move_forward", KeyW;
+    MoveDown = "move_down", ShiftLeft;
+    MoveUp = "move_up", Space;
+    MoveSlow = "move_slow", ControlLeft;
+    RollLeft = "roll_left", KeyQ;
+    RollRight = "roll_right", KeyE;
+    ToggleWorldLock = "toggle_world_lock", KeyF;
+    ToggleTurntable = "toggle_turntable", KeyT;
+    TurntableSlower = "turntable_slower", BracketLeft;
+    TurntableFaster = "turntable_faster", BracketRight;
+    ToggleWrap = "toggle_wrap", KeyG;
+    ZoomOut = "zoom_out", Minus;
+    ZoomIn = "zoom_in", Equal;
+    ClipCloser = "clip_closer", Comma;
+    ClipFurther = "clip_further", Period;
+    ToggleFxaa = "toggle_fxaa", KeyX;
+    CycleTonemap = "cycle_tonemap", KeyM;
+    ExposureDown = "exposure_down", KeyJ;
+    ExposureUp = "exposure_up", KeyK;
+    RenderScaleDown = "render_scale_down", KeyY;
+    RenderScaleUp = "render_scale_up", KeyU;
+    ToggleOverlay = "toggle_overlay", KeyO;
+    ToggleHud = "toggle_hud", KeyH;
+    ToggleGrid = "toggle_grid", KeyC;
+    ToggleFpsCap = "toggle_fps_cap", KeyL;
+    CyclePresentMode = "cycle_present_mode", KeyV;
+    ToggleCursor = "toggle_cursor", Escape;
+    // only while initializing, there is nothing to toggle yet
+    Quit = "quit", Escape;
+    Screenshot = "screenshot", F12;
+    RenderStill = "render_still", F11;
+    SaveCamera = "save_camera", F6;
+    LoadCamera = "load_camera", F7;
+    Probe = "probe", F9;
+    Explode = "explode", KeyB;
+    Undo = "undo", KeyZ;
+    Redo = "redo", KeyR;
+    // lists the restorable edits with their timestamps
+    History = "history", KeyN;
+    Stats = "stats", F8;
+    VisionNormal = "vision_normal", F1;
+    VisionBrightness = "vision_brightness", F2;
+    VisionDepth = "vision_depth", F3;
+    VisionNormals = "vision_normals", F4;
+    VisionSteps = "vision_steps", F5;
+}
+
+/// which key triggers each [`Action`]
+pub struct InputMap {
+    keys: FxHashMap<Action, KeyCode>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        Self {
+            keys: Action::ALL
+                .iter()
+                .map(|action| (*action, action.default_key()))
+                .collect(),
+        }
+    }
+}
+
+impl InputMap {
+    /// the defaults, with the bindings of `path` on top,
+    /// see [`Self::parse`], a missing file is not an error
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        match fs::read_to_string(path.as_ref()) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// the defaults, with `action = KeyName` lines on top,
+    /// `#` starts a comment, bad lines are warned about and skipped
+    pub fn parse(text: &str) -> Self {
+        let mut map = Self::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let Some((name, key)) = line.split_once('=') else {
+                tracing::warn!("keymap line {}: expected action = Key", i + 1);
+                continue;
+            };
+            let (name, key) = (name.trim(), key.trim());
+
+            let Some(action) = Action::ALL.iter().find(|a| a.name() == name)
+            else {
+                tracing::warn!("keymap line {}: unknown action {name}", i + 1);
+                continue;
+            };
+            let Some(key) = key_from_name(key) else {
+                tracing::warn!("keymap line {}: unknown key {key}", i + 1);
+                continue;
+            };
+
+            map.keys.insert(*action, key);
+        }
+
+        map
+    }
+
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.keys[&action]
+    }
+}
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        /// winit [`KeyCode`] from its variant name
+        fn key_from_name(name: &str) -> Option<KeyCode> {
+            match name {
+                $(stringify!($key) => Some(KeyCode::$key),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+key_names! {
+    KeyA, KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL,
+    KeyM, KeyN, KeyO, KeyP, KeyQ, KeyR, KeyS, KeyT, KeyU, KeyV, KeyW, KeyX,
+    KeyY, KeyZ,
+    Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8,
+    Digit9,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    ArrowUp, ArrowDown, ArrowLeft, ArrowRight,
+    Space, Enter, Tab, Escape, Backspace,
+    ShiftLeft, ShiftRight, ControlLeft, ControlRight, AltLeft, AltRight,
+    Minus, Equal, Comma, Period, Slash, Semicolon, Quote, Backquote,
+    Backslash, BracketLeft, BracketRight,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7,
+    Numpad8, Numpad9, NumpadAdd, NumpadSubtract,
+    Home, End, PageUp, PageDown, Insert, Delete,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rebinds() {
+        let map = InputMap::parse(
+            "# arrows instead of WASD\n\
+             move_forward = ArrowUp\n\
+             \n\
+             move_back=ArrowDown # trailing comment\n",
+        );
+
+        assert_eq!(map.key(Action::MoveForward), KeyCode::ArrowUp);
+        assert_eq!(map.key(Action::MoveBack), KeyCode::ArrowDown);
+        // everything else keeps the default
+        assert_eq!(map.key(Action::MoveLeft), KeyCode::KeyA);
+        assert_eq!(map.keys.len(), Action::ALL.len());
+    }
+
+    #[test]
+    fn parse_skips_bad_lines() {
+        let map = InputMap::parse(
+            "move_left\n\
+             fly_away = KeyP\n\
+             move_right = NotAKey\n\
+             undo = \"KeyP\"\n\
+             redo = KeyP\n",
+        );
+
+        assert_eq!(map.key(Action::MoveLeft), KeyCode::KeyA);
+        assert_eq!(map.key(Action::MoveRight), KeyCode::KeyD);
+        assert_eq!(map.key(Action::Undo), KeyCode::KeyZ);
+        assert_eq!(map.key(Action::Redo), KeyCode::KeyP);
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(key_from_name("F12"), Some(KeyCode::F12));
+        assert_eq!(key_from_name("Numpad7"), Some(KeyCode::Numpad7));
+        assert_eq!(key_from_name("keya"), None);
+    }
+}
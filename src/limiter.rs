@@ -0,0 +1,81 @@
+use std::{
+    hint,
+    time::{Duration, Instant},
+};
+
+//
+
+/// caps the frame rate by delaying the start of the next frame
+pub struct FrameLimiter {
+    /// frames per second, 0 is uncapped
+    fps: u32,
+    enabled: bool,
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    /// the cap [`Self::toggle`] turns on when none was configured
+    pub const DEFAULT_FPS: u32 = 60;
+    /// the end of every wait is spun instead of slept,
+    /// OS sleeps can overshoot by a timer tick
+    pub const SPIN: Duration = Duration::from_millis(2);
+
+    pub fn new(fps: u32, enabled: bool) -> Self {
+        Self {
+            fps,
+            enabled,
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// `LUMINARY_FPS_CAP`, 0 disables the cap,
+    /// without it the limiter starts disabled at [`Self::DEFAULT_FPS`]
+    pub fn from_env() -> Self {
+        match std::env::var("LUMINARY_FPS_CAP")
+            .ok()
+            .and_then(|fps| fps.parse().ok())
+        {
+            Some(fps) => Self::new(fps, true),
+            None => Self::new(Self::DEFAULT_FPS, false),
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled ^= true;
+        self.next_frame = Instant::now();
+    }
+
+    /// the frame rate cap in effect
+    pub fn fps(&self) -> Option<u32> {
+        (self.enabled && self.fps != 0).then_some(self.fps)
+    }
+
+    fn budget(&self) -> Option<Duration> {
+        self.fps().map(|fps| Duration::from_secs(1) / fps)
+    }
+
+    /// returns when the event loop should wake up again if the next frame
+    /// is far away, otherwise spins until it can start and returns `None`
+    pub fn wait(&self) -> Option<Instant> {
+        self.budget()?;
+
+        let now = Instant::now();
+        if self.next_frame.saturating_duration_since(now) > Self::SPIN {
+            return Some(self.next_frame - Self::SPIN);
+        }
+        while Instant::now() < self.next_frame {
+            hint::spin_loop();
+        }
+        None
+    }
+
+    /// schedule the next frame one budget after this one,
+    /// a late frame only shortens the next budget by how late it was
+    pub fn frame_started(&mut self) {
+        let Some(budget) = self.budget() else {
+            return;
+        };
+        let now = Instant::now();
+        self.next_frame = (self.next_frame + budget).max(now);
+    }
+}
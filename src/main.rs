@@ -1,17 +1,17 @@
 #![allow(internal_features)]
-#![feature(
-    core_intrinsics,
-    unsigned_is_multiple_of,
-    const_heap,
-    alloc_layout_extra,
-    ptr_as_uninit,
-    maybe_uninit_slice
-)]
+#![feature(core_intrinsics, unsigned_is_multiple_of, const_heap)]
 
-use std::{default, sync::Arc, time::Instant};
+use std::{
+    default, env,
+    f32::consts::TAU,
+    fs,
+    path::Path,
+    sync::{Arc, atomic::AtomicBool},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use eyre::Result;
-use glam::{Mat4, Vec2, Vec3};
+use glam::{Mat4, Vec2, Vec3, Vec4};
 use rustc_hash::{FxHashMap, FxHashSet};
 use winit::{
     application::ApplicationHandler,
@@ -25,14 +25,30 @@ use winit::{
     window::{CursorGrabMode, Window, WindowId},
 };
 
-use self::graphics::{Graphics, PushConst};
+use self::graphics::{
+    Access, BuildConfig, CustomPassDesc, FxaaPushConst, Graphics,
+    GridPushConst, ImportLimits, InsertionPoint, MaterialInfo, PushConst,
+    RenderState, Scene, ScreenshotData, VisionMode, WorldEvent,
+    world::{
+        history::{History, format_time},
+        voxels::Octree,
+    },
+};
+use self::input::{Action, InputMap};
 
 //
 
 mod counter;
 mod flycam;
 mod graphics;
+mod host;
+mod input;
+mod limiter;
+mod overlay;
+mod png;
+mod profiling;
 mod renderer;
+mod soak;
 
 //
 
@@ -44,39 +60,94 @@ struct App {
 struct AppInner {
     window: Arc<Window>,
     graphics: Graphics,
+    /// edits that can be undone, saved to [`WORLD_FILE`] after each one
+    history: History,
     eye: flycam::Flycam,
+    projection: flycam::Projection,
     dt: Instant,
     speed: f32,
 
     cursor_visible: bool,
+    /// false while another window has the keyboard focus
+    focused: bool,
+    /// true while the window is completely hidden, nothing is rendered
+    occluded: bool,
+    /// keep turning the camera with mouse motion while unfocused,
+    /// `LUMINARY_UNFOCUSED_INPUT=continue`
+    unfocused_mouse: bool,
     mode_flags: u32,
 
+    turntable: bool,
+    /// radians per second
+    turntable_speed: f32,
+    fxaa: bool,
+    /// debug stats drawn over the frame
+    overlay: overlay::Overlay,
+    limiter: limiter::FrameLimiter,
+
+    input: InputMap,
     just_pressed: FxHashSet<KeyCode>,
     just_released: FxHashSet<KeyCode>,
     pressed: FxHashSet<KeyCode>,
 }
 
+const WINDOW_TITLE: &str = "luminar";
+
+/// `action = KeyName` lines that rebind the default keys
+const KEYMAP_FILE: &str = "keymap.cfg";
+
+/// F6 saves the camera pose here and F7 loads it
+const CAMERA_FILE: &str = "camera.json";
+
+/// the voxels and the undo history, loaded on start
+/// and saved after every edit, so a crash loses nothing
+const WORLD_FILE: &str = "world.lum";
+
+/// undoable edits, older ones are forgotten
+const HISTORY_LIMIT: usize = 64;
+
+/// how far away the explode action reaches, in voxels
+const EXPLODE_DISTANCE: f32 = 128.0;
+
 impl AppInner {
     pub fn render(&mut self) {
-        self.update();
+        profiling::zone!("render");
+        self.limiter.frame_started();
+        let timer = self.graphics.metrics().timer("update");
+        timer.time(|| self.update());
+        let timer = self.graphics.metrics().timer("overlay");
+        timer.time(|| self.draw_overlay());
 
         let size = self.window.inner_size().cast::<f32>();
 
-        let projection_view = Mat4::perspective_rh(
-            90.0f32.to_radians(),
-            size.width / size.height,
-            0.01,
-            10.0,
-        ) * self.eye.view_matrix();
+        let projection_view = self.projection.matrix(size.width / size.height)
+            * self.eye.view_matrix();
         let projection_view = projection_view.inverse();
 
-        self.graphics
-            .draw(PushConst {
-                projection_view,
-                mode_flags: self.mode_flags,
-                _pad: [0; 3],
-            })
-            .expect("failed to draw");
+        let drawn = self.graphics.draw(PushConst {
+            projection_view,
+            mode_flags: self.mode_flags,
+            max_ray_distance: 0.0,
+            min_ray_distance: 0.0,
+            render_extent: 0,
+            grid: GridPushConst::default(),
+        });
+        if let Err(err) = drawn {
+            if !self.graphics.is_device_lost() {
+                panic!("failed to draw: {err:?}");
+            }
+            // one rebuild per lost device, the app only exits if it fails
+            self.graphics
+                .rebuild()
+                .expect("failed to rebuild after losing the device");
+            if let Err(err) = add_posterize(&mut self.graphics) {
+                tracing::error!("failed to add the posterize pass: {err}");
+            }
+            // the textures egui uploaded were lost with the device
+            self.overlay =
+                overlay::Overlay::new(&self.window, self.overlay.visible());
+        }
+        profiling::frame_mark();
     }
 
     pub fn update(&mut self) {
@@ -84,58 +155,509 @@ impl AppInner {
         self.dt = Instant::now();
 
         let mut delta = Vec3::ZERO;
-        if self.pressed.contains(&KeyCode::KeyA) {
+        if self.held(Action::MoveLeft) {
             delta.x -= 1.0;
         }
-        if self.pressed.contains(&KeyCode::KeyD) {
+        if self.held(Action::MoveRight) {
             delta.x += 1.0;
         }
-        if self.pressed.contains(&KeyCode::KeyS) {
+        if self.held(Action::MoveBack) {
             delta.z -= 1.0;
         }
-        if self.pressed.contains(&KeyCode::KeyW) {
+        if self.held(Action::MoveForward) {
             delta.z += 1.0;
         }
-        if self.pressed.contains(&KeyCode::ShiftLeft) {
+        if self.held(Action::MoveDown) {
             delta.y -= 1.0;
         }
-        if self.pressed.contains(&KeyCode::Space) {
+        if self.held(Action::MoveUp) {
             delta.y += 1.0;
         }
-        if self.pressed.contains(&KeyCode::ControlLeft) {
+        if self.held(Action::MoveSlow) {
             delta *= 0.2;
         }
-        self.eye.movement(delta * delta_seconds * 10.0 * self.speed);
+        self.eye.movement(delta * 10.0 * self.speed, delta_seconds);
+
+        if self.triggered(Action::ToggleWorldLock) {
+            self.eye.set_world_locked(!self.eye.world_locked());
+            tracing::info!("world locked={}", self.eye.world_locked());
+        }
+        let mut roll = 0.0;
+        if self.held(Action::RollLeft) {
+            roll -= 1.0;
+        }
+        if self.held(Action::RollRight) {
+            roll += 1.0;
+        }
+        self.eye.roll(roll * delta_seconds);
+
+        if self.triggered(Action::ToggleTurntable) {
+            self.turntable ^= true;
+            if self.turntable {
+                self.eye.look_at(TURNTABLE_TARGET);
+            }
+            // the camera moves every frame while turning,
+            // so a recorded frame could never be reused
+            self.graphics.set_static_recording(!self.turntable);
+            tracing::info!("turntable={}", self.turntable);
+        }
+        let mut turn = 0.0;
+        if self.triggered(Action::TurntableSlower) {
+            turn -= 0.25;
+        }
+        if self.triggered(Action::TurntableFaster) {
+            turn += 0.25;
+        }
+        if turn != 0.0 {
+            // negative speeds turn the other way
+            self.turntable_speed = (self.turntable_speed + turn)
+                .clamp(-TURNTABLE_MAX_SPEED, TURNTABLE_MAX_SPEED);
+            tracing::info!("turntable speed={}", self.turntable_speed);
+        }
+        if self.turntable {
+            self.eye
+                .orbit(TURNTABLE_TARGET, delta_seconds * self.turntable_speed);
+        }
+
+        if self.triggered(Action::ToggleWrap) {
+            self.mode_flags ^= PushConst::WRAP_FLAG;
+            let wrap = self.mode_flags & PushConst::WRAP_FLAG != 0;
+            self.eye.set_wrap(wrap.then_some(Vec3::splat(32.0)));
+            tracing::info!("wrap around={wrap}");
+        }
+
+        let mut zoom = 0.0;
+        if self.held(Action::ZoomOut) {
+            zoom -= 1.0;
+        }
+        if self.held(Action::ZoomIn) {
+            zoom += 1.0;
+        }
+        if zoom != 0.0 {
+            // zooming in narrows the field of view
+            let fov = self.projection.fov() - zoom * delta_seconds;
+            self.projection.set_fov(fov);
+            tracing::debug!("fov={}", self.projection.fov().to_degrees());
+        }
+
+        let mut clip = 0.0;
+        if self.triggered(Action::ClipCloser) {
+            clip -= 1.0;
+        }
+        if self.triggered(Action::ClipFurther) {
+            clip += 1.0;
+        }
+        if clip != 0.0 {
+            let clip = self.graphics.min_ray_distance() + clip;
+            self.graphics.set_min_ray_distance(clip);
+            tracing::info!("near clip={}", self.graphics.min_ray_distance());
+        }
+
+        if self.triggered(Action::ToggleFxaa) {
+            self.fxaa ^= true;
+            self.graphics.set_fxaa(self.fxaa.then_some(FxaaPushConst {
+                intensity: 0.75,
+                edge_threshold: 0.05,
+            }));
+            tracing::info!("fxaa={}", self.fxaa);
+        }
+
+        let old_tonemap = self.graphics.tonemap();
+        let mut tonemap = old_tonemap;
+        if self.triggered(Action::CycleTonemap) {
+            tonemap.operator = tonemap.operator.next();
+        }
+        // half a stop per press
+        if self.triggered(Action::ExposureDown) {
+            tonemap.exposure *= 0.5f32.sqrt();
+        }
+        if self.triggered(Action::ExposureUp) {
+            tonemap.exposure *= 2.0f32.sqrt();
+        }
+        if tonemap != old_tonemap {
+            self.graphics.set_tonemap(tonemap);
+            tracing::info!(
+                "tonemap={:?} exposure={:.2}",
+                tonemap.operator,
+                tonemap.exposure
+            );
+        }
+
+        let mut scale = 0.0;
+        if self.triggered(Action::RenderScaleDown) {
+            scale -= 0.25;
+        }
+        if self.triggered(Action::RenderScaleUp) {
+            scale += 0.25;
+        }
+        if scale != 0.0 {
+            let scale = self.graphics.render_scale() + scale;
+            if let Err(err) = self.graphics.set_render_scale(scale) {
+                tracing::error!("failed to change the render scale: {err}");
+            }
+            let extent = self.graphics.render_extent();
+            tracing::info!(
+                "render scale={} ({}x{})",
+                self.graphics.render_scale(),
+                extent.width,
+                extent.height
+            );
+        }
+
+        if self.triggered(Action::ToggleGrid) {
+            let grid = self.graphics.grid().spacing == 0.0;
+            let spacing = if grid { 4.0 } else { 0.0 };
+            self.graphics.set_grid(
+                spacing,
+                0.1,
+                256.0,
+                Vec4::new(1.0, 1.0, 1.0, 0.5),
+            );
+            tracing::info!("grid={grid}");
+        }
+        if self.triggered(Action::ToggleHud) {
+            let hud = !self.graphics.hud();
+            self.graphics.set_hud(hud);
+            tracing::info!("hud={hud}");
+        }
+        if self.triggered(Action::ToggleFpsCap) {
+            self.limiter.toggle();
+            match self.limiter.fps() {
+                Some(fps) => tracing::info!("fps cap={fps}"),
+                None => tracing::info!("fps cap=off"),
+            }
+        }
+        if self.triggered(Action::CyclePresentMode)
+            && let Some(mode) = self.graphics.present_mode()
+        {
+            self.graphics.set_present_mode(mode.next());
+            tracing::info!("present mode={:?}", mode.next());
+        }
+        if self.triggered(Action::ToggleOverlay) {
+            self.overlay.toggle();
+        }
+
+        if self.triggered(Action::Screenshot)
+            && let Err(err) = self.save_screenshot()
+        {
+            tracing::error!("failed to save a screenshot: {err}");
+        }
+        if self.triggered(Action::RenderStill)
+            && let Err(err) = self.save_still()
+        {
+            tracing::error!("failed to render a still: {err}");
+        }
+
+        if self.triggered(Action::SaveCamera) {
+            let pose = self.eye.pose().to_json();
+            match std::fs::write(CAMERA_FILE, pose) {
+                Ok(()) => tracing::info!("saved {CAMERA_FILE}"),
+                Err(err) => {
+                    tracing::error!("failed to save {CAMERA_FILE}: {err}")
+                }
+            }
+        }
+        if self.triggered(Action::LoadCamera) {
+            match std::fs::read_to_string(CAMERA_FILE)
+                .map_err(eyre::Report::from)
+                .and_then(|json| flycam::CameraPose::from_json(&json))
+            {
+                Ok(pose) => {
+                    self.eye.set_pose(pose);
+                    tracing::info!("loaded {CAMERA_FILE}");
+                }
+                Err(err) => {
+                    tracing::error!("failed to load {CAMERA_FILE}: {err}")
+                }
+            }
+        }
+
+        if self.triggered(Action::Probe) {
+            let size = self.window.inner_size();
+            match self.graphics.read_pixel(size.width / 2, size.height / 2) {
+                Ok(pixel) => tracing::info!("center pixel: {pixel:?}"),
+                Err(err) => tracing::error!("failed to read a pixel: {err}"),
+            }
+            match self.graphics.probe(size.width / 2, size.height / 2) {
+                Ok(probe) => tracing::info!("center probe: {probe:?}"),
+                Err(err) => tracing::error!("failed to probe: {err}"),
+            }
+            if let Some(hit) = self.graphics.raycast(
+                self.eye.pose().position,
+                self.eye.looking_to(),
+                EXPLODE_DISTANCE,
+            ) {
+                let material = self.graphics.material_of(hit);
+                tracing::info!("looking at voxel {hit}: {material:?}");
+            }
+        }
+
+        if self.triggered(Action::Stats) {
+            tracing::info!("voxels: {}", self.graphics.voxel_stats());
+            tracing::info!(
+                "voxel load: {}",
+                self.graphics.voxel_load_timings()
+            );
+            tracing::info!("device features: {:?}", self.graphics.features());
+            if let Some(format) = self.graphics.output_format() {
+                tracing::info!(
+                    "output: {format:?} {:?} ({:?})",
+                    self.graphics.output_color_space().unwrap_or_default(),
+                    self.graphics.output_encoding()
+                );
+            }
+            tracing::info!(
+                "driver overrides: {:?}",
+                self.graphics.driver_overrides()
+            );
+            tracing::info!("gpu memory: {}", self.graphics.memory_report());
+            tracing::info!("gpu heaps: {}", self.graphics.memory_stats());
+            for (label, ms) in self.graphics.timings() {
+                tracing::info!("gpu {label}: {ms:.3}ms");
+            }
+            tracing::info!(
+                "descriptor write calls per frame: {}",
+                self.graphics.descriptor_write_calls()
+            );
+            let (pools, cbufs) = self.graphics.command_buffer_counts();
+            tracing::info!("command pools: {pools}, command buffers: {cbufs}");
+        }
 
-        if self.just_pressed.contains(&KeyCode::F1) {
-            // normal vision
-            self.mode_flags &= !15;
+        const VISION_ACTIONS: [Action; 5] = [
+            Action::VisionNormal,
+            Action::VisionBrightness,
+            Action::VisionDepth,
+            Action::VisionNormals,
+            Action::VisionSteps,
+        ];
+        for (action, mode) in VISION_ACTIONS.iter().zip(VisionMode::ALL) {
+            if !self.triggered(*action) {
+                continue;
+            }
+
+            if !mode.is_available(BuildConfig::CURRENT) {
+                tracing::warn!(
+                    "{} vision is not available in this build",
+                    mode.name
+                );
+                continue;
+            }
+
+            self.mode_flags &= !VisionMode::MASK;
+            self.mode_flags |= mode.flags;
         }
-        if self.just_pressed.contains(&KeyCode::F2) {
-            // brightness vision
-            self.mode_flags &= !15;
-            self.mode_flags |= 1;
+
+        if self.triggered(Action::Explode) {
+            self.explode();
+        }
+        if self.triggered(Action::Undo) {
+            let graphics = &mut self.graphics;
+            match self.history.undo(|changes| graphics.edit_voxels(changes)) {
+                Ok(Some(time)) => {
+                    tracing::info!("undid the edit from {}", format_time(time));
+                    self.save_world();
+                }
+                Ok(None) => tracing::info!("nothing to undo"),
+                Err(err) => tracing::error!("failed to undo: {err}"),
+            }
         }
-        if self.just_pressed.contains(&KeyCode::F3) {
-            // depth vision
-            self.mode_flags &= !15;
-            self.mode_flags |= 2;
+        if self.triggered(Action::Redo) {
+            let graphics = &mut self.graphics;
+            match self.history.redo(|changes| graphics.edit_voxels(changes)) {
+                Ok(Some(time)) => {
+                    tracing::info!("redid the edit from {}", format_time(time));
+                    self.save_world();
+                }
+                Ok(None) => tracing::info!("nothing to redo"),
+                Err(err) => tracing::error!("failed to redo: {err}"),
+            }
         }
-        if self.just_pressed.contains(&KeyCode::F4) {
-            // normals vision
-            self.mode_flags &= !15;
-            self.mode_flags |= 4;
+        if self.triggered(Action::History) {
+            tracing::info!("history:");
+            for op in self.history.redoable() {
+                tracing::info!(
+                    "  redo {} ({} voxels)",
+                    format_time(op.time),
+                    op.changes.len()
+                );
+            }
+            for op in self.history.undoable() {
+                tracing::info!(
+                    "  undo {} ({} voxels)",
+                    format_time(op.time),
+                    op.changes.len()
+                );
+            }
         }
-        if self.just_pressed.contains(&KeyCode::F5) {
-            // step counter vision
-            self.mode_flags &= !15;
-            self.mode_flags |= 8;
+
+        for event in self.graphics.take_world_events() {
+            self.on_world_event(event);
         }
 
         self.just_pressed.clear();
         self.just_released.clear();
     }
 
+    /// blow a hole where the camera looks
+    fn explode(&mut self) {
+        let Some(hit) = self.graphics.raycast(
+            self.eye.pose().position,
+            self.eye.looking_to(),
+            EXPLODE_DISTANCE,
+        ) else {
+            tracing::info!("nothing to explode");
+            return;
+        };
+
+        match self.graphics.edit_voxels(Octree::ball(hit, 3, 0)) {
+            Ok(inverse) => {
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs());
+                self.history.record(time, inverse);
+                self.save_world();
+            }
+            Err(err) => tracing::error!("failed to explode: {err}"),
+        }
+    }
+
+    fn save_world(&self) {
+        if let Err(err) = self.graphics.save_world(WORLD_FILE, &self.history) {
+            tracing::error!("failed to save {WORLD_FILE}: {err}");
+        }
+    }
+
+    /// gameplay reactions to voxel changes,
+    /// there is no audio or particle system yet so both are only logged
+    fn on_world_event(&mut self, event: WorldEvent) {
+        match event {
+            WorldEvent::VoxelBroken { at, material } => {
+                let cue = match material.break_effect {
+                    MaterialInfo::BREAK_STONE => "stone",
+                    MaterialInfo::BREAK_GLASS => "glass",
+                    _ => return,
+                };
+                // debris is a bit darker than the voxel itself
+                let [r, g, b, _] = material.color.map(|c| c * 0.6);
+                tracing::debug!(
+                    "voxel {at} broken, cue={cue} debris=({r:.2}, {g:.2}, {b:.2})"
+                );
+            }
+        }
+    }
+
+    /// FPS, camera, vision mode and GPU memory
+    fn draw_overlay(&mut self) {
+        let fps = self.graphics.fps_estimate().unwrap_or(0.0);
+        let position = self.eye.pose().position;
+        let dir = self.eye.looking_to();
+        let yaw = dir.x.atan2(dir.z).to_degrees();
+        let pitch = dir.y.clamp(-1.0, 1.0).asin().to_degrees();
+        let vision = VisionMode::ALL
+            .iter()
+            .find(|mode| mode.flags == self.mode_flags & VisionMode::MASK)
+            .map_or("custom", |mode| mode.name);
+        let memory = self.graphics.memory_stats();
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        let output = self
+            .graphics
+            .output_format()
+            .zip(self.graphics.output_color_space());
+
+        let drawn = self.overlay.run(&self.window, &mut self.graphics, |ctx| {
+            egui::Window::new("stats").resizable(false).show(ctx, |ui| {
+                ui.label(format!("{fps:.1} FPS"));
+                ui.label(format!(
+                    "position {:.1} {:.1} {:.1}",
+                    position.x, position.y, position.z
+                ));
+                ui.label(format!("yaw {yaw:.0}° pitch {pitch:.0}°"));
+                ui.label(format!("vision {vision}"));
+                ui.label(format!(
+                    "gpu memory {:.1}/{:.1} MiB",
+                    mib(memory.allocated_bytes),
+                    mib(memory.reserved_bytes)
+                ));
+                if let Some((format, color_space)) = output {
+                    ui.label(format!("output {format:?} {color_space:?}"));
+                }
+            });
+        });
+        if let Err(err) = drawn {
+            tracing::error!("failed to draw the overlay: {err}");
+        }
+    }
+
+    /// the key of `action` is held down
+    fn held(&self, action: Action) -> bool {
+        self.pressed.contains(&self.input.key(action))
+    }
+
+    /// the key of `action` was pressed since the last update
+    fn triggered(&self, action: Action) -> bool {
+        self.just_pressed.contains(&self.input.key(action))
+    }
+
+    fn save_screenshot(&mut self) -> Result<()> {
+        let screenshot = self.graphics.screenshot()?;
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let path = format!("screenshot-{secs}.png");
+        png::write_rgba8(
+            &path,
+            screenshot.width,
+            screenshot.height,
+            &screenshot.pixels,
+        )?;
+        tracing::info!("saved {path}");
+        Ok(())
+    }
+
+    /// render the current view at `LUMINARY_STILL_SIZE`, like `3840x2160`,
+    /// or 8K by default, without touching the window
+    fn save_still(&mut self) -> Result<()> {
+        let (width, height) = env::var("LUMINARY_STILL_SIZE")
+            .ok()
+            .and_then(|size| {
+                let (width, height) = size.split_once('x')?;
+                Some((width.parse().ok()?, height.parse().ok()?))
+            })
+            .unwrap_or((7680, 4320));
+
+        let projection_view =
+            self.projection.matrix(width as f32 / height as f32)
+                * self.eye.view_matrix();
+        let push_const = PushConst {
+            projection_view: projection_view.inverse(),
+            mode_flags: self.mode_flags,
+            max_ray_distance: 0.0,
+            min_ray_distance: 0.0,
+            render_extent: 0,
+            grid: GridPushConst::default(),
+        };
+
+        tracing::info!("rendering a {width}x{height} still");
+        let still = self
+            .graphics
+            .render_still(push_const, ash::vk::Extent2D { width, height })?;
+
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let path = format!("still-{secs}.png");
+        png::write_rgba8(&path, still.width, still.height, &still.pixels)?;
+        tracing::info!("saved {path}");
+        Ok(())
+    }
+
+    /// key releases are not delivered while unfocused,
+    /// so every key is released when the focus is lost
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        if !focused {
+            self.pressed.clear();
+            self.just_pressed.clear();
+            self.just_released.clear();
+        }
+    }
+
     pub fn ev(&mut self, ev: &WindowEvent) {
         let WindowEvent::KeyboardInput {
             event:
@@ -169,7 +691,7 @@ impl ApplicationHandler for App {
             let window: Arc<Window> = event_loop
                 .create_window(
                     Window::default_attributes()
-                        .with_title("luminar")
+                        .with_title(WINDOW_TITLE)
                         .with_inner_size(PhysicalSize::<u32>::from((
                             64u32, 64u32,
                         ))),
@@ -177,21 +699,76 @@ impl ApplicationHandler for App {
                 .unwrap()
                 .into();
 
-            let graphics = Graphics::new(window.clone())
+            let mut graphics = Graphics::new(window.clone())
                 .expect("failed to initialize graphics");
+            if let Err(err) = add_posterize(&mut graphics) {
+                tracing::error!("failed to add the posterize pass: {err}");
+            }
+            // frames only get recorded again when something changes
+            graphics.set_static_recording(true);
+            graphics.set_max_ray_distance(env_f32("LUMINARY_MAX_RAY_DISTANCE"));
+            if let Some(timeout) = env::var("LUMINARY_SWAPCHAIN_TIMEOUT_MS")
+                .ok()
+                .and_then(|ms| ms.parse().ok())
+            {
+                graphics.set_swapchain_timeout(Duration::from_millis(timeout));
+            }
+            if let Some(fraction) = env_f32("LUMINARY_MEMORY_WARNING_FRACTION")
+            {
+                graphics.set_memory_warning_fraction(fraction);
+            }
+
+            let mut eye = flycam::Flycam::new();
+            if let Some(sensitivity) = env_f32("LUMINARY_MOUSE_SENSITIVITY") {
+                eye.set_sensitivity(sensitivity);
+            }
+            if let Some(accel) = env_f32("LUMINARY_FLYCAM_ACCEL") {
+                eye.set_accel(accel);
+            }
+            if let Some(damping) = env_f32("LUMINARY_FLYCAM_DAMPING") {
+                eye.set_damping(damping);
+            }
+            eye.set_invert_y(
+                env::var("LUMINARY_INVERT_Y").is_ok_and(|invert| invert == "1"),
+            );
 
-            let eye = flycam::Flycam::new();
+            let history = if Path::new(WORLD_FILE).exists() {
+                graphics
+                    .load_world(WORLD_FILE, HISTORY_LIMIT)
+                    .unwrap_or_else(|err| {
+                        tracing::error!("failed to load {WORLD_FILE}: {err}");
+                        History::new(HISTORY_LIMIT)
+                    })
+            } else {
+                History::new(HISTORY_LIMIT)
+            };
 
             AppInner {
+                overlay: overlay::Overlay::new(&window, false),
                 window,
                 graphics,
+                history,
                 eye,
+                projection: flycam::Projection::default(),
                 dt: Instant::now(),
                 speed: 1.0,
 
                 cursor_visible: true,
+                focused: true,
+                occluded: false,
+                unfocused_mouse: env::var("LUMINARY_UNFOCUSED_INPUT")
+                    .is_ok_and(|mode| mode == "continue"),
                 mode_flags: 0,
 
+                turntable: false,
+                turntable_speed: 0.5,
+                fxaa: false,
+                limiter: limiter::FrameLimiter::from_env(),
+
+                input: InputMap::load(KEYMAP_FILE).unwrap_or_else(|err| {
+                    tracing::warn!("failed to load {KEYMAP_FILE}: {err}");
+                    InputMap::default()
+                }),
                 just_pressed: <_>::default(),
                 just_released: <_>::default(),
                 pressed: <_>::default(),
@@ -209,6 +786,50 @@ impl ApplicationHandler for App {
             return;
         };
 
+        if let WindowEvent::Focused(focused) = event {
+            inner.set_focused(focused);
+        }
+        if let WindowEvent::Occluded(occluded) = event {
+            inner.occluded = occluded;
+            tracing::debug!("occluded={occluded}");
+        }
+
+        if inner.graphics.state() != RenderState::Ready {
+            // only allow quitting while initializing
+            match event {
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(code),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } if code == inner.input.key(Action::Quit) => {
+                    tracing::info!("closing while initializing");
+                    el.exit();
+                }
+                WindowEvent::CloseRequested => {
+                    tracing::info!("closing while initializing");
+                    el.exit();
+                }
+                WindowEvent::RedrawRequested => {
+                    inner.render();
+                }
+                WindowEvent::Resized(_) => {
+                    if let Err(err) = inner.graphics.resize() {
+                        tracing::error!("failed to resize: {err}");
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        // the app only sees what the overlay doesn't use
+        if inner.overlay.on_window_event(&inner.window, &event) {
+            return;
+        }
         inner.ev(&event);
 
         // tracing::debug!("event: {event:?}");
@@ -217,12 +838,12 @@ impl ApplicationHandler for App {
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
-                        physical_key: PhysicalKey::Code(KeyCode::Escape),
+                        physical_key: PhysicalKey::Code(code),
                         state: ElementState::Pressed,
                         ..
                     },
                 ..
-            } => {
+            } if code == inner.input.key(Action::ToggleCursor) => {
                 inner.cursor_visible ^= true;
                 inner
                     .window
@@ -249,7 +870,9 @@ impl ApplicationHandler for App {
                 // tracing::info!("speed={} delta={y}", inner.speed);
             }
             WindowEvent::Resized(size) => {
-                inner.graphics.resize().expect("failed to resize");
+                if let Err(err) = inner.graphics.resize() {
+                    tracing::error!("failed to resize: {err}");
+                }
                 tracing::debug!("resized to {}x{}", size.width, size.height);
             }
             _ => {}
@@ -266,31 +889,77 @@ impl ApplicationHandler for App {
             return;
         };
 
-        if inner.cursor_visible {
+        if inner.cursor_visible
+            || (!inner.focused && !inner.unfocused_mouse)
+            || inner.graphics.state() != RenderState::Ready
+        {
             return;
         }
 
         if let DeviceEvent::MouseMotion { delta } = event {
-            inner
-                .eye
-                .mouse_delta(Vec2::new(-delta.0 as _, -delta.1 as _));
+            inner.eye.mouse_delta(Vec2::new(delta.0 as _, delta.1 as _));
         }
     }
 
-    fn about_to_wait(&mut self, _: &ActiveEventLoop) {
+    fn about_to_wait(&mut self, el: &ActiveEventLoop) {
         let Some(inner) = self.inner.as_mut() else {
             return;
         };
+
+        // sleep until the window is restored or visible instead of spinning
+        if inner.graphics.is_minimized() || inner.occluded {
+            el.set_control_flow(ControlFlow::Wait);
+            return;
+        }
+        // sleep through most of the frame budget, events still arrive
+        if let Some(wake_up) = inner.limiter.wait() {
+            el.set_control_flow(ControlFlow::WaitUntil(wake_up));
+            return;
+        }
+        el.set_control_flow(ControlFlow::Poll);
+
         inner.render();
     }
 }
 
 //
 
+/// the environment variable `name` as a float,
+/// `None` if it is not set or not a number
+fn env_f32(name: &str) -> Option<f32> {
+    env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+/// the example custom pass in examples/posterize.glsl,
+/// enabled with `LUMINARY_POSTERIZE` color levels per channel
+fn add_posterize(graphics: &mut Graphics) -> Result<()> {
+    let Some(levels) = env_f32("LUMINARY_POSTERIZE") else {
+        return Ok(());
+    };
+
+    let spirv =
+        include_bytes!(concat!(env!("OUT_DIR"), "/posterize.comp.spirv"))
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect::<Vec<_>>();
+    let id = graphics.add_custom_pass(&CustomPassDesc {
+        name: "posterize",
+        spirv: &spirv,
+        resources: &[("hdr_color", Access::ReadWrite)],
+        private_images: 0,
+        insertion: InsertionPoint::PreHud,
+    })?;
+    graphics.set_custom_pass_push_constants(id, bytemuck::bytes_of(&levels))
+}
+
 fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
     color_eyre::install()?;
 
+    if let Some(headless) = Headless::from_args(env::args().skip(1))? {
+        return headless.run();
+    }
+
     let el = EventLoop::new()?;
     el.set_control_flow(ControlFlow::Poll);
     el.run_app(&mut App::default())?;
@@ -298,6 +967,499 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// the turntable camera orbits around this point
+const TURNTABLE_TARGET: Vec3 = Vec3::splat(16.0);
+/// radians per second in either direction
+const TURNTABLE_MAX_SPEED: f32 = 4.0;
+
+/// `--headless [--frames N] [--out PATH] [--size WxH] [--scene NAME]
+/// [--verify] [--reference PATH] [--tolerance N] [--soak MINUTES [--seed N]]
+/// [--sequence DIR] [--export PATH] [--external] [--import PATH [--force]]`,
+/// renders without a window and saves the last frame
+struct Headless {
+    frames: usize,
+    out: String,
+    width: u32,
+    height: u32,
+    scene: Option<Scene>,
+    /// check the single voxel scene renders as expected
+    verify: bool,
+    /// golden image the output has to match
+    reference: Option<String>,
+    /// largest allowed per channel difference to the reference
+    tolerance: u8,
+    /// run [`soak::Soak`] for this many minutes instead of `frames`
+    soak: Option<f32>,
+    seed: u64,
+    /// save every one of `frames` of a full turntable turn
+    /// to `frame_0000.png`, `frame_0001.png`, .. in this directory
+    sequence: Option<String>,
+    /// save the voxels as a greedy mesh, `.gltf` or `.obj`,
+    /// instead of rendering
+    export: Option<String>,
+    /// create the Vulkan device here and hand it over like a host
+    /// application would, see [`Graphics::from_external`], one more
+    /// frame is recorded by [`host::Host`] into `<out>-host.png`
+    external: bool,
+    /// a `.vox` model or `.r16` heightmap replacing the scene
+    import: Option<String>,
+    /// import even if it could use too much memory
+    force: bool,
+}
+
+impl Headless {
+    fn from_args(
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<Option<Self>> {
+        let mut headless = false;
+        let mut this = Self {
+            frames: 1,
+            out: "out.png".to_owned(),
+            width: 1280,
+            height: 720,
+            scene: None,
+            verify: false,
+            reference: None,
+            tolerance: 2,
+            soak: None,
+            seed: 1,
+            sequence: None,
+            export: None,
+            external: false,
+            import: None,
+            force: false,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| eyre::eyre!("missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "--headless" => headless = true,
+                "--frames" => this.frames = value()?.parse()?,
+                "--out" => this.out = value()?,
+                "--size" => {
+                    let size = value()?;
+                    let (w, h) = size
+                        .split_once('x')
+                        .ok_or_else(|| eyre::eyre!("invalid size {size}"))?;
+                    this.width = w.parse()?;
+                    this.height = h.parse()?;
+                }
+                "--scene" => {
+                    let name = value()?;
+                    this.scene =
+                        Some(Scene::from_name(&name).ok_or_else(|| {
+                            eyre::eyre!("unknown scene {name}")
+                        })?);
+                }
+                "--verify" => this.verify = true,
+                "--reference" => this.reference = Some(value()?),
+                "--tolerance" => this.tolerance = value()?.parse()?,
+                "--soak" => this.soak = Some(value()?.parse()?),
+                "--seed" => this.seed = value()?.parse()?,
+                "--sequence" => this.sequence = Some(value()?),
+                "--export" => this.export = Some(value()?),
+                "--external" => this.external = true,
+                "--import" => this.import = Some(value()?),
+                "--force" => this.force = true,
+                _ => eyre::bail!("unknown argument {arg}"),
+            }
+        }
+
+        if this.verify {
+            if this.scene.is_some_and(|scene| scene != Scene::SingleVoxel) {
+                eyre::bail!("--verify only checks the single-voxel scene");
+            }
+            this.scene = Some(Scene::SingleVoxel);
+        }
+
+        Ok(headless.then_some(this))
+    }
+
+    fn run(self) -> Result<()> {
+        let extent = ash::vk::Extent2D {
+            width: self.width,
+            height: self.height,
+        };
+        if !self.external {
+            return self.render(&mut Graphics::new_headless(extent)?);
+        }
+
+        let context = Graphics::create_context()?;
+        let instance = context.instance.clone();
+        let device = context.device.clone();
+        let (gpu, queue_family) = (context.gpu, context.queue_family);
+        let mut graphics = Graphics::from_external(context, None, extent)?;
+        let res = self.render(&mut graphics).and_then(|()| {
+            // one more frame recorded the way a host application would
+            let mut host =
+                host::Host::new(&instance, gpu, &device, queue_family, extent)?;
+            let res = host.render(&mut graphics, self.push_const());
+            host.destroy();
+
+            let screenshot = res?;
+            let stem = self.out.strip_suffix(".png").unwrap_or(&self.out);
+            let path = format!("{stem}-host.png");
+            png::write_rgba8(
+                &path,
+                screenshot.width,
+                screenshot.height,
+                &screenshot.pixels,
+            )?;
+            tracing::info!("saved {path}");
+            Ok(())
+        });
+
+        // the host outlives the renderer using its device
+        drop(graphics);
+        unsafe {
+            device.destroy_device(None);
+            instance.destroy_instance(None);
+        }
+        res
+    }
+
+    fn render(&self, graphics: &mut Graphics) -> Result<()> {
+        graphics.set_max_ray_distance(env_f32("LUMINARY_MAX_RAY_DISTANCE"));
+        if let Some(scene) = self.scene {
+            graphics.load_scene(scene)?;
+        }
+        if let Some(path) = &self.import {
+            let limits = ImportLimits::new(self.force);
+            graphics.import_voxels(path, &limits, &AtomicBool::new(false))?;
+        }
+
+        if let Some(path) = &self.export {
+            graphics.export_mesh(path)?;
+            tracing::info!("saved {path}");
+            return Ok(());
+        }
+        if let Some(minutes) = self.soak {
+            let stem = self.out.strip_suffix(".png").unwrap_or(&self.out);
+            return soak::Soak {
+                duration: Duration::from_secs_f32(minutes * 60.0),
+                seed: self.seed,
+                csv: format!("{stem}-soak.csv"),
+                out: self.out.clone(),
+            }
+            .run(graphics, self.width, self.height);
+        }
+        if let Some(dir) = &self.sequence {
+            return self.run_sequence(graphics, dir);
+        }
+
+        for _ in 0..self.frames.max(1) {
+            graphics.draw(self.push_const())?;
+        }
+        graphics.report_fps();
+
+        let screenshot = graphics.screenshot()?;
+        png::write_rgba8(
+            &self.out,
+            screenshot.width,
+            screenshot.height,
+            &screenshot.pixels,
+        )?;
+        tracing::info!("saved {}", self.out);
+
+        if self.verify {
+            Self::verify_single_voxel(&screenshot, self.projection_view())?;
+            tracing::info!("single voxel scene verified");
+        }
+
+        if let Some(reference) = &self.reference {
+            self.compare(&screenshot, reference)?;
+            tracing::info!("matches {reference}");
+        }
+
+        Ok(())
+    }
+
+    /// the default camera looking at the scene
+    fn projection_view(&self) -> Mat4 {
+        flycam::Projection::default()
+            .matrix(self.width as f32 / self.height as f32)
+            * flycam::Flycam::new().view_matrix()
+    }
+
+    fn push_const(&self) -> PushConst {
+        PushConst {
+            projection_view: self.projection_view().inverse(),
+            mode_flags: 0,
+            max_ray_distance: 0.0,
+            min_ray_distance: 0.0,
+            render_extent: 0,
+            grid: GridPushConst::default(),
+        }
+    }
+
+    /// one turntable turn split into `frames` evenly spaced frames,
+    /// the angle only depends on the frame index, so the output does not
+    /// depend on how fast the frames are rendered
+    fn run_sequence(&self, graphics: &mut Graphics, dir: &str) -> Result<()> {
+        fs::create_dir_all(dir)?;
+
+        let frames = self.frames.max(1);
+        let projection = flycam::Projection::default()
+            .matrix(self.width as f32 / self.height as f32);
+        let mut eye = flycam::Flycam::new();
+        eye.look_at(TURNTABLE_TARGET);
+
+        for i in 0..frames {
+            graphics.draw(PushConst {
+                projection_view: (projection * eye.view_matrix()).inverse(),
+                mode_flags: 0,
+                max_ray_distance: 0.0,
+                min_ray_distance: 0.0,
+                render_extent: 0,
+                grid: GridPushConst::default(),
+            })?;
+
+            // waits for the frame to finish, every image is complete
+            let screenshot = graphics.screenshot()?;
+            let path = Path::new(dir).join(format!("frame_{i:04}.png"));
+            png::write_rgba8(
+                &path,
+                screenshot.width,
+                screenshot.height,
+                &screenshot.pixels,
+            )?;
+
+            eye.orbit(TURNTABLE_TARGET, TAU / frames as f32);
+        }
+
+        tracing::info!("saved {frames} frames to {dir}");
+        Ok(())
+    }
+
+    /// fail if any channel differs from `reference` by more than the
+    /// tolerance, the differing pixels are written to a `-diff.png`
+    /// next to the output
+    fn compare(
+        &self,
+        screenshot: &ScreenshotData,
+        reference: &str,
+    ) -> Result<()> {
+        let (width, height, expected) = png::read_rgba8(reference)?;
+        if (width, height) != (screenshot.width, screenshot.height) {
+            eyre::bail!(
+                "{reference} is {width}x{height}, the output is {}x{}",
+                screenshot.width,
+                screenshot.height
+            );
+        }
+
+        // differing pixels are red, the rest is a dimmed copy
+        let mut diff = Vec::with_capacity(expected.len());
+        let mut differing = 0usize;
+        for (got, expected) in screenshot
+            .pixels
+            .chunks_exact(4)
+            .zip(expected.chunks_exact(4))
+        {
+            let drift = got
+                .iter()
+                .zip(expected)
+                .map(|(got, expected)| got.abs_diff(*expected))
+                .max()
+                .unwrap_or(0);
+            if drift > self.tolerance {
+                differing += 1;
+                diff.extend([255, 0, 0, 255]);
+            } else {
+                diff.extend([got[0] / 4, got[1] / 4, got[2] / 4, 255]);
+            }
+        }
+
+        if differing == 0 {
+            return Ok(());
+        }
+
+        let diff_path = match self.out.strip_suffix(".png") {
+            Some(stem) => format!("{stem}-diff.png"),
+            None => format!("{}-diff.png", self.out),
+        };
+        png::write_rgba8(&diff_path, width, height, &diff)?;
+        eyre::bail!(
+            "{differing} pixels differ from {reference} by more than {}, \
+             see {diff_path}",
+            self.tolerance
+        );
+    }
+
+    /// the pixel the voxel projects to has to be lit
+    /// and the image corners have to show the background
+    fn verify_single_voxel(
+        screenshot: &ScreenshotData,
+        projection_view: Mat4,
+    ) -> Result<()> {
+        let pixel = |x: u32, y: u32| {
+            let i = (y * screenshot.width + x) as usize * 4;
+            &screenshot.pixels[i..i + 4]
+        };
+
+        let (w, h) = (screenshot.width, screenshot.height);
+        let background = pixel(0, 0);
+        for (x, y) in [(w - 1, 0), (0, h - 1), (w - 1, h - 1)] {
+            if pixel(x, y) != background {
+                eyre::bail!("corner ({x}, {y}) is not the background");
+            }
+        }
+
+        // the shader casts rays through NDC without flipping y
+        let center = Scene::SINGLE_VOXEL.as_vec3() + 0.5;
+        let clip = projection_view * center.extend(1.0);
+        let ndc = clip.truncate() / clip.w;
+        let x = ((ndc.x * 0.5 + 0.5) * w as f32) as u32;
+        let y = ((ndc.y * 0.5 + 0.5) * h as f32) as u32;
+        // behind the camera the projection is mirrored into the view
+        if clip.w <= 0.0 || x >= w || y >= h {
+            eyre::bail!("the voxel is not in view");
+        }
+        if pixel(x, y) == background {
+            eyre::bail!("the voxel at pixel ({x}, {y}) was not rendered");
+        }
+
+        Ok(())
+    }
+}
+
 /// just a function to mark some branch as cold
 #[cold]
 fn cold() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the projection of the headless run, for a 64x48 image
+    fn default_view() -> Mat4 {
+        flycam::Projection::default().matrix(64.0 / 48.0)
+            * flycam::Flycam::new().view_matrix()
+    }
+
+    fn image(lit: Option<(u32, u32)>) -> ScreenshotData {
+        let (width, height) = (64, 48);
+        let mut pixels = [10, 20, 30, 255].repeat((width * height) as usize);
+        if let Some((x, y)) = lit {
+            let i = (y * width + x) as usize * 4;
+            pixels[i..i + 4].copy_from_slice(&[255; 4]);
+        }
+        ScreenshotData {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// where [`Headless::verify_single_voxel`] looks for the voxel
+    fn voxel_pixel(projection_view: Mat4) -> (u32, u32) {
+        let center = Scene::SINGLE_VOXEL.as_vec3() + 0.5;
+        let ndc = projection_view.project_point3(center);
+        (
+            ((ndc.x * 0.5 + 0.5) * 64.0) as u32,
+            ((ndc.y * 0.5 + 0.5) * 48.0) as u32,
+        )
+    }
+
+    #[test]
+    fn single_voxel_lit() {
+        let view = default_view();
+        let lit = image(Some(voxel_pixel(view)));
+        Headless::verify_single_voxel(&lit, view).unwrap();
+    }
+
+    #[test]
+    fn single_voxel_missing() {
+        let err = Headless::verify_single_voxel(&image(None), default_view())
+            .unwrap_err();
+        assert!(err.to_string().contains("was not rendered"), "{err}");
+    }
+
+    #[test]
+    fn single_voxel_corners() {
+        let err = Headless::verify_single_voxel(
+            &image(Some((63, 47))),
+            default_view(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("corner"), "{err}");
+    }
+
+    #[test]
+    fn single_voxel_out_of_view() {
+        // looking away from the voxel
+        let view = flycam::Projection::default().matrix(64.0 / 48.0)
+            * Mat4::look_to_rh(Vec3::splat(40.0), Vec3::ONE, Vec3::NEG_Y);
+        let err =
+            Headless::verify_single_voxel(&image(None), view).unwrap_err();
+        assert!(err.to_string().contains("not in view"), "{err}");
+    }
+
+    /// a headless run writing to and comparing in a fresh directory
+    fn golden(name: &str, tolerance: u8) -> (Headless, std::path::PathBuf) {
+        let dir = env::temp_dir()
+            .join(format!("luminary-golden-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let args = [
+            "--headless".to_owned(),
+            "--out".to_owned(),
+            dir.join("out.png").display().to_string(),
+            "--tolerance".to_owned(),
+            tolerance.to_string(),
+        ];
+        let headless = Headless::from_args(args.into_iter()).unwrap().unwrap();
+        (headless, dir)
+    }
+
+    #[test]
+    fn golden_within_tolerance() {
+        let (headless, dir) = golden("within", 2);
+        let reference = dir.join("reference.png");
+        let expected = image(None);
+        png::write_rgba8(&reference, 64, 48, &expected.pixels).unwrap();
+
+        let mut drifted = image(None);
+        drifted.pixels[0] += 2;
+        headless
+            .compare(&drifted, reference.to_str().unwrap())
+            .unwrap();
+        assert!(!dir.join("out-diff.png").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn golden_drift_writes_a_diff() {
+        let (headless, dir) = golden("drift", 2);
+        let reference = dir.join("reference.png");
+        png::write_rgba8(&reference, 64, 48, &image(None).pixels).unwrap();
+
+        let lit = image(Some((1, 0)));
+        let err = headless
+            .compare(&lit, reference.to_str().unwrap())
+            .unwrap_err();
+        assert!(err.to_string().starts_with("1 pixels differ"), "{err}");
+
+        // the differing pixel is red, the rest is dimmed
+        let (width, height, diff) =
+            png::read_rgba8(dir.join("out-diff.png")).unwrap();
+        assert_eq!((width, height), (64, 48));
+        assert_eq!(diff[..8], [2, 5, 7, 255, 255, 0, 0, 255]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn golden_size_mismatch() {
+        let (headless, dir) = golden("size", 2);
+        let reference = dir.join("reference.png");
+        png::write_rgba8(&reference, 2, 1, &[0; 8]).unwrap();
+
+        let err = headless
+            .compare(&image(None), reference.to_str().unwrap())
+            .unwrap_err();
+        assert!(err.to_string().contains("is 2x1"), "{err}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use egui::{Context, TexturesDelta, ViewportId};
+use eyre::Result;
+use winit::{event::WindowEvent, window::Window};
+
+use crate::graphics::Graphics;
+
+//
+
+/// egui drawn over the frame, it gets the window events first
+/// while visible, so clicks on it don't reach the camera
+pub struct Overlay {
+    ctx: Context,
+    state: egui_winit::State,
+    visible: bool,
+    /// the meshes of the last visible frame are still set
+    drawn: bool,
+}
+
+impl Overlay {
+    pub fn new(window: &Window, visible: bool) -> Self {
+        let ctx = Context::default();
+        let state = egui_winit::State::new(
+            ctx.clone(),
+            ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            None,
+        );
+        Self {
+            ctx,
+            state,
+            visible,
+            drawn: false,
+        }
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible ^= true;
+    }
+
+    /// true if egui used `event`, then the app should ignore it
+    pub fn on_window_event(
+        &mut self,
+        window: &Window,
+        event: &WindowEvent,
+    ) -> bool {
+        self.visible && self.state.on_window_event(window, event).consumed
+    }
+
+    /// lay out the UI with `add_contents` and hand it to `graphics`,
+    /// a hidden overlay only clears the last meshes
+    pub fn run(
+        &mut self,
+        window: &Window,
+        graphics: &mut Graphics,
+        add_contents: impl FnMut(&Context),
+    ) -> Result<()> {
+        if !self.visible {
+            if self.drawn {
+                self.drawn = false;
+                graphics.set_ui(&[], &TexturesDelta::default(), 1.0)?;
+            }
+            return Ok(());
+        }
+
+        let input = self.state.take_egui_input(window);
+        let output = self.ctx.run(input, add_contents);
+        self.state
+            .handle_platform_output(window, output.platform_output);
+
+        let primitives =
+            self.ctx.tessellate(output.shapes, output.pixels_per_point);
+        self.drawn = true;
+        graphics.set_ui(
+            &primitives,
+            &output.textures_delta,
+            output.pixels_per_point,
+        )
+    }
+}
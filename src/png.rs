@@ -0,0 +1,194 @@
+use std::{fs, path::Path};
+
+use eyre::{Result, bail};
+
+//
+
+/// write 8-bit RGBA `pixels` as an uncompressed PNG
+pub fn write_rgba8(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+) -> Result<()> {
+    if pixels.len() != width as usize * height as usize * 4 {
+        bail!(
+            "{} bytes of pixels do not match a {width}x{height} RGBA image",
+            pixels.len()
+        );
+    }
+
+    // every scanline starts with the filter type, 0 is no filter
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks_exact(width as usize * 4) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend(width.to_be_bytes());
+    ihdr.extend(height.to_be_bytes());
+    // bit depth 8, color type RGBA, deflate, adaptive filters, no interlace
+    ihdr.extend([8, 6, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &ihdr);
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+
+    fs::write(path, png)?;
+    Ok(())
+}
+
+/// read an 8-bit RGBA PNG written by [`write_rgba8`],
+/// compressed or filtered images are not supported
+pub fn read_rgba8(path: impl AsRef<Path>) -> Result<(u32, u32, Vec<u8>)> {
+    let png = fs::read(path)?;
+    let Some(mut rest) = png.strip_prefix(b"\x89PNG\r\n\x1a\n") else {
+        bail!("not a PNG");
+    };
+
+    let mut size = None;
+    let mut zlib = Vec::new();
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[..4].try_into()?) as usize;
+        let ty = &rest[4..8];
+        let Some(data) = rest.get(8..8 + len) else {
+            bail!("truncated {} chunk", String::from_utf8_lossy(ty));
+        };
+        match ty {
+            b"IHDR" => {
+                if data.len() != 13 || data[8..] != [8, 6, 0, 0, 0] {
+                    bail!("only 8-bit non-interlaced RGBA is supported");
+                }
+                size = Some((
+                    u32::from_be_bytes(data[..4].try_into()?),
+                    u32::from_be_bytes(data[4..8].try_into()?),
+                ));
+            }
+            b"IDAT" => zlib.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = &rest[(12 + len).min(rest.len())..];
+    }
+
+    let Some((width, height)) = size else {
+        bail!("missing IHDR chunk");
+    };
+    let raw = zlib_unstored(&zlib)?;
+    let stride = width as usize * 4 + 1;
+    if raw.len() != stride * height as usize {
+        bail!("image data does not match a {width}x{height} RGBA image");
+    }
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in raw.chunks_exact(stride) {
+        if row[0] != 0 {
+            bail!("filtered scanlines are not supported");
+        }
+        pixels.extend_from_slice(&row[1..]);
+    }
+
+    Ok((width, height, pixels))
+}
+
+fn chunk(png: &mut Vec<u8>, ty: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(ty);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// zlib stream made of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+/// the data of a zlib stream made of uncompressed deflate blocks
+fn zlib_unstored(zlib: &[u8]) -> Result<Vec<u8>> {
+    let mut rest = zlib.get(2..).unwrap_or_default();
+    let mut out = Vec::new();
+    loop {
+        let Some(&[header, l0, l1, _, _]) = rest.get(..5) else {
+            bail!("truncated deflate block");
+        };
+        if header & 0b110 != 0 {
+            bail!("compressed deflate blocks are not supported");
+        }
+        let len = u16::from_le_bytes([l0, l1]) as usize;
+        let Some(block) = rest.get(5..5 + len) else {
+            bail!("truncated deflate block");
+        };
+        out.extend_from_slice(block);
+        rest = &rest[5 + len..];
+
+        if header & 1 != 0 {
+            return Ok(out);
+        }
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in bytes.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zlib_round_trip() {
+        // more than one stored block
+        let data = (0..200_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        assert_eq!(zlib_unstored(&zlib_stored(&data)).unwrap(), data);
+        assert_eq!(zlib_unstored(&zlib_stored(&[])).unwrap(), []);
+        assert!(zlib_unstored(&zlib_stored(&data)[..1000]).is_err());
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+}
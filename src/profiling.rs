@@ -0,0 +1,28 @@
+//! profiler zones and frame marks as `tracing` spans and events,
+//! everything compiles to nothing without the `tracy` feature
+
+/// a named CPU zone that lasts until the end of the enclosing scope
+macro_rules! zone {
+    ($name:literal) => {
+        #[cfg(feature = "tracy")]
+        let _zone = tracing::trace_span!($name).entered();
+    };
+}
+
+pub(crate) use zone;
+
+/// end of a presented frame
+#[inline(always)]
+pub fn frame_mark() {
+    #[cfg(feature = "tracy")]
+    tracing::trace!(frame_mark = true);
+}
+
+/// a GPU region measured with timestamp queries
+#[inline(always)]
+pub fn gpu_zone(label: &str, ms: f32) {
+    #[cfg(feature = "tracy")]
+    tracing::trace!(gpu_zone = label, ms);
+    #[cfg(not(feature = "tracy"))]
+    let _ = (label, ms);
+}
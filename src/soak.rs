@@ -0,0 +1,298 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    time::{Duration, Instant},
+};
+
+use eyre::{Result, bail};
+use glam::{Quat, U64Vec3, Vec3};
+
+use crate::{
+    flycam::{CameraPose, Flycam, Projection},
+    graphics::{
+        BuildConfig, FxaaPushConst, Graphics, GridPushConst, PushConst,
+        VisionMode, world,
+    },
+    png,
+};
+
+//
+
+/// `--soak MINUTES [--seed N]`, a long seeded workload that looks for
+/// leaks and slow growth, samples are written to a CSV every minute
+/// and the run fails if the invariants do not hold at the end
+pub struct Soak {
+    pub duration: Duration,
+    pub seed: u64,
+    pub csv: String,
+    /// the last readback is saved here
+    pub out: String,
+}
+
+/// one row of the soak CSV
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    secs: f32,
+    frames: u64,
+    p50: f32,
+    p95: f32,
+    p99: f32,
+    allocated_bytes: u64,
+    reserved_bytes: u64,
+    allocations: usize,
+    blocks: usize,
+    command_pools: usize,
+    command_buffers: usize,
+    voxel_nodes: usize,
+    validation_errors: u64,
+}
+
+impl Sample {
+    const HEADER: &str = "secs,frames,p50_ms,p95_ms,p99_ms,allocated_bytes,\
+        reserved_bytes,allocations,blocks,command_pools,command_buffers,\
+        voxel_nodes,validation_errors";
+
+    fn row(&self) -> String {
+        format!(
+            "{:.1},{},{:.3},{:.3},{:.3},{},{},{},{},{},{},{},{}",
+            self.secs,
+            self.frames,
+            self.p50,
+            self.p95,
+            self.p99,
+            self.allocated_bytes,
+            self.reserved_bytes,
+            self.allocations,
+            self.blocks,
+            self.command_pools,
+            self.command_buffers,
+            self.voxel_nodes,
+            self.validation_errors,
+        )
+    }
+}
+
+impl Soak {
+    const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+    /// frames between explosions, every other one undoes the last
+    const EXPLOSION_FRAMES: u64 = 30;
+    const VISION_FRAMES: u64 = 120;
+    const TOGGLE_FRAMES: u64 = 200;
+    /// allowed growth of the allocated GPU memory after the first sample
+    const MEMORY_SLACK: f64 = 1.10;
+
+    pub fn run(
+        &self,
+        graphics: &mut Graphics,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        let mut rng = Rng::new(self.seed);
+        let mut csv = format!("{}\n", Sample::HEADER);
+        let mut samples = Vec::new();
+
+        let aspect = width as f32 / height as f32;
+        let projection = Projection::default();
+        let mut eye = Flycam::new();
+
+        let vision_modes = VisionMode::ALL
+            .iter()
+            .filter(|mode| mode.is_available(BuildConfig::CURRENT))
+            .collect::<Vec<_>>();
+        let mut mode_flags = 0;
+        let mut fxaa = false;
+        let mut hud = graphics.hud();
+        let mut undo = None;
+
+        let start = Instant::now();
+        let mut next_sample = start + Self::SAMPLE_INTERVAL;
+        let mut frames = 0u64;
+        // only the samples report, so each one covers its whole minute
+        graphics.set_fps_interval(Duration::MAX);
+        graphics.report_fps();
+
+        tracing::info!(
+            "soaking for {:.0} minutes with seed {}",
+            self.duration.as_secs_f32() / 60.0,
+            self.seed
+        );
+        loop {
+            let now = Instant::now();
+            let done = now.duration_since(start) >= self.duration;
+
+            if now >= next_sample || done {
+                next_sample += Self::SAMPLE_INTERVAL;
+                let sample = Self::sample(graphics, start, frames);
+                writeln!(csv, "{}", sample.row())?;
+                fs::write(&self.csv, &csv)?;
+                tracing::info!("soak sample: {}", sample.row());
+                samples.push(sample);
+
+                // exercises the readback path as often as the samples
+                let screenshot = graphics.screenshot()?;
+                png::write_rgba8(
+                    &self.out,
+                    screenshot.width,
+                    screenshot.height,
+                    &screenshot.pixels,
+                )?;
+            }
+            if done {
+                break;
+            }
+
+            // orbit the scene at a drifting radius and height
+            let t = start.elapsed().as_secs_f32();
+            let center = Vec3::splat(world::voxels::Octree::SIZE as f32 / 2.0);
+            let radius = 36.0 + 12.0 * (t * 0.13).sin();
+            let height = 20.0 * (t * 0.07).cos();
+            let position =
+                center + Vec3::new(radius * t.cos(), height, radius * t.sin());
+            eye.set_pose(CameraPose {
+                position,
+                orientation: Quat::IDENTITY,
+            });
+            eye.look_at(center);
+
+            if frames.is_multiple_of(Self::EXPLOSION_FRAMES) {
+                let changes = match undo.take() {
+                    Some(undo) => undo,
+                    None => Self::explosion(&mut rng),
+                };
+                let inverse = graphics.edit_voxels(changes)?;
+                if frames.is_multiple_of(Self::EXPLOSION_FRAMES * 2) {
+                    undo = Some(inverse);
+                }
+            }
+            // nothing reacts to the events here, they only must not pile up
+            graphics.take_world_events();
+
+            if frames.is_multiple_of(Self::VISION_FRAMES)
+                && !vision_modes.is_empty()
+            {
+                mode_flags = vision_modes
+                    [rng.below(vision_modes.len() as u64) as usize]
+                    .flags;
+            }
+            if frames.is_multiple_of(Self::TOGGLE_FRAMES) {
+                fxaa ^= rng.below(2) == 0;
+                hud ^= rng.below(2) == 0;
+                graphics.set_fxaa(fxaa.then_some(FxaaPushConst {
+                    intensity: 0.75,
+                    edge_threshold: 0.05,
+                }));
+                graphics.set_hud(hud);
+            }
+
+            let projection_view = projection.matrix(aspect) * eye.view_matrix();
+            graphics.draw(PushConst {
+                projection_view: projection_view.inverse(),
+                mode_flags,
+                max_ray_distance: 0.0,
+                min_ray_distance: 0.0,
+                render_extent: 0,
+                grid: GridPushConst::default(),
+            })?;
+            frames += 1;
+        }
+
+        Self::check(&samples)
+    }
+
+    fn sample(graphics: &mut Graphics, start: Instant, frames: u64) -> Sample {
+        let stats = graphics.report_fps();
+        let memory = graphics.memory_report();
+        let (command_pools, command_buffers) = graphics.command_buffer_counts();
+        Sample {
+            secs: start.elapsed().as_secs_f32(),
+            frames,
+            p50: stats.map_or(0.0, |stats| stats.p50),
+            p95: stats.map_or(0.0, |stats| stats.p95),
+            p99: stats.map_or(0.0, |stats| stats.p99),
+            allocated_bytes: memory.allocated_bytes,
+            reserved_bytes: memory.reserved_bytes,
+            allocations: memory.allocations,
+            blocks: memory.blocks.len(),
+            command_pools,
+            command_buffers,
+            voxel_nodes: graphics.voxel_stats().nodes,
+            validation_errors: graphics.validation_errors(),
+        }
+    }
+
+    /// clear a ball of a few voxels somewhere in the octree
+    fn explosion(rng: &mut Rng) -> Vec<(U64Vec3, u32)> {
+        let size = world::voxels::Octree::SIZE as u64;
+        let center =
+            U64Vec3::new(rng.below(size), rng.below(size), rng.below(size));
+        world::voxels::Octree::ball(center, 2 + rng.below(3), 0)
+    }
+
+    /// the first sample is the baseline, everything is allowed
+    /// to settle in during the first minute
+    fn check(samples: &[Sample]) -> Result<()> {
+        let (Some(first), Some(last)) = (samples.first(), samples.last())
+        else {
+            bail!("the soak ended without samples");
+        };
+
+        let mut violations = Vec::new();
+        if last.validation_errors != 0 {
+            violations
+                .push(format!("{} validation errors", last.validation_errors));
+        }
+        if last.command_pools > first.command_pools
+            || last.command_buffers > first.command_buffers
+        {
+            violations.push(format!(
+                "command pools/buffers grew from {}/{} to {}/{}",
+                first.command_pools,
+                first.command_buffers,
+                last.command_pools,
+                last.command_buffers
+            ));
+        }
+        if last.allocations > first.allocations {
+            violations.push(format!(
+                "GPU allocations grew from {} to {}",
+                first.allocations, last.allocations
+            ));
+        }
+        if last.allocated_bytes as f64
+            > first.allocated_bytes as f64 * Self::MEMORY_SLACK
+        {
+            violations.push(format!(
+                "GPU memory grew from {}B to {}B",
+                first.allocated_bytes, last.allocated_bytes
+            ));
+        }
+
+        if !violations.is_empty() {
+            bail!("soak invariants violated: {}", violations.join(", "));
+        }
+        tracing::info!("soak passed after {} frames", last.frames);
+        Ok(())
+    }
+}
+
+/// xorshift64*, reproducible from the seed on every platform
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // the state must never be 0
+        Self(seed ^ 0x9e37_79b9_7f4a_7c15 | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// `0..n`, the modulo bias is irrelevant here
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}